@@ -0,0 +1,13 @@
+#![no_main]
+
+use codecrafters_interpreter::tokenizer::Tokenizer;
+use libfuzzer_sys::fuzz_target;
+
+// Any bytes at all can be streamed (bad UTF-8 is a read error), and any str has to check out
+fuzz_target!(|data: &[u8]| {
+    Tokenizer::from_reader(data).for_each(drop);
+
+    if let Ok(source) = std::str::from_utf8(data) {
+        Tokenizer::check(source).unwrap();
+    }
+});
@@ -0,0 +1,45 @@
+/*
+ * jp-lox, a Lox interpreter, for hosts that aren't written in Rust
+ *
+ * This matches src/ffi.rs (`just test-ffi` checks), build the library with `just ffi`. Output
+ * from print goes to stdout.
+ */
+
+#ifndef JP_LOX_H
+#define JP_LOX_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* An interpreter, only ever used through a pointer */
+typedef struct JpLox JpLox;
+
+/* Return codes from jp_lox_run, the same as the command line's exit codes */
+#define JP_LOX_OK 0
+#define JP_LOX_INVALID_ARGUMENT 64
+#define JP_LOX_SYNTAX_ERROR 65
+#define JP_LOX_RUNTIME_ERROR 70
+
+/* A new interpreter, globals persist from one jp_lox_run to the next */
+JpLox *jp_lox_new(void);
+
+/* Run a NUL-terminated UTF-8 program, returning one of the codes above */
+int jp_lox_run(JpLox *lox, const char *source);
+
+/*
+ * The message from the last jp_lox_run that failed, or NULL if the last one succeeded
+ *
+ * The string belongs to the interpreter and is only valid until its next jp_lox_run or
+ * jp_lox_free.
+ */
+const char *jp_lox_last_error(const JpLox *lox);
+
+/* Free an interpreter from jp_lox_new, NULL is ignored */
+void jp_lox_free(JpLox *lox);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
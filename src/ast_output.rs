@@ -0,0 +1,198 @@
+use crate::json::Json;
+use crate::parser::{Ast, AstNode, NodeId};
+
+/// The AST as JSON, each node an object with its type, span, and fields
+pub fn json(ast: &Ast) -> Json {
+    node_json(ast, ast.root())
+}
+
+fn node_json(ast: &Ast, id: NodeId) -> Json {
+    let node = &ast[id];
+    let json = |id: &NodeId| node_json(ast, *id);
+    let nodes = |nodes: &[NodeId]| Json::Array(nodes.iter().map(json).collect());
+
+    let fields = match node {
+        AstNode::Literal(_, value) => vec![("value", value.into())],
+        AstNode::Symbol(_, name) => vec![("name", name.as_ref().into())],
+        AstNode::Block(_, body) | AstNode::Program(_, body) => vec![("body", nodes(body))],
+        AstNode::Group(_, expression) => vec![("expression", json(expression))],
+        AstNode::Binary(_, op, lhs, rhs) => vec![
+            ("op", op.to_value().into()),
+            ("lhs", json(lhs)),
+            ("rhs", json(rhs)),
+        ],
+        AstNode::Logical(_, op, lhs, rhs) => vec![
+            ("op", op.to_value().into()),
+            ("lhs", json(lhs)),
+            ("rhs", json(rhs)),
+        ],
+        AstNode::Unary(_, op, operand) => {
+            vec![("op", op.to_value().into()), ("operand", json(operand))]
+        }
+        AstNode::Call(_, callee, args) => {
+            vec![("callee", json(callee)), ("args", nodes(args))]
+        }
+        AstNode::Get(_, object, name) | AstNode::OptionalGet(_, object, name) => {
+            vec![("object", json(object)), ("name", name.as_str().into())]
+        }
+        AstNode::OptionalChain(_, chain) => vec![("chain", json(chain))],
+        AstNode::Coalesce(_, lhs, rhs) => vec![("lhs", json(lhs)), ("rhs", json(rhs))],
+        AstNode::Index(_, object, index) => vec![("object", json(object)), ("index", json(index))],
+        AstNode::Declaration(_, name, value) | AstNode::Assignment(_, name, value) => {
+            vec![("name", name.as_ref().into()), ("value", json(value))]
+        }
+        AstNode::Enum(_, name, variants) => vec![
+            ("name", name.as_str().into()),
+            (
+                "variants",
+                Json::Array(variants.iter().map(|v| v.as_str().into()).collect()),
+            ),
+        ],
+        AstNode::Import(_, module) => vec![("module", module.as_str().into())],
+        AstNode::ForIn(_, name, iterable, body) => vec![
+            ("name", name.as_ref().into()),
+            ("iterable", json(iterable)),
+            ("body", json(body)),
+        ],
+        AstNode::Assert(_, condition, message) => vec![
+            ("condition", json(condition)),
+            ("message", message.as_ref().map(json).into()),
+        ],
+        AstNode::Print(_, value) | AstNode::Throw(_, value) => vec![("value", json(value))],
+        AstNode::Try(_, body, catch, finally) => vec![
+            ("body", json(body)),
+            (
+                "catch",
+                catch
+                    .as_ref()
+                    .map(|(name, handler)| {
+                        Json::object([
+                            ("type", "Catch".into()),
+                            ("name", name.as_ref().into()),
+                            ("body", json(handler)),
+                        ])
+                    })
+                    .into(),
+            ),
+            ("finally", finally.as_ref().map(json).into()),
+        ],
+    };
+
+    let mut object = vec![
+        ("type".to_string(), node.kind().into()),
+        ("span".to_string(), (&node.span()).into()),
+    ];
+    object.extend(fields.into_iter().map(|(k, v)| (k.to_string(), v)));
+    Json::Object(object)
+}
+
+/// The AST as an indented tree, one node per line with its span
+///
+/// Simple fields are shown inline as key=value, nodes are nested below labeled with their field.
+pub fn tree(ast: &Ast) -> String {
+    let mut output = String::new();
+    write_tree(&mut output, None, &json(ast), 0);
+    output
+}
+
+fn write_tree(output: &mut String, label: Option<&str>, node: &Json, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let label = label.map(|label| format!("{label}: ")).unwrap_or_default();
+    output.push_str(&format!("{indent}{label}{}\n", summary(node, " ")));
+
+    for (key, child) in children(node) {
+        write_tree(output, Some(key), child, depth + 1);
+    }
+}
+
+// A node's type, simple fields, and span, joined by the separator
+fn summary(node: &Json, separator: &str) -> String {
+    let Json::Object(fields) = node else {
+        return node.to_string();
+    };
+
+    let mut parts = vec![match node.get("type") {
+        Some(Json::String(kind)) => kind.clone(),
+        _ => "?".to_string(),
+    }];
+
+    for (key, value) in fields {
+        if key != "type" && key != "span" && is_scalar(value) {
+            parts.push(format!("{key}={value}"));
+        }
+    }
+
+    if let Some(span) = node.get("span") {
+        if let (Some(line), Some(start), Some(end)) =
+            (span.get("line"), span.get("start"), span.get("end"))
+        {
+            parts.push(format!("@ {line}:{start}-{end}"));
+        }
+    }
+
+    parts.join(separator)
+}
+
+// Child nodes along with the field they're in, lists of nodes are flattened
+fn children(node: &Json) -> Vec<(&str, &Json)> {
+    let Json::Object(fields) = node else {
+        return vec![];
+    };
+
+    let mut children = vec![];
+    for (key, value) in fields {
+        match value {
+            Json::Object(_) if key != "span" => children.push((key.as_str(), value)),
+            Json::Array(values) if !is_scalar(value) => {
+                children.extend(values.iter().map(|value| (key.as_str(), value)))
+            }
+            _ => {}
+        }
+    }
+    children
+}
+
+fn is_scalar(value: &Json) -> bool {
+    match value {
+        Json::Object(_) => false,
+        Json::Array(values) => values.iter().all(is_scalar),
+        _ => true,
+    }
+}
+
+/// The AST as a Graphviz digraph, with labeled edges from each node to its children
+pub fn dot(ast: &Ast) -> String {
+    let mut output = String::from("digraph ast {\n    node [shape=box];\n");
+    let mut next_id = 0;
+    write_dot(&mut output, &json(ast), &mut next_id);
+    output.push_str("}\n");
+    output
+}
+
+// Write a node and everything below it, returning the node's id
+fn write_dot(output: &mut String, node: &Json, next_id: &mut usize) -> usize {
+    let id = *next_id;
+    *next_id += 1;
+
+    let label = dot_string(&summary(node, "\n"));
+    output.push_str(&format!("    n{id} [label={label}];\n"));
+
+    for (key, child) in children(node) {
+        let child_id = write_dot(output, child, next_id);
+        output.push_str(&format!(
+            "    n{id} -> n{child_id} [label={}];\n",
+            dot_string(key)
+        ));
+    }
+
+    id
+}
+
+// A quoted DOT string, newlines become centered line breaks
+fn dot_string(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{escaped}\"")
+}
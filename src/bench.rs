@@ -0,0 +1,53 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+
+/// Timing for repeated runs of the same program
+#[derive(Debug)]
+pub struct Report {
+    pub iterations: usize,
+    pub min: Duration,
+    pub median: Duration,
+    pub mean: Duration,
+    pub steps: usize,
+}
+
+/// Call run warmup times without measuring, then iterations times measuring each
+///
+/// Run returns how many nodes it evaluated, which should be the same every run.
+pub fn bench(
+    iterations: usize,
+    warmup: usize,
+    mut run: impl FnMut() -> Result<usize>,
+) -> Result<Report> {
+    if iterations == 0 {
+        return Err(anyhow!("Need at least one iteration to benchmark"));
+    }
+
+    for _ in 0..warmup {
+        run()?;
+    }
+
+    let mut times = Vec::with_capacity(iterations);
+    let mut steps = 0;
+    for _ in 0..iterations {
+        let start = Instant::now();
+        steps = run()?;
+        times.push(start.elapsed());
+    }
+
+    times.sort();
+    let median = if iterations % 2 == 0 {
+        (times[iterations / 2 - 1] + times[iterations / 2]) / 2
+    } else {
+        times[iterations / 2]
+    };
+
+    Ok(Report {
+        iterations,
+        min: times[0],
+        median,
+        mean: times.iter().sum::<Duration>() / iterations as u32,
+        steps,
+    })
+}
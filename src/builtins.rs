@@ -1,15 +1,233 @@
+use std::cmp::Ordering;
+use std::fmt::{self, Display};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
 use anyhow::{anyhow, Result};
 
-use crate::values::Value::{self, *};
+use crate::const_enum;
+use crate::context::Context;
+use crate::diagnostic::frame_text;
+use crate::evaluator::{call_value, RuntimeError};
+use crate::span::Span;
+use crate::values::{with_article, Value::{self, *}};
+
+// Widen a numeric argument to a float, only call this after checking is_number
+fn float(value: &Value) -> f64 {
+    value.as_f64().expect("numeric argument")
+}
+
+// The most digits String.toFixed will show after the decimal point
+const MAX_FIXED_DIGITS: i64 = 100;
+
+// Read one line from the context's input without the line ending, None at EOF
+fn read_line(ctx: &mut Context) -> Result<Option<std::string::String>> {
+    let mut line = std::string::String::new();
+    if ctx.input.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+
+    let trimmed = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(trimmed);
+    Ok(Some(line))
+}
+
+// Parse a number the same way the tokenizer would type it, nil if it doesn't parse
+// Rust also parses nan, inf, and ones too big for a float, which Lox numbers can't be written as
+fn parse_number(text: &str) -> Value {
+    let text = text.trim();
+    match text.parse::<i64>() {
+        Ok(i) => Integer(i),
+        Err(_) => text
+            .parse::<f64>()
+            .ok()
+            .filter(|n| n.is_finite())
+            .map_or(Nil, Number),
+    }
+}
+
+// Call back into a Lox callable from inside a builtin, errors are on the builtin's line
+fn call(ctx: &mut Context, span: Span, f: &Value, args: Vec<Value>) -> Result<Value> {
+    call_value(ctx, span, f.clone(), args)
+}
+
+// Sort with a fallible comparison, the callback returns a negative number if a comes before b,
+// a positive one if it comes after, and zero if they're the same; without a callback use natural
+// order
+fn sort_values(
+    ctx: &mut Context,
+    span: Span,
+    items: &[Value],
+    cmp: Option<&Value>,
+) -> Result<Value> {
+    let mut compare = |a: &Value, b: &Value| -> Result<Ordering> {
+        match cmp {
+            // Anything else (NaN included) wouldn't be a consistent order
+            Some(f) => {
+                let result = call(ctx, span, f, vec![a.clone(), b.clone()])?;
+                match result.as_f64().and_then(|n| n.partial_cmp(&0.0)) {
+                    Some(ordering) => Ok(ordering),
+                    None => Err(RuntimeError::InvalidComparison(span, result.lox_display()).into()),
+                }
+            }
+            None => match (a, b) {
+                (String(a), String(b)) => Ok(a.cmp(b)),
+                // NaN is neither before nor after anything
+                (a, b) if a.is_number() && b.is_number() => a
+                    .compare_numbers(b)
+                    .ok_or_else(|| RuntimeError::NotComparable(span, a.lox_display(), b.lox_display()).into()),
+                (a, b) => Err(RuntimeError::NotComparable(span, a.lox_display(), b.lox_display()).into()),
+            },
+        }
+    };
+
+    let sorted = merge_sort(items.to_vec(), &mut compare)?;
+    Ok(List(Arc::new(sorted)))
+}
+
+// A stable merge sort that stops at the first error
+//
+// The std sorts can panic if the comparison isn't a total order, which a Lox callback doesn't have
+// to be, this only ever gives some order of the same items.
+fn merge_sort(
+    mut items: Vec<Value>,
+    compare: &mut impl FnMut(&Value, &Value) -> Result<Ordering>,
+) -> Result<Vec<Value>> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+
+    let right = items.split_off(items.len() / 2);
+    let left = merge_sort(items, compare)?;
+    let right = merge_sort(right, compare)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        // Later items are compared to earlier ones, like the std sorts do. Ties keep the left one
+        // first, so equal items stay in order.
+        if compare(b, a)? == Ordering::Less {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+// Format a string with {} (next argument), {0} (by position), and {:.2} (precision) placeholders
+// Use {{ and }} for literal braces
+fn format_string(span: Span, format: &str, args: &[Value]) -> Result<std::string::String> {
+    let invalid = |message: std::string::String| RuntimeError::InvalidFormat(span, message);
+    let mut result = std::string::String::new();
+    let mut next_arg = 0;
+    let mut chars = format.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                result.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                result.push('}');
+            }
+            '{' => {
+                let mut spec = std::string::String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => return Err(invalid("Unterminated placeholder".into()).into()),
+                    }
+                }
+
+                let (position, precision) = match spec.split_once(':') {
+                    Some((position, precision)) => (position, Some(precision)),
+                    None => (spec.as_str(), None),
+                };
+
+                let index = if position.is_empty() {
+                    next_arg += 1;
+                    next_arg - 1
+                } else {
+                    position
+                        .parse::<usize>()
+                        .map_err(|_| invalid(format!("Invalid placeholder {{{spec}}}")))?
+                };
+
+                let arg = args
+                    .get(index)
+                    .ok_or_else(|| invalid(format!("Missing argument {index}")))?;
+
+                match precision {
+                    None => result.push_str(&arg.lox_display()),
+                    Some(precision) => {
+                        let digits = precision
+                            .strip_prefix('.')
+                            .and_then(|digits| digits.parse::<usize>().ok())
+                            .ok_or_else(|| invalid(format!("Invalid precision {{{spec}}}")))?;
+
+                        match arg {
+                            n if n.is_number() => result.push_str(&format!("{:.*}", digits, float(n))),
+                            String(s) => result.extend(s.chars().take(digits)),
+                            other => {
+                                let message = format!("Cannot apply precision to {}", other.lox_display());
+                                return Err(invalid(message).into());
+                            }
+                        }
+                    }
+                }
+            }
+            '}' => return Err(invalid("Unmatched '}'".into()).into()),
+            c => result.push(c),
+        }
+    }
+
+    Ok(result)
+}
+
+// Integer division stays an integer only if it's exact, otherwise promote to float
+fn divide_integers(a: i64, b: i64) -> Value {
+    match (a.checked_rem(b), a.checked_div(b)) {
+        (Some(0), Some(quotient)) => Integer(quotient),
+        _ => Number(a as f64 / b as f64),
+    }
+}
+
+// A signature's parameters as a static slice, eg `(a: Number, b: ?Number)` or `(items: ..Any)`
+macro_rules! params {
+    (@[$(($name:ident, $accepts:ident, $count:ident)),*]) => {
+        &[$(Param { name: stringify!($name), accepts: ParamType::$accepts, count: ParamCount::$count }),*]
+    };
+    (@[$($done:tt),*] $name:ident: ?$accepts:ident $(, $($rest:tt)*)?) => {
+        params!(@[$($done,)* ($name, $accepts, Optional)] $($($rest)*)?)
+    };
+    (@[$($done:tt),*] $name:ident: ..$accepts:ident) => {
+        params!(@[$($done,)* ($name, $accepts, Many)])
+    };
+    (@[$($done:tt),*] $name:ident: $accepts:ident $(, $($rest:tt)*)?) => {
+        params!(@[$($done,)* ($name, $accepts, One)] $($($rest)*)?)
+    };
+    ($($params:tt)*) => { params!(@[] $($params)*) };
+}
 
 macro_rules! define_builtins {
     (
+        |$ctx:ident, $span:ident|
         $(
             $variant:ident
             $token:literal 
+            $(in $namespace:ident)?
+            ($($params:tt)*)
+            $([$($capability:ident),+])?
             {
                 $(
-                    $args_pat:pat => $body:tt
+                    $args_pat:pat $(if $guard:expr)? => $body:tt
                 ),+
                 $(,)?
             }
@@ -33,15 +251,51 @@ macro_rules! define_builtins {
         }
 
         impl BuiltIn {
-            #[allow(unused_braces)]
-            pub fn call(&self, args: Vec<Value>) -> Result<Value> {
+            /// Every builtin, in definition order
+            pub fn values() -> Vec<BuiltIn> {
+                vec![$(BuiltIn::$variant),+]
+            }
+
+            /// The name this builtin is called by in Lox code
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(BuiltIn::$variant => $token,)+
+                }
+            }
+
+            /// The namespace it's a member of, None for the ones that are globals
+            pub fn namespace(&self) -> Option<Namespace> {
+                match self {
+                    $(BuiltIn::$variant => None$(.or(Some(Namespace::$namespace)))?,)+
+                }
+            }
+
+            /// Its parameters, what each accepts, and so how many arguments it takes
+            pub fn signature(&self) -> Signature {
+                let params: &'static [Param] = match self {
+                    $(BuiltIn::$variant => params!($($params)*),)+
+                };
+                Signature { name: self.name(), namespace: self.namespace(), params }
+            }
+
+            /// What this builtin needs from outside the interpreter, empty if nothing
+            pub fn capabilities(&self) -> &'static [Capability] {
+                match self {
+                    $(BuiltIn::$variant => &[$($(Capability::$capability),+)?],)+
+                }
+            }
+
+            // Bodies can return early with an error, which makes the wrapping Ok unreachable
+            // Variadic builtins match any arguments, which makes the fallback unreachable
+            #[allow(unused_braces, unreachable_code, unreachable_patterns)]
+            pub fn call(&self, $ctx: &mut Context, $span: Span, args: Vec<Value>) -> Result<Value> {
                 match self {
                     $(BuiltIn::$variant => { // Each builtin by symbol, eg +
                         match args.as_slice() {
                             $(
-                                $args_pat => { Ok($body) },
+                                $args_pat $(if $guard)? => { Ok($body) },
                             )+
-                            _ => Err(anyhow!("Invalid arguments {args:?} for builtin: {}", stringify!($variant))),
+                            _ => Err(invalid_arguments($span, self, args)),
                         }
                     },)+
                 }
@@ -50,62 +304,607 @@ macro_rules! define_builtins {
     };
 }
 
+// Arguments none of a builtin's patterns matched, which is either the wrong number of them, one
+// that isn't the type its parameter takes, or values the builtin can't do anything with
+fn invalid_arguments(span: Span, builtin: &BuiltIn, args: Vec<Value>) -> anyhow::Error {
+    let signature = builtin.signature();
+    let arity = signature.arity();
+    if !arity.accepts(args.len()) {
+        return RuntimeError::WrongArity(span, arity, args.len()).into();
+    }
+
+    // Past the end of the parameters is only possible if the last one takes the rest
+    let mismatch = args.iter().enumerate().find_map(|(i, arg)| {
+        let param = signature.params.get(i).or(signature.params.last())?;
+        (!param.accepts.accepts(arg)).then_some((param, arg))
+    });
+    let message = match mismatch {
+        Some((param, arg)) => format!(
+            "Expected {} for {} but got {}.",
+            with_article(param.accepts.to_value()),
+            param.name,
+            with_article(arg.type_name())
+        ),
+        None => {
+            let args = args.iter().map(|arg| arg.lox_display()).collect::<Vec<_>>();
+            format!("Invalid arguments ({}).", args.join(", "))
+        }
+    };
+    RuntimeError::InvalidArguments(span, signature.qualified_name(), message).into()
+}
+
+const_enum! {
+    pub ParamType as &str {
+        Any => "any",
+        Number => "number",
+        String => "string",
+        List => "list",
+        Function => "function",
+        Builder => "builder",
+    }
+}
+
+impl ParamType {
+    /// If a value is this type, any callable for Function
+    pub fn accepts(&self, value: &Value) -> bool {
+        match self {
+            ParamType::Any => true,
+            ParamType::Function => value.is_callable(),
+            _ => value.type_name() == self.to_value(),
+        }
+    }
+}
+
+/// How many arguments a parameter takes, Many only for the last one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamCount {
+    One,
+    Optional,
+    Many,
+}
+
+/// One of a builtin's parameters, declared in define_builtins! like `(x: Number)`
+///
+/// Optional ones are written `?Number` and come after the others, a last one that takes the rest
+/// of the arguments is written `..Any`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Param {
+    pub name: &'static str,
+    pub accepts: ParamType,
+    pub count: ParamCount,
+}
+
+/// A builtin's name and parameters, shown like `List.sort(items: list, compare?: function)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signature {
+    pub name: &'static str,
+    pub namespace: Option<Namespace>,
+    pub params: &'static [Param],
+}
+
+impl Signature {
+    /// As Lox code calls it, eg Math.sqrt
+    pub fn qualified_name(&self) -> std::string::String {
+        match self.namespace {
+            Some(namespace) => format!("{}.{}", namespace.to_value(), self.name),
+            None => self.name.to_string(),
+        }
+    }
+
+    pub fn arity(&self) -> Arity {
+        let count = |count| self.params.iter().filter(|p| p.count == count).count();
+        let min = count(ParamCount::One);
+        let max = match count(ParamCount::Many) {
+            0 => Some(min + count(ParamCount::Optional)),
+            _ => None,
+        };
+        Arity { min, max }
+    }
+}
+
+impl Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let params = self.params.iter().map(|param| match param.count {
+            ParamCount::One => format!("{}: {}", param.name, param.accepts.to_value()),
+            ParamCount::Optional => format!("{}?: {}", param.name, param.accepts.to_value()),
+            ParamCount::Many => format!("..{}: {}", param.name, param.accepts.to_value()),
+        });
+        write!(f, "{}({})", self.qualified_name(), params.collect::<Vec<_>>().join(", "))
+    }
+}
+
+/// How many arguments something takes, with no max if it takes any number after min
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arity {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        count >= self.min && self.max.map_or(true, |max| count <= max)
+    }
+}
+
+// As the start of an error, eg "1 argument" or "1 to 2 arguments"
+impl Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let arguments = |n: usize| if n == 1 { "argument" } else { "arguments" };
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{} {}", max, arguments(max)),
+            Some(max) => write!(f, "{} to {} {}", self.min, max, arguments(max)),
+            None => write!(f, "at least {} {}", self.min, arguments(self.min)),
+        }
+    }
+}
+
+/// Something outside the interpreter a builtin can touch, see BuiltinSet
+///
+/// Builtins declare theirs in define_builtins! by name in brackets after their parameters, eg
+/// `Clock "clock" () [Clock]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// The process environment, getenv and setenv
+    Env,
+    /// The system clock
+    Clock,
+    /// Reading from the context's input
+    Input,
+}
+
+/// Which builtins Lox code can use, everything by default
+///
+/// Builtins left out can't be looked up or called, eg everything but readLine and readNumber is
+/// left in `BuiltinSet::all().deny(Capability::Input)`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuiltinSet {
+    denied: Vec<Capability>,
+    removed: Vec<BuiltIn>,
+}
+
+impl BuiltinSet {
+    /// Every builtin
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Only builtins that can't touch the environment or the clock, for --sandbox
+    pub fn sandbox() -> Self {
+        Self::all().deny(Capability::Env).deny(Capability::Clock)
+    }
+
+    /// Leave out every builtin that needs this capability
+    pub fn deny(mut self, capability: Capability) -> Self {
+        if !self.denied.contains(&capability) {
+            self.denied.push(capability);
+        }
+        self
+    }
+
+    /// Leave out this one builtin
+    pub fn remove(mut self, builtin: BuiltIn) -> Self {
+        if !self.removed.contains(&builtin) {
+            self.removed.push(builtin);
+        }
+        self
+    }
+
+    pub fn allows(&self, builtin: BuiltIn) -> bool {
+        !self.removed.contains(&builtin)
+            && !builtin
+                .capabilities()
+                .iter()
+                .any(|capability| self.denied.contains(capability))
+    }
+}
+
+const_enum! {
+    pub Namespace as &str {
+        Math => "Math",
+        String => "String",
+        List => "List",
+    }
+}
+
+// Named constants, members of their namespace like builtins are
+const CONSTANTS: [(Namespace, &str, Value); 2] = [
+    (Namespace::Math, "PI", Number(std::f64::consts::PI)),
+    (Namespace::Math, "E", Number(std::f64::consts::E)),
+];
+
+impl BuiltIn {
+    /// The builtin a name means on its own, if nothing's declared with it
+    ///
+    /// Members of namespaces are only globals with compat, which is how they were before there
+    /// were namespaces.
+    pub fn global(name: &str, compat: bool) -> Option<BuiltIn> {
+        BuiltIn::try_from(name)
+            .ok()
+            .filter(|builtin| compat || builtin.namespace().is_none())
+    }
+
+    /// The constant a name means on its own, the same way
+    pub fn constant(name: &str, compat: bool) -> Option<Value> {
+        CONSTANTS
+            .iter()
+            .find(|(_, constant, _)| compat && *constant == name)
+            .map(|(_, _, value)| value.clone())
+    }
+}
+
+impl Namespace {
+    /// A builtin or constant in this namespace, eg sqrt for Math.sqrt
+    pub fn member(&self, name: &str) -> Option<Value> {
+        let builtin = BuiltIn::try_from(name)
+            .ok()
+            .filter(|builtin| builtin.namespace() == Some(*self))
+            .map(Value::Builtin);
+        builtin.or_else(|| {
+            CONSTANTS
+                .iter()
+                .find(|(namespace, constant, _)| namespace == self && *constant == name)
+                .map(|(_, _, value)| value.clone())
+        })
+    }
+
+    /// Every member's name, builtins first
+    pub fn members(&self) -> Vec<&'static str> {
+        let builtins = BuiltIn::values()
+            .into_iter()
+            .filter(|builtin| builtin.namespace() == Some(*self))
+            .map(|builtin| builtin.name());
+        let constants = CONSTANTS
+            .iter()
+            .filter(|(namespace, _, _)| namespace == self)
+            .map(|(_, constant, _)| *constant);
+        builtins.chain(constants).collect()
+    }
+}
+
 define_builtins!{
+    |ctx, span|
+
     // Arithmetic
-    Plus "+" {
-       [Number(a), Number(b)] => { Number(a + b) },
+    // Integers stay integers until they overflow or mix with a float
+    Plus "+" (a: Any, b: Any) {
+       [Integer(a), Integer(b)] => { a.checked_add(*b).map_or(Number(*a as f64 + *b as f64), Integer) },
+       [a, b] if a.is_number() && b.is_number() => { Number(float(a) + float(b)) },
        [String(a), String(b)] => { 
             let mut result = std::string::String::new();
-            result.push_str(&a);
-            result.push_str(&b);
-            String(result)
+            result.push_str(a);
+            result.push_str(b);
+            ctx.strings.concatenations += 1;
+            ctx.strings.concatenated_bytes += result.len();
+            String(result.into())
        },
+       // Only with --lenient-concat, numbers print as they would with print
+       [String(a), Integer(b)] if ctx.lenient_concat => { String(format!("{a}{b}").into()) },
+       [String(a), Number(b)] if ctx.lenient_concat => { String(format!("{a}{b}").into()) },
+       [Integer(a), String(b)] if ctx.lenient_concat => { String(format!("{a}{b}").into()) },
+       [Number(a), String(b)] if ctx.lenient_concat => { String(format!("{a}{b}").into()) },
+       _ => { return Err(RuntimeError::OperandsNotNumbersOrStrings(span).into()) },
     },
-    Minus "-" {
-        [Number(a), Number(b)] => { Number(a - b) },
+    Minus "-" (a: Number, b: ?Number) {
+        [Integer(a), Integer(b)] => { a.checked_sub(*b).map_or(Number(*a as f64 - *b as f64), Integer) },
+        [a, b] if a.is_number() && b.is_number() => { Number(float(a) - float(b)) },
+        [Integer(v)] => { v.checked_neg().map_or(Number(-(*v as f64)), Integer) },
         [Number(v)] => { Number(-v) },
+        [_] => { return Err(RuntimeError::OperandNotNumber(span).into()) },
+        _ => { return Err(RuntimeError::OperandsNotNumbers(span).into()) },
     },
-    Times "*" {
-        [Number(a), Number(b)] => { Number(a * b) },
+    Times "*" (a: Number, b: Number) {
+        [Integer(a), Integer(b)] => { a.checked_mul(*b).map_or(Number(*a as f64 * *b as f64), Integer) },
+        [a, b] if a.is_number() && b.is_number() => { Number(float(a) * float(b)) },
+        _ => { return Err(RuntimeError::OperandsNotNumbers(span).into()) },
     },
-    Divide "/" {
-        [Number(a), Number(b)] => { Number(a / b) },
+    Divide "/" (a: Number, b: Number) {
+        // Only with --ieee-math, dividing by zero is inf (or NaN for 0 / 0)
+        [a, b] if a.is_number() && b.as_f64() == Some(0.0) && !ctx.ieee_math => {
+            return Err(RuntimeError::DivisionByZero(span).into())
+        },
+        [Integer(a), Integer(b)] => { divide_integers(*a, *b) },
+        [a, b] if a.is_number() && b.is_number() => { Number(float(a) / float(b)) },
+        _ => { return Err(RuntimeError::OperandsNotNumbers(span).into()) },
     },
 
     // Boolean
-    And "and" {
-        [Bool(a), Bool(b)] => { Bool(*a && *b) },
+    // Any value can be a condition, and and or return whichever operand decided the result
+    And "and" (a: Any, b: Any) {
+        [a, b] => { if a.is_truthy() { b.clone() } else { a.clone() } },
     },
-    Or "or" {
-        [Bool(a), Bool(b)] => { Bool(*a || *b) },
+    Or "or" (a: Any, b: Any) {
+        [a, b] => { if a.is_truthy() { a.clone() } else { b.clone() } },
     },
-    Not "!" {
-        [Bool(v)] => { Bool(!v) },
+    Not "!" (value: Any) {
+        [v] => { Bool(!v.is_truthy()) },
     },
 
     // Comparisons
-    LessThan "<" {
-        [Number(a), Number(b)] => { Bool(a < b) },
+    // Numbers compare exactly, like ==, so NaN is never less or greater than anything. Strings
+    // compare lexicographically, mixed types fall through to an error
+    LessThan "<" (a: Any, b: Any) {
+        [a, b] if a.is_number() && b.is_number() => { Bool(a.compare_numbers(b).is_some_and(Ordering::is_lt)) },
+        [String(a), String(b)] => { Bool(a < b) },
+        _ => { return Err(RuntimeError::OperandsNotNumbersOrStrings(span).into()) },
     },
-    LessThanOrEqual "<=" {
-        [Number(a), Number(b)] => { Bool(a <= b) },
+    LessThanOrEqual "<=" (a: Any, b: Any) {
+        [a, b] if a.is_number() && b.is_number() => { Bool(a.compare_numbers(b).is_some_and(Ordering::is_le)) },
+        [String(a), String(b)] => { Bool(a <= b) },
+        _ => { return Err(RuntimeError::OperandsNotNumbersOrStrings(span).into()) },
     },
-    GreaterThanOrEqual ">=" {
-        [Number(a), Number(b)] => { Bool(a >= b) },
+    GreaterThanOrEqual ">=" (a: Any, b: Any) {
+        [a, b] if a.is_number() && b.is_number() => { Bool(a.compare_numbers(b).is_some_and(Ordering::is_ge)) },
+        [String(a), String(b)] => { Bool(a >= b) },
+        _ => { return Err(RuntimeError::OperandsNotNumbersOrStrings(span).into()) },
     },
-    GreaterThan ">" {
-        [Number(a), Number(b)] => { Bool(a > b) },
+    GreaterThan ">" (a: Any, b: Any) {
+        [a, b] if a.is_number() && b.is_number() => { Bool(a.compare_numbers(b).is_some_and(Ordering::is_gt)) },
+        [String(a), String(b)] => { Bool(a > b) },
+        _ => { return Err(RuntimeError::OperandsNotNumbersOrStrings(span).into()) },
     },
-    Equal "==" {
+    Equal "==" (a: Any, b: Any) {
         [a, b] => { Bool(a == b) },
     },
-    NotEqual "!=" {
+    NotEqual "!=" (a: Any, b: Any) {
         [a, b] => { Bool(a != b) },
     },
+    Is "is" (a: Any, b: Any) {
+        [a, b] => { Bool(a.is(b)) },
+    },
     
+    // Math
+    Sqrt "sqrt" in Math (x: Number) {
+        [a] if a.is_number() => { Number(float(a).sqrt()) },
+    },
+    Abs "abs" in Math (x: Number) {
+        [Integer(a)] => { a.checked_abs().map_or(Number((*a as f64).abs()), Integer) },
+        [Number(a)] => { Number(a.abs()) },
+    },
+    Floor "floor" in Math (x: Number) {
+        [Integer(a)] => { Integer(*a) },
+        [Number(a)] => { Number(a.floor()) },
+    },
+    Ceil "ceil" in Math (x: Number) {
+        [Integer(a)] => { Integer(*a) },
+        [Number(a)] => { Number(a.ceil()) },
+    },
+    Round "round" in Math (x: Number) {
+        [Integer(a)] => { Integer(*a) },
+        [Number(a)] => { Number(a.round()) },
+    },
+    Min "min" in Math (a: Number, b: Number) {
+        [Integer(a), Integer(b)] => { Integer(*a.min(b)) },
+        [a, b] if a.is_number() && b.is_number() => { Number(float(a).min(float(b))) },
+    },
+    Max "max" in Math (a: Number, b: Number) {
+        [Integer(a), Integer(b)] => { Integer(*a.max(b)) },
+        [a, b] if a.is_number() && b.is_number() => { Number(float(a).max(float(b))) },
+    },
+    Sin "sin" in Math (x: Number) {
+        [a] if a.is_number() => { Number(float(a).sin()) },
+    },
+    Cos "cos" in Math (x: Number) {
+        [a] if a.is_number() => { Number(float(a).cos()) },
+    },
+    // Integer powers stay integers if the exponent is non-negative and it fits
+    Pow "pow" in Math (base: Number, exponent: Number) {
+        [Integer(a), Integer(b)] if *b >= 0 => {
+            u32::try_from(*b)
+                .ok()
+                .and_then(|b| a.checked_pow(b))
+                .map_or(Number((*a as f64).powf(*b as f64)), Integer)
+        },
+        [a, b] if a.is_number() && b.is_number() => { Number(float(a).powf(float(b))) },
+    },
+
+    // Conversion and formatting
+    ParseNumber "parseNumber" in String (text: String) {
+        [String(s)] => { parse_number(s) },
+    },
+    // Up to 100 digits, the same as JavaScript's toFixed
+    ToFixed "toFixed" in String (n: Number, digits: Number) {
+        [n, Integer(digits @ 0..=MAX_FIXED_DIGITS)] if n.is_number() => {
+            String(format!("{:.*}", *digits as usize, float(n)).into())
+        },
+        [n, Integer(digits)] if n.is_number() => {
+            let message = format!("Digits must be between 0 and {MAX_FIXED_DIGITS}, got {digits}.");
+            return Err(RuntimeError::InvalidArguments(span, "String.toFixed".into(), message).into())
+        },
+    },
+    Format "format" in String (format: String, args: ..Any) {
+        [String(format), args @ ..] => { String(format_string(span, format, args)?.into()) },
+    },
+    Upper "upper" in String (text: String) {
+        [String(s)] => { String(s.to_uppercase().into()) },
+    },
+    Lower "lower" in String (text: String) {
+        [String(s)] => { String(s.to_lowercase().into()) },
+    },
+    // Builders are appended to in place, so building a string a piece at a time only copies each
+    // piece once, where s = s + piece copies all of s every time
+    StringBuilder "StringBuilder" (initial: ?String) {
+        [] => { StringBuilder(Default::default()) },
+        [String(s)] => { StringBuilder(Arc::new(Mutex::new(s.to_string()))) },
+    },
+    // Anything else is appended the way print would show it, returning the builder to chain them
+    Append "append" in String (builder: Builder, piece: Any) {
+        [StringBuilder(builder), piece] => {
+            // Shown before locking, the piece could be (or contain) this builder
+            let shown;
+            let piece = match piece {
+                String(s) => s,
+                _ => {
+                    shown = piece.lox_display();
+                    shown.as_str()
+                }
+            };
+
+            let mut text = builder.lock().unwrap();
+            let before = text.len();
+            text.push_str(piece);
+            ctx.strings.appends += 1;
+            ctx.strings.appended_bytes += text.len() - before;
+            drop(text);
+            StringBuilder(builder.clone())
+        },
+    },
+    ToString "toString" (value: Any) {
+        [v] => { String(v.lox_display().into()) },
+    },
+
+    // Environment variables, only with --allow-env
+    Getenv "getenv" (name: String) [Env] {
+        [String(name)] if ctx.allow_env => { std::env::var(&**name).map_or(Nil, Value::from) },
+        [String(_)] => { return Err(RuntimeError::EnvDisabled(span).into()) },
+    },
+    Setenv "setenv" (name: String, value: Any) [Env] {
+        [String(name), value] if ctx.allow_env => { std::env::set_var(&**name, value.lox_display()); Nil },
+        [String(_), _] => { return Err(RuntimeError::EnvDisabled(span).into()) },
+    },
+
+    // Reflection
+    Type "type" (value: Any) {
+        [v] => { String(v.type_name().into()) },
+    },
+    // How many calls this one is inside of, 0 at top level
+    CallDepth "callDepth" () {
+        [] => { Integer(ctx.call_stack.len().saturating_sub(1) as i64) },
+    },
+    // Those calls innermost first, the same as a runtime error's backtrace
+    StackTrace "stackTrace" () {
+        [] => {
+            let callers = &ctx.call_stack[..ctx.call_stack.len().saturating_sub(1)];
+            let frames = (0..callers.len()).rev().map(|i| Value::from(frame_text(callers, i)));
+            List(Arc::new(frames.collect()))
+        },
+    },
+
+    // Lists
+    List "list" (items: ..Any) {
+        items => { List(Arc::new(items.to_vec())) },
+    },
+    // Strings count characters (unicode scalar values), not bytes
+    Len "len" (value: Any) {
+        [String(s)] => { Integer(s.chars().count() as i64) },
+        [List(items)] => { Integer(items.len() as i64) },
+        [StringBuilder(builder)] => { Integer(builder.lock().unwrap().chars().count() as i64) },
+    },
+    Map "map" in List (items: List, f: Function) {
+        [List(items), f] if f.is_callable() => {
+            let mut result = Vec::with_capacity(items.len());
+            for item in items.iter() {
+                result.push(call(ctx, span, f, vec![item.clone()])?);
+            }
+            List(Arc::new(result))
+        },
+    },
+    Filter "filter" in List (items: List, f: Function) {
+        [List(items), f] if f.is_callable() => {
+            let mut result = vec![];
+            for item in items.iter() {
+                if call(ctx, span, f, vec![item.clone()])?.is_truthy() {
+                    result.push(item.clone());
+                }
+            }
+            List(Arc::new(result))
+        },
+    },
+    Reduce "reduce" in List (items: List, f: Function, initial: Any) {
+        [List(items), f, init] if f.is_callable() => {
+            let mut acc = init.clone();
+            for item in items.iter() {
+                acc = call(ctx, span, f, vec![acc, item.clone()])?;
+            }
+            acc
+        },
+    },
+    Sort "sort" in List (items: List, compare: ?Function) {
+        [List(items)] => { sort_values(ctx, span, items, None)? },
+        [List(items), f] if f.is_callable() => { sort_values(ctx, span, items, Some(f))? },
+    },
+
+    // Functions
+    Partial "partial" (f: Function, bound: ..Any) {
+        [f, bound @ ..] if f.is_callable() => { Partial(Arc::new(f.clone()), Arc::new(bound.to_vec())) },
+    },
+
+    // Time
+    Clock "clock" () [Clock] {
+        [] => { Number(ctx.host.now()) },
+    },
+
+    // Random numbers, from 0 up to 1
+    Random "random" in Math () {
+        [] => { Number(ctx.host.random()) },
+    },
+
     // I/O
-    Print "print" {
-        [Number(n)] => { println!("{}", n); Nil },
-        [a] => { println!("{}", a); Nil },
+    Print "print" (value: Any) {
+        [a] => { writeln!(ctx.output, "{}", a.display(ctx.print_style))?; Nil },
+    },
+    Printf "printf" (format: String, args: ..Any) {
+        [String(format), args @ ..] => { write!(ctx.output, "{}", format_string(span, format, args)?)?; Nil },
+    },
+    ReadLine "readLine" () [Input] {
+        [] => { read_line(ctx)?.map_or(Nil, Value::from) },
     },
-}
\ No newline at end of file
+    // Numbers that don't parse (or EOF) are nil
+    ReadNumber "readNumber" () [Input] {
+        [] => { read_line(ctx)?.map_or(Nil, |line| parse_number(&line)) },
+    },
+}
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn numbers(values: &[i64]) -> Vec<Value> {
+        values.iter().map(|n| Integer(*n)).collect()
+    }
+
+    #[test]
+    fn merge_sort_is_stable() {
+        let items = numbers(&[3, 10, 2, 13, 1, 12]);
+        // Only the last digit counts, so 3 and 13 tie (and 2 and 12)
+        let sorted = merge_sort(items, &mut |a, b| {
+            Ok((a.as_f64().unwrap() % 10.0).total_cmp(&(b.as_f64().unwrap() % 10.0)))
+        })
+        .unwrap();
+
+        assert_eq!(sorted, numbers(&[10, 1, 2, 12, 3, 13]));
+    }
+
+    #[test]
+    fn merge_sort_survives_comparisons_that_arent_an_order() {
+        let items = numbers(&(0..100).collect::<Vec<_>>());
+        let mut calls = 0;
+        let sorted = merge_sort(items.clone(), &mut |_, _| {
+            calls += 1;
+            match calls % 3 {
+                0 => Ok(Ordering::Less),
+                _ => Ok(Ordering::Greater),
+            }
+        })
+        .unwrap();
+
+        // Some order of the same items, nothing lost or repeated
+        let mut sorted = sorted.iter().map(Value::lox_display).collect::<Vec<_>>();
+        sorted.sort_by_key(|n| n.parse::<i64>().unwrap());
+        assert_eq!(
+            sorted,
+            items.iter().map(Value::lox_display).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn merge_sort_stops_at_the_first_error() {
+        let mut calls = 0;
+        let result = merge_sort(numbers(&[4, 3, 2, 1]), &mut |_, _| {
+            calls += 1;
+            Err(anyhow::anyhow!("stop"))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+}
@@ -0,0 +1,496 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+
+use crate::interner::Interner;
+use crate::named_source::NamedSource;
+use crate::parser::{Ast, AstNode, NodeId};
+use crate::span::Span;
+use crate::values::Value;
+
+/// Every compiled file starts with these bytes
+pub const MAGIC: &[u8; 4] = b"LOXC";
+
+/// Bump this whenever the encoding changes, older files are rejected rather than misread
+pub const VERSION: u16 = 4;
+
+/// Encode parsed programs so they can be run later without tokenizing or parsing
+///
+/// The layout is the magic bytes, a little endian u16 version, then a count of programs. Each
+/// program is its source name and text (kept for error messages) followed by its AST. Numbers
+/// are LEB128 varints except for floats, which are their raw 8 bytes.
+pub fn compile(programs: &[(NamedSource, Ast)]) -> Vec<u8> {
+    let mut writer = Writer {
+        bytes: vec![],
+        ast: &Ast::new(),
+    };
+
+    writer.bytes.extend_from_slice(MAGIC);
+    writer.bytes.extend_from_slice(&VERSION.to_le_bytes());
+
+    writer.usize(programs.len());
+    for (source, ast) in programs {
+        writer.string(&source.name);
+        writer.string(&source.bytes);
+        writer.ast = ast;
+        writer.node(ast.root());
+    }
+
+    writer.bytes
+}
+
+/// Decode programs written by compile
+pub fn load(bytes: &[u8]) -> Result<Vec<(NamedSource, Ast)>> {
+    if bytes.len() < MAGIC.len() + 2 || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(anyhow!("Not a compiled Lox file"));
+    }
+
+    let version = u16::from_le_bytes([bytes[MAGIC.len()], bytes[MAGIC.len() + 1]]);
+    if version != VERSION {
+        return Err(anyhow!(
+            "Compiled with format version {}, but this version of jp-lox can only run version {}; recompile it",
+            version,
+            VERSION
+        ));
+    }
+
+    let mut reader = Reader {
+        bytes,
+        position: MAGIC.len() + 2,
+        names: Interner::new(),
+        ast: Ast::new(),
+    };
+
+    let count = reader.usize()?;
+    let mut programs = vec![];
+    for _ in 0..count {
+        let name = reader.string()?;
+        let text = reader.string()?;
+        reader.node()?;
+        let ast = std::mem::take(&mut reader.ast);
+        programs.push((NamedSource::new(name, text), ast));
+    }
+
+    if reader.position != bytes.len() {
+        return Err(anyhow!("Unexpected data after the end of the program"));
+    }
+
+    Ok(programs)
+}
+
+// One tag per kind of node, these are part of the format so never reorder them
+mod tag {
+    pub const LITERAL: u8 = 0;
+    pub const SYMBOL: u8 = 1;
+    pub const GROUP: u8 = 2;
+    pub const BLOCK: u8 = 3;
+    pub const CALL: u8 = 4;
+    pub const GET: u8 = 5;
+    pub const OPTIONAL_GET: u8 = 6;
+    pub const OPTIONAL_CHAIN: u8 = 7;
+    pub const COALESCE: u8 = 8;
+    pub const INDEX: u8 = 9;
+    pub const DECLARATION: u8 = 10;
+    pub const ASSIGNMENT: u8 = 11;
+    pub const ENUM: u8 = 12;
+    pub const FOR_IN: u8 = 13;
+    pub const ASSERT: u8 = 14;
+    pub const THROW: u8 = 15;
+    pub const TRY: u8 = 16;
+    pub const PROGRAM: u8 = 17;
+    pub const BINARY: u8 = 18;
+    pub const UNARY: u8 = 19;
+    pub const LOGICAL: u8 = 20;
+    pub const PRINT: u8 = 21;
+    pub const IMPORT: u8 = 22;
+
+    pub const NIL: u8 = 0;
+    pub const BOOL: u8 = 1;
+    pub const INTEGER: u8 = 2;
+    pub const NUMBER: u8 = 3;
+    pub const STRING: u8 = 4;
+}
+
+struct Writer<'a> {
+    bytes: Vec<u8>,
+    ast: &'a Ast, // The program being written
+}
+
+impl Writer<'_> {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u64(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.bytes.push(byte);
+                return;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+
+    fn usize(&mut self, value: usize) {
+        self.u64(value as u64);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    fn string(&mut self, value: &str) {
+        self.usize(value.len());
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn span(&mut self, span: &Span) {
+        self.usize(span.line);
+        self.usize(span.column);
+        self.usize(span.start);
+        self.usize(span.end);
+        self.usize(span.byte_start);
+        self.usize(span.byte_end);
+    }
+
+    fn nodes(&mut self, nodes: &[NodeId]) {
+        self.usize(nodes.len());
+        for node in nodes {
+            self.node(*node);
+        }
+    }
+
+    fn optional_node(&mut self, node: &Option<NodeId>) {
+        self.bool(node.is_some());
+        if let Some(node) = node {
+            self.node(*node);
+        }
+    }
+
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::Nil => self.u8(tag::NIL),
+            Value::Bool(b) => {
+                self.u8(tag::BOOL);
+                self.bool(*b);
+            }
+            Value::Integer(i) => {
+                self.u8(tag::INTEGER);
+                self.bytes.extend_from_slice(&i.to_le_bytes());
+            }
+            Value::Number(n) => {
+                self.u8(tag::NUMBER);
+                self.bytes.extend_from_slice(&n.to_le_bytes());
+            }
+            Value::String(s) => {
+                self.u8(tag::STRING);
+                self.string(s);
+            }
+            value => unreachable!("literal values are never {}", value.type_name()),
+        }
+    }
+
+    fn node(&mut self, id: NodeId) {
+        let ast = self.ast;
+        match &ast[id] {
+            AstNode::Literal(span, value) => {
+                self.u8(tag::LITERAL);
+                self.span(span);
+                self.value(value);
+            }
+            AstNode::Symbol(span, name) => {
+                self.u8(tag::SYMBOL);
+                self.span(span);
+                self.string(name);
+            }
+            AstNode::Group(span, expression) => {
+                self.u8(tag::GROUP);
+                self.span(span);
+                self.node(*expression);
+            }
+            AstNode::Block(span, nodes) => {
+                self.u8(tag::BLOCK);
+                self.span(span);
+                self.nodes(nodes);
+            }
+            AstNode::Binary(span, op, lhs, rhs) => {
+                self.u8(tag::BINARY);
+                self.span(span);
+                self.string(op.to_value());
+                self.node(*lhs);
+                self.node(*rhs);
+            }
+            AstNode::Unary(span, op, operand) => {
+                self.u8(tag::UNARY);
+                self.span(span);
+                self.string(op.to_value());
+                self.node(*operand);
+            }
+            AstNode::Logical(span, op, lhs, rhs) => {
+                self.u8(tag::LOGICAL);
+                self.span(span);
+                self.string(op.to_value());
+                self.node(*lhs);
+                self.node(*rhs);
+            }
+            AstNode::Call(span, func, args) => {
+                self.u8(tag::CALL);
+                self.span(span);
+                self.node(*func);
+                self.nodes(args);
+            }
+            AstNode::Get(span, object, name) => {
+                self.u8(tag::GET);
+                self.span(span);
+                self.node(*object);
+                self.string(name);
+            }
+            AstNode::OptionalGet(span, object, name) => {
+                self.u8(tag::OPTIONAL_GET);
+                self.span(span);
+                self.node(*object);
+                self.string(name);
+            }
+            AstNode::OptionalChain(span, chain) => {
+                self.u8(tag::OPTIONAL_CHAIN);
+                self.span(span);
+                self.node(*chain);
+            }
+            AstNode::Coalesce(span, lhs, rhs) => {
+                self.u8(tag::COALESCE);
+                self.span(span);
+                self.node(*lhs);
+                self.node(*rhs);
+            }
+            AstNode::Index(span, object, index) => {
+                self.u8(tag::INDEX);
+                self.span(span);
+                self.node(*object);
+                self.node(*index);
+            }
+            AstNode::Declaration(span, name, value) => {
+                self.u8(tag::DECLARATION);
+                self.span(span);
+                self.string(name);
+                self.node(*value);
+            }
+            AstNode::Assignment(span, name, value) => {
+                self.u8(tag::ASSIGNMENT);
+                self.span(span);
+                self.string(name);
+                self.node(*value);
+            }
+            AstNode::Enum(span, name, variants) => {
+                self.u8(tag::ENUM);
+                self.span(span);
+                self.string(name);
+                self.usize(variants.len());
+                for variant in variants {
+                    self.string(variant);
+                }
+            }
+            AstNode::Import(span, module) => {
+                self.u8(tag::IMPORT);
+                self.span(span);
+                self.string(module);
+            }
+            AstNode::ForIn(span, name, iterable, body) => {
+                self.u8(tag::FOR_IN);
+                self.span(span);
+                self.string(name);
+                self.node(*iterable);
+                self.node(*body);
+            }
+            AstNode::Assert(span, condition, message) => {
+                self.u8(tag::ASSERT);
+                self.span(span);
+                self.node(*condition);
+                self.optional_node(message);
+            }
+            AstNode::Print(span, value) => {
+                self.u8(tag::PRINT);
+                self.span(span);
+                self.node(*value);
+            }
+            AstNode::Throw(span, value) => {
+                self.u8(tag::THROW);
+                self.span(span);
+                self.node(*value);
+            }
+            AstNode::Try(span, body, catch, finally) => {
+                self.u8(tag::TRY);
+                self.span(span);
+                self.node(*body);
+                self.bool(catch.is_some());
+                if let Some((name, handler)) = catch {
+                    self.string(name);
+                    self.node(*handler);
+                }
+                self.optional_node(finally);
+            }
+            AstNode::Program(span, nodes) => {
+                self.u8(tag::PROGRAM);
+                self.span(span);
+                self.nodes(nodes);
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    names: Interner, // Shared the same way the parser shares them
+    ast: Ast,        // Nodes of the program being read
+}
+
+impl Reader<'_> {
+    fn take(&mut self, count: usize) -> Result<&[u8]> {
+        if self.position + count > self.bytes.len() {
+            return Err(anyhow!("Unexpected end of compiled file"));
+        }
+
+        let bytes = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift >= 64 {
+                return Err(anyhow!("Invalid number in compiled file"));
+            }
+
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
+
+    fn usize(&mut self) -> Result<usize> {
+        Ok(self.u64()? as usize)
+    }
+
+    fn bool(&mut self) -> Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn eight_bytes(&mut self) -> Result<[u8; 8]> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(bytes)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.usize()?;
+        let bytes = self.take(len)?.to_vec();
+        String::from_utf8(bytes).map_err(|_| anyhow!("Invalid string in compiled file"))
+    }
+
+    fn name(&mut self) -> Result<Arc<str>> {
+        let name = self.string()?;
+        Ok(self.names.intern(&name))
+    }
+
+    // Operators are written as their symbol, eg +
+    fn operator<T: for<'s> TryFrom<&'s str>>(&mut self) -> Result<T> {
+        let op = self.string()?;
+        T::try_from(&op).map_err(|_| anyhow!("Unknown operator {} in compiled file", op))
+    }
+
+    fn span(&mut self) -> Result<Span> {
+        Ok(Span {
+            line: self.usize()?,
+            column: self.usize()?,
+            start: self.usize()?,
+            end: self.usize()?,
+            byte_start: self.usize()?,
+            byte_end: self.usize()?,
+        })
+    }
+
+    fn nodes(&mut self) -> Result<Vec<NodeId>> {
+        let len = self.usize()?;
+        (0..len).map(|_| self.node()).collect()
+    }
+
+    fn optional_node(&mut self) -> Result<Option<NodeId>> {
+        if self.bool()? {
+            Ok(Some(self.node()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        Ok(match self.u8()? {
+            tag::NIL => Value::Nil,
+            tag::BOOL => Value::Bool(self.bool()?),
+            tag::INTEGER => Value::Integer(i64::from_le_bytes(self.eight_bytes()?)),
+            tag::NUMBER => Value::Number(f64::from_le_bytes(self.eight_bytes()?)),
+            tag::STRING => Value::String(self.string()?.into()),
+            tag => return Err(anyhow!("Unknown value tag {} in compiled file", tag)),
+        })
+    }
+
+    // Children are read (and added) before the node itself, the same order the parser adds them
+    fn node(&mut self) -> Result<NodeId> {
+        let tag = self.u8()?;
+        let span = self.span()?;
+
+        let node = match tag {
+            tag::LITERAL => AstNode::Literal(span, self.value()?),
+            tag::SYMBOL => AstNode::Symbol(span, self.name()?),
+            tag::GROUP => AstNode::Group(span, self.node()?),
+            tag::BLOCK => AstNode::Block(span, self.nodes()?),
+            tag::BINARY => AstNode::Binary(span, self.operator()?, self.node()?, self.node()?),
+            tag::UNARY => AstNode::Unary(span, self.operator()?, self.node()?),
+            tag::LOGICAL => AstNode::Logical(span, self.operator()?, self.node()?, self.node()?),
+            tag::CALL => AstNode::Call(span, self.node()?, self.nodes()?),
+            tag::GET => AstNode::Get(span, self.node()?, self.string()?),
+            tag::OPTIONAL_GET => AstNode::OptionalGet(span, self.node()?, self.string()?),
+            tag::OPTIONAL_CHAIN => AstNode::OptionalChain(span, self.node()?),
+            tag::COALESCE => AstNode::Coalesce(span, self.node()?, self.node()?),
+            tag::INDEX => AstNode::Index(span, self.node()?, self.node()?),
+            tag::DECLARATION => AstNode::Declaration(span, self.name()?, self.node()?),
+            tag::ASSIGNMENT => AstNode::Assignment(span, self.name()?, self.node()?),
+            tag::ENUM => {
+                let name = self.string()?;
+                let len = self.usize()?;
+                let variants = (0..len)
+                    .map(|_| self.string())
+                    .collect::<Result<Vec<_>>>()?;
+                AstNode::Enum(span, name, variants)
+            }
+            tag::IMPORT => AstNode::Import(span, self.string()?),
+            tag::FOR_IN => AstNode::ForIn(span, self.name()?, self.node()?, self.node()?),
+            tag::ASSERT => AstNode::Assert(span, self.node()?, self.optional_node()?),
+            tag::PRINT => AstNode::Print(span, self.node()?),
+            tag::THROW => AstNode::Throw(span, self.node()?),
+            tag::TRY => {
+                let body = self.node()?;
+                let catch = if self.bool()? {
+                    Some((self.name()?, self.node()?))
+                } else {
+                    None
+                };
+                AstNode::Try(span, body, catch, self.optional_node()?)
+            }
+            tag::PROGRAM => AstNode::Program(span, self.nodes()?),
+            tag => return Err(anyhow!("Unknown node tag {} in compiled file", tag)),
+        };
+
+        Ok(self.ast.add(node))
+    }
+}
@@ -4,12 +4,13 @@ macro_rules! const_enum {
         $($value:ident => $char:expr),+
         $(,)?
     }) => {
-        #[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
+        #[derive(Debug, ::derive_more::Display, Clone, Copy, PartialEq, Eq)]
         $vis enum $name {
             $($value),+
         }
 
         impl $name {
+            #[allow(clippy::wrong_self_convention)]
             pub fn to_value(&self) -> $type {
                 match *self {
                     $(
@@ -27,12 +28,12 @@ macro_rules! const_enum {
         impl TryFrom<$type> for $name {
             type Error = ();
 
-            fn try_from(value: $type) -> Result<Self, Self::Error> {
+            fn try_from(value: $type) -> ::std::result::Result<Self, Self::Error> {
                 match value {
                     $(
-                        $char => Ok($name::$value),
+                        $char => ::std::result::Result::Ok($name::$value),
                     )+
-                    _ => Err(()),
+                    _ => ::std::result::Result::Err(()),
                 }
             }
         }
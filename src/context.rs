@@ -0,0 +1,264 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::builtins::BuiltinSet;
+use crate::coverage::Coverage;
+use crate::debugger::Debugger;
+use crate::hook::{EvalHook, Tracer};
+use crate::interpreter::{Host, SystemHost};
+use crate::module::ModulePath;
+use crate::named_source::NamedSource;
+use crate::profile::Profile;
+use crate::span::Span;
+use crate::values::{PrintStyle, Value};
+
+/// Interpreter-wide settings and state shared by the evaluator and builtins
+pub struct Context {
+    /// Allow `+` to stringify a number when the other operand is a string
+    pub lenient_concat: bool,
+
+    /// Allow dividing by zero, giving inf or NaN the way floats do, instead of an error
+    pub ieee_math: bool,
+
+    /// How print shows numbers
+    pub print_style: PrintStyle,
+
+    /// Allow namespaced builtins by their names alone (eg sqrt for Math.sqrt), like they used to be
+    pub compat: bool,
+
+    /// The source being evaluated, used to quote code in error messages
+    pub source: Option<NamedSource>,
+
+    /// Where readLine() and friends read from, stdin by default
+    pub input: Box<dyn BufRead + Send>,
+
+    /// Where print and printf write to, stdout by default
+    pub output: Box<dyn Write + Send>,
+
+    /// Where clock() and random() come from, the system's by default
+    pub host: Box<dyn Host>,
+
+    /// Where import looks for modules that aren't next to the file importing them
+    pub module_path: ModulePath,
+
+    /// Every module imported so far, so each only runs once
+    pub imported: Vec<PathBuf>,
+
+    /// Allow getenv() and setenv() to touch the process environment
+    pub allow_env: bool,
+
+    /// Which builtins can be used at all, see BuiltinSet::sandbox
+    pub builtins: BuiltinSet,
+
+    /// How many AST nodes have been evaluated so far
+    pub steps: usize,
+
+    /// How much building strings has copied so far, for --stats
+    pub strings: StringStats,
+
+    /// How deeply nodes can be nested while evaluating before it's a stack overflow
+    ///
+    /// Each level is a Rust stack frame, so this needs a stack::stack_size(max_depth) stack.
+    pub max_depth: usize,
+
+    /// How deeply nested the node being evaluated is
+    pub depth: usize,
+
+    /// Writes a line for every node evaluated for --trace, off by default
+    pub tracer: Option<Tracer>,
+
+    /// Where the time goes while running, only collected if this is set
+    pub profile: Option<Profile>,
+
+    /// Which lines have run, only collected if this is set
+    pub coverage: Option<Coverage>,
+
+    /// Anything else watching the evaluator, called after coverage, the profile, and the tracer
+    pub hooks: Vec<Box<dyn EvalHook>>,
+
+    /// Stops before statements to let someone look around, see the debugger module
+    pub debugger: Option<Debugger>,
+
+    /// Caps on how much each run can do, for code that can't be trusted
+    pub limits: Limits,
+
+    /// When the current run started and how many steps came before it, see start_run
+    pub started: Instant,
+    pub steps_at_start: usize,
+
+    /// Every call in progress, outermost first, builtins calling back into Lox included
+    pub call_stack: Vec<Frame>,
+
+    /// The call stack as it was when the last error happened, empty if it was at the top level
+    pub trace: Vec<Frame>,
+}
+
+/// One call in progress, what's being called and where it was called from
+///
+/// Calls made by builtins (eg map calling its function) or by the host have Span::ZERO.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub callee: Value,
+    pub span: Span,
+}
+
+/// Limits on a single run, each None (unlimited) by default
+///
+/// Going over any of them is a RuntimeError::LimitExceeded.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    /// How many AST nodes can be evaluated
+    pub max_steps: Option<usize>,
+
+    /// How long it can take, checked every so many steps rather than exactly
+    pub max_duration: Option<Duration>,
+
+    /// How deeply calls can be nested
+    pub max_call_depth: Option<usize>,
+}
+
+/// Strings built with + and with string builders, and how many bytes each copied
+///
+/// Every + copies both strings into a new one, so `s = s + piece` in a loop copies all of s each
+/// time around, where appending to a builder only copies the piece.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StringStats {
+    pub concatenations: usize,
+    pub concatenated_bytes: usize,
+    pub appends: usize,
+    pub appended_bytes: usize,
+}
+
+/// The default max_depth, enough for a chain of 10,000 terms like 1 + 1 + ... + 1
+pub const DEFAULT_MAX_DEPTH: usize = 25_000;
+
+impl Default for Context {
+    fn default() -> Self {
+        Self {
+            lenient_concat: false,
+            compat: false,
+            ieee_math: false,
+            print_style: PrintStyle::default(),
+            source: None,
+            input: Box::new(io::BufReader::new(io::stdin())),
+            output: Box::new(io::stdout()),
+            host: Box::new(SystemHost::default()),
+            module_path: ModulePath::default(),
+            imported: vec![],
+            allow_env: false,
+            builtins: BuiltinSet::all(),
+            steps: 0,
+            strings: StringStats::default(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            depth: 0,
+            tracer: None,
+            profile: None,
+            coverage: None,
+            hooks: vec![],
+            debugger: None,
+            limits: Limits::default(),
+            started: Instant::now(),
+            steps_at_start: 0,
+            call_stack: vec![],
+            trace: vec![],
+        }
+    }
+}
+
+impl Context {
+    /// Start timing and counting steps for limits from now, call this before each run
+    pub fn start_run(&mut self) {
+        self.started = Instant::now();
+        self.steps_at_start = self.steps;
+        self.trace.clear();
+    }
+
+    /// If any hooks are on, so the evaluator can skip the work of calling them
+    pub fn is_watched(&self) -> bool {
+        self.coverage.is_some()
+            || self.profile.is_some()
+            || self.tracer.is_some()
+            || !self.hooks.is_empty()
+    }
+
+    /// Every hook that's on, in the order they're called, with the name of the current source
+    pub fn all_hooks(&mut self) -> (&str, impl Iterator<Item = &mut (dyn EvalHook + 'static)>) {
+        let source = self
+            .source
+            .as_ref()
+            .map_or("<unknown>", |s| s.name.as_str());
+        let built_in = [
+            self.coverage.as_mut().map(|hook| hook as &mut dyn EvalHook),
+            self.profile.as_mut().map(|hook| hook as &mut dyn EvalHook),
+            self.tracer.as_mut().map(|hook| hook as &mut dyn EvalHook),
+        ];
+        let added = self.hooks.iter_mut().map(|hook| hook.as_mut());
+        (source, built_in.into_iter().flatten().chain(added))
+    }
+}
+
+impl fmt::Debug for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Context")
+            .field("lenient_concat", &self.lenient_concat)
+            .field("ieee_math", &self.ieee_math)
+            .field("print_style", &self.print_style)
+            .field("compat", &self.compat)
+            .field("source", &self.source)
+            .field("allow_env", &self.allow_env)
+            .field("builtins", &self.builtins)
+            .field("steps", &self.steps)
+            .field("strings", &self.strings)
+            .field("max_depth", &self.max_depth)
+            .field("tracing", &self.tracer.is_some())
+            .field("profiling", &self.profile.is_some())
+            .field("coverage", &self.coverage.is_some())
+            .field("hooks", &self.hooks.len())
+            .field("debugging", &self.debugger.is_some())
+            .field("limits", &self.limits)
+            .finish_non_exhaustive()
+    }
+}
+
+/// An output that can be read back after the context writing to it is done, or from elsewhere
+///
+/// Clones share the same buffer, so keep one and give another to a context.
+#[derive(Debug, Clone, Default)]
+pub struct OutputBuffer {
+    bytes: Arc<Mutex<Vec<u8>>>,
+}
+
+impl OutputBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything written so far, invalid UTF-8 is replaced
+    pub fn contents(&self) -> String {
+        let bytes = self.bytes.lock().expect("output buffer lock");
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    /// Everything written so far, leaving the buffer empty
+    pub fn take(&self) -> String {
+        let bytes = std::mem::take(&mut *self.bytes.lock().expect("output buffer lock"));
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes
+            .lock()
+            .expect("output buffer lock")
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
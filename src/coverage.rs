@@ -0,0 +1,151 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anyhow::Result;
+
+use crate::hook::{EvalHook, Node};
+use crate::named_source::NamedSource;
+use crate::parser::Ast;
+use crate::span::Span;
+
+/// Which lines ran and how often, filled in as a hook while Context::coverage is set
+#[derive(Debug, Default)]
+pub struct Coverage {
+    executed: HashMap<String, HashMap<(usize, usize, &'static str), usize>>, // By line, column, kind
+}
+
+/// How many of a source's lines have code and how many of those ran
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub lines: usize,
+    pub hit: usize,
+}
+
+impl Summary {
+    pub fn percent(&self) -> f64 {
+        if self.lines == 0 {
+            100.0
+        } else {
+            self.hit as f64 * 100.0 / self.lines as f64
+        }
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count one node of this kind evaluated at this span
+    pub fn record(&mut self, source: &str, span: Span, kind: &'static str) {
+        // The program itself is on line 0, it isn't a line anyone wrote
+        if span.line == 0 {
+            return;
+        }
+
+        let at = (span.line, span.column, kind);
+        match self.executed.get_mut(source) {
+            Some(nodes) => *nodes.entry(at).or_default() += 1,
+            None => {
+                let nodes = HashMap::from([(at, 1)]);
+                self.executed.insert(source.to_string(), nodes);
+            }
+        }
+    }
+
+    /// Every line with code on it, and how many times it ran (0 if it never did)
+    ///
+    /// A line runs as often as the node on it that ran the most, so an expression with several
+    /// nodes counts once but a loop body counts each time around.
+    pub fn lines(&self, source: &NamedSource, ast: &Ast) -> BTreeMap<usize, usize> {
+        let executable = ast
+            .nodes()
+            .map(|node| node.span().line)
+            .filter(|line| *line > 0)
+            .collect::<BTreeSet<_>>();
+
+        let mut lines = executable
+            .into_iter()
+            .map(|line| (line, 0))
+            .collect::<BTreeMap<_, _>>();
+        for (&(line, _, _), &count) in self.executed.get(&source.name).into_iter().flatten() {
+            let most = lines.entry(line).or_default();
+            *most = (*most).max(count);
+        }
+        lines
+    }
+
+    pub fn summary(&self, source: &NamedSource, ast: &Ast) -> Summary {
+        let lines = self.lines(source, ast);
+        Summary {
+            lines: lines.len(),
+            hit: lines.values().filter(|count| **count > 0).count(),
+        }
+    }
+
+    /// A summary line for each program and in total, then each program with counts by each line
+    ///
+    /// Lines with code that never ran are marked with #####, the same as gcov.
+    pub fn report(&self, programs: &[(NamedSource, Ast)]) -> String {
+        let mut output = String::new();
+        let mut total = Summary { lines: 0, hit: 0 };
+
+        for (source, ast) in programs {
+            let summary = self.summary(source, ast);
+            total.lines += summary.lines;
+            total.hit += summary.hit;
+            output.push_str(&summary_line(&source.name, summary));
+        }
+        if programs.len() > 1 {
+            output.push_str(&summary_line("total", total));
+        }
+
+        for (source, ast) in programs {
+            let lines = self.lines(source, ast);
+            output.push_str(&format!("\n{}:\n", source.name));
+            for (index, text) in source.bytes.lines().enumerate() {
+                let count = match lines.get(&(index + 1)) {
+                    Some(0) => "#####".to_string(),
+                    Some(count) => count.to_string(),
+                    None => "-".to_string(),
+                };
+                output.push_str(&format!("{count:>8} | {text}\n"));
+            }
+        }
+
+        output
+    }
+
+    /// The same in LCOV's tracefile format, for tools like genhtml
+    pub fn lcov(&self, programs: &[(NamedSource, Ast)]) -> String {
+        let mut output = String::from("TN:\n");
+
+        for (source, ast) in programs {
+            let lines = self.lines(source, ast);
+            output.push_str(&format!("SF:{}\n", source.name));
+            for (line, count) in &lines {
+                output.push_str(&format!("DA:{line},{count}\n"));
+            }
+            let summary = self.summary(source, ast);
+            output.push_str(&format!("LF:{}\nLH:{}\n", summary.lines, summary.hit));
+            output.push_str("end_of_record\n");
+        }
+
+        output
+    }
+}
+
+impl EvalHook for Coverage {
+    fn before_node(&mut self, node: &Node) -> Result<()> {
+        self.record(node.source, node.span(), node.kind());
+        Ok(())
+    }
+}
+
+fn summary_line(name: &str, summary: Summary) -> String {
+    format!(
+        "{name}: {}/{} lines ({:.1}%)\n",
+        summary.hit,
+        summary.lines,
+        summary.percent()
+    )
+}
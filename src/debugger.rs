@@ -0,0 +1,290 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::context::Context;
+use crate::environment::Environment;
+use crate::evaluator::{EvalOutcome, Evaluate};
+use crate::parser::{Ast, NodeId, Parser};
+use crate::tokenizer::Tokenizer;
+use crate::values::Value;
+
+const HELP: &str = "\
+step (s)          run to the next statement
+next (n)          run to the next statement, stepping over calls
+continue (c)      run to the next breakpoint
+break (b) LINE    stop before statements on LINE
+delete (d) LINE   remove the breakpoint on LINE
+breakpoints       list the breakpoints
+locals (l)        print the variables in each scope, innermost first
+print (p) EXPR    evaluate an expression where the program is stopped
+where (w)         print the calls in progress
+list              print the lines around where the program is stopped
+quit (q)          stop the program
+An empty line repeats the last step, next, or continue.";
+
+// How many lines on either side list shows
+const LIST_CONTEXT: usize = 3;
+
+/// Quitting from the debugger, which stops the program but isn't an error to report
+#[derive(Debug, Error)]
+#[error("Quit from the debugger")]
+pub struct Quit;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Step,
+    Next(usize), // Stop once calls are no deeper than this
+    Continue,
+}
+
+/// Stops the evaluator before statements and reads commands until one says to keep going
+///
+/// It stops before the first statement, then wherever stepping or a breakpoint says to. Set it
+/// as Context::debugger to use it; commands come from stdin and go to stdout unless with_io is
+/// used instead. Type help at the prompt for the commands.
+pub struct Debugger {
+    input: Box<dyn BufRead + Send>,
+    output: Box<dyn Write + Send>,
+    breakpoints: Vec<usize>,
+    mode: Mode,
+    last_command: String, // Repeated by an empty line
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::with_io(io::BufReader::new(io::stdin()), io::stdout())
+    }
+}
+
+impl fmt::Debug for Debugger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Debugger")
+            .field("breakpoints", &self.breakpoints)
+            .field("mode", &self.mode)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Let the debugger stop before this statement, if the context has one
+pub(crate) fn pause(
+    ast: &Ast,
+    id: NodeId,
+    env: &mut impl Environment<Value>,
+    ctx: &mut Context,
+) -> Result<()> {
+    // Take it out while it's stopped, so expressions it evaluates don't stop in it again
+    let Some(mut debugger) = ctx.debugger.take() else {
+        return Ok(());
+    };
+    let result = debugger.statement(ast, id, env, ctx);
+    ctx.debugger = Some(debugger);
+    result
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read commands from input and write everything to output instead of stdin and stdout
+    pub fn with_io(
+        input: impl BufRead + Send + 'static,
+        output: impl Write + Send + 'static,
+    ) -> Self {
+        Self {
+            input: Box::new(input),
+            output: Box::new(output),
+            breakpoints: vec![],
+            mode: Mode::Step,
+            last_command: String::new(),
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, line: usize) {
+        if !self.breakpoints.contains(&line) {
+            self.breakpoints.push(line);
+            self.breakpoints.sort();
+        }
+    }
+
+    /// Don't stop before the first statement, only once a breakpoint is reached
+    pub fn continue_to_breakpoint(&mut self) {
+        self.mode = Mode::Continue;
+    }
+
+    fn statement(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        env: &mut impl Environment<Value>,
+        ctx: &mut Context,
+    ) -> Result<()> {
+        let line = ast[id].span().line;
+        let depth = ctx.call_stack.len();
+        let stop = match self.mode {
+            Mode::Step => true,
+            Mode::Next(max) => depth <= max,
+            Mode::Continue => false,
+        };
+        if !stop && !self.breakpoints.contains(&line) {
+            return Ok(());
+        }
+
+        let name = ctx.source.as_ref().map_or("<unknown>", |s| s.name.as_str());
+        writeln!(self.output, "Stopped at {name}:{line}")?;
+        self.list(ctx, line, 0)?;
+
+        loop {
+            write!(self.output, "(debug) ")?;
+            self.output.flush()?;
+
+            let mut command = String::new();
+            if self.input.read_line(&mut command)? == 0 {
+                // Nothing left to read, so let the program finish
+                self.mode = Mode::Continue;
+                self.breakpoints.clear();
+                writeln!(self.output)?;
+                return Ok(());
+            }
+
+            let mut command = command.trim().to_string();
+            if command.is_empty() {
+                command = self.last_command.clone();
+            }
+            let (name, rest) = command
+                .split_once(' ')
+                .map_or((command.as_str(), ""), |(name, rest)| (name, rest.trim()));
+
+            match name {
+                "s" | "step" | "n" | "next" | "c" | "continue" => {
+                    self.mode = match name {
+                        "s" | "step" => Mode::Step,
+                        "n" | "next" => Mode::Next(depth),
+                        _ => Mode::Continue,
+                    };
+                    self.last_command = name.to_string();
+                    return Ok(());
+                }
+                "b" | "break" => match rest.parse::<usize>() {
+                    Ok(line) => {
+                        self.add_breakpoint(line);
+                        writeln!(self.output, "Breakpoint at line {line}")?;
+                    }
+                    Err(_) => writeln!(self.output, "break needs a line number")?,
+                },
+                "d" | "delete" => match rest.parse::<usize>() {
+                    Ok(line) if self.breakpoints.contains(&line) => {
+                        self.breakpoints.retain(|l| *l != line);
+                        writeln!(self.output, "Deleted the breakpoint at line {line}")?;
+                    }
+                    Ok(line) => writeln!(self.output, "No breakpoint at line {line}")?,
+                    Err(_) => writeln!(self.output, "delete needs a line number")?,
+                },
+                "breakpoints" if self.breakpoints.is_empty() => {
+                    writeln!(self.output, "No breakpoints")?
+                }
+                "breakpoints" => {
+                    for line in &self.breakpoints {
+                        writeln!(self.output, "line {line}")?;
+                    }
+                }
+                "l" | "locals" => self.locals(env)?,
+                "p" | "print" => self.print(rest, env, ctx)?,
+                "w" | "where" => self.where_(ctx, line)?,
+                "list" => self.list(ctx, line, LIST_CONTEXT)?,
+                "q" | "quit" => return Err(Quit.into()),
+                "h" | "help" => writeln!(self.output, "{HELP}")?,
+                "" => {}
+                _ => writeln!(self.output, "Unknown command {name}, try help")?,
+            }
+        }
+    }
+
+    // The source around a line, with an arrow at the line itself
+    fn list(&mut self, ctx: &Context, line: usize, context: usize) -> Result<()> {
+        let Some(source) = &ctx.source else {
+            return Ok(());
+        };
+
+        let first = line.saturating_sub(context).max(1);
+        for (index, text) in source.bytes.lines().enumerate() {
+            let number = index + 1;
+            if number >= first && number <= line + context {
+                let marker = if number == line { "->" } else { "  " };
+                writeln!(self.output, "{marker} {number:>4} | {text}")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn locals(&mut self, env: &impl Environment<Value>) -> Result<()> {
+        let scopes = env.scopes();
+        let count = scopes.len();
+        for (index, scope) in scopes.into_iter().enumerate() {
+            let title = if index + 1 == count {
+                "globals".to_string()
+            } else {
+                format!("scope {}", count - index)
+            };
+            writeln!(self.output, "{title}:")?;
+            for (name, value) in scope {
+                writeln!(self.output, "    {name} = {}", show(value))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn print(
+        &mut self,
+        expression: &str,
+        env: &mut impl Environment<Value>,
+        ctx: &mut Context,
+    ) -> Result<()> {
+        let mut parser = Parser::from(Tokenizer::new(expression));
+        let ast = match parser.parse_single_expression() {
+            Ok(_) if parser.tokenizer_had_errors() => {
+                for error in parser.tokenizer_iter_errors() {
+                    writeln!(self.output, "{error}")?;
+                }
+                return Ok(());
+            }
+            Ok(ast) => ast,
+            Err(error) => {
+                writeln!(self.output, "{error}")?;
+                return Ok(());
+            }
+        };
+        drop(parser);
+
+        match ast.evaluate(env, ctx) {
+            Ok(EvalOutcome::Value(value)) => writeln!(self.output, "{}", show(&value))?,
+            Ok(EvalOutcome::Unit) => {}
+            Err(error) => writeln!(self.output, "{error}")?,
+        }
+        Ok(())
+    }
+
+    // Calls in progress innermost first, then the statement stopped at
+    fn where_(&mut self, ctx: &Context, line: usize) -> Result<()> {
+        for frame in ctx.call_stack.iter().rev() {
+            writeln!(
+                self.output,
+                "in {}, called from line {}",
+                frame.callee, frame.span.line
+            )?;
+        }
+        writeln!(self.output, "at line {line}")?;
+        Ok(())
+    }
+}
+
+// Strings are quoted so they can be told apart from other values
+fn show(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("{s:?}"),
+        value => value.lox_display(),
+    }
+}
@@ -0,0 +1,659 @@
+use std::fmt::{self, Display};
+
+use crate::context::Frame;
+use crate::evaluator::{Limit, RuntimeError, Thrown};
+use crate::json::Json;
+use crate::lint::{Rule, Warning};
+use crate::named_source::NamedSource;
+use crate::parser::ParseError;
+use crate::resolver::ResolverError;
+use crate::span::{SourceCursor, Span};
+use crate::tokenizer::TokenizerError;
+
+/// An error that knows where in the source it happened, and maybe how to fix it
+pub trait Diagnostic {
+    fn span(&self) -> Option<Span>;
+
+    /// Which kind of error it is, stable across versions so it can be looked up with explain
+    ///
+    /// E00 is for names, E01 syntax, E02 reading source, E03 values, and E04 stopping the run.
+    fn code(&self) -> &'static str;
+
+    fn help(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+impl Diagnostic for TokenizerError {
+    fn span(&self) -> Option<Span> {
+        match self {
+            TokenizerError::UnexpectedCharacter(span, _)
+            | TokenizerError::UnterminatedString(span)
+            | TokenizerError::MalformedNumber(span, _)
+            | TokenizerError::ReadFailed(span, _) => Some(*span),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            TokenizerError::UnexpectedCharacter(_, _) => "E0201",
+            TokenizerError::UnterminatedString(_) => "E0202",
+            TokenizerError::MalformedNumber(_, _) => "E0203",
+            TokenizerError::ReadFailed(_, _) => "E0204",
+        }
+    }
+
+    fn help(&self) -> Option<&'static str> {
+        match self {
+            TokenizerError::UnterminatedString(_) => Some("strings end with a matching '\"'"),
+            _ => None,
+        }
+    }
+}
+
+impl Diagnostic for ParseError {
+    fn span(&self) -> Option<Span> {
+        match self {
+            ParseError::Expected(span, _, _)
+            | ParseError::ExpectedExpression(span, _)
+            | ParseError::InvalidAssignmentTarget(span)
+            | ParseError::ExpectedSemicolon(span)
+            | ParseError::UnexpectedEnd(span, _)
+            | ParseError::TooDeep(span) => Some(*span),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ParseError::Expected(_, _, _) => "E0101",
+            ParseError::ExpectedExpression(_, _) => "E0103",
+            ParseError::InvalidAssignmentTarget(_) => "E0104",
+            ParseError::ExpectedSemicolon(_) => "E0102",
+            ParseError::UnexpectedEnd(_, _) => "E0105",
+            ParseError::TooDeep(_) => "E0106",
+        }
+    }
+
+    fn help(&self) -> Option<&'static str> {
+        match self {
+            ParseError::InvalidAssignmentTarget(_) => Some("only variables can be assigned to"),
+            ParseError::ExpectedSemicolon(_) => Some("statements end with ';'"),
+            ParseError::TooDeep(_) => Some("raise the limit with --max-depth"),
+            _ => None,
+        }
+    }
+}
+
+impl Diagnostic for ResolverError {
+    fn span(&self) -> Option<Span> {
+        match self {
+            ResolverError::UndefinedVariable(span, _)
+            | ResolverError::OwnInitializer(span, _)
+            | ResolverError::AlreadyDeclared(span, _)
+            | ResolverError::DuplicateVariant(span, _) => Some(*span),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ResolverError::UndefinedVariable(_, _) => "E0001",
+            ResolverError::OwnInitializer(_, _) => "E0002",
+            ResolverError::AlreadyDeclared(_, _) => "E0003",
+            ResolverError::DuplicateVariant(_, _) => "E0006",
+        }
+    }
+
+    fn help(&self) -> Option<&'static str> {
+        match self {
+            ResolverError::UndefinedVariable(_, _) => Some("declare it with var before using it"),
+            ResolverError::OwnInitializer(_, _) => {
+                Some("use a different name to read the outer variable")
+            }
+            ResolverError::AlreadyDeclared(_, _) => Some("assign to it with = instead"),
+            ResolverError::DuplicateVariant(_, _) => Some("give each variant a different name"),
+        }
+    }
+}
+
+impl Diagnostic for RuntimeError {
+    fn span(&self) -> Option<Span> {
+        match self {
+            RuntimeError::UndefinedVariable(span, _)
+            | RuntimeError::Unavailable(span, _)
+            | RuntimeError::EnvDisabled(span)
+            | RuntimeError::UndefinedProperty(span, _)
+            | RuntimeError::NotAnInstance(span)
+            | RuntimeError::NotCallable(span)
+            | RuntimeError::IndexNotInteger(span)
+            | RuntimeError::NotIndexable(span)
+            | RuntimeError::IndexOutOfRange(span, _)
+            | RuntimeError::NotIterable(span)
+            | RuntimeError::OperandNotNumber(span)
+            | RuntimeError::OperandsNotNumbers(span)
+            | RuntimeError::OperandsNotNumbersOrStrings(span)
+            | RuntimeError::InvalidArguments(span, _, _)
+            | RuntimeError::WrongArity(span, _, _)
+            | RuntimeError::NotComparable(span, _, _)
+            | RuntimeError::InvalidComparison(span, _)
+            | RuntimeError::InvalidFormat(span, _)
+            | RuntimeError::DivisionByZero(span)
+            | RuntimeError::ModuleNotFound(span, _, _)
+            | RuntimeError::ImportFailed(span, _, _)
+            | RuntimeError::StackOverflow(span, _)
+            | RuntimeError::LimitExceeded(span, _)
+            | RuntimeError::AssertionFailed(span, _, _, _) => Some(*span),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            RuntimeError::UndefinedVariable(_, _) => "E0001",
+            RuntimeError::Unavailable(_, _) => "E0004",
+            RuntimeError::EnvDisabled(_) => "E0005",
+            RuntimeError::UndefinedProperty(_, _) => "E0301",
+            RuntimeError::NotAnInstance(_) => "E0302",
+            RuntimeError::NotCallable(_) => "E0303",
+            RuntimeError::IndexNotInteger(_) => "E0304",
+            RuntimeError::NotIndexable(_) => "E0305",
+            RuntimeError::IndexOutOfRange(_, _) => "E0306",
+            RuntimeError::NotIterable(_) => "E0307",
+            RuntimeError::OperandNotNumber(_) => "E0308",
+            RuntimeError::OperandsNotNumbers(_) => "E0309",
+            RuntimeError::OperandsNotNumbersOrStrings(_) => "E0310",
+            RuntimeError::InvalidArguments(_, _, _) => "E0311",
+            RuntimeError::DivisionByZero(_) => "E0312",
+            RuntimeError::ModuleNotFound(_, _, _) => "E0313",
+            RuntimeError::ImportFailed(_, _, _) => "E0314",
+            RuntimeError::WrongArity(_, _, _) => "E0315",
+            RuntimeError::NotComparable(_, _, _) => "E0316",
+            RuntimeError::InvalidComparison(_, _) => "E0317",
+            RuntimeError::InvalidFormat(_, _) => "E0318",
+            RuntimeError::StackOverflow(_, _) => "E0401",
+            RuntimeError::LimitExceeded(_, _) => "E0402",
+            RuntimeError::AssertionFailed(_, _, _, _) => "E0403",
+        }
+    }
+
+    fn help(&self) -> Option<&'static str> {
+        match self {
+            RuntimeError::UndefinedVariable(_, _) => Some("declare it with var before using it"),
+            RuntimeError::Unavailable(_, _) => {
+                Some("it uses something outside the interpreter, which isn't allowed in a sandbox")
+            }
+            RuntimeError::EnvDisabled(_) => Some("run with --allow-env to let scripts use it"),
+            RuntimeError::UndefinedProperty(_, _) => {
+                Some("enums have their variants as properties, variants have name and ordinal")
+            }
+            RuntimeError::IndexNotInteger(_) => Some("indexes are whole numbers starting at 0"),
+            RuntimeError::IndexOutOfRange(_, _) => Some("len() is the number of items"),
+            RuntimeError::DivisionByZero(_) => Some("run with --ieee-math to get inf or NaN instead"),
+            RuntimeError::NotComparable(_, _, _) => {
+                Some("only numbers and strings have a natural order, pass List.sort a comparison")
+            }
+            RuntimeError::InvalidComparison(_, _) => {
+                Some("negative if the first comes first, positive if it comes after, or zero")
+            }
+            RuntimeError::InvalidFormat(_, _) => {
+                Some("placeholders are {}, {0}, or {:.2}, and {{ or }} for literal braces")
+            }
+            RuntimeError::ModuleNotFound(_, _, _) => {
+                Some("add the directory it's in with --include or LOX_PATH")
+            }
+            RuntimeError::StackOverflow(_, _) => Some("raise the limit with --max-depth"),
+            RuntimeError::LimitExceeded(_, limit) => Some(match limit {
+                Limit::Steps(_) => "raise the limit with --max-steps",
+                Limit::Duration(_) => "raise the limit with --timeout",
+                Limit::CallDepth(_) => "raise the limit with --max-call-depth",
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Diagnostic for Thrown {
+    fn span(&self) -> Option<Span> {
+        Some(self.0)
+    }
+
+    fn code(&self) -> &'static str {
+        "E0404"
+    }
+
+    fn help(&self) -> Option<&'static str> {
+        Some("use try and catch to handle it")
+    }
+}
+
+/// Look through an error for one of the diagnostics above
+pub fn find(error: &anyhow::Error) -> Option<&dyn Diagnostic> {
+    if let Some(error) = error.downcast_ref::<TokenizerError>() {
+        Some(error)
+    } else if let Some(error) = error.downcast_ref::<ParseError>() {
+        Some(error)
+    } else if let Some(error) = error.downcast_ref::<ResolverError>() {
+        Some(error)
+    } else if let Some(error) = error.downcast_ref::<RuntimeError>() {
+        Some(error)
+    } else if let Some(error) = error.downcast_ref::<Thrown>() {
+        Some(error)
+    } else {
+        None
+    }
+}
+
+/// An error with the file name, line, and a caret under where it happened, eg:
+///
+/// ```text
+/// error[E0001]: Undefined variable 'x'
+///  --> test.lox:3:7
+///   |
+/// 3 | print x;
+///   |       ^
+///   = help: declare it with var before using it
+///   = note: jp-lox explain E0001 has more
+/// ```
+///
+/// Errors without a span are just the message.
+pub fn render(source: &NamedSource, error: &anyhow::Error) -> String {
+    let diagnostic = find(error);
+    let mut output = match diagnostic {
+        Some(diagnostic) => format!("error[{}]: {}\n", diagnostic.code(), message(error)),
+        None => format!("error: {}\n", message(error)),
+    };
+
+    let mut gutter = String::new();
+
+    if let Some(span) = diagnostic.and_then(|d| d.span()) {
+        let chars = source.bytes.chars().collect::<Vec<_>>();
+        let start = span.start.min(chars.len());
+        let end = span.end.clamp(start, chars.len());
+
+        // Spans are character offsets, find the whole line the span starts on
+        let line_start = chars[..start]
+            .iter()
+            .rposition(|c| *c == '\n')
+            .map_or(0, |i| i + 1);
+        let line_end = chars[start..]
+            .iter()
+            .position(|c| *c == '\n')
+            .map_or(chars.len(), |i| start + i);
+        let SourceCursor { line, column, .. } = SourceCursor::at(&source.bytes, start);
+
+        let text = chars[line_start..line_end].iter().collect::<String>();
+        let width = (end.min(line_end) - start).max(1);
+        gutter = " ".repeat(line.to_string().len());
+
+        output.push_str(&format!("{gutter}--> {}:{line}:{column}\n", source.name));
+        output.push_str(&format!("{gutter} |\n"));
+        output.push_str(&format!("{line} | {text}\n"));
+        output.push_str(&format!(
+            "{gutter} | {}{}\n",
+            " ".repeat(column - 1),
+            "^".repeat(width)
+        ));
+    }
+
+    if let Some(help) = diagnostic.and_then(|d| d.help()) {
+        output.push_str(&format!("{gutter} = help: {help}\n"));
+    }
+    if let Some(diagnostic) = diagnostic {
+        let code = diagnostic.code();
+        output.push_str(&format!("{gutter} = note: jp-lox explain {code} has more\n"));
+    }
+
+    output
+}
+
+/// The same as a JSON object, for editors and other tools, eg:
+///
+/// ```text
+/// {"severity":"error","code":"E0001","message":"Undefined variable 'x'",
+///  "file":"test.lox","line":3,"column":7,
+///  "span":{"start":20,"end":21,"byte_start":20,"byte_end":21}}
+/// ```
+///
+/// Anything that isn't known (like where an error without a span is) is null. Spans are character
+/// offsets, with byte offsets too for tools that slice the source as bytes.
+pub fn json(source: Option<&NamedSource>, error: &anyhow::Error) -> Json {
+    let diagnostic = find(error);
+    let span = diagnostic.and_then(|d| d.span());
+
+    // Multi-line strings are on the line they end, the same as render this is where they start
+    let (line, column) = match (source, span) {
+        (Some(source), Some(span)) => {
+            let cursor = SourceCursor::at(&source.bytes, span.start);
+            (Json::from(cursor.line), Json::from(cursor.column))
+        }
+        (None, Some(span)) => (span.line.into(), span.column.into()),
+        (_, None) => (Json::Null, Json::Null),
+    };
+
+    Json::object([
+        ("severity", "error".into()),
+        ("code", diagnostic.map(|d| d.code()).into()),
+        ("message", message(error).into()),
+        ("file", source.map(|source| source.name.as_str()).into()),
+        ("line", line),
+        ("column", column),
+        (
+            "span",
+            span.map_or(Json::Null, |span| span_json(&span)),
+        ),
+    ])
+}
+
+/// How seriously to take a lint rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+/// A lint warning at the level it was reported at, and the file it's in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reported {
+    pub file: String,
+    pub level: Level,
+    pub warning: Warning,
+}
+
+impl Reported {
+    /// The same fields as json for errors, with warning or error as the severity
+    pub fn json(&self) -> Json {
+        let span = self.warning.span;
+        Json::object([
+            ("severity", self.severity().to_lowercase().into()),
+            ("code", self.warning.rule.to_value().into()),
+            ("message", self.warning.message.as_str().into()),
+            ("file", self.file.as_str().into()),
+            ("line", span.line.into()),
+            ("column", span.column.into()),
+            ("span", span_json(&span)),
+        ])
+    }
+
+    fn severity(&self) -> &'static str {
+        match self.level {
+            Level::Deny => "Error",
+            _ => "Warning",
+        }
+    }
+}
+
+// The same as lint has always printed them, eg [line 3] Warning [shadowing]: ...
+impl Display for Reported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[line {}] {} [{}]: {}",
+            self.warning.span.line,
+            self.severity(),
+            self.warning.rule.to_value(),
+            self.warning.message
+        )
+    }
+}
+
+/// Where warnings go to be kept or dropped by their rule's level, separate from errors
+///
+/// Every rule starts at the same level (off when running, on for lint) unless it's set on its
+/// own. With deny_warnings, any warning that would be reported is an error instead.
+#[derive(Debug, Clone)]
+pub struct DiagnosticSink {
+    default: Level,
+    levels: Vec<(Rule, Level)>,
+    deny_warnings: bool,
+    reported: Vec<Reported>,
+}
+
+impl DiagnosticSink {
+    pub fn new(default: Level) -> Self {
+        Self {
+            default,
+            levels: vec![],
+            deny_warnings: false,
+            reported: vec![],
+        }
+    }
+
+    /// Set one rule's level, the last one set wins
+    pub fn set(&mut self, rule: Rule, level: Level) {
+        self.levels.retain(|(r, _)| *r != rule);
+        self.levels.push((rule, level));
+    }
+
+    pub fn deny_warnings(&mut self) {
+        self.deny_warnings = true;
+    }
+
+    pub fn level(&self, rule: Rule) -> Level {
+        let level = self
+            .levels
+            .iter()
+            .find(|(r, _)| *r == rule)
+            .map_or(self.default, |(_, level)| *level);
+
+        match level {
+            Level::Warn if self.deny_warnings => Level::Deny,
+            level => level,
+        }
+    }
+
+    /// If any rule would be reported, so there's no point linting otherwise
+    pub fn enabled(&self) -> bool {
+        Rule::values()
+            .into_iter()
+            .any(|rule| self.level(rule) != Level::Allow)
+    }
+
+    /// Keep each warning that isn't allowed, in order
+    pub fn report(&mut self, file: &str, warnings: impl IntoIterator<Item = Warning>) {
+        for warning in warnings {
+            let level = self.level(warning.rule);
+            if level != Level::Allow {
+                self.reported.push(Reported {
+                    file: file.to_string(),
+                    level,
+                    warning,
+                });
+            }
+        }
+    }
+
+    pub fn reported(&self) -> &[Reported] {
+        &self.reported
+    }
+
+    /// If anything reported was denied, which fails like an error would
+    pub fn denied(&self) -> bool {
+        self.reported
+            .iter()
+            .any(|reported| reported.level == Level::Deny)
+    }
+}
+
+// Both kinds of offsets, editors count some positions in one and some in the other
+fn span_json(span: &Span) -> Json {
+    Json::object([
+        ("start", span.start.into()),
+        ("end", span.end.into()),
+        ("byte_start", span.byte_start.into()),
+        ("byte_end", span.byte_end.into()),
+    ])
+}
+
+// Where the error is shows the line, so "[line 3] Error at 'x': ..." is just the part after
+fn message(error: &anyhow::Error) -> String {
+    let message = error.to_string();
+    let message = match message.strip_prefix("[line ") {
+        Some(rest) => rest.split_once("] ").map_or(rest, |(_, rest)| rest),
+        None => &message,
+    };
+
+    let message = match message.strip_prefix("Error") {
+        Some(rest) => rest.split_once(": ").map_or(message, |(_, rest)| rest),
+        None => message,
+    };
+    message.to_string()
+}
+
+/// One frame of a call stack (outermost first) and where it was called from, eg
+/// `<builtin map>, called from line 3`
+pub fn frame_text(stack: &[Frame], i: usize) -> String {
+    let frame = &stack[i];
+    let caller = match (frame.span.line, i.checked_sub(1)) {
+        (0, Some(outer)) => stack[outer].callee.to_string(),
+        (0, None) => "the host".to_string(),
+        (line, _) => format!("line {line}"),
+    };
+    format!("{}, called from {caller}", frame.callee)
+}
+
+// Deep recursion would print thousands of frames, keep the innermost
+const MAX_TRACE_FRAMES: usize = 10;
+
+/// Where an error happened inside nested calls, innermost first, eg:
+///
+/// ```text
+///   in <builtin +>, called from <builtin map>
+///   in <builtin map>, called from line 3
+///   at top level
+/// ```
+///
+/// An error in a single call already says which line it's on, so that's empty.
+pub fn backtrace(trace: &[Frame]) -> String {
+    if trace.len() < 2 {
+        return String::new();
+    }
+
+    let mut output = String::new();
+    for i in (0..trace.len()).rev().take(MAX_TRACE_FRAMES) {
+        output.push_str(&format!("  in {}\n", frame_text(trace, i)));
+    }
+
+    if trace.len() > MAX_TRACE_FRAMES {
+        let more = trace.len() - MAX_TRACE_FRAMES;
+        output.push_str(&format!("  ... {more} more\n"));
+    }
+    output.push_str("  at top level\n");
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::BuiltIn;
+    use crate::interpreter::Interpreter;
+    use crate::parser::Parser;
+    use crate::tokenizer::Tokenizer;
+    use crate::values::Value;
+
+    fn frame(name: &str, line: usize) -> Frame {
+        let builtin = BuiltIn::global(name, true).expect("a builtin");
+        Frame {
+            callee: Value::Builtin(builtin),
+            span: Span { line, ..Span::ZERO },
+        }
+    }
+
+    #[test]
+    fn single_calls_have_no_backtrace() {
+        assert_eq!(backtrace(&[]), "");
+        assert_eq!(backtrace(&[frame("map", 3)]), "");
+    }
+
+    #[test]
+    fn backtraces_are_innermost_first() {
+        let trace = [frame("map", 3), frame("+", 0)];
+        assert_eq!(
+            backtrace(&trace),
+            "  in <builtin +>, called from <builtin map>\n  \
+             in <builtin map>, called from line 3\n  \
+             at top level\n"
+        );
+
+        // Called from Rust, not from a line of Lox
+        assert_eq!(
+            frame_text(&[frame("map", 0)], 0),
+            "<builtin map>, called from the host"
+        );
+    }
+
+    #[test]
+    fn long_backtraces_keep_the_innermost_frames() {
+        let trace = (1..=MAX_TRACE_FRAMES + 5)
+            .map(|line| frame("map", line))
+            .collect::<Vec<_>>();
+        let backtrace = backtrace(&trace);
+        let lines = backtrace.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), MAX_TRACE_FRAMES + 2);
+        assert_eq!(lines[0], "  in <builtin map>, called from line 15");
+        assert_eq!(lines[MAX_TRACE_FRAMES], "  ... 5 more");
+        assert_eq!(lines[MAX_TRACE_FRAMES + 1], "  at top level");
+    }
+
+    #[test]
+    fn runtime_errors_leave_the_stack_they_happened_in() {
+        let mut interpreter = Interpreter::new();
+        let source = "print List.map(list(1, \"a\"), partial(Math.max, 1));";
+        assert!(interpreter.run(source).is_err());
+
+        assert_eq!(
+            backtrace(&interpreter.context().trace),
+            "  in <partial <builtin max>>, called from line 1\n  \
+             in <builtin map>, called from line 1\n  \
+             at top level\n"
+        );
+    }
+
+    #[test]
+    fn renders_the_line_with_a_caret_under_the_span() {
+        let source = NamedSource::new("test.lox".into(), "var a = 1;\nprint a + xyz;".into());
+        let span = Span {
+            line: 2,
+            column: 11,
+            start: 21,
+            end: 24,
+            byte_start: 21,
+            byte_end: 24,
+        };
+        let error = RuntimeError::UndefinedVariable(span, "xyz".into()).into();
+
+        assert_eq!(
+            render(&source, &error),
+            "error[E0001]: Undefined variable 'xyz'\n \
+             --> test.lox:2:11\n  \
+             |\n\
+             2 | print a + xyz;\n  \
+             |           ^^^\n  \
+             = help: declare it with var before using it\n  \
+             = note: jp-lox explain E0001 has more\n"
+        );
+    }
+
+    #[test]
+    fn renders_parse_errors_without_the_line_prefix() {
+        let source = NamedSource::new("test.lox".into(), "print ;".into());
+        let error = Parser::from(Tokenizer::new(&source.bytes))
+            .parse()
+            .unwrap_err();
+
+        // Plain output is the Display, rendered output moves the line into the location
+        assert!(error.to_string().starts_with("[line 1] Error at ';': "));
+        let rendered = render(&source, &error);
+        assert!(rendered.starts_with("error[E0103]: Expect expression\n --> test.lox:1:7\n"));
+        assert!(rendered.contains("1 | print ;\n  |       ^\n"));
+    }
+
+    #[test]
+    fn errors_without_a_span_are_just_the_message() {
+        let source = NamedSource::new("test.lox".into(), "".into());
+        let error = anyhow::anyhow!("something else went wrong");
+        assert_eq!(
+            render(&source, &error),
+            "error: something else went wrong\n"
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use crate::formatter;
+use crate::highlight;
+use crate::named_source::NamedSource;
+use crate::parser::{Ast, AstNode};
+use crate::trivia::Trivia;
+
+/// A declaration at the top level of a program, with the /// comments before it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    pub kind: &'static str, // var or enum
+    pub name: String,
+    pub line: usize,
+    pub signature: String,    // The declaration, formatted
+    pub arity: Option<usize>, // How many variants an enum has
+    pub doc: Option<String>,
+}
+
+/// Every top level declaration in order, documented or not
+///
+/// Declarations in blocks are local, so they aren't included.
+pub fn items(ast: &Ast, trivia: &Trivia) -> Vec<Item> {
+    let AstNode::Program(_, statements) = &ast[ast.root()] else {
+        return vec![];
+    };
+
+    statements
+        .iter()
+        .filter_map(|id| {
+            let (kind, name, arity) = match &ast[*id] {
+                AstNode::Declaration(_, name, _) => ("var", name.to_string(), None),
+                AstNode::Enum(_, name, variants) => ("enum", name.clone(), Some(variants.len())),
+                _ => return None,
+            };
+
+            Some(Item {
+                kind,
+                name,
+                line: ast[*id].span().line,
+                signature: formatter::format_statement(ast, *id),
+                arity,
+                doc: trivia.doc(*id),
+            })
+        })
+        .collect()
+}
+
+/// Documentation for one source as Markdown, a section per declaration
+pub fn markdown(source: &NamedSource, items: &[Item]) -> String {
+    let mut output = format!("# {}\n", source.name);
+    if items.is_empty() {
+        output.push_str("\nNo declarations.\n");
+    }
+
+    for item in items {
+        output.push_str(&format!("\n## {} {}\n\n", item.kind, item.name));
+        output.push_str(&format!("```lox\n{}\n```\n\n", item.signature));
+        output.push_str(&format!("{}\n", summary(item)));
+        if let Some(doc) = &item.doc {
+            output.push_str(&format!("\n{doc}\n"));
+        }
+    }
+
+    output
+}
+
+/// The same as an HTML fragment, with signatures highlighted the same as highlight --html
+pub fn html(source: &NamedSource, items: &[Item]) -> String {
+    let escape = highlight::escape_html;
+    let mut output = format!("<h1>{}</h1>\n", escape(&source.name));
+    if items.is_empty() {
+        output.push_str("<p>No declarations.</p>\n");
+    }
+
+    for item in items {
+        output.push_str(&format!(
+            "<h2 id=\"{}\">{} {}</h2>\n",
+            escape(&item.name),
+            item.kind,
+            escape(&item.name)
+        ));
+        output.push_str(&highlight::html(&item.signature));
+        output.push_str(&format!("<p>{}</p>\n", escape(&summary(item))));
+        if let Some(doc) = &item.doc {
+            // A blank /// line separates paragraphs, like Markdown
+            for paragraph in doc.split("\n\n") {
+                output.push_str(&format!("<p>{}</p>\n", escape(paragraph)));
+            }
+        }
+    }
+
+    output
+}
+
+fn summary(item: &Item) -> String {
+    match item.arity {
+        Some(1) => format!("Line {}, 1 variant.", item.line),
+        Some(arity) => format!("Line {}, {arity} variants.", item.line),
+        None => format!("Line {}.", item.line),
+    }
+}
@@ -1,47 +1,270 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
 pub trait Environment<T> {
-    fn get(&self, key: &str) -> Option<T>;
-    fn set(&mut self, key: &str, value: T);
+    fn get(&self, key: &str) -> Option<&T>;
+
+    /// Create a new variable in the innermost scope, shadowing any outer one
+    fn define(&mut self, key: impl Into<Arc<str>>, value: T);
+
+    /// Change the closest existing variable, false if there isn't one
+    fn assign(&mut self, key: &str, value: T) -> bool;
+
+    /// The variable depth scopes out from the innermost one, defined slot-th in that scope
+    fn get_at(&self, depth: usize, slot: usize) -> Option<&T>;
+
+    /// Change the variable at depth and slot, false if there isn't one
+    fn assign_at(&mut self, depth: usize, slot: usize, value: T) -> bool;
+
     fn enter(&mut self);
     fn exit(&mut self);
+
+    /// How many scopes deep the innermost one is, 1 for just the globals
+    fn depth(&self) -> usize;
+
+    /// Every variable in each scope, innermost scope first and names sorted within each
+    fn scopes(&self) -> Vec<Vec<(&str, &T)>>;
+}
+
+// Values are kept in the order they were defined, so the resolver's slots can index them
+#[derive(Debug)]
+struct Frame<T> {
+    slots: HashMap<Arc<str>, usize>,
+    values: Vec<T>,
 }
 
+impl<T> Frame<T> {
+    fn new() -> Self {
+        Self {
+            slots: HashMap::new(),
+            values: vec![],
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&T> {
+        self.slots.get(key).map(|&slot| &self.values[slot])
+    }
+
+    fn get_mut(&mut self, key: &str) -> Option<&mut T> {
+        self.slots.get(key).map(|&slot| &mut self.values[slot])
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&Arc<str>, &T)> {
+        self.slots
+            .iter()
+            .map(|(name, &slot)| (name, &self.values[slot]))
+    }
+}
+
+#[derive(Debug)]
 pub struct EnvironmentStack<T> {
-    stack: Vec<Vec<(String, T)>>,
+    stack: Vec<Frame<T>>,
 }
 
 impl<T> EnvironmentStack<T> {
     pub fn new() -> Self {
         Self {
-            stack: vec![vec![]],
+            stack: vec![Frame::new()],
         }
     }
-}
 
-impl<T: Clone> Environment<T> for EnvironmentStack<T> {
-    fn get(&self, key: &str) -> Option<T> {
+    /// Every name visible from the current scope, innermost first and without duplicates
+    ///
+    /// Names in the same scope are sorted rather than in the order they were defined.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = vec![];
         for frame in self.stack.iter().rev() {
-            for (k, v) in frame.iter().rev() {
-                if k == key {
-                    return Some(v.clone());
-                }
+            let mut keys = frame
+                .slots
+                .keys()
+                .map(|k| k.to_string())
+                .filter(|k| !names.contains(k))
+                .collect::<Vec<_>>();
+            keys.sort();
+            names.extend(keys);
+        }
+
+        names
+    }
+}
+
+impl<T> Default for EnvironmentStack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Environment<T> for EnvironmentStack<T> {
+    fn get(&self, key: &str) -> Option<&T> {
+        self.stack.iter().rev().find_map(|frame| frame.get(key))
+    }
+
+    // Redefining a name in the same scope replaces it, keeping its slot
+    fn define(&mut self, key: impl Into<Arc<str>>, value: T) {
+        let frame = self.stack.last_mut().unwrap();
+        let key = key.into();
+        match frame.slots.get(&key) {
+            Some(&slot) => frame.values[slot] = value,
+            None => {
+                frame.slots.insert(key, frame.values.len());
+                frame.values.push(value);
             }
         }
+    }
 
-        None
+    fn assign(&mut self, key: &str, value: T) -> bool {
+        match self
+            .stack
+            .iter_mut()
+            .rev()
+            .find_map(|frame| frame.get_mut(key))
+        {
+            Some(slot) => {
+                *slot = value;
+                true
+            }
+            None => false,
+        }
     }
 
-    fn set(&mut self, key: &str, value: T) {
-        self.stack
-            .last_mut()
-            .unwrap()
-            .push((key.to_string(), value));
+    fn get_at(&self, depth: usize, slot: usize) -> Option<&T> {
+        let frame = self.stack.len().checked_sub(depth + 1)?;
+        self.stack[frame].values.get(slot)
+    }
+
+    fn assign_at(&mut self, depth: usize, slot: usize, value: T) -> bool {
+        let Some(frame) = self.stack.len().checked_sub(depth + 1) else {
+            return false;
+        };
+        match self.stack[frame].values.get_mut(slot) {
+            Some(variable) => {
+                *variable = value;
+                true
+            }
+            None => false,
+        }
     }
 
     fn enter(&mut self) {
-        self.stack.push(vec![]);
+        self.stack.push(Frame::new());
     }
 
     fn exit(&mut self) {
         self.stack.pop();
     }
+
+    fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    fn scopes(&self) -> Vec<Vec<(&str, &T)>> {
+        self.stack
+            .iter()
+            .rev()
+            .map(|frame| {
+                let mut variables = frame
+                    .iter()
+                    .map(|(name, value)| (name.as_ref(), value))
+                    .collect::<Vec<_>>();
+                variables.sort_by_key(|(name, _)| *name);
+                variables
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Not Clone, so get has to hand back a reference
+    #[derive(Debug, PartialEq)]
+    struct Unclonable(i32);
+
+    #[test]
+    fn gets_the_innermost_variable_by_reference() {
+        let mut env = EnvironmentStack::new();
+        env.define("a", Unclonable(1));
+        env.define("b", Unclonable(2));
+
+        env.enter();
+        env.define("a", Unclonable(3));
+        assert_eq!(env.get("a"), Some(&Unclonable(3)));
+        assert_eq!(env.get("b"), Some(&Unclonable(2)));
+        assert_eq!(env.get("c"), None);
+
+        env.exit();
+        assert_eq!(env.get("a"), Some(&Unclonable(1)));
+    }
+
+    #[test]
+    fn assigns_the_closest_variable() {
+        let mut env = EnvironmentStack::new();
+        env.define("a", 1);
+        env.enter();
+
+        assert!(env.assign("a", 2));
+        assert!(!env.assign("missing", 3));
+        assert_eq!(env.get("missing"), None);
+
+        env.exit();
+        assert_eq!(env.get("a"), Some(&2));
+    }
+
+    #[test]
+    fn redefining_in_the_same_scope_replaces() {
+        let mut env = EnvironmentStack::new();
+        env.define("a", 1);
+        env.define("a", 2);
+
+        assert_eq!(env.get("a"), Some(&2));
+        assert_eq!(env.scopes(), vec![vec![("a", &2)]]);
+    }
+
+    #[test]
+    fn names_are_innermost_first_and_sorted_within_a_scope() {
+        let mut env = EnvironmentStack::new();
+        env.define("z", 1);
+        env.define("b", 2);
+        env.enter();
+        env.define("y", 3);
+        env.define("b", 4);
+
+        assert_eq!(env.names(), vec!["b", "y", "z"]);
+        assert_eq!(env.depth(), 2);
+    }
+
+    #[test]
+    fn slots_are_in_definition_order_and_kept_when_redefined() {
+        let mut env = EnvironmentStack::new();
+        env.define("z", 1);
+        env.define("a", 2);
+        env.enter();
+        env.define("b", 3);
+        env.define("z", 4);
+        env.define("b", 5);
+
+        assert_eq!(env.get_at(0, 0), Some(&5));
+        assert_eq!(env.get_at(0, 1), Some(&4));
+        assert_eq!(env.get_at(1, 0), Some(&1));
+        assert_eq!(env.get_at(1, 1), Some(&2));
+        assert_eq!(env.get_at(0, 2), None);
+        assert_eq!(env.get_at(2, 0), None);
+    }
+
+    #[test]
+    fn assigns_by_depth_and_slot() {
+        let mut env = EnvironmentStack::new();
+        env.define("a", 1);
+        env.enter();
+        env.define("a", 2);
+
+        assert!(env.assign_at(1, 0, 3));
+        assert!(!env.assign_at(0, 1, 4));
+        assert!(!env.assign_at(2, 0, 5));
+        assert_eq!(env.get("a"), Some(&2));
+
+        env.exit();
+        assert_eq!(env.get("a"), Some(&3));
+    }
 }
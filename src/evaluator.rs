@@ -1,86 +1,723 @@
+use crate::builtins::{Arity, BuiltIn, Namespace};
+use crate::context::{Context, Frame};
+use crate::debugger;
 use crate::environment::Environment;
-use crate::values::Value;
-use crate::{parser::AstNode, tokenizer::Keyword};
-use crate::builtins::BuiltIn;
+use crate::hook::Node;
+use crate::interpreter::parse_source_with_max_depth;
+use crate::named_source::NamedSource;
+use crate::phase::{self, Timed};
+use crate::resolver::Resolution;
+use crate::span::Span;
+use crate::values::{EnumType, Value};
+use crate::{
+    parser::{Ast, AstNode, BinaryOp, LogicalOp, NodeId, UnaryOp},
+    tokenizer::Keyword,
+};
 
-use anyhow::{anyhow, Result};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
-pub trait Evaluate {
-    fn evaluate(&self, env: &mut impl Environment<Value>) -> Result<Value>;
+use anyhow::Result;
+use derive_more::Display;
+use thiserror::Error;
+
+/// A value thrown by user code
+///
+/// This is kept distinct from runtime errors so that `catch` only ever sees user exceptions
+#[derive(Debug, Error)]
+#[error("[line {}] Uncaught exception: {}", .0.line, .1.lox_display())]
+pub struct Thrown(pub Span, pub Value);
+
+/// Everything that can go wrong evaluating, other than values thrown by user code
+///
+/// Each displays with the line it happened on, the same as the official Lox implementation.
+#[derive(Debug, Error)]
+pub enum RuntimeError {
+    #[error("[line {}] Undefined variable '{}'", .0.line, .1)]
+    UndefinedVariable(Span, String),
+
+    #[error("[line {}] Builtin '{}' is not available here.", .0.line, .1)]
+    Unavailable(Span, String),
+
+    #[error("[line {}] Environment access is disabled (use --allow-env).", .0.line)]
+    EnvDisabled(Span),
+
+    #[error("[line {}] Undefined property '{}'.", .0.line, .1)]
+    UndefinedProperty(Span, String),
+
+    #[error("[line {}] Only instances have properties.", .0.line)]
+    NotAnInstance(Span),
+
+    #[error("[line {}] Can only call functions and classes.", .0.line)]
+    NotCallable(Span),
+
+    #[error("[line {}] Index must be an integer.", .0.line)]
+    IndexNotInteger(Span),
+
+    #[error("[line {}] Only strings and lists can be indexed.", .0.line)]
+    NotIndexable(Span),
+
+    #[error("[line {}] Index {} out of range.", .0.line, .1)]
+    IndexOutOfRange(Span, i64),
+
+    #[error("[line {}] Can only iterate over strings and lists.", .0.line)]
+    NotIterable(Span),
+
+    #[error("[line {}] Operand must be a number.", .0.line)]
+    OperandNotNumber(Span),
+
+    #[error("[line {}] Operands must be numbers.", .0.line)]
+    OperandsNotNumbers(Span),
+
+    #[error("[line {}] Operands must be two numbers or two strings.", .0.line)]
+    OperandsNotNumbersOrStrings(Span),
+
+    #[error("[line {}] Division by zero.", .0.line)]
+    DivisionByZero(Span),
+
+    // Module, and every file that was tried for it
+    #[error("[line {}] Module '{}' not found, searched: {}", .0.line, .1, searched_text(.2))]
+    ModuleNotFound(Span, String, Vec<PathBuf>),
+
+    // Module, and why it couldn't be read or parsed
+    #[error("[line {}] Couldn't import '{}': {}", .0.line, .1, .2)]
+    ImportFailed(Span, String, String),
+
+    #[error("[line {}] Stack overflow, nested deeper than {}.", .0.line, .1)]
+    StackOverflow(Span, usize),
+
+    #[error("[line {}] Execution limit exceeded: {}.", .0.line, .1)]
+    LimitExceeded(Span, Limit),
+
+    // Source file, source text of the condition, and the message if there was one
+    #[error("[line {}] Assertion failed in {}: {}", .0.line, .1, assertion_text(.2, .3))]
+    AssertionFailed(Span, String, String, Option<String>),
+
+    // Builtin as Lox code calls it, eg Math.sqrt, and what's wrong with its arguments
+    #[error("[line {}] {}: {}", .0.line, .1, .2)]
+    InvalidArguments(Span, String, String),
+
+    #[error("[line {}] Expected {} but got {}.", .0.line, .1, .2)]
+    WrongArity(Span, Arity, usize),
+
+    // Both values, as print would show them
+    #[error("[line {}] Cannot compare {} and {} without a comparison function.", .0.line, .1, .2)]
+    NotComparable(Span, String, String),
+
+    // What's wrong with it, eg Missing argument 2
+    #[error("[line {}] {} in format string.", .0.line, .1)]
+    InvalidFormat(Span, String),
+
+    // What the comparison returned, as print would show it
+    #[error("[line {}] Sort comparison must return a number, got {}.", .0.line, .1)]
+    InvalidComparison(Span, String),
+}
+
+/// Which of the context's limits was exceeded, with what it was set to
+#[derive(Debug, Display, Clone, Copy, PartialEq)]
+pub enum Limit {
+    #[display("more than {} steps", _0)]
+    Steps(usize),
+
+    #[display("ran longer than {:?}", _0)]
+    Duration(Duration),
+
+    #[display("calls nested deeper than {}", _0)]
+    CallDepth(usize),
+}
+
+fn assertion_text(text: &str, message: &Option<String>) -> String {
+    match message {
+        Some(message) => format!("{message} ({text})"),
+        None => text.to_string(),
+    }
+}
+
+fn searched_text(searched: &[PathBuf]) -> String {
+    let searched = searched.iter().map(|path| path.display().to_string());
+    searched.collect::<Vec<_>>().join(", ")
+}
+
+// Raised by ?. on a nil receiver and caught by the enclosing OptionalChain
+#[derive(Debug, Error)]
+#[error("optional chain short circuited")]
+struct ShortCircuit;
+
+fn get_property(ctx: &Context, span: Span, object: Value, name: &str) -> Result<Value> {
+    let undefined = || RuntimeError::UndefinedProperty(span, name.to_string()).into();
+    match object {
+        Value::Namespace(namespace) => match namespace.member(name) {
+            Some(Value::Builtin(builtin)) if !ctx.builtins.allows(builtin) => {
+                Err(RuntimeError::Unavailable(span, format!("{namespace}.{name}")).into())
+            }
+            Some(member) => Ok(member),
+            None => Err(undefined()),
+        },
+        Value::Enum(enum_type) => match enum_type.variants.iter().position(|v| v == name) {
+            Some(ordinal) => Ok(Value::EnumVariant(enum_type, ordinal)),
+            None => Err(undefined()),
+        },
+        Value::EnumVariant(enum_type, ordinal) => match name {
+            "name" => Ok(enum_type.variants[ordinal].as_str().into()),
+            "ordinal" => Ok(Value::Integer(ordinal as i64)),
+            _ => Err(undefined()),
+        },
+        // Methods are the builtins with the builder already bound, the same as partial would
+        Value::StringBuilder(_) => {
+            let method = match name {
+                "append" => BuiltIn::Append,
+                "toString" => BuiltIn::ToString,
+                _ => return Err(undefined()),
+            };
+            Ok(Value::Partial(
+                Arc::new(Value::Builtin(method)),
+                Arc::new(vec![object]),
+            ))
+        }
+        _ => Err(RuntimeError::NotAnInstance(span).into()),
+    }
+}
+
+/// Call any callable value with already evaluated arguments
+pub fn call_value(ctx: &mut Context, span: Span, callee: Value, args: Vec<Value>) -> Result<Value> {
+    if let Some(max) = ctx.limits.max_call_depth {
+        if ctx.call_stack.len() >= max {
+            return Err(RuntimeError::LimitExceeded(span, Limit::CallDepth(max)).into());
+        }
+    }
+
+    ctx.call_stack.push(Frame {
+        callee: callee.clone(),
+        span,
+    });
+    // Naming the callee for the log isn't free, so calls are only timed if something's watching
+    let timed = (ctx.is_watched() || log::log_enabled!(target: phase::CALL, log::Level::Debug))
+        .then(|| Timed::start(phase::CALL, format!("Calling {callee}")));
+    let result = call(ctx, span, callee, args);
+
+    if let Some(timed) = timed {
+        let elapsed = timed.elapsed();
+        let callee = ctx.call_stack.last().expect("pushed above").callee.clone();
+        for hook in ctx.all_hooks().1 {
+            hook.on_call(&callee, span, elapsed);
+        }
+    }
+
+    // The innermost call to fail has the whole stack, the ones it returns through don't
+    if result.is_err() && ctx.trace.is_empty() {
+        ctx.trace = ctx.call_stack.clone();
+    }
+    ctx.call_stack.pop();
+    result
+}
+
+fn call(ctx: &mut Context, span: Span, callee: Value, args: Vec<Value>) -> Result<Value> {
+    match callee {
+        Value::Builtin(builtin) => builtin.call(ctx, span, args),
+        Value::Native(native) => native.call(&args),
+        Value::Partial(callable, bound) => {
+            let mut all_args = bound.to_vec();
+            all_args.extend(args);
+            call(ctx, span, callable.as_ref().clone(), all_args)
+        }
+        _ => Err(RuntimeError::NotCallable(span).into()),
+    }
 }
 
-impl Evaluate for AstNode {
-    fn evaluate(&self, env: &mut impl Environment<Value>) -> Result<Value> {
+fn binary_builtin(op: BinaryOp) -> BuiltIn {
+    match op {
+        BinaryOp::Plus => BuiltIn::Plus,
+        BinaryOp::Minus => BuiltIn::Minus,
+        BinaryOp::Times => BuiltIn::Times,
+        BinaryOp::Divide => BuiltIn::Divide,
+        BinaryOp::Equal => BuiltIn::Equal,
+        BinaryOp::NotEqual => BuiltIn::NotEqual,
+        BinaryOp::Is => BuiltIn::Is,
+        BinaryOp::Less => BuiltIn::LessThan,
+        BinaryOp::LessEqual => BuiltIn::LessThanOrEqual,
+        BinaryOp::Greater => BuiltIn::GreaterThan,
+        BinaryOp::GreaterEqual => BuiltIn::GreaterThanOrEqual,
+    }
+}
+
+// Negating is Minus with one argument
+fn unary_builtin(op: UnaryOp) -> BuiltIn {
+    match op {
+        UnaryOp::Negate => BuiltIn::Minus,
+        UnaryOp::Not => BuiltIn::Not,
+    }
+}
+
+/// What evaluating an Ast gave: the value of an expression, or Unit for a statement, which is run
+/// for what it does and has none
+///
+/// A program is the same as its last statement, so `1 + 2;` is 3 but `var x = 3;` is Unit.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalOutcome {
+    Value(Value),
+    Unit,
+}
+
+impl EvalOutcome {
+    /// The value, nil for statements
+    pub fn into_value(self) -> Value {
         match self {
-            AstNode::Literal(_, value) => Ok(value.clone()),
-            AstNode::Symbol(span, name) => {
-                // Keywords become builtins; fall back to env; then error
-                if Keyword::try_from(name.as_str()).is_ok() {
-                    return Ok(Value::Builtin(name.clone()));
-                }
+            EvalOutcome::Value(value) => value,
+            EvalOutcome::Unit => Value::Nil,
+        }
+    }
+}
+
+pub trait Evaluate {
+    fn evaluate(&self, env: &mut impl Environment<Value>, ctx: &mut Context)
+        -> Result<EvalOutcome>;
+}
+
+impl Evaluate for Ast {
+    fn evaluate(
+        &self,
+        env: &mut impl Environment<Value>,
+        ctx: &mut Context,
+    ) -> Result<EvalOutcome> {
+        let _timed = Timed::start(phase::EVALUATOR, "Evaluating");
+        let value = evaluate(self, self.root(), env, ctx)?;
+
+        let last = match &self[self.root()] {
+            AstNode::Program(_, nodes) => nodes.last().copied(),
+            _ => Some(self.root()),
+        };
+        match last {
+            Some(id) if !self[id].is_statement() => Ok(EvalOutcome::Value(value)),
+            _ => Ok(EvalOutcome::Unit),
+        }
+    }
+}
+
+// Run a module where the import is, the first time it's imported
+//
+// Errors inside it are reported with its source, so they quote the module's lines.
+fn import(
+    span: Span,
+    module: &str,
+    env: &mut impl Environment<Value>,
+    ctx: &mut Context,
+) -> Result<Value> {
+    let importer = ctx.source.as_ref().map(|source| source.name.as_str());
+    let path = ctx
+        .module_path
+        .find(importer, module)
+        .map_err(|searched| RuntimeError::ModuleNotFound(span, module.to_string(), searched))?;
+
+    let id = path.canonicalize().unwrap_or_else(|_| path.clone());
+    if ctx.imported.contains(&id) {
+        return Ok(Value::Nil);
+    }
+
+    let failed = |reason: String| RuntimeError::ImportFailed(span, module.to_string(), reason);
+    let text = fs::read_to_string(&path).map_err(|e| failed(format!("{}: {e}", path.display())))?;
+    let source = NamedSource::new(path.display().to_string(), text);
+    let ast = parse_source_with_max_depth(&source, ctx.max_depth).map_err(|errors| {
+        let errors = errors.iter().map(|e| format!("{}: {e}", path.display()));
+        failed(errors.collect::<Vec<_>>().join("\n"))
+    })?;
+
+    // Only once it's there and parses, so importing it again after fixing it tries again. It
+    // counts before running though, so modules importing each other only run once.
+    ctx.imported.push(id);
+
+    // Back to the importer's source whether or not the module ran, it can catch what it throws
+    let importer = ctx.source.replace(source);
+    let result = evaluate(&ast, ast.root(), env, ctx);
+    ctx.source = importer;
+    result?;
+    Ok(Value::Nil)
+}
+
+/// Run a program's top level statements in order, calling ran with each one once it has
+///
+/// For the REPL, which keeps what each statement declared even when a later one on the same line
+/// fails.
+pub(crate) fn evaluate_each(
+    ast: &Ast,
+    env: &mut impl Environment<Value>,
+    ctx: &mut Context,
+    mut ran: impl FnMut(NodeId),
+) -> Result<()> {
+    let _timed = Timed::start(phase::EVALUATOR, "Evaluating");
+    let statements = match &ast[ast.root()] {
+        AstNode::Program(_, nodes) => nodes.clone(),
+        _ => vec![ast.root()],
+    };
+
+    for id in statements {
+        statement(ast, id, env, ctx)?;
+        ran(id);
+    }
+    Ok(())
+}
+
+// A node run for what it does rather than its value, where the debugger can stop
+// Blocks only stop at the statements in them
+fn statement(
+    ast: &Ast,
+    id: NodeId,
+    env: &mut impl Environment<Value>,
+    ctx: &mut Context,
+) -> Result<Value> {
+    if ctx.debugger.is_some() && !matches!(ast[id], AstNode::Block(_, _)) {
+        debugger::pause(ast, id, env, ctx)?;
+    }
+    evaluate(ast, id, env, ctx)
+}
+
+// Each nested node is another Rust call, so limit them rather than overflow the real stack
+fn evaluate(
+    ast: &Ast,
+    id: NodeId,
+    env: &mut impl Environment<Value>,
+    ctx: &mut Context,
+) -> Result<Value> {
+    ctx.steps += 1;
+    check_limits(ctx, ast[id].span())?;
+
+    if ctx.depth >= ctx.max_depth {
+        return Err(RuntimeError::StackOverflow(ast[id].span(), ctx.max_depth).into());
+    }
+
+    if !ctx.is_watched() {
+        ctx.depth += 1;
+        let result = evaluate_node(ast, id, env, ctx);
+        ctx.depth -= 1;
+        return result;
+    }
+
+    // The node borrows the context's source name, so it's made again once the node is done
+    let (scopes, calls) = (env.depth(), ctx.call_stack.len());
+
+    let (source, hooks) = ctx.all_hooks();
+    let node = Node {
+        ast,
+        id,
+        source,
+        scopes,
+        calls,
+    };
+    for hook in hooks {
+        hook.before_node(&node)?;
+    }
+
+    ctx.depth += 1;
+    let result = evaluate_node(ast, id, env, ctx);
+    ctx.depth -= 1;
+
+    let (source, hooks) = ctx.all_hooks();
+    let node = Node {
+        ast,
+        id,
+        source,
+        scopes,
+        calls,
+    };
+    for hook in hooks {
+        hook.after_node(&node, &result)?;
+    }
+    result
+}
+
+// Reading the clock is slow compared to evaluating a node, so only do it this often
+const STEPS_PER_CLOCK_CHECK: usize = 1024;
+
+fn check_limits(ctx: &Context, span: Span) -> Result<()> {
+    let steps = ctx.steps.saturating_sub(ctx.steps_at_start);
+
+    if let Some(max) = ctx.limits.max_steps {
+        if steps > max {
+            return Err(RuntimeError::LimitExceeded(span, Limit::Steps(max)).into());
+        }
+    }
+
+    if let Some(max) = ctx.limits.max_duration {
+        if steps % STEPS_PER_CLOCK_CHECK == 0 && ctx.started.elapsed() > max {
+            return Err(RuntimeError::LimitExceeded(span, Limit::Duration(max)).into());
+        }
+    }
+
+    Ok(())
+}
+
+fn evaluate_node(
+    ast: &Ast,
+    id: NodeId,
+    env: &mut impl Environment<Value>,
+    ctx: &mut Context,
+) -> Result<Value> {
+    match &ast[id] {
+        AstNode::Literal(_, value) => Ok(value.clone()),
+        AstNode::Symbol(span, name) => {
+            // Keywords become builtins; fall back to env, then named builtins, constants, and namespaces
+            if Keyword::try_from(name.as_ref()).is_ok() {
+                return Ok(Value::Builtin(BuiltIn::try_from(name.as_ref())?));
+            }
 
-                match env.get(name) {
-                    Some(value) => Ok(value),
-                    None => {
-                        let line = span.line;
-                        Err(anyhow!("[line {line}] Undefined variable '{name}'"))
+            // Locals the resolver found are right where it said, everything else is by name
+            let variable = match ast.resolution(id) {
+                Some(Resolution { depth, slot }) => env.get_at(depth, slot),
+                None => env.get(name),
+            };
+
+            // Variables can shadow builtins like clock, and namespaces like Math
+            match variable {
+                Some(value) => Ok(value.clone()),
+                None if BuiltIn::global(name, ctx.compat).is_some() => {
+                    let builtin = BuiltIn::global(name, ctx.compat).unwrap();
+                    if !ctx.builtins.allows(builtin) {
+                        return Err(RuntimeError::Unavailable(*span, name.to_string()).into());
                     }
+                    Ok(Value::Builtin(builtin))
+                }
+                None if BuiltIn::constant(name, ctx.compat).is_some() => {
+                    Ok(BuiltIn::constant(name, ctx.compat).unwrap())
                 }
+                None if Namespace::try_from(name.as_ref()).is_ok() => Ok(Value::Namespace(
+                    Namespace::try_from(name.as_ref()).unwrap(),
+                )),
+                None => Err(RuntimeError::UndefinedVariable(*span, name.to_string()).into()),
             }
+        }
 
-            AstNode::Program(_, nodes) | AstNode::Group(_, nodes) => {
-                let mut last = Value::Nil;
-                for node in nodes {
-                    last = node.evaluate(env)?;
-                }
+        AstNode::Program(_, nodes) => {
+            let mut last = Value::Nil;
+            for node in nodes {
+                last = statement(ast, *node, env, ctx)?;
+            }
+
+            Ok(last)
+        }
+
+        AstNode::Group(_, expression) => evaluate(ast, *expression, env, ctx),
+
+        AstNode::Print(span, value) => {
+            let value = evaluate(ast, *value, env, ctx)?;
+            BuiltIn::Print.call(ctx, *span, vec![value])
+        }
+
+        AstNode::Block(_, nodes) => {
+            env.enter();
+
+            // Exit the scope even on errors, a catch may continue in the enclosing scope
+            let result = nodes
+                .iter()
+                .try_fold(Value::Nil, |_, node| statement(ast, *node, env, ctx));
+
+            env.exit();
+
+            result
+        }
+
+        // Operators run their builtin directly, they aren't calls and can't be shadowed
+        AstNode::Binary(span, op, lhs, rhs) => {
+            let lhs = evaluate(ast, *lhs, env, ctx)?;
+            let rhs = evaluate(ast, *rhs, env, ctx)?;
+            binary_builtin(*op).call(ctx, *span, vec![lhs, rhs])
+        }
+
+        AstNode::Unary(span, op, operand) => {
+            let operand = evaluate(ast, *operand, env, ctx)?;
+            unary_builtin(*op).call(ctx, *span, vec![operand])
+        }
 
-                Ok(last)
+        // Whichever side decided the result, without evaluating the rhs if it's the lhs
+        AstNode::Logical(_, op, lhs, rhs) => {
+            let lhs = evaluate(ast, *lhs, env, ctx)?;
+            match (op, lhs.is_truthy()) {
+                (LogicalOp::And, false) | (LogicalOp::Or, true) => Ok(lhs),
+                _ => evaluate(ast, *rhs, env, ctx),
             }
+        }
 
-            AstNode::Block(_, nodes) => {
-                env.enter();
+        // The callee first, then the arguments left to right
+        AstNode::Call(span, func, args) => {
+            let callee = evaluate(ast, *func, env, ctx)?;
 
-                let mut last = Value::Nil;
-                for node in nodes {
-                    last = node.evaluate(env)?;
-                }
+            let mut arg_values = Vec::new();
+            for arg in args {
+                arg_values.push(evaluate(ast, *arg, env, ctx)?);
+            }
+
+            call_value(ctx, *span, callee, arg_values)
+        }
+
+        AstNode::Get(span, object, name) => {
+            let object = evaluate(ast, *object, env, ctx)?;
+            get_property(ctx, *span, object, name)
+        }
+
+        AstNode::OptionalGet(span, object, name) => match evaluate(ast, *object, env, ctx)? {
+            Value::Nil => Err(ShortCircuit.into()),
+            object => get_property(ctx, *span, object, name),
+        },
+
+        AstNode::OptionalChain(_, chain) => match evaluate(ast, *chain, env, ctx) {
+            Err(error) if error.is::<ShortCircuit>() => Ok(Value::Nil),
+            result => result,
+        },
+
+        AstNode::Index(span, object, index) => {
+            let object = evaluate(ast, *object, env, ctx)?;
+            let index = match evaluate(ast, *index, env, ctx)? {
+                Value::Integer(i) => i,
+                Value::Number(n) if n.fract() == 0.0 => n as i64,
+                _ => return Err(RuntimeError::IndexNotInteger(*span).into()),
+            };
 
+            // Strings index by character (unicode scalar value), not byte
+            let item = match (&object, usize::try_from(index)) {
+                (Value::String(s), Ok(i)) => s.chars().nth(i).map(|c| c.to_string().into()),
+                (Value::List(items), Ok(i)) => items.get(i).cloned(),
+                (Value::String(_) | Value::List(_), Err(_)) => None,
+                _ => return Err(RuntimeError::NotIndexable(*span).into()),
+            };
+
+            item.ok_or_else(|| RuntimeError::IndexOutOfRange(*span, index).into())
+        }
+
+        AstNode::ForIn(span, name, iterable, body) => {
+            let items = match evaluate(ast, *iterable, env, ctx)? {
+                Value::String(s) => s.chars().map(|c| c.to_string().into()).collect(),
+                Value::List(items) => items.to_vec(),
+                _ => return Err(RuntimeError::NotIterable(*span).into()),
+            };
+
+            for item in items {
+                env.enter();
+                env.define(name.clone(), item);
+                let result = statement(ast, *body, env, ctx);
                 env.exit();
+                result?;
+            }
+
+            Ok(Value::Nil)
+        }
+
+        AstNode::Coalesce(_, lhs, rhs) => match evaluate(ast, *lhs, env, ctx)? {
+            Value::Nil => evaluate(ast, *rhs, env, ctx),
+            value => Ok(value),
+        },
+
+        AstNode::Enum(_, name, variants) => {
+            let enum_type = EnumType {
+                name: name.clone(),
+                variants: variants.clone(),
+            };
+            let value = Value::Enum(Arc::new(enum_type));
+            env.define(name.as_str(), value.clone());
+            Ok(value)
+        }
 
-                Ok(last)
+        AstNode::Import(span, module) => import(*span, module, env, ctx),
+
+        AstNode::Declaration(_, name, body) => {
+            let value = evaluate(ast, *body, env, ctx)?;
+            env.define(name.clone(), value.clone());
+            Ok(value)
+        }
+
+        AstNode::Assert(span, condition, message) => {
+            let value = evaluate(ast, *condition, env, ctx)?;
+            if value.is_truthy() {
+                return Ok(Value::Nil);
             }
 
-            AstNode::Application(_span, func, args) => {
-                let mut arg_values = Vec::new();
-                for arg in args {
-                    arg_values.push(arg.evaluate(env)?);
-                }
-                
-                match func.evaluate(env)? {
-                    Value::Builtin(name) => {
-                        let callable = BuiltIn::try_from(name.as_str())?;
-                        callable.call(arg_values)
-                    }
-                    _ => unimplemented!("Only built ins are implemented"),
+            // Quote the original source if we have it, otherwise fall back to the AST
+            let (name, text) = match &ctx.source {
+                Some(source) => (source.name.clone(), source.slice(&ast[*condition].span())),
+                None => ("<unknown>".to_string(), ast.display(*condition).to_string()),
+            };
+            let message = match message {
+                Some(message) => Some(evaluate(ast, *message, env, ctx)?.lox_display()),
+                None => None,
+            };
+
+            Err(RuntimeError::AssertionFailed(*span, name, text, message).into())
+        }
+
+        AstNode::Throw(span, body) => {
+            let value = evaluate(ast, *body, env, ctx)?;
+            Err(Thrown(*span, value).into())
+        }
+
+        AstNode::Try(_, body, catch, finally) => {
+            let result = match (evaluate(ast, *body, env, ctx), catch) {
+                (Err(error), Some((name, handler))) if error.is::<Thrown>() => {
+                    let Thrown(_, value) = error.downcast::<Thrown>()?;
+                    ctx.trace.clear();
+
+                    env.enter();
+                    env.define(name.clone(), value);
+                    let result = evaluate(ast, *handler, env, ctx);
+                    env.exit();
+
+                    result
                 }
-            }
+                (result, _) => result,
+            };
 
-            AstNode::Declaration(_, name, body) => {
-                let value = body.evaluate(env)?;
-                env.set(name, value.clone());
-                Ok(value)
+            // Finally always runs; if it fails, that error replaces the original result
+            if let Some(finally) = finally {
+                evaluate(ast, *finally, env, ctx)?;
             }
 
-            AstNode::Assignment(span, name, body) => {
-                if env.get(name).is_none() {
-                    let line = span.line;
-                    return Err(anyhow!("[line {line}] Undefined variable '{name}'"));
-                }
+            result
+        }
 
-                let value = body.evaluate(env)?;
-                env.set(name, value.clone());
-                Ok(value)
+        AstNode::Assignment(span, name, body) => {
+            let value = evaluate(ast, *body, env, ctx)?;
+            let assigned = match ast.resolution(id) {
+                Some(Resolution { depth, slot }) => env.assign_at(depth, slot, value.clone()),
+                None => env.assign(name, value.clone()),
+            };
+            if !assigned {
+                return Err(RuntimeError::UndefinedVariable(*span, name.to_string()).into());
             }
+            Ok(value)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::EnvironmentStack;
+    use crate::interpreter::parse_source;
+    use crate::named_source::NamedSource;
+
+    fn outcome(source: &str) -> EvalOutcome {
+        let source = NamedSource::new("test.lox".into(), source.into());
+        let ast = parse_source(&source).unwrap();
+        let mut ctx = Context {
+            source: Some(source),
+            ..Default::default()
+        };
+        ast.evaluate(&mut EnvironmentStack::new(), &mut ctx)
+            .unwrap()
+    }
+
+    #[test]
+    fn expressions_have_values_and_statements_dont() {
+        assert_eq!(outcome("1 + 2;"), EvalOutcome::Value(Value::Integer(3)));
+        assert_eq!(outcome("nil;"), EvalOutcome::Value(Value::Nil));
+        assert_eq!(outcome("var x = 3;"), EvalOutcome::Unit);
+        assert_eq!(outcome("{ 1; }"), EvalOutcome::Unit);
+        assert_eq!(outcome(""), EvalOutcome::Unit);
+
+        // The last one decides
+        assert_eq!(
+            outcome("var x = 3;\nx;"),
+            EvalOutcome::Value(Value::Integer(3))
+        );
+        assert_eq!(outcome("1;\nvar x = 3;"), EvalOutcome::Unit);
+        assert_eq!(EvalOutcome::Unit.into_value(), Value::Nil);
+    }
+}
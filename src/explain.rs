@@ -0,0 +1,644 @@
+/// The longer description of an error code, for `jp-lox explain`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Explanation {
+    pub code: &'static str,
+    pub title: &'static str,
+    pub description: &'static str, // Markdown, with an example and how to fix it
+}
+
+/// Every code from Diagnostic::code, in order
+pub const EXPLANATIONS: &[Explanation] = &[
+    Explanation {
+        code: "E0001",
+        title: "Undefined variable",
+        description: "\
+A variable was read or assigned before it was declared.
+
+```lox
+print count; // error: count hasn't been declared
+```
+
+Declare it with var first:
+
+```lox
+var count = 0;
+print count;
+```
+
+Globals are looked up when they're used, so in a file that's run this is an error when the
+line runs. `check` reports it without running anything.",
+    },
+    Explanation {
+        code: "E0002",
+        title: "Local variable read in its own initializer",
+        description: "\
+A local variable was used in the expression that gives it its first value.
+
+```lox
+var x = 1;
+{
+  var x = x + 1; // error: this x is the new one, which has no value yet
+}
+```
+
+Give the inner variable a different name to read the outer one:
+
+```lox
+var x = 1;
+{
+  var y = x + 1;
+}
+```",
+    },
+    Explanation {
+        code: "E0003",
+        title: "Variable already declared in this scope",
+        description: "\
+A block declared the same local variable twice.
+
+```lox
+{
+  var a = 1;
+  var a = 2; // error: a is already declared in this block
+}
+```
+
+Assign to it instead, or use an inner block if a new variable was meant:
+
+```lox
+{
+  var a = 1;
+  a = 2;
+}
+```
+
+Globals can be redeclared, this is only for locals.",
+    },
+    Explanation {
+        code: "E0004",
+        title: "Builtin not available",
+        description: "\
+A builtin that reaches outside the interpreter (the environment, the clock, files) was used
+where it isn't allowed, like with --sandbox.
+
+```lox
+print clock(); // error with --sandbox
+```
+
+Run without --sandbox, or leave the builtin out of scripts meant to run sandboxed. getenv and
+setenv also need --allow-env.",
+    },
+    Explanation {
+        code: "E0005",
+        title: "Environment access disabled",
+        description: "\
+getenv or setenv was called without --allow-env. Scripts can't read or change the process
+environment unless they're allowed to.
+
+```lox
+print getenv(\"HOME\"); // error without --allow-env
+```
+
+Run with --allow-env if the script should have it.",
+    },
+    Explanation {
+        code: "E0006",
+        title: "Enum variant already declared",
+        description: "\
+An enum has two variants with the same name, so the second could never be used.
+
+```lox
+enum Color { Red, Green, Red } // error: Red is already a variant
+```
+
+Rename or remove one of them.",
+    },
+    Explanation {
+        code: "E0101",
+        title: "Expected something else",
+        description: "\
+The parser needed a particular token, like a name after var or a closing parenthesis, and found
+something else. The message says what it expected.
+
+```lox
+var = 1; // error: expected a name after var
+```
+
+Add or fix whatever's missing where the error points.",
+    },
+    Explanation {
+        code: "E0102",
+        title: "Expected ';'",
+        description: "\
+A statement didn't end with a semicolon.
+
+```lox
+print 1
+print 2;
+```
+
+Every statement except blocks ends with `;`:
+
+```lox
+print 1;
+print 2;
+```
+
+The last statement in a file can leave it out.",
+    },
+    Explanation {
+        code: "E0103",
+        title: "Expected an expression",
+        description: "\
+Something that isn't a value was found where a value was needed, often an operator missing one
+side.
+
+```lox
+print 1 +;   // error: nothing after +
+var x = ;    // error: no value for x
+```
+
+Finish the expression, or leave out `=` to declare a variable as nil.",
+    },
+    Explanation {
+        code: "E0104",
+        title: "Invalid assignment target",
+        description: "\
+The left side of `=` isn't something that can be assigned to.
+
+```lox
+1 = 2;       // error
+a + b = 3;   // error
+```
+
+Only variables can be assigned:
+
+```lox
+var a = 1;
+a = 2;
+```",
+    },
+    Explanation {
+        code: "E0105",
+        title: "Unexpected end of input",
+        description: "\
+The source ended in the middle of something, usually a block or group that was never closed.
+
+```lox
+{
+  print 1;
+// error: the block is never closed
+```
+
+Close whatever was left open:
+
+```lox
+{
+  print 1;
+}
+```",
+    },
+    Explanation {
+        code: "E0106",
+        title: "Expression too deeply nested",
+        description: "\
+Expressions, blocks, or statements were nested deeper than the parser allows, like thousands of
+parentheses inside each other. Past the limit it's this error rather than the parser running out
+of stack.
+
+```lox
+print ((((((((((1)))))))))); // error with --max-depth 5
+```
+
+Simplify the source, or raise the limit with --max-depth, which also limits nesting while
+running.",
+    },
+    Explanation {
+        code: "E0201",
+        title: "Unexpected character",
+        description: "\
+A character that isn't part of Lox was found outside a string or comment.
+
+```lox
+print 1 @ 2; // error: @ isn't an operator
+```
+
+Remove it, or put it in a string if it was meant to be printed. A #! line is only allowed as
+the very first line.",
+    },
+    Explanation {
+        code: "E0202",
+        title: "Unterminated string",
+        description: "\
+A string was opened with `\"` and never closed.
+
+```lox
+print \"hello;
+```
+
+Close it with a matching `\"`:
+
+```lox
+print \"hello\";
+```
+
+Strings can run over several lines, so the error is on the line the string started. The rest of
+that line is skipped and the lines after it are still checked.",
+    },
+    Explanation {
+        code: "E0203",
+        title: "Malformed number",
+        description: "\
+A number literal couldn't be read, like a hex or binary prefix with no digits after it.
+
+```lox
+print 0x; // error: no hex digits after 0x
+```
+
+Write the digits for the prefix: 0x for hex (0-9, a-f), 0b for binary (0 and 1).",
+    },
+    Explanation {
+        code: "E0204",
+        title: "Error reading source",
+        description: "\
+The source couldn't be read, for example because a file isn't valid UTF-8 or a pipe closed
+early. The message has the error from the operating system.
+
+Check that the file exists, can be read, and is saved as UTF-8.",
+    },
+    Explanation {
+        code: "E0301",
+        title: "Undefined property",
+        description: "\
+A property was read that the value doesn't have.
+
+```lox
+enum Color { Red, Green }
+print Color.Blue;      // error: Color has no Blue
+print Color.Red.size;  // error: variants have name and ordinal
+```
+
+Enums have their variants as properties, and each variant has name and ordinal.",
+    },
+    Explanation {
+        code: "E0302",
+        title: "Only instances have properties",
+        description: "\
+A property was read from a value that doesn't have any, like a number or string.
+
+```lox
+var n = 5;
+print n.name; // error
+```
+
+Only enums and their variants have properties. For strings and lists, use builtins like len.",
+    },
+    Explanation {
+        code: "E0303",
+        title: "Not callable",
+        description: "\
+Something was called that isn't a function.
+
+```lox
+var name = \"lox\";
+name(); // error: strings can't be called
+```
+
+Only builtins can be called, there are no user defined functions yet.",
+    },
+    Explanation {
+        code: "E0304",
+        title: "Index must be an integer",
+        description: "\
+A string or list was indexed with something other than a whole number.
+
+```lox
+var letters = \"abc\";
+print letters[1.5]; // error
+print letters[\"a\"]; // error
+```
+
+Indexes are whole numbers starting at 0.",
+    },
+    Explanation {
+        code: "E0305",
+        title: "Not indexable",
+        description: "\
+Something other than a string or list was indexed.
+
+```lox
+var n = 10;
+print n[0]; // error
+```
+
+Only strings and lists have items to index.",
+    },
+    Explanation {
+        code: "E0306",
+        title: "Index out of range",
+        description: "\
+An index was past the end of a string or list (or before the start).
+
+```lox
+var items = list(1, 2, 3);
+print items[3]; // error: the last index is 2
+```
+
+Indexes go from 0 to len(items) - 1.",
+    },
+    Explanation {
+        code: "E0307",
+        title: "Not iterable",
+        description: "\
+A for-in loop was given something other than a string or list.
+
+```lox
+for (var x in 10) print x; // error
+```
+
+Loop over a list or string instead, eg list(1, 2, 3).",
+    },
+    Explanation {
+        code: "E0308",
+        title: "Operand must be a number",
+        description: "\
+Negation was used on something that isn't a number.
+
+```lox
+print -\"five\"; // error
+```
+
+Convert it first, eg -String.parseNumber(\"5\").",
+    },
+    Explanation {
+        code: "E0309",
+        title: "Operands must be numbers",
+        description: "\
+An arithmetic operator other than + was used on values that aren't both numbers.
+
+```lox
+print \"a\" - 1; // error
+print nil * 2; // error
+```
+
+Only + works on strings. Convert values with String.parseNumber first if they're numbers as text.",
+    },
+    Explanation {
+        code: "E0310",
+        title: "Operands must be two numbers or two strings",
+        description: "\
++ adds two numbers or joins two strings, and comparisons like < compare two of either. One got
+something else.
+
+```lox
+print \"total: \" + 3; // error
+print nil < 2;       // error
+```
+
+Convert the number with toString, or run with --lenient-concat to allow mixing them:
+
+```lox
+print \"total: \" + toString(3);
+```",
+    },
+    Explanation {
+        code: "E0311",
+        title: "Invalid arguments for builtin",
+        description: "\
+A builtin was called with the right number of arguments, but not of types it takes. The message
+has the builtin, and which parameter got what if it's one that only takes one type; `:type` in
+the REPL shows what each of its parameters takes.
+
+```lox
+print Math.sqrt(\"four\"); // error: Math.sqrt: Expected a number for x but got a string.
+print len(5);             // error: len takes a string or list
+```",
+    },
+    Explanation {
+        code: "E0312",
+        title: "Division by zero",
+        description: "\
+A number was divided by zero (or 0.0).
+
+```lox
+var count = 0;
+print 10 / count; // error
+```
+
+Check for zero before dividing, or run with --ieee-math to get floating point's answers instead:
+inf or -inf, and NaN for 0 / 0.",
+    },
+    Explanation {
+        code: "E0313",
+        title: "Module not found",
+        description: "\
+An import named a module that isn't in any of the places imports look. The message lists every
+file that was tried.
+
+```lox
+import \"math\"; // error if there's no math.lox to find
+```
+
+Modules are looked for next to the file importing them first, then in each directory given with
+--include, then in each directory in LOX_PATH:
+
+```sh
+jp-lox run --include lib main.lox
+LOX_PATH=lib:vendor jp-lox run main.lox
+```",
+    },
+    Explanation {
+        code: "E0314",
+        title: "Module couldn't be imported",
+        description: "\
+A module was found but couldn't be read, or has syntax errors. The message has the errors, with
+line numbers in the module.
+
+```lox
+import \"broken\"; // error if broken.lox doesn't parse
+```
+
+Fix the module, checking it on its own with `jp-lox check broken.lox`.",
+    },
+    Explanation {
+        code: "E0315",
+        title: "Wrong number of arguments",
+        description: "\
+A builtin was called with more or fewer arguments than it takes. Optional parameters can be left
+off, and some builtins (like list and String.format) take any number after their first ones.
+
+```lox
+print len(\"a\", \"b\");   // error: Expected 1 argument but got 2.
+print Math.pow(2);     // error: Expected 2 arguments but got 1.
+```
+
+`:type` in the REPL shows a builtin's parameters, eg `:type List.sort`.",
+    },
+    Explanation {
+        code: "E0316",
+        title: "Values can't be compared",
+        description: "\
+List.sort was called without a comparison on a list with values that don't have a natural order.
+Numbers sort with numbers and strings with strings, anything else (or a mix) needs a comparison.
+
+```lox
+print List.sort(list(2, \"a\")); // error: a number and a string
+```
+
+Pass a comparison as the second argument, see E0317 for what it returns.",
+    },
+    Explanation {
+        code: "E0317",
+        title: "Invalid sort comparison",
+        description: "\
+The comparison given to List.sort returned something other than a number. It's called with two
+items and returns a negative number if the first comes before the second, a positive number if it
+comes after, or zero if they're the same.
+
+```lox
+print List.sort(list(3, 1, 2), Math.max); // sorts, max returns numbers
+print List.sort(list(3, 1, 2), list);     // error: got a list
+```
+
+Booleans aren't accepted: true or false can't say that two items are the same, so they don't
+give a consistent order.",
+    },
+    Explanation {
+        code: "E0318",
+        title: "Invalid format string",
+        description: "\
+String.format or printf was given a format string it can't fill in: a placeholder that isn't
+closed or doesn't parse, one for an argument that wasn't passed, or a } on its own.
+
+```lox
+print String.format(\"{} and {}\", 1); // error: Missing argument 1 in format string.
+print String.format(\"{x}\", 1);       // error: Invalid placeholder {x} in format string.
+```
+
+Placeholders are {} for the next argument, {0} for one by position, and {:.2} or {0:.2} for a
+number's digits or a string's length. Use {{ and }} for literal braces.",
+    },
+    Explanation {
+        code: "E0401",
+        title: "Stack overflow",
+        description: "\
+Expressions nested deeper than the interpreter allows while running, usually from very long
+chains of operators or deeply nested groups.
+
+Simplify the expression, or raise the limit with --max-depth.",
+    },
+    Explanation {
+        code: "E0402",
+        title: "Execution limit exceeded",
+        description: "\
+The program ran into one of the limits set on the command line: --max-steps, --timeout, or
+--max-call-depth. The message says which.
+
+```lox
+for (var x in list(1, 2, 3)) print x; // error with --max-steps 5
+```
+
+Raise or remove the limit, or find why the program runs longer than expected.",
+    },
+    Explanation {
+        code: "E0403",
+        title: "Assertion failed",
+        description: "\
+An assert's condition was false. The message has the condition and the assert's message, if it
+has one.
+
+```lox
+var total = 1 + 1;
+assert total == 3, \"math works\"; // error
+```
+
+Fix whatever made the condition false, or the condition if it's wrong.",
+    },
+    Explanation {
+        code: "E0404",
+        title: "Uncaught exception",
+        description: "\
+A value was thrown and nothing caught it.
+
+```lox
+throw \"oops\"; // error
+```
+
+Catch it with try, or make sure it isn't thrown:
+
+```lox
+try {
+  throw \"oops\";
+} catch (e) {
+  print e;
+}
+```",
+    },
+];
+
+/// Look up a code, with or without the E and in either case (eg E0001, e0001, 0001)
+pub fn explain(code: &str) -> Option<&'static Explanation> {
+    let code = code.trim().to_uppercase();
+    let code = code.strip_prefix('E').unwrap_or(&code);
+    EXPLANATIONS.iter().find(|e| &e.code[1..] == code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::{self, Diagnostic};
+    use crate::interpreter::{parse_source, Interpreter};
+    use crate::named_source::NamedSource;
+
+    #[test]
+    fn codes_are_unique_and_in_order() {
+        for pair in EXPLANATIONS.windows(2) {
+            assert!(
+                pair[0].code < pair[1].code,
+                "{} then {}",
+                pair[0].code,
+                pair[1].code
+            );
+        }
+    }
+
+    #[test]
+    fn every_code_given_to_an_error_is_explained() {
+        // Each code is a string on its own in Diagnostic::code
+        let source = include_str!("diagnostic.rs");
+        let codes = source
+            .split('"')
+            .filter(|s| {
+                s.len() == 5 && s.starts_with('E') && s[1..].chars().all(|c| c.is_ascii_digit())
+            })
+            .collect::<Vec<_>>();
+
+        assert!(codes.len() >= EXPLANATIONS.len());
+        for code in codes {
+            assert!(explain(code).is_some(), "{code} has no explanation");
+        }
+    }
+
+    #[test]
+    fn errors_have_the_code_for_what_went_wrong() {
+        let source = NamedSource::new("test.lox".into(), "print ;".into());
+        let errors = parse_source(&source).unwrap_err();
+        let code = diagnostic::find(&errors[0]).map(|d| d.code());
+        assert_eq!(code, Some("E0103"));
+
+        let mut interpreter = Interpreter::new();
+        let error = interpreter.run("print missing;").unwrap_err();
+        let code = error.runtime_error().map(Diagnostic::code);
+        assert_eq!(code, Some("E0001"));
+        assert_eq!(explain("E0001").unwrap().title, "Undefined variable");
+    }
+
+    #[test]
+    fn looks_up_codes_written_any_way() {
+        for code in ["E0001", "e0001", "0001", " E0001\n"] {
+            assert_eq!(explain(code).map(|e| e.code), Some("E0001"), "{code:?}");
+        }
+        assert_eq!(explain("E9999"), None);
+        assert_eq!(explain(""), None);
+    }
+}
@@ -0,0 +1,179 @@
+//! A C interface for hosts that aren't written in Rust, see include/jp_lox.h
+//!
+//! Build it as a shared library with `just ffi`. Every function takes the handle from
+//! jp_lox_new, which has to be freed with jp_lox_free.
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::ptr;
+
+use crate::interpreter::{Interpreter, LoxError};
+
+// The same as the exit codes from the command line
+const OK: c_int = 0;
+const SYNTAX_ERROR: c_int = 65;
+const RUNTIME_ERROR: c_int = 70;
+const INVALID_ARGUMENT: c_int = 64;
+
+/// An interpreter and the message from the last error it had, if any
+pub struct JpLox {
+    interpreter: Interpreter,
+    last_error: Option<CString>,
+}
+
+impl JpLox {
+    fn fail(&mut self, code: c_int, message: String) -> c_int {
+        // Messages can't have a NUL in C, replace any so the rest still gets through
+        let message = message.replace('\0', "\u{FFFD}");
+        self.last_error = Some(CString::new(message).expect("NULs were replaced"));
+        code
+    }
+}
+
+/// A new interpreter, globals persist from one jp_lox_run to the next
+#[no_mangle]
+pub extern "C" fn jp_lox_new() -> *mut JpLox {
+    Box::into_raw(Box::new(JpLox {
+        interpreter: Interpreter::new(),
+        last_error: None,
+    }))
+}
+
+/// Run a NUL-terminated UTF-8 program, returning 0 on success, 65 if it doesn't parse, 70 if
+/// it fails while running, or 64 if the arguments are invalid
+///
+/// # Safety
+///
+/// lox must come from jp_lox_new and not have been freed, and source must be NULL or point to
+/// a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn jp_lox_run(lox: *mut JpLox, source: *const c_char) -> c_int {
+    let Some(lox) = lox.as_mut() else {
+        return INVALID_ARGUMENT;
+    };
+    lox.last_error = None;
+
+    if source.is_null() {
+        return lox.fail(INVALID_ARGUMENT, "source is NULL".to_string());
+    }
+    let source = match CStr::from_ptr(source).to_str() {
+        Ok(source) => source,
+        Err(error) => return lox.fail(INVALID_ARGUMENT, format!("source isn't UTF-8: {error}")),
+    };
+
+    match lox.interpreter.run(source) {
+        Ok(_) => OK,
+        Err(error @ LoxError::Syntax(_)) => lox.fail(SYNTAX_ERROR, error.to_string()),
+        Err(error @ LoxError::Runtime(_)) => lox.fail(RUNTIME_ERROR, error.to_string()),
+    }
+}
+
+/// The message from the last jp_lox_run that failed, or NULL if the last one succeeded
+///
+/// The string belongs to the interpreter and is only valid until its next jp_lox_run or
+/// jp_lox_free.
+///
+/// # Safety
+///
+/// lox must be NULL or come from jp_lox_new and not have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn jp_lox_last_error(lox: *const JpLox) -> *const c_char {
+    match lox.as_ref().and_then(|lox| lox.last_error.as_ref()) {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Free an interpreter from jp_lox_new, NULL is ignored
+///
+/// # Safety
+///
+/// lox must be NULL or come from jp_lox_new, and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn jp_lox_free(lox: *mut JpLox) {
+    if !lox.is_null() {
+        drop(Box::from_raw(lox));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(lox: *mut JpLox, source: &str) -> c_int {
+        let source = CString::new(source).expect("test sources have no NULs");
+        unsafe { jp_lox_run(lox, source.as_ptr()) }
+    }
+
+    fn last_error(lox: *const JpLox) -> Option<String> {
+        let message = unsafe { jp_lox_last_error(lox) };
+        (!message.is_null()).then(|| {
+            unsafe { CStr::from_ptr(message) }
+                .to_string_lossy()
+                .into_owned()
+        })
+    }
+
+    #[test]
+    fn runs_programs_and_reports_errors() {
+        let lox = jp_lox_new();
+
+        assert_eq!(run(lox, "var a = 1;"), OK);
+        assert_eq!(last_error(lox), None);
+        // Globals persist from one run to the next
+        assert_eq!(run(lox, "assert a == 1;"), OK);
+
+        assert_eq!(run(lox, "var = 1;"), SYNTAX_ERROR);
+        assert!(last_error(lox).is_some_and(|e| e.contains("Expect identifier")));
+
+        assert_eq!(run(lox, "a = missing;"), RUNTIME_ERROR);
+        assert_eq!(
+            last_error(lox).as_deref(),
+            Some("[line 1] Undefined variable 'missing'")
+        );
+
+        assert_eq!(unsafe { jp_lox_run(lox, ptr::null()) }, INVALID_ARGUMENT);
+        assert_eq!(last_error(lox).as_deref(), Some("source is NULL"));
+
+        // Succeeding clears the last error
+        assert_eq!(run(lox, "a = 2;"), OK);
+        assert_eq!(last_error(lox), None);
+
+        unsafe { jp_lox_free(lox) };
+    }
+
+    #[test]
+    fn null_interpreters_are_invalid() {
+        assert_eq!(run(ptr::null_mut(), "print 1;"), INVALID_ARGUMENT);
+        assert_eq!(last_error(ptr::null()), None);
+        unsafe { jp_lox_free(ptr::null_mut()) };
+    }
+
+    // Each function with the type Rust gives it and the prototype the header declares, so
+    // changing one without the other fails here
+    #[test]
+    fn header_matches() {
+        let header = include_str!("../include/jp_lox.h");
+        let _: extern "C" fn() -> *mut JpLox = jp_lox_new;
+        let _: unsafe extern "C" fn(*mut JpLox, *const c_char) -> c_int = jp_lox_run;
+        let _: unsafe extern "C" fn(*const JpLox) -> *const c_char = jp_lox_last_error;
+        let _: unsafe extern "C" fn(*mut JpLox) = jp_lox_free;
+        for prototype in [
+            "JpLox *jp_lox_new(void);",
+            "int jp_lox_run(JpLox *lox, const char *source);",
+            "const char *jp_lox_last_error(const JpLox *lox);",
+            "void jp_lox_free(JpLox *lox);",
+        ] {
+            assert!(header.contains(prototype), "missing {prototype}");
+        }
+
+        for (name, code) in [
+            ("JP_LOX_OK", OK),
+            ("JP_LOX_INVALID_ARGUMENT", INVALID_ARGUMENT),
+            ("JP_LOX_SYNTAX_ERROR", SYNTAX_ERROR),
+            ("JP_LOX_RUNTIME_ERROR", RUNTIME_ERROR),
+        ] {
+            let define = format!("#define {name} {code}\n");
+            assert!(header.contains(&define), "missing {define}");
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use crate::parser::{Ast, AstNode, NodeId};
+use crate::trivia::{Line, Trivia};
+use crate::values::Value;
+
+const INDENT: &str = "    ";
+
+/// Format a parsed program back into canonical Lox source
+///
+/// Comments aren't part of the AST, so they're put back from the trivia attached to each
+/// statement: a comment on the same line after a statement stays there, anything else goes on
+/// its own line before the statement that follows it. Single blank lines are also kept.
+pub fn format(ast: &Ast, trivia: &Trivia) -> String {
+    let mut formatter = Formatter {
+        ast,
+        trivia,
+        output: String::new(),
+        indent: 0,
+    };
+
+    let root = ast.root();
+    match &ast[root] {
+        AstNode::Program(_, nodes) => formatter.statements(root, nodes),
+        _ => formatter.statements(root, &[root]),
+    }
+
+    formatter.output
+}
+
+/// A single statement formatted on its own, without any comments around it
+pub fn format_statement(ast: &Ast, id: NodeId) -> String {
+    let mut formatter = Formatter {
+        ast,
+        trivia: &Trivia::default(),
+        output: String::new(),
+        indent: 0,
+    };
+    formatter.statement(id);
+    formatter.output
+}
+
+struct Formatter<'a> {
+    ast: &'a Ast,
+    trivia: &'a Trivia,
+    output: String,
+    indent: usize,
+}
+
+impl<'a> Formatter<'a> {
+    // A list of statements at the current indentation, then whatever's before parent ends
+    fn statements(&mut self, parent: NodeId, nodes: &[NodeId]) {
+        for &node in nodes {
+            self.lines(self.trivia.leading(node));
+            self.write_indent();
+            self.statement(node);
+
+            if let Some(comment) = self.trivia.trailing(node) {
+                self.output.push(' ');
+                self.output.push_str(&comment.text);
+            }
+            self.output.push('\n');
+        }
+
+        self.lines(self.trivia.closing(parent));
+    }
+
+    fn statement(&mut self, id: NodeId) {
+        let ast = self.ast;
+        match &ast[id] {
+            AstNode::Declaration(span, name, value) => {
+                // var x; has a nil literal with the whole declaration's span, var x = nil; doesn't
+                if matches!(ast[*value], AstNode::Literal(_, Value::Nil))
+                    && ast[*value].span() == *span
+                {
+                    self.output.push_str(&format!("var {};", name));
+                } else {
+                    let value = self.expression(*value);
+                    self.output.push_str(&format!("var {} = {};", name, value));
+                }
+            }
+
+            AstNode::Print(_, value) => {
+                let value = self.expression(*value);
+                self.output.push_str(&format!("print {};", value));
+            }
+
+            AstNode::Block(_, nodes) => self.block(id, nodes),
+
+            AstNode::ForIn(_, name, iterable, body) => {
+                let iterable = self.expression(*iterable);
+                self.output
+                    .push_str(&format!("for (var {} in {}) ", name, iterable));
+                self.statement(*body);
+            }
+
+            AstNode::Enum(_, name, variants) => {
+                if variants.is_empty() {
+                    self.output.push_str(&format!("enum {} {{}}", name));
+                } else {
+                    self.output
+                        .push_str(&format!("enum {} {{ {} }}", name, variants.join(", ")));
+                }
+            }
+
+            AstNode::Import(_, module) => {
+                self.output.push_str(&format!("import \"{}\";", module));
+            }
+
+            AstNode::Assert(_, condition, message) => {
+                let condition = self.expression(*condition);
+                match message {
+                    Some(message) => {
+                        let message = self.expression(*message);
+                        self.output
+                            .push_str(&format!("assert {}, {};", condition, message));
+                    }
+                    None => self.output.push_str(&format!("assert {};", condition)),
+                }
+            }
+
+            AstNode::Throw(_, value) => {
+                let value = self.expression(*value);
+                self.output.push_str(&format!("throw {};", value));
+            }
+
+            AstNode::Try(_, body, catch, finally) => {
+                self.output.push_str("try ");
+                self.statement(*body);
+
+                if let Some((name, handler)) = catch {
+                    self.output.push_str(&format!(" catch ({}) ", name));
+                    self.statement(*handler);
+                }
+
+                if let Some(finally) = finally {
+                    self.output.push_str(" finally ");
+                    self.statement(*finally);
+                }
+            }
+
+            AstNode::Program(_, nodes) => self.statements(id, nodes),
+
+            _ => {
+                let expression = self.expression(id);
+                self.output.push_str(&format!("{};", expression));
+            }
+        }
+    }
+
+    fn block(&mut self, id: NodeId, nodes: &[NodeId]) {
+        if nodes.is_empty() && self.trivia.closing(id).is_empty() {
+            self.output.push_str("{}");
+            return;
+        }
+
+        self.output.push_str("{\n");
+        self.indent += 1;
+        self.statements(id, nodes);
+        self.indent -= 1;
+        self.write_indent();
+        self.output.push('}');
+    }
+
+    fn expression(&self, id: NodeId) -> String {
+        let ast = self.ast;
+        match &ast[id] {
+            AstNode::Literal(_, value) => literal(value),
+            AstNode::Symbol(_, name) => name.to_string(),
+
+            AstNode::Group(_, expression) => format!("({})", self.expression(*expression)),
+
+            AstNode::Binary(_, op, lhs, rhs) => {
+                let (lhs, rhs) = (self.expression(*lhs), self.expression(*rhs));
+                format!("{} {} {}", lhs, op.to_value(), rhs)
+            }
+            AstNode::Logical(_, op, lhs, rhs) => {
+                let (lhs, rhs) = (self.expression(*lhs), self.expression(*rhs));
+                format!("{} {} {}", lhs, op.to_value(), rhs)
+            }
+            AstNode::Unary(_, op, operand) => {
+                format!("{}{}", op.to_value(), self.expression(*operand))
+            }
+
+            AstNode::Call(_, func, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.expression(*arg))
+                    .collect::<Vec<_>>();
+                format!("{}({})", self.expression(*func), args.join(", "))
+            }
+
+            AstNode::Get(_, object, name) => format!("{}.{}", self.expression(*object), name),
+            AstNode::OptionalGet(_, object, name) => {
+                format!("{}?.{}", self.expression(*object), name)
+            }
+            AstNode::OptionalChain(_, chain) => self.expression(*chain),
+            AstNode::Coalesce(_, lhs, rhs) => {
+                format!("{} ?? {}", self.expression(*lhs), self.expression(*rhs))
+            }
+            AstNode::Index(_, object, index) => {
+                format!("{}[{}]", self.expression(*object), self.expression(*index))
+            }
+            AstNode::Assignment(_, name, value) => {
+                format!("{} = {}", name, self.expression(*value))
+            }
+
+            _ => unreachable!("not an expression: {}", ast.display(id)),
+        }
+    }
+
+    fn lines(&mut self, lines: &[Line]) {
+        for line in lines {
+            match line {
+                Line::Blank => self.output.push('\n'),
+                Line::Comment(comment) => self.line(&comment.text),
+            }
+        }
+    }
+
+    fn line(&mut self, text: &str) {
+        self.write_indent();
+        self.output.push_str(text);
+        self.output.push('\n');
+    }
+
+    fn write_indent(&mut self) {
+        for _ in 0..self.indent {
+            self.output.push_str(INDENT);
+        }
+    }
+}
+
+// Values as they would be written in source, so they tokenize back to the same value
+fn literal(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("\"{}\"", s),
+        Value::Integer(i) => format!("{}", i),
+        Value::Number(n) if n.fract() == 0.0 && n.is_finite() => format!("{:.1}", n),
+        Value::Number(n) => format!("{}", n),
+        value => format!("{}", value),
+    }
+}
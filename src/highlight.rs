@@ -0,0 +1,143 @@
+use crate::builtins::{BuiltIn, Namespace};
+use crate::tokenizer::{Token, Tokenizer};
+use crate::values::Value;
+
+// What a span of source is, which decides how it's colored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Keyword,
+    Operator,
+    String,
+    Number,
+    Builtin,
+    Identifier,
+    Comment,
+    Text, // Whitespace and anything the tokenizer skipped
+}
+
+impl Kind {
+    fn css_class(&self) -> &'static str {
+        match self {
+            Kind::Keyword => "keyword",
+            Kind::Operator => "operator",
+            Kind::String => "string",
+            Kind::Number => "number",
+            Kind::Builtin => "builtin",
+            Kind::Identifier => "identifier",
+            Kind::Comment => "comment",
+            Kind::Text => "text",
+        }
+    }
+
+    fn ansi_color(&self) -> Option<&'static str> {
+        match self {
+            Kind::Keyword => Some("\x1b[1;35m"),
+            Kind::String => Some("\x1b[32m"),
+            Kind::Number => Some("\x1b[36m"),
+            Kind::Builtin => Some("\x1b[34m"),
+            Kind::Comment => Some("\x1b[90m"),
+            Kind::Operator | Kind::Identifier | Kind::Text => None,
+        }
+    }
+}
+
+const ANSI_RESET: &str = "\x1b[0m";
+
+/// Source with ANSI colors for the terminal, otherwise exactly as written
+pub fn ansi(source: &str) -> String {
+    let mut output = String::new();
+
+    for (kind, text) in classify(source) {
+        match kind.ansi_color() {
+            Some(color) => output.push_str(&format!("{color}{text}{ANSI_RESET}")),
+            None => output.push_str(&text),
+        }
+    }
+
+    output
+}
+
+/// Source as an HTML pre block, with a CSS class on each token for whatever stylesheet
+pub fn html(source: &str) -> String {
+    let mut output = String::from("<pre class=\"lox\">");
+
+    for (kind, text) in classify(source) {
+        let text = escape_html(&text);
+        if kind == Kind::Text {
+            output.push_str(&text);
+        } else {
+            output.push_str(&format!(
+                "<span class=\"{}\">{}</span>",
+                kind.css_class(),
+                text
+            ));
+        }
+    }
+
+    output.push_str("</pre>\n");
+    output
+}
+
+// Split the source into consecutive runs covering every character, so nothing is lost
+fn classify(source: &str) -> Vec<(Kind, String)> {
+    let chars = source.chars().collect::<Vec<_>>();
+    let mut tokenizer = Tokenizer::new(source);
+
+    let mut spans = vec![];
+    for token in &mut tokenizer {
+        let kind = match &token {
+            Token::EOF(_) => continue,
+            Token::Keyword(_, keyword) => {
+                if keyword.to_value().chars().all(|c| c.is_alphabetic()) {
+                    Kind::Keyword
+                } else {
+                    Kind::Operator
+                }
+            }
+            Token::Literal(_, _, Value::String(_)) => Kind::String,
+            Token::Literal(_, _, Value::Integer(_) | Value::Number(_)) => Kind::Number,
+            Token::Literal(_, _, _) => Kind::Keyword, // true, false, nil
+            Token::Identifier(_, name) if BuiltIn::try_from(name.as_str()).is_ok() => Kind::Builtin,
+            Token::Identifier(_, name) if Namespace::try_from(name.as_str()).is_ok() => {
+                Kind::Builtin
+            }
+            Token::Identifier(_, _) => Kind::Identifier,
+        };
+
+        let span = token.span();
+        spans.push((span.start, span.end, kind));
+    }
+
+    for comment in tokenizer.iter_comments() {
+        spans.push((comment.span.start, comment.span.end, Kind::Comment));
+    }
+    spans.sort_by_key(|(start, _, _)| *start);
+
+    let mut runs = vec![];
+    let mut position = 0;
+    for (start, end, kind) in spans {
+        let start = start.max(position).min(chars.len());
+        let end = end.max(start).min(chars.len());
+
+        if start > position {
+            runs.push((Kind::Text, chars[position..start].iter().collect()));
+        }
+        if end > start {
+            runs.push((kind, chars[start..end].iter().collect()));
+        }
+        position = end;
+    }
+
+    if position < chars.len() {
+        runs.push((Kind::Text, chars[position..].iter().collect()));
+    }
+
+    runs
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,187 @@
+use std::io::Write;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::parser::{Ast, NodeId};
+use crate::span::Span;
+use crate::values::Value;
+
+/// Something watching the evaluator, called around every node and after every call
+///
+/// Coverage, Profile, and Tracer (for --trace) are all hooks, and embedders can add their own with
+/// Context::hooks. An error returned from a hook stops the program the same as a runtime error.
+pub trait EvalHook: Send {
+    /// Before a node is evaluated, once its limits have been checked
+    fn before_node(&mut self, _node: &Node) -> Result<()> {
+        Ok(())
+    }
+
+    /// Once a node is done, with what it became
+    fn after_node(&mut self, _node: &Node, _result: &Result<Value>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Once a call returns, with how long it took (including anything it called)
+    fn on_call(&mut self, _callee: &Value, _span: Span, _elapsed: Duration) {}
+}
+
+/// The node being evaluated and where, for hooks
+#[derive(Debug, Clone, Copy)]
+pub struct Node<'a> {
+    pub ast: &'a Ast,
+    pub id: NodeId,
+
+    /// The name of the source it's from, <unknown> if the context doesn't have one
+    pub source: &'a str,
+
+    /// How many scopes deep it is, 1 for just the globals
+    pub scopes: usize,
+
+    /// How many calls are in progress
+    pub calls: usize,
+}
+
+impl Node<'_> {
+    pub fn span(&self) -> Span {
+        self.ast[self.id].span()
+    }
+
+    pub fn kind(&self) -> &'static str {
+        self.ast[self.id].kind()
+    }
+}
+
+/// Writes a line for every node evaluated, once it's done: where it is, what it is, and what it
+/// became
+///
+/// Children finish first, so they come before their parents; calls are indented.
+pub struct Tracer {
+    output: Box<dyn Write + Send>,
+}
+
+// Nodes near the top of the tree are most of the program, only show the start of them
+const MAX_TRACE_TEXT: usize = 60;
+
+impl Tracer {
+    pub fn new(output: impl Write + Send + 'static) -> Self {
+        Self {
+            output: Box::new(output),
+        }
+    }
+}
+
+impl EvalHook for Tracer {
+    fn after_node(&mut self, node: &Node, result: &Result<Value>) -> Result<()> {
+        let span = node.span();
+
+        let mut text = node
+            .ast
+            .display(node.id)
+            .to_string()
+            .trim_end()
+            .replace('\n', " ");
+        if text.chars().count() > MAX_TRACE_TEXT {
+            text = text.chars().take(MAX_TRACE_TEXT).collect::<String>() + "...";
+        }
+
+        let outcome = match result {
+            Ok(Value::String(s)) => format!("=> {s:?}"),
+            Ok(value) => format!("=> {}", value.lox_display()),
+            Err(error) => format!("!! {error}"),
+        };
+
+        writeln!(
+            self.output,
+            "{}[{}:{}] {} {text} {outcome} (scope {})",
+            "  ".repeat(node.calls),
+            span.line,
+            span.column,
+            node.kind(),
+            node.scopes
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::interpreter::Interpreter;
+
+    // Writes down everything it's told, or stops at a node kind if it's given one
+    #[derive(Default)]
+    struct Recorder {
+        events: Arc<Mutex<Vec<String>>>,
+        stop_at: Option<&'static str>,
+    }
+
+    impl EvalHook for Recorder {
+        fn before_node(&mut self, node: &Node) -> Result<()> {
+            if self.stop_at == Some(node.kind()) {
+                anyhow::bail!("stopped at {}", node.kind());
+            }
+            let event = format!("before {} {}", node.kind(), node.span().line);
+            self.events.lock().unwrap().push(event);
+            Ok(())
+        }
+
+        fn after_node(&mut self, node: &Node, result: &Result<Value>) -> Result<()> {
+            let value = result
+                .as_ref()
+                .map_or("error".to_string(), Value::lox_display);
+            let event = format!("after {} {value}", node.kind());
+            self.events.lock().unwrap().push(event);
+            Ok(())
+        }
+
+        fn on_call(&mut self, callee: &Value, span: Span, _elapsed: Duration) {
+            let event = format!("call {callee} {}", span.line);
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    #[test]
+    fn hooks_see_every_node_and_call() {
+        let recorder = Recorder::default();
+        let events = recorder.events.clone();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.add_hook(recorder);
+        interpreter.run("\n-len(\"ab\");").unwrap();
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                "before Program 0",
+                "before Unary 2",
+                "before Call 2",
+                "before Symbol 2",
+                "after Symbol <builtin len>",
+                "before Literal 2",
+                "after Literal ab",
+                "call <builtin len> 2",
+                "after Call 2",
+                "after Unary -2",
+                "after Program -2",
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_from_hooks_stop_the_program() {
+        let recorder = Recorder {
+            stop_at: Some("Print"),
+            ..Default::default()
+        };
+
+        let mut interpreter = Interpreter::new();
+        interpreter.add_hook(recorder);
+        let error = interpreter.run("var a = 1;\nprint a;").unwrap_err();
+
+        assert_eq!(error.to_string(), "stopped at Print");
+        assert_eq!(interpreter.get("a"), Some(Value::Integer(1)));
+    }
+}
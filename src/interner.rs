@@ -0,0 +1,44 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// One shared copy of each distinct string, so equal names share an allocation
+///
+/// Cloning an interned string is just a reference count, which is most of what the evaluator
+/// does with variable names.
+#[derive(Debug, Default)]
+pub struct Interner {
+    strings: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, s: &str) -> Arc<str> {
+        if let Some(existing) = self.strings.get(s) {
+            return existing.clone();
+        }
+
+        let interned: Arc<str> = Arc::from(s);
+        self.strings.insert(interned.clone());
+        interned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_strings_share_one_allocation() {
+        let mut interner = Interner::new();
+        let a = interner.intern("name");
+        let b = interner.intern(&String::from("name"));
+        let c = interner.intern("other");
+
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&a, &c));
+        assert_eq!(&*c, "other");
+    }
+}
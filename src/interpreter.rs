@@ -0,0 +1,771 @@
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+
+use crate::builtins::{BuiltIn, BuiltinSet};
+use crate::context::{Context, Limits, OutputBuffer, DEFAULT_MAX_DEPTH};
+use crate::environment::{Environment, EnvironmentStack};
+use crate::evaluator::{self, EvalOutcome, Evaluate, RuntimeError, Thrown};
+use crate::hook::EvalHook;
+use crate::named_source::NamedSource;
+use crate::parser::{Ast, Parser};
+use crate::phase::{self, Timed};
+use crate::span::Span;
+use crate::stack;
+use crate::tokenizer::Tokenizer;
+use crate::values::{NativeFunction, Value};
+
+/// Everything that can go wrong running Lox code from Rust
+#[derive(Debug, Error)]
+pub enum LoxError {
+    /// The source didn't tokenize or parse, with every error in the order found
+    #[error("{}", .0.join("\n"))]
+    Syntax(Vec<String>),
+
+    /// Evaluation failed, including values thrown and not caught
+    #[error("{0}")]
+    Runtime(anyhow::Error),
+}
+
+impl LoxError {
+    /// What went wrong at runtime, if it was the interpreter and not a value thrown by Lox
+    pub fn runtime_error(&self) -> Option<&RuntimeError> {
+        match self {
+            LoxError::Runtime(error) => error.downcast_ref(),
+            LoxError::Syntax(_) => None,
+        }
+    }
+
+    /// The value thrown by Lox code and never caught, if that's what went wrong
+    pub fn thrown(&self) -> Option<&Value> {
+        match self {
+            LoxError::Runtime(error) => {
+                error.downcast_ref::<Thrown>().map(|Thrown(_, value)| value)
+            }
+            LoxError::Syntax(_) => None,
+        }
+    }
+}
+
+/// Tokenize and parse a source, collecting every error from either
+pub fn parse_source(source: &NamedSource) -> Result<Ast, Vec<anyhow::Error>> {
+    parse_source_with_max_depth(source, DEFAULT_MAX_DEPTH)
+}
+
+/// The same, with a different limit on how deeply the source can nest (see Parser::with_max_depth)
+pub fn parse_source_with_max_depth(
+    source: &NamedSource,
+    max_depth: usize,
+) -> Result<Ast, Vec<anyhow::Error>> {
+    let _timed = Timed::start(phase::PARSER, format!("Parsing {}", source.name));
+    parse_with(source, max_depth, |parser| parser.parse())
+}
+
+/// Tokenize and parse a source that must be one expression, like `evaluate` takes, rather than a
+/// program (see Parser::parse_single_expression)
+pub fn parse_expression_source(
+    source: &NamedSource,
+    max_depth: usize,
+) -> Result<Ast, Vec<anyhow::Error>> {
+    let _timed = Timed::start(
+        phase::PARSER,
+        format!("Parsing {} as an expression", source.name),
+    );
+    parse_with(source, max_depth, |parser| parser.parse_single_expression())
+}
+
+fn parse_with(
+    source: &NamedSource,
+    max_depth: usize,
+    parse: impl FnOnce(&mut Parser) -> anyhow::Result<Ast> + Send,
+) -> Result<Ast, Vec<anyhow::Error>> {
+    stack::with_stack(max_depth, || parse_here(source, max_depth, parse))
+        .unwrap_or_else(|e| Err(vec![e.into()]))
+}
+
+fn parse_here(
+    source: &NamedSource,
+    max_depth: usize,
+    parse: impl FnOnce(&mut Parser) -> anyhow::Result<Ast>,
+) -> Result<Ast, Vec<anyhow::Error>> {
+    let mut parser = Parser::from(Tokenizer::new(&source.bytes)).with_max_depth(max_depth);
+
+    let ast = parse(&mut parser);
+
+    // Tokenizer errors come first, the parse error is often only because of one (eg a string left
+    // open). They're only those found before parsing stopped.
+    let mut errors = parser
+        .tokenizer_iter_errors()
+        .map(|e| e.clone().into())
+        .collect::<Vec<_>>();
+
+    match ast {
+        Ok(ast) if errors.is_empty() => Ok(ast),
+        Ok(_) => Err(errors),
+        Err(e) => {
+            errors.push(e);
+            Err(errors)
+        }
+    }
+}
+
+/// How one of the programs given to Interpreter::run_many went
+#[derive(Debug)]
+pub struct ScriptResult {
+    pub name: String,
+
+    /// Everything it printed, including before an error
+    pub output: String,
+
+    pub result: Result<Value, LoxError>,
+}
+
+/// Where clock() and random() get the time and random numbers, so runs can be made repeatable
+///
+/// Contexts have a SystemHost unless they're given another, like a DeterministicHost for tests.
+pub trait Host: Send {
+    /// Seconds since the Unix epoch
+    fn now(&mut self) -> f64;
+
+    /// A number from 0 up to (but not including) 1
+    fn random(&mut self) -> f64;
+}
+
+/// The system clock, and random numbers seeded from it
+pub struct SystemHost {
+    rng: SplitMix64,
+}
+
+impl Default for SystemHost {
+    fn default() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Self {
+            rng: SplitMix64(now.as_nanos() as u64),
+        }
+    }
+}
+
+impl Host for SystemHost {
+    fn now(&mut self) -> f64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        now.as_secs_f64()
+    }
+
+    fn random(&mut self) -> f64 {
+        self.rng.next_f64()
+    }
+}
+
+/// The same time and random numbers every run, for --deterministic and golden tests
+///
+/// The clock starts at DETERMINISTIC_EPOCH and moves forward a millisecond each time it's read, so
+/// it still only goes forward.
+pub struct DeterministicHost {
+    now: f64,
+    rng: SplitMix64,
+}
+
+/// When a DeterministicHost's clock starts, 2000-01-01 00:00:00 UTC
+pub const DETERMINISTIC_EPOCH: f64 = 946_684_800.0;
+
+impl DeterministicHost {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            now: DETERMINISTIC_EPOCH,
+            rng: SplitMix64(seed),
+        }
+    }
+}
+
+impl Default for DeterministicHost {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl Host for DeterministicHost {
+    fn now(&mut self) -> f64 {
+        let now = self.now;
+        self.now += 0.001;
+        now
+    }
+
+    fn random(&mut self) -> f64 {
+        self.rng.next_f64()
+    }
+}
+
+// Small and fast, with a different sequence for every seed (including 0)
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // The top 53 bits, all a float can hold between 0 and 1
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// A Lox interpreter for embedding, globals persist from one run to the next
+///
+/// Output from print goes to stdout unless it's redirected with set_output. Interpreters are
+/// Send, so each worker thread can have its own.
+#[derive(Debug, Default)]
+pub struct Interpreter {
+    env: EnvironmentStack<Value>,
+    ctx: Context,
+}
+
+// Hosts run scripts on worker threads, so don't let anything stop these being moved or shared
+const _: fn() = || {
+    fn send<T: Send>() {}
+    fn send_and_sync<T: Send + Sync>() {}
+
+    send::<Interpreter>();
+    send_and_sync::<Value>();
+    send_and_sync::<Ast>();
+    send_and_sync::<NamedSource>();
+};
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use the given settings, input, and output instead of the defaults
+    pub fn with_context(ctx: Context) -> Self {
+        Self {
+            env: EnvironmentStack::new(),
+            ctx,
+        }
+    }
+
+    /// Stop each run that goes over any of the limits with a RuntimeError::LimitExceeded
+    pub fn with_limits(limits: Limits) -> Self {
+        Self::with_context(Context {
+            limits,
+            ..Default::default()
+        })
+    }
+
+    /// Only allow Lox code to use some builtins, eg BuiltinSet::sandbox()
+    pub fn with_builtins(builtins: BuiltinSet) -> Self {
+        Self::with_context(Context {
+            builtins,
+            ..Default::default()
+        })
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.ctx
+    }
+
+    pub fn context_mut(&mut self) -> &mut Context {
+        &mut self.ctx
+    }
+
+    /// Send print and printf somewhere other than stdout, like an OutputBuffer
+    pub fn set_output(&mut self, output: impl Write + Send + 'static) {
+        self.ctx.output = Box::new(output);
+    }
+
+    /// Read readLine and readNumber from somewhere other than stdin
+    pub fn set_input(&mut self, input: impl BufRead + Send + 'static) {
+        self.ctx.input = Box::new(input);
+    }
+
+    /// Get the time and random numbers from somewhere else, eg a DeterministicHost
+    pub fn set_host(&mut self, host: impl Host + 'static) {
+        self.ctx.host = Box::new(host);
+    }
+
+    /// Run a program, returning the value of its last statement
+    pub fn run(&mut self, source: &str) -> Result<Value, LoxError> {
+        self.run_named("<embedded>", source)
+    }
+
+    /// Run a program with a name for error messages, like a file name
+    pub fn run_named(&mut self, name: &str, source: &str) -> Result<Value, LoxError> {
+        let source = NamedSource::new(name.to_string(), source.to_string());
+        let ast = parse_source_with_max_depth(&source, self.ctx.max_depth)
+            .map_err(|errors| LoxError::Syntax(errors.iter().map(|e| e.to_string()).collect()))?;
+        self.evaluate(source, &ast)
+    }
+
+    /// Run a program that's already been parsed, eg with parse_source
+    ///
+    /// Asts are Send and Sync, so one can be parsed once and shared in an Arc between
+    /// interpreters on different threads.
+    pub fn run_parsed(&mut self, source: &NamedSource, ast: &Ast) -> Result<Value, LoxError> {
+        self.evaluate(source.clone(), ast)
+    }
+
+    /// Evaluate a single expression (without a trailing ;) and return its value
+    pub fn eval_expr(&mut self, source: &str) -> Result<Value, LoxError> {
+        let source = NamedSource::new("<expression>".to_string(), source.to_string());
+        let ast = parse_expression_source(&source, self.ctx.max_depth)
+            .map_err(|errors| LoxError::Syntax(errors.iter().map(|e| e.to_string()).collect()))?;
+        self.evaluate(source, &ast)
+    }
+
+    fn evaluate(&mut self, source: NamedSource, ast: &Ast) -> Result<Value, LoxError> {
+        self.ctx.source = Some(source);
+        self.ctx.start_run();
+        stack::with_stack(self.ctx.max_depth, || {
+            ast.evaluate(&mut self.env, &mut self.ctx)
+                .map(EvalOutcome::into_value)
+                .map_err(LoxError::Runtime)
+        })
+        .unwrap_or_else(|e| Err(LoxError::Runtime(e.into())))
+    }
+
+    /// Call a function by name, either a global defined by Lox code or a builtin
+    ///
+    /// Handlers defined in a script can be run this way from the host's own event loop.
+    pub fn call(&mut self, name: &str, args: Vec<Value>) -> Result<Value, LoxError> {
+        let function = match self.env.get(name) {
+            Some(value) => value.clone(),
+            None => match BuiltIn::global(name, self.ctx.compat) {
+                Some(builtin) if self.ctx.builtins.allows(builtin) => Value::Builtin(builtin),
+                Some(_) => {
+                    let error = RuntimeError::Unavailable(Span::ZERO, name.to_string());
+                    return Err(LoxError::Runtime(error.into()));
+                }
+                None => {
+                    let error = RuntimeError::UndefinedVariable(Span::ZERO, name.to_string());
+                    return Err(LoxError::Runtime(error.into()));
+                }
+            },
+        };
+
+        self.call_value(function, args)
+    }
+
+    /// Call a function value, like one returned from an earlier run
+    pub fn call_value(&mut self, function: Value, args: Vec<Value>) -> Result<Value, LoxError> {
+        self.ctx.start_run();
+        stack::with_stack(self.ctx.max_depth, || {
+            evaluator::call_value(&mut self.ctx, Span::ZERO, function, args)
+                .map_err(LoxError::Runtime)
+        })
+        .unwrap_or_else(|e| Err(LoxError::Runtime(e.into())))
+    }
+
+    /// The current value of a global variable, if it's defined
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.env.get(name).cloned()
+    }
+
+    /// Define or replace a global variable
+    pub fn set(&mut self, name: &str, value: Value) {
+        self.env.define(name, value);
+    }
+
+    /// Expose a Rust function to Lox code as a global, it can be shadowed like any other
+    ///
+    /// Errors returned from the function are runtime errors in Lox.
+    pub fn register_fn<F>(&mut self, name: &str, function: F)
+    where
+        F: Fn(&[Value]) -> anyhow::Result<Value> + Send + Sync + 'static,
+    {
+        let native = NativeFunction::new(name, function);
+        self.env.define(name, Value::Native(Arc::new(native)));
+    }
+
+    /// Watch every node and call from now on, after any hooks added before it
+    pub fn add_hook(&mut self, hook: impl EvalHook + 'static) {
+        self.ctx.hooks.push(Box::new(hook));
+    }
+
+    /// Every global variable name, sorted
+    pub fn globals(&self) -> Vec<String> {
+        self.env.names()
+    }
+
+    /// Parse and run programs that don't depend on each other on a thread per core
+    ///
+    /// Each gets a new interpreter, so globals aren't shared, and nothing to read from stdin.
+    /// Results are in the same order as the sources.
+    pub fn run_many(sources: Vec<NamedSource>) -> Vec<ScriptResult> {
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(Vec::with_capacity(sources.len()));
+        let workers = thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(sources.len());
+
+        // Workers take the next source until there are none left, so slow ones don't hold up
+        // the rest. Each has a stack big enough to parse and run them itself.
+        let work = || loop {
+            let index = next.fetch_add(1, Ordering::Relaxed);
+            let Some(source) = sources.get(index) else {
+                break;
+            };
+            let result = run_isolated(source);
+            results.lock().expect("results lock").push((index, result));
+        };
+        thread::scope(|scope| {
+            let started = (0..workers)
+                .take_while(|_| stack::spawn_scoped(scope, DEFAULT_MAX_DEPTH, work).is_ok())
+                .count();
+
+            // Without any threads, this one still gets through them, one stack at a time
+            if started == 0 {
+                work();
+            }
+        });
+
+        let mut results = results.into_inner().expect("results lock");
+        results.sort_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+}
+
+fn run_isolated(source: &NamedSource) -> ScriptResult {
+    let output = OutputBuffer::new();
+    let mut interpreter = Interpreter::new();
+    interpreter.set_output(output.clone());
+    interpreter.set_input(io::empty());
+
+    let result = parse_source(source)
+        .map_err(|errors| LoxError::Syntax(errors.iter().map(|e| e.to_string()).collect()))
+        .and_then(|ast| interpreter.run_parsed(source, &ast));
+
+    ScriptResult {
+        name: source.name.clone(),
+        output: output.take(),
+        result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calling_an_undefined_name_is_a_runtime_error() {
+        let mut interpreter = Interpreter::new();
+        let error = interpreter.call("missing", vec![]).unwrap_err();
+
+        assert!(matches!(
+            error.runtime_error(),
+            Some(RuntimeError::UndefinedVariable(_, name)) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn runtime_errors_are_typed_with_where_they_happened() {
+        let mut interpreter = Interpreter::new();
+
+        let error = interpreter.run("var a = 1;\nprint -\"x\";").unwrap_err();
+        assert!(matches!(
+            error.runtime_error(),
+            Some(RuntimeError::OperandNotNumber(span)) if span.line == 2
+        ));
+        assert_eq!(error.to_string(), "[line 2] Operand must be a number.");
+
+        let error = interpreter.run("\n\nprint List.sort();").unwrap_err();
+        assert!(matches!(
+            error.runtime_error(),
+            Some(RuntimeError::WrongArity(span, _, 0)) if span.line == 3
+        ));
+        assert_eq!(
+            error.to_string(),
+            "[line 3] Expected 1 to 2 arguments but got 0."
+        );
+    }
+
+    #[test]
+    fn parses_a_single_expression_without_statements() {
+        let parse = |text: &str| {
+            let source = NamedSource::new("test.lox".into(), text.into());
+            parse_expression_source(&source, DEFAULT_MAX_DEPTH)
+                .map_err(|errors| errors.iter().map(|e| e.to_string()).collect::<Vec<_>>())
+        };
+
+        let ast = parse("(1 + 2) * 3").unwrap();
+        assert_eq!(
+            ast.display(ast.root()).to_string(),
+            "(* (group (+ 1.0 2.0)) 3.0)"
+        );
+
+        assert_eq!(
+            parse("1 + 2;").unwrap_err(),
+            vec!["[line 1] Error at ';': Expect end of expression"]
+        );
+        assert_eq!(
+            parse("var a = 1").unwrap_err(),
+            vec!["[line 1] Error at 'var': Expect expression"]
+        );
+
+        // The same statement is fine as a program
+        let source = NamedSource::new("test.lox".into(), "1 + 2;".into());
+        assert!(parse_source(&source).is_ok());
+    }
+
+    #[test]
+    fn registers_rust_functions() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_fn("double", |args| {
+            let n = args[0].clone().extract::<f64>()?;
+            Ok(Value::from(n * 2.0))
+        });
+
+        assert_eq!(
+            interpreter.eval_expr("double(21)").unwrap(),
+            Value::Number(42.0)
+        );
+        let error = interpreter.run("double(\"x\");").unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("Expected a number, got a string: x"),
+            "{error}"
+        );
+
+        // Shadowed like any other global
+        interpreter.run("var double = 2;").unwrap();
+        assert_eq!(interpreter.get("double"), Some(Value::Integer(2)));
+    }
+
+    #[test]
+    fn converts_values_to_and_from_rust() {
+        assert_eq!(Value::from(true), Value::Bool(true));
+        assert_eq!(Value::from(1_i64), Value::Integer(1));
+        assert_eq!(Value::from(None::<i64>), Value::Nil);
+        assert_eq!(
+            Value::from(vec!["a", "b"]),
+            Value::List(Arc::new(vec![Value::from("a"), Value::from("b")]))
+        );
+
+        assert!(Value::Bool(true).extract::<bool>().unwrap());
+        assert_eq!(Value::Number(3.0).extract::<i64>().unwrap(), 3);
+        assert_eq!(Value::Integer(3).extract::<f64>().unwrap(), 3.0);
+        assert_eq!(Value::from("s").extract::<String>().unwrap(), "s");
+        assert_eq!(
+            Value::from(vec![1_i64, 2]).extract_list::<i64>().unwrap(),
+            [1, 2]
+        );
+
+        // Nothing is silently truncated or turned into another type
+        let error = Value::Number(1.5).extract::<i64>().unwrap_err();
+        assert_eq!(error.to_string(), "Expected an integer, got a number: 1.5");
+        let error = Value::Nil.extract::<f64>().unwrap_err();
+        assert_eq!(error.to_string(), "Expected a number, got nil: nil");
+        let error = Value::Integer(1).extract::<String>().unwrap_err();
+        assert_eq!(error.to_string(), "Expected a string, got a number: 1");
+
+        // Whole floats convert all the way to the ends of the range, and not past them
+        assert_eq!(
+            Value::Number(i64::MIN as f64).extract::<i64>().unwrap(),
+            i64::MIN
+        );
+        assert!(Value::Number(i64::MAX as f64).extract::<i64>().is_err());
+        assert!(Value::Nil.extract::<String>().is_err());
+        assert!(Value::from(vec!["a"]).extract_list::<i64>().is_err());
+    }
+
+    #[test]
+    fn native_functions_are_values_like_builtins() {
+        let mut interpreter = Interpreter::new();
+        let output = OutputBuffer::new();
+        interpreter.set_output(output.clone());
+        interpreter.register_fn("shout", |args| {
+            let text = args[0].clone().extract::<String>()?;
+            Ok(Value::from(text.to_uppercase()))
+        });
+
+        interpreter
+            .run("print List.map(list(\"a\", \"b\"), shout); print shout; print type(shout);")
+            .unwrap();
+        assert_eq!(output.take(), "[A, B]\n<native shout>\nfunction\n");
+
+        // Errors from Rust stop the program like any other runtime error
+        let error = interpreter.run("print \"before\";\nshout(1);").unwrap_err();
+        assert_eq!(error.to_string(), "Expected a string, got a number: 1");
+        assert_eq!(output.take(), "before\n");
+
+        // And it's a global like any other, so Lox code can replace it
+        interpreter.run("var shout = \"quiet\";").unwrap();
+        assert_eq!(interpreter.get("shout"), Some(Value::from("quiet")));
+    }
+
+    #[test]
+    fn calls_functions_from_the_host() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .run("var atLeast10 = partial(Math.max, 10);")
+            .unwrap();
+
+        let value = interpreter
+            .call("atLeast10", vec![Value::from(3_i64)])
+            .unwrap();
+        assert_eq!(value, Value::Integer(10));
+        let value = interpreter.call("len", vec![Value::from("abc")]).unwrap();
+        assert_eq!(value, Value::Integer(3));
+
+        let at_least_10 = interpreter.get("atLeast10").unwrap();
+        let value = interpreter
+            .call_value(at_least_10, vec![Value::from(30_i64)])
+            .unwrap();
+        assert_eq!(value, Value::Integer(30));
+
+        let error = interpreter.call_value(Value::Nil, vec![]).unwrap_err();
+        assert!(error.runtime_error().is_some());
+    }
+
+    #[test]
+    fn calls_from_the_host_are_checked_like_calls_from_lox() {
+        let mut interpreter = Interpreter::with_builtins(BuiltinSet::sandbox());
+        let output = OutputBuffer::new();
+        interpreter.set_output(output.clone());
+
+        interpreter.call("print", vec![Value::from("hi")]).unwrap();
+        assert_eq!(output.take(), "hi\n");
+
+        let error = interpreter.call("clock", vec![]).unwrap_err();
+        assert!(matches!(
+            error.runtime_error(),
+            Some(RuntimeError::Unavailable(_, name)) if name == "clock"
+        ));
+
+        let error = interpreter.call("len", vec![]).unwrap_err();
+        assert!(matches!(
+            error.runtime_error(),
+            Some(RuntimeError::WrongArity(_, _, 0))
+        ));
+
+        interpreter.run("var notAFunction = 1;").unwrap();
+        let error = interpreter.call("notAFunction", vec![]).unwrap_err();
+        assert!(matches!(
+            error.runtime_error(),
+            Some(RuntimeError::NotCallable(_))
+        ));
+    }
+
+    #[test]
+    fn reads_input_and_writes_output_elsewhere() {
+        let mut interpreter = Interpreter::new();
+        let output = OutputBuffer::new();
+        interpreter.set_output(output.clone());
+        interpreter.set_input(std::io::Cursor::new("first\n2.5\n"));
+
+        interpreter
+            .run("print readLine(); printf(\"{}\", readNumber() * 2); print readLine();")
+            .unwrap();
+        assert_eq!(output.take(), "first\n5nil\n");
+        assert_eq!(output.contents(), "");
+    }
+
+    #[test]
+    fn keeps_each_interpreters_output_separate() {
+        let first = OutputBuffer::new();
+        let second = OutputBuffer::new();
+        let mut a = Interpreter::with_context(Context {
+            output: Box::new(first.clone()),
+            ..Default::default()
+        });
+        let mut b = Interpreter::new();
+        b.set_output(second.clone());
+
+        a.run("print \"a\";").unwrap();
+        b.run("print \"b\";").unwrap();
+        a.run("printf(\"{} and {}\", 1, 2);").unwrap();
+        assert_eq!(first.contents(), "a\n1 and 2");
+        assert_eq!(second.contents(), "b\n");
+
+        // What was printed before an error is still there
+        let source = "print \"before\"; print nil + 1; print \"after\";";
+        assert!(b.run(source).is_err());
+        assert_eq!(second.take(), "b\nbefore\n");
+    }
+
+    #[test]
+    fn imports_a_module_again_once_it_parses() {
+        let dir = std::env::temp_dir().join(format!("jp-lox-import-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let module = dir.join("fixed.lox");
+        std::fs::write(&module, "var fixed = ;").unwrap();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.context_mut().module_path = crate::module::ModulePath::new([dir.clone()]);
+        assert!(interpreter.run("import \"fixed\";").is_err());
+
+        std::fs::write(&module, "var fixed = true;").unwrap();
+        interpreter.run("import \"fixed\";").unwrap();
+        assert_eq!(interpreter.get("fixed"), Some(Value::Bool(true)));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // Test threads have a small stack, far too small for these without a bigger one of their own
+    #[test]
+    fn runs_nesting_up_to_the_limit_on_any_thread() {
+        let depth = DEFAULT_MAX_DEPTH - 10;
+        let source = format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+
+        let value = Interpreter::new().eval_expr(&source).unwrap();
+        assert_eq!(value, Value::Integer(1));
+    }
+
+    #[test]
+    fn a_stack_too_big_to_make_is_an_error() {
+        let mut interpreter = Interpreter::new();
+        interpreter.context_mut().max_depth = usize::MAX;
+
+        let error = interpreter.run("print 1;").unwrap_err();
+        assert!(matches!(error, LoxError::Syntax(_)));
+        let message = error.to_string();
+        assert!(message.starts_with("Can't make a stack for a max depth of"));
+
+        let error = interpreter.call("len", vec![Value::from("a")]).unwrap_err();
+        let message = error.to_string();
+        assert!(message.starts_with("Can't make a stack for a max depth of"));
+    }
+
+    #[test]
+    fn run_many_reports_deep_nesting_as_errors() {
+        let nested = |depth: usize| format!("print {}1{};", "(".repeat(depth), ")".repeat(depth));
+        let sources = vec![
+            NamedSource::new("shallow".to_string(), nested(10)),
+            NamedSource::new("at the limit".to_string(), nested(DEFAULT_MAX_DEPTH - 10)),
+            NamedSource::new("too deep to run".to_string(), nested(DEFAULT_MAX_DEPTH - 2)),
+            NamedSource::new(
+                "too deep to parse".to_string(),
+                nested(DEFAULT_MAX_DEPTH * 2),
+            ),
+        ];
+
+        let results = Interpreter::run_many(sources);
+        assert_eq!(results[0].output, "1\n");
+        assert_eq!(results[1].output, "1\n");
+        assert!(matches!(
+            results[2].result.as_ref().unwrap_err().runtime_error(),
+            Some(RuntimeError::StackOverflow(_, _))
+        ));
+        assert!(matches!(results[3].result, Err(LoxError::Syntax(_))));
+    }
+
+    #[test]
+    fn rejects_nesting_past_the_limit() {
+        let depth = DEFAULT_MAX_DEPTH;
+        let source = format!("print {}1;", "-".repeat(depth));
+
+        let error = Interpreter::new().run(&source).unwrap_err();
+        assert!(matches!(error, LoxError::Syntax(_)));
+    }
+}
@@ -0,0 +1,166 @@
+use std::fmt::{self, Display};
+
+use crate::span::Span;
+use crate::tokenizer::Token;
+use crate::values::Value;
+
+/// Just enough JSON to write out ASTs and tokens for other tools
+///
+/// Objects keep fields in insertion order so output is stable and readable.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object<const N: usize>(fields: [(&str, Json); N]) -> Json {
+        Json::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Json::String(value)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(value: usize) -> Self {
+        Json::Integer(value as i64)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+impl From<&Span> for Json {
+    fn from(span: &Span) -> Self {
+        Json::object([
+            ("line", span.line.into()),
+            ("column", span.column.into()),
+            ("start", span.start.into()),
+            ("end", span.end.into()),
+        ])
+    }
+}
+
+// Values without a JSON equivalent (like functions) are written as they would print
+impl From<&Value> for Json {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Nil => Json::Null,
+            Value::Bool(b) => Json::Bool(*b),
+            Value::Integer(i) => Json::Integer(*i),
+            Value::Number(n) => Json::Number(*n),
+            Value::String(s) => Json::String(s.to_string()),
+            Value::List(values) => Json::Array(values.iter().map(Json::from).collect()),
+            value => Json::String(value.to_string()),
+        }
+    }
+}
+
+// Only numbers and strings have a literal value, like the code crafters format
+impl From<&Token> for Json {
+    fn from(token: &Token) -> Self {
+        let literal = match token {
+            Token::Literal(
+                _,
+                _,
+                value @ (Value::Integer(_) | Value::Number(_) | Value::String(_)),
+            ) => value.into(),
+            _ => Json::Null,
+        };
+
+        let span = token.span();
+        Json::object([
+            ("kind", token.kind_name().into()),
+            ("lexeme", token.lexeme().into()),
+            ("literal", literal),
+            ("line", span.line.into()),
+            ("column", span.column.into()),
+            ("start", span.start.into()),
+            ("end", span.end.into()),
+            ("byte_start", span.byte_start.into()),
+            ("byte_end", span.byte_end.into()),
+        ])
+    }
+}
+
+impl Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Integer(i) => write!(f, "{}", i),
+            // JSON has no NaN or infinity
+            Json::Number(n) if !n.is_finite() => write!(f, "null"),
+            Json::Number(n) if n.fract() == 0.0 => write!(f, "{:.1}", n),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(s) => write_string(f, s),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
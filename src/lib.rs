@@ -0,0 +1,153 @@
+//! jp-lox, a Lox interpreter that can be embedded in other Rust programs
+//!
+//! ```
+//! use std::sync::atomic::{AtomicUsize, Ordering};
+//! use std::sync::Arc;
+//! use std::thread;
+//!
+//! use codecrafters_interpreter::hook::{EvalHook, Node};
+//! use codecrafters_interpreter::{
+//!     parse_source, BuiltinSet, DeterministicHost, Interpreter, Limits, NamedSource, OutputBuffer,
+//!     RuntimeError, Value,
+//! };
+//!
+//! let mut interp = Interpreter::new();
+//! interp.run("var x = 6 * 7;").unwrap();
+//! assert_eq!(interp.get("x"), Some(Value::Integer(42)));
+//!
+//! // Rust functions can be called from Lox like any builtin
+//! interp.register_fn("double", |args| match args {
+//!     [Value::Integer(n)] => Ok(Value::Integer(n * 2)),
+//!     _ => Err(anyhow::anyhow!("double takes one integer")),
+//! });
+//! assert_eq!(interp.eval_expr("double(x)").unwrap(), Value::Integer(84));
+//!
+//! // Values convert to and from Rust types
+//! interp.set("names", vec!["a", "b"].into());
+//! let names = interp.eval_expr("names").unwrap().extract_list::<String>().unwrap();
+//! assert_eq!(names, ["a", "b"]);
+//!
+//! // And Lox functions can be called from Rust
+//! interp.run("var twice = partial(List.map, list(1, 2));").unwrap();
+//! let doubled = interp.call("twice", vec![interp.get("double").unwrap()]).unwrap();
+//! assert_eq!(doubled, vec![2_i64, 4].into());
+//!
+//! // Output can be captured instead of going to stdout
+//! let output = OutputBuffer::new();
+//! interp.set_output(output.clone());
+//! interp.run("print x;").unwrap();
+//! assert_eq!(output.contents(), "42\n");
+//!
+//! // Hooks see every node as it's evaluated, here to count them
+//! struct Counter(Arc<AtomicUsize>);
+//! impl EvalHook for Counter {
+//!     fn before_node(&mut self, _node: &Node) -> anyhow::Result<()> {
+//!         self.0.fetch_add(1, Ordering::Relaxed);
+//!         Ok(())
+//!     }
+//! }
+//! let count = Arc::new(AtomicUsize::new(0));
+//! interp.add_hook(Counter(Arc::clone(&count)));
+//! interp.run("print 1 + 2;").unwrap();
+//! assert_eq!(count.load(Ordering::Relaxed), 5); // program, print, +, 1, 2
+//!
+//! // Errors have a kind to match on, their messages are the same as the CLI prints
+//! let error = interp.run("print missing;").unwrap_err();
+//! let kind = error.runtime_error();
+//! assert!(matches!(kind, Some(RuntimeError::UndefinedVariable(_, name)) if name == "missing"));
+//!
+//! // Code that can't be trusted can be stopped before it does too much
+//! let limits = Limits { max_steps: Some(100), ..Default::default() };
+//! let mut sandbox = Interpreter::with_limits(limits);
+//! let error = sandbox
+//!     .run("var n = 0; var s = \"abcdefghij\"; for (var a in s) for (var b in s) n = n + 1;")
+//!     .unwrap_err();
+//! assert!(matches!(error.runtime_error(), Some(RuntimeError::LimitExceeded(_, _))));
+//!
+//! // Or kept away from the environment and the clock
+//! let mut sandbox = Interpreter::with_builtins(BuiltinSet::sandbox());
+//! let error = sandbox.run("print clock();").unwrap_err();
+//! assert!(matches!(error.runtime_error(), Some(RuntimeError::Unavailable(_, _))));
+//!
+//! // The clock and random numbers can be made the same every run, for tests
+//! let rolls = (0..2).map(|_| {
+//!     let mut interp = Interpreter::new();
+//!     interp.set_host(DeterministicHost::new(7));
+//!     interp.eval_expr("Math.random()").unwrap()
+//! });
+//! let rolls = rolls.collect::<Vec<_>>();
+//! assert_eq!(rolls[0], rolls[1]);
+//!
+//! // Programs can be parsed once and run by interpreters on other threads
+//! let source = NamedSource::new("shared.lox".to_string(), "6 * 7;".to_string());
+//! let ast = Arc::new(parse_source(&source).unwrap());
+//! let workers = (0..2).map(|_| {
+//!     let (source, ast) = (source.clone(), Arc::clone(&ast));
+//!     thread::spawn(move || Interpreter::new().run_parsed(&source, &ast).unwrap())
+//! });
+//! for worker in workers.collect::<Vec<_>>() {
+//!     assert_eq!(worker.join().unwrap(), Value::Integer(42));
+//! }
+//!
+//! // Or many programs can be run at once, each on its own
+//! let sources = ["print 1;", "print missing;"]
+//!     .map(|code| NamedSource::new("batch.lox".to_string(), code.to_string()));
+//! let results = Interpreter::run_many(sources.to_vec());
+//! assert_eq!(results[0].output, "1\n");
+//! assert!(results[1].result.is_err());
+//! ```
+
+pub mod ast_output;
+pub mod bench;
+pub mod builtins;
+pub mod compile;
+mod const_enum;
+pub mod context;
+pub mod coverage;
+pub mod debugger;
+pub mod diagnostic;
+pub mod doc;
+pub mod explain;
+pub mod environment;
+pub mod evaluator;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod formatter;
+pub mod highlight;
+pub mod hook;
+pub mod interner;
+pub mod interpreter;
+pub mod json;
+pub mod lint;
+pub mod literate;
+pub mod module;
+pub mod named_source;
+pub mod parser;
+pub mod phase;
+pub mod profile;
+pub mod repl;
+pub mod reparse;
+pub mod resolver;
+pub mod span;
+pub mod stack;
+pub mod stdlib;
+pub mod symbols;
+pub mod test_runner;
+pub mod tokenizer;
+pub mod transpile;
+pub mod trivia;
+pub mod values;
+pub mod visit;
+pub mod wat;
+pub mod watch;
+
+pub use builtins::BuiltinSet;
+pub use context::{Limits, OutputBuffer};
+pub use evaluator::RuntimeError;
+pub use interpreter::{
+    parse_expression_source, parse_source, parse_source_with_max_depth, DeterministicHost, Host,
+    Interpreter, LoxError, ScriptResult, SystemHost,
+};
+pub use named_source::NamedSource;
+pub use parser::ParseError;
+pub use values::Value;
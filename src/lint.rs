@@ -0,0 +1,259 @@
+use crate::const_enum;
+use crate::parser::{Ast, AstNode, BinaryOp, NodeId};
+use crate::span::Span;
+use crate::values::Value;
+use crate::visit::{walk, Visitor};
+
+// Each rule is named as it's used with --allow and --deny
+const_enum! {
+    pub Rule as &str {
+        UnusedVariable => "unused-variable",
+        Shadowing => "shadowing",
+        UnreachableCode => "unreachable-code",
+        EmptyBlock => "empty-block",
+        SelfComparison => "self-comparison",
+        NoEffect => "no-effect",
+        ImplicitNil => "implicit-nil",
+    }
+}
+
+const COMPARISONS: [BinaryOp; 7] = [
+    BinaryOp::Equal,
+    BinaryOp::NotEqual,
+    BinaryOp::Is,
+    BinaryOp::Less,
+    BinaryOp::LessEqual,
+    BinaryOp::Greater,
+    BinaryOp::GreaterEqual,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub rule: Rule,
+    pub span: Span,
+    pub message: String,
+}
+
+/// Walk a program and collect warnings for every rule, in the order they're found
+pub fn lint(ast: &Ast) -> Vec<Warning> {
+    let mut linter = Linter {
+        ast,
+        scopes: vec![vec![]],
+        warnings: vec![],
+    };
+
+    linter.node(ast.root());
+    linter.warnings
+}
+
+// A declared variable and whether anything has read it yet
+struct Binding {
+    name: String,
+    span: Span,
+    used: bool,
+}
+
+struct Linter<'a> {
+    ast: &'a Ast,
+    scopes: Vec<Vec<Binding>>,
+    warnings: Vec<Warning>,
+}
+
+impl Linter<'_> {
+    fn warn(&mut self, rule: Rule, span: Span, message: String) {
+        self.warnings.push(Warning {
+            rule,
+            span,
+            message,
+        });
+    }
+
+    // Only the nodes rules look at are here, the rest are walked through
+    fn node(&mut self, id: NodeId) {
+        let ast = self.ast;
+        match &ast[id] {
+            AstNode::Symbol(_, name) => {
+                let binding = self
+                    .scopes
+                    .iter_mut()
+                    .rev()
+                    .flat_map(|scope| scope.iter_mut().rev())
+                    .find(|binding| *binding.name == **name);
+
+                if let Some(binding) = binding {
+                    binding.used = true;
+                }
+            }
+
+            AstNode::Program(_, nodes) => self.statements(nodes),
+
+            AstNode::Block(span, nodes) => {
+                if nodes.is_empty() {
+                    self.warn(Rule::EmptyBlock, *span, "Empty block".to_string());
+                }
+
+                self.scoped(|linter| linter.statements(nodes));
+            }
+
+            AstNode::Binary(span, op, lhs, rhs) => {
+                if COMPARISONS.contains(op)
+                    && is_simple(ast, *lhs)
+                    && ast.display(*lhs).to_string() == ast.display(*rhs).to_string()
+                {
+                    self.warn(
+                        Rule::SelfComparison,
+                        *span,
+                        format!("Comparing '{}' with itself", ast.display(*lhs)),
+                    );
+                }
+
+                walk(self, ast, id);
+            }
+
+            AstNode::Declaration(span, name, value) => {
+                // Without an initializer the parser makes a nil with the declaration's own span
+                if matches!(&ast[*value], AstNode::Literal(nil_span, Value::Nil) if nil_span == span)
+                {
+                    self.warn(
+                        Rule::ImplicitNil,
+                        *span,
+                        format!("Variable '{}' is implicitly initialized to nil", name),
+                    );
+                }
+
+                self.node(*value);
+                self.declare(name, *span);
+            }
+
+            AstNode::ForIn(span, name, iterable, body) => {
+                self.node(*iterable);
+                self.scoped(|linter| {
+                    linter.declare(name, *span);
+                    linter.node(*body);
+                });
+            }
+
+            AstNode::Try(span, body, catch, finally) => {
+                self.node(*body);
+
+                if let Some((name, handler)) = catch {
+                    self.scoped(|linter| {
+                        linter.declare(name, *span);
+                        linter.node(*handler);
+                    });
+                }
+
+                if let Some(finally) = finally {
+                    self.node(*finally);
+                }
+            }
+
+            // Everything else only has its children checked. Groups are expressions, so only
+            // statements can have no effect or be unreachable, and assigning to a variable
+            // doesn't count as using it.
+            _ => walk(self, ast, id),
+        }
+    }
+
+    // Anything after a statement that always throws can never run
+    fn statements(&mut self, nodes: &[NodeId]) {
+        let mut unreachable_reported = false;
+        let mut exited = false;
+
+        for &node in nodes {
+            if exited && !unreachable_reported {
+                self.warn(
+                    Rule::UnreachableCode,
+                    self.ast[node].span(),
+                    "Unreachable code".to_string(),
+                );
+                unreachable_reported = true;
+            }
+
+            if has_no_effect(self.ast, node) {
+                self.warn(
+                    Rule::NoEffect,
+                    self.ast[node].span(),
+                    "Statement has no effect".to_string(),
+                );
+            }
+
+            self.node(node);
+            exited = exited || matches!(self.ast[node], AstNode::Throw(_, _));
+        }
+    }
+
+    fn declare(&mut self, name: &str, span: Span) {
+        // Only inner scopes can shadow, redeclaring in the same scope replaces the variable
+        let shadows = self.scopes[..self.scopes.len() - 1]
+            .iter()
+            .any(|scope| scope.iter().any(|binding| binding.name == name));
+
+        if shadows {
+            self.warn(
+                Rule::Shadowing,
+                span,
+                format!("Variable '{}' shadows an outer variable", name),
+            );
+        }
+
+        self.scopes.last_mut().unwrap().push(Binding {
+            name: name.to_string(),
+            span,
+            used: false,
+        });
+    }
+
+    fn scoped(&mut self, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(vec![]);
+        f(self);
+
+        // Globals might be used by another file, so only locals are checked
+        // Names starting with _ are intentionally unused
+        let scope = self.scopes.pop().unwrap();
+        for binding in scope {
+            if !binding.used && !binding.name.starts_with('_') {
+                self.warn(
+                    Rule::UnusedVariable,
+                    binding.span,
+                    format!("Variable '{}' is never used", binding.name),
+                );
+            }
+        }
+    }
+}
+
+impl Visitor for Linter<'_> {
+    fn visit(&mut self, _ast: &Ast, id: NodeId) {
+        self.node(id);
+    }
+}
+
+// Expressions that always evaluate to the same thing, so comparing two copies is pointless
+fn is_simple(ast: &Ast, id: NodeId) -> bool {
+    match &ast[id] {
+        AstNode::Literal(_, _) | AstNode::Symbol(_, _) => true,
+        AstNode::Group(_, expression) => is_simple(ast, *expression),
+        AstNode::Get(_, object, _) => is_simple(ast, *object),
+        AstNode::Index(_, object, index) => is_simple(ast, *object) && is_simple(ast, *index),
+        _ => false,
+    }
+}
+
+// An expression statement that can't do anything, only operators on things that are already there
+// Builtins are calls, so even len(x) might have been meant to do something
+fn has_no_effect(ast: &Ast, id: NodeId) -> bool {
+    match &ast[id] {
+        AstNode::Literal(_, _) | AstNode::Symbol(_, _) => true,
+        AstNode::Group(_, expression) => has_no_effect(ast, *expression),
+        AstNode::Get(_, object, _) | AstNode::OptionalGet(_, object, _) => {
+            has_no_effect(ast, *object)
+        }
+        AstNode::Index(_, lhs, rhs)
+        | AstNode::Coalesce(_, lhs, rhs)
+        | AstNode::Binary(_, _, lhs, rhs)
+        | AstNode::Logical(_, _, lhs, rhs) => has_no_effect(ast, *lhs) && has_no_effect(ast, *rhs),
+        AstNode::Unary(_, _, operand) => has_no_effect(ast, *operand),
+        _ => false,
+    }
+}
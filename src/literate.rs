@@ -0,0 +1,160 @@
+use std::ops::Range;
+
+use crate::named_source::NamedSource;
+
+// What weave marks the blocks it adds with, so weaving again replaces them
+const OUTPUT_INFO: &str = "output";
+
+/// A fenced ```lox block in a Markdown document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlock {
+    pub code: String,
+
+    /// The line in the document the code starts on, after the opening fence
+    pub line: usize,
+
+    // From the end of the closing fence to the end of the output block after it, if there is one
+    after: Range<usize>,
+}
+
+impl CodeBlock {
+    /// The code as a source of its own, with blank lines before it so that errors have the
+    /// document's line numbers
+    pub fn source(&self, name: &str) -> NamedSource {
+        NamedSource::new(name.to_string(), "\n".repeat(self.line - 1) + &self.code)
+    }
+}
+
+/// Every ```lox (or ~~~lox) block in a Markdown document, in order
+///
+/// Other fenced blocks are skipped, and an unclosed fence runs to the end of the document like it
+/// does in CommonMark.
+pub fn code_blocks(markdown: &str) -> Vec<CodeBlock> {
+    let lines = markdown
+        .split_inclusive('\n')
+        .scan(0, |offset, line| {
+            let start = *offset;
+            *offset += line.len();
+            Some((start, line))
+        })
+        .collect::<Vec<_>>();
+    let offset = |index: usize| lines.get(index).map_or(markdown.len(), |(start, _)| *start);
+
+    let mut blocks = vec![];
+    let mut index = 0;
+    while index < lines.len() {
+        let Some(fenced) = fenced_at(&lines, index) else {
+            index += 1;
+            continue;
+        };
+        index = fenced.next;
+        if fenced.info != "lox" {
+            continue;
+        }
+
+        // An output block from an earlier weave, maybe after a blank line
+        let mut output = index;
+        if lines
+            .get(output)
+            .is_some_and(|(_, line)| line.trim().is_empty())
+        {
+            output += 1;
+        }
+        let after = match fenced_at(&lines, output) {
+            Some(previous) if previous.info == OUTPUT_INFO => {
+                index = previous.next;
+                offset(fenced.next)..offset(previous.next)
+            }
+            _ => offset(fenced.next)..offset(fenced.next),
+        };
+
+        blocks.push(CodeBlock {
+            code: lines[fenced.body.clone()]
+                .iter()
+                .map(|(_, line)| *line)
+                .collect(),
+            line: fenced.body.start + 1,
+            after,
+        });
+    }
+
+    blocks
+}
+
+/// The document with what each block printed in an ```output block after it
+///
+/// Output blocks from an earlier weave are replaced, so weaving a woven document again only
+/// changes what's changed. Blocks that don't print anything don't get one.
+pub fn weave(markdown: &str, blocks: &[CodeBlock], outputs: &[String]) -> String {
+    let mut woven = String::new();
+    let mut last = 0;
+
+    for (block, output) in blocks.iter().zip(outputs) {
+        woven.push_str(&markdown[last..block.after.start]);
+        last = block.after.end;
+        if output.is_empty() {
+            continue;
+        }
+
+        // The closing fence might be the end of the document, without a newline
+        if !woven.ends_with('\n') {
+            woven.push('\n');
+        }
+
+        // Longer than any run of backticks in the output, so it can't close the block early
+        let longest = output.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+        let fence = "`".repeat(longest.max(2) + 1);
+
+        woven.push_str(&format!("\n{fence}{OUTPUT_INFO}\n{output}"));
+        if !output.ends_with('\n') {
+            woven.push('\n');
+        }
+        woven.push_str(&format!("{fence}\n"));
+    }
+
+    woven.push_str(&markdown[last..]);
+    woven
+}
+
+// A fenced block starting on a line, with its lines by index
+struct Fenced<'a> {
+    info: &'a str,
+    body: Range<usize>,
+    next: usize, // The line after the closing fence
+}
+
+// None if the line doesn't open a fence (or there isn't one)
+fn fenced_at<'a>(lines: &[(usize, &'a str)], index: usize) -> Option<Fenced<'a>> {
+    let (_, line) = lines.get(index)?;
+    let (fence, info) = opening_fence(line)?;
+
+    let start = index + 1;
+    let end = (start..lines.len())
+        .find(|i| closes(lines[*i].1, fence))
+        .unwrap_or(lines.len());
+
+    Some(Fenced {
+        info,
+        body: start..end,
+        next: (end + 1).min(lines.len()),
+    })
+}
+
+// Three or more backticks or tildes, and the first word after them
+fn opening_fence(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_start();
+    let marker = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let length = line.chars().take_while(|c| *c == marker).count();
+    if length < 3 {
+        return None;
+    }
+
+    let (fence, info) = line.split_at(length);
+    Some((fence, info.split_whitespace().next().unwrap_or("")))
+}
+
+// At least as many of the same character as opened it, and nothing else
+fn closes(line: &str, fence: &str) -> bool {
+    let line = line.trim();
+    line.len() >= fence.len() && line.chars().all(|c| fence.starts_with(c))
+}
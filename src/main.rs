@@ -1,150 +1,1139 @@
-use std::io::Read;
+use std::io::{BufReader, IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::Result;
-use clap::{Parser as ClapParser, Subcommand};
+use anyhow::{anyhow, Result};
+use clap::error::{ContextKind, ContextValue, ErrorKind};
+use clap::{Parser as ClapParser, Subcommand, ValueEnum};
 use clap_stdin::FileOrStdin;
-use env_logger;
-
-mod builtins;
-mod const_enum;
-mod environment;
-mod evaluator;
-mod named_source;
-mod parser;
-mod span;
-mod tokenizer;
-mod values;
-
-use environment::EnvironmentStack;
-use evaluator::Evaluate;
-use named_source::NamedSource;
-use parser::Parser;
-use tokenizer::Tokenizer;
+
+use codecrafters_interpreter::builtins::BuiltinSet;
+use codecrafters_interpreter::context::{Context, Limits, OutputBuffer, DEFAULT_MAX_DEPTH};
+use codecrafters_interpreter::coverage::Coverage;
+use codecrafters_interpreter::debugger::{self, Debugger};
+use codecrafters_interpreter::diagnostic::{DiagnosticSink, Level};
+use codecrafters_interpreter::environment::{Environment, EnvironmentStack};
+use codecrafters_interpreter::evaluator::{EvalOutcome, Evaluate};
+use codecrafters_interpreter::hook::Tracer;
+use codecrafters_interpreter::interpreter::{
+    parse_expression_source, parse_source_with_max_depth, DeterministicHost, Host, SystemHost,
+};
+use codecrafters_interpreter::module::ModulePath;
+use codecrafters_interpreter::named_source::NamedSource;
+use codecrafters_interpreter::parser::{Ast, Parser};
+use codecrafters_interpreter::phase::{self, Timed};
+use codecrafters_interpreter::profile::Profile;
+use codecrafters_interpreter::stack;
+use codecrafters_interpreter::stdlib;
+use codecrafters_interpreter::tokenizer::Tokenizer;
+use codecrafters_interpreter::trivia::Trivia;
+use codecrafters_interpreter::values::{PrintStyle, Value};
+use codecrafters_interpreter::{
+    ast_output, bench, compile, diagnostic, doc, explain, formatter, highlight, json, lint,
+    literate, repl, resolver, symbols, test_runner, transpile, wat, watch,
+};
 
 /// Implementation of the lox programming language for code crafters
 #[derive(Debug, ClapParser)]
 #[clap(name = "jp-lox", version)]
 pub struct Args {
-    /// Debug mode
-    #[clap(short, long)]
-    debug: bool,
+    /// Log what the interpreter is doing to stderr, with how long each phase took
+    ///
+    /// Give phases to only log those, eg --debug=parser,evaluator::call. They're tokenizer, parser,
+    /// resolver, and evaluator, which includes evaluator::call.
+    #[clap(
+        short,
+        long,
+        value_name = "PHASES",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ""
+    )]
+    debug: Option<String>,
+
+    /// Allow `+` to concatenate strings with numbers (eg "a" + 1)
+    #[clap(long, global = true)]
+    lenient_concat: bool,
+
+    /// Allow namespaced builtins by their names alone (eg sqrt for Math.sqrt), like they used to be
+    #[clap(long, global = true)]
+    compat: bool,
+
+    /// Give clock() and random() the same answers every run, for tests
+    #[clap(long, global = true)]
+    deterministic: bool,
+
+    /// Seed random() with this instead of 0, with --deterministic
+    #[clap(long, global = true, requires = "deterministic", default_value_t = 0)]
+    seed: u64,
+
+    /// Don't load the standard library, so only the builtins are defined (like CodeCrafters)
+    #[clap(long, global = true)]
+    no_std: bool,
+
+    /// Look for imported modules in this directory too, before the ones in LOX_PATH
+    #[clap(long = "include", short = 'I', global = true, value_name = "DIR")]
+    include: Vec<PathBuf>,
+
+    /// Allow scripts to read and write environment variables
+    #[clap(long, global = true)]
+    allow_env: bool,
+
+    /// Don't allow scripts to use builtins that touch the environment or the clock
+    #[clap(long, global = true)]
+    sandbox: bool,
+
+    /// Print every node to stderr as it's evaluated, with its line and value
+    #[clap(long, global = true)]
+    trace: bool,
+
+    /// Print errors on one line each, instead of with source when stderr is a terminal
+    #[clap(long, global = true)]
+    plain: bool,
+
+    /// Report lint warnings for every rule to stderr when checking or running
+    #[clap(long, global = true)]
+    warn: bool,
+
+    /// Report warnings for this lint rule when checking or running (eg -W shadowing)
+    #[clap(short = 'W', long = "warning", value_name = "RULE", global = true)]
+    warnings: Vec<String>,
+
+    /// Don't report warnings for this lint rule
+    #[clap(long, value_name = "RULE", global = true)]
+    allow: Vec<String>,
+
+    /// Fail on warnings for this lint rule, or every warning reported with --deny warnings
+    #[clap(long, value_name = "RULE", global = true)]
+    deny: Vec<String>,
+
+    /// Let dividing by zero give inf or NaN, instead of being an error
+    #[clap(long, global = true)]
+    ieee_math: bool,
+
+    /// How print and evaluate show numbers, lox has no .0 on whole numbers
+    #[clap(long, global = true, value_enum, default_value_t = PrintStyle::Lox)]
+    print_style: PrintStyle,
+
+    /// Report every static error, from every input, before running anything
+    ///
+    /// Otherwise running stops at the first input that doesn't parse. Either way nothing writes
+    /// to stdout until the errors that stop a program from starting have all been printed.
+    #[clap(long, global = true)]
+    strict_order: bool,
+
+    /// How to print errors to stderr
+    #[clap(long, global = true, value_enum, default_value_t = ErrorFormat::Human)]
+    error_format: ErrorFormat,
+
+    /// How deeply expressions and blocks can nest, parsing or running, before it's an error
+    #[clap(long, global = true, default_value_t = DEFAULT_MAX_DEPTH)]
+    max_depth: usize,
+
+    /// Stop running after this many seconds (eg 2.5)
+    #[clap(long, global = true, value_parser = parse_seconds)]
+    timeout: Option<Duration>,
+
+    /// Stop running after evaluating this many AST nodes
+    #[clap(long, global = true)]
+    max_steps: Option<usize>,
+
+    /// How deeply calls can nest while running, including builtins like map calling back
+    #[clap(long, global = true)]
+    max_call_depth: Option<usize>,
 
-    /// Subcommand to run
+    /// Subcommand to run, run if it's left out
     #[clap(subcommand)]
     command: Command,
+}
+
+impl Args {
+    fn limits(&self) -> Limits {
+        Limits {
+            max_steps: self.max_steps,
+            max_duration: self.timeout,
+            max_call_depth: self.max_call_depth,
+        }
+    }
+
+    fn error_style(&self) -> ErrorStyle {
+        ErrorStyle {
+            format: self.error_format,
+            plain: self.plain,
+        }
+    }
+
+    // Warning levels from the command line, over the default for every rule
+    fn diagnostics(&self, default: Level) -> Result<DiagnosticSink> {
+        let default = if self.warn { Level::Warn } else { default };
+        let mut sink = DiagnosticSink::new(default);
+
+        let rule = |name: &String| {
+            lint::Rule::try_from(name.as_str()).map_err(|_| anyhow!("Unknown lint rule: {}", name))
+        };
+        for name in &self.warnings {
+            sink.set(rule(name)?, Level::Warn);
+        }
+        for name in &self.allow {
+            sink.set(rule(name)?, Level::Allow);
+        }
+        for name in &self.deny {
+            if name == "warnings" {
+                sink.deny_warnings();
+            } else {
+                sink.set(rule(name)?, Level::Deny);
+            }
+        }
+
+        Ok(sink)
+    }
+
+    fn tracer(&self) -> Option<Tracer> {
+        self.trace.then(|| Tracer::new(std::io::stderr()))
+    }
+
+    fn host(&self) -> Box<dyn Host> {
+        if self.deterministic {
+            Box::new(DeterministicHost::new(self.seed))
+        } else {
+            Box::new(SystemHost::default())
+        }
+    }
 
-    /// The input file (or - for stdin)
-    #[arg(global=true)]
-    input: Option<FileOrStdin>,
+    /// A context with everything the flags set, the same for every subcommand that runs code
+    fn context(&self) -> Context {
+        Context {
+            lenient_concat: self.lenient_concat,
+            compat: self.compat,
+            print_style: self.print_style,
+            ieee_math: self.ieee_math,
+            allow_env: self.allow_env,
+            max_depth: self.max_depth,
+            limits: self.limits(),
+            builtins: self.builtins(),
+            tracer: self.tracer(),
+            host: self.host(),
+            module_path: self.module_path(),
+            ..Default::default()
+        }
+    }
+
+    fn module_path(&self) -> ModulePath {
+        ModulePath::new(self.include.clone()).with_env()
+    }
+
+    fn builtins(&self) -> BuiltinSet {
+        if self.sandbox {
+            BuiltinSet::sandbox()
+        } else {
+            BuiltinSet::all()
+        }
+    }
+}
+
+impl Args {
+    /// Parse the command line, where run is the subcommand if there isn't one
+    ///
+    /// A file in place of the subcommand is run, which is how a script with a #! line is run, as
+    /// `jp-lox script.lox ARGS...`, so anything after the script is passed to it as ARGS. With
+    /// nothing at all, source piped to stdin is run; at a terminal that's the help instead.
+    fn parse_or_run() -> Self {
+        let mut argv = std::env::args_os().collect::<Vec<_>>();
+        let error = match Args::try_parse_from(&argv) {
+            Ok(args) => return args,
+            Err(error) if error.kind() == ErrorKind::InvalidSubcommand => error,
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    ErrorKind::MissingSubcommand
+                        | ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand
+                ) && !std::io::stdin().is_terminal() =>
+            {
+                // Options before it are global, so they still apply after
+                argv.push("run".into());
+                return Args::parse_from(argv);
+            }
+            Err(error) => error.exit(),
+        };
+
+        // Only for files (or - for stdin), so a mistyped subcommand is still an error about that
+        let script = match error.get(ContextKind::InvalidSubcommand) {
+            Some(ContextValue::String(script))
+                if script == "-" || std::path::Path::new(script).is_file() =>
+            {
+                script
+            }
+            _ => error.exit(),
+        };
+        let Some(at) = argv.iter().position(|arg| arg == script.as_str()) else {
+            error.exit();
+        };
+
+        argv.insert(at, "run".into());
+        if at + 2 < argv.len() && !argv[at + 2..].iter().any(|arg| arg == "--") {
+            argv.insert(at + 2, "--".into());
+        }
+        Args::parse_from(argv)
+    }
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let seconds = s.parse::<f64>().map_err(|e| e.to_string())?;
+    Duration::try_from_secs_f64(seconds).map_err(|e| e.to_string())
+}
+
+/// Where to read source code from, shared by every subcommand that reads source
+#[derive(Debug, Clone, clap::Args)]
+struct Input {
+    /// The input files (or - for stdin), run in order as one program
+    inputs: Vec<FileOrStdin>,
+
+    /// Use the given source code instead of an input file
+    #[arg(short, long, conflicts_with = "inputs")]
+    eval: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum TokenFormat {
+    /// KIND lexeme literal, eg NUMBER 1.5 1.5
+    CodeCrafters,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ErrorFormat {
+    /// With the source and a caret where it happened at a terminal, otherwise a line each
+    Human,
+    /// One JSON object per line, with severity, code, message, file, line, column, and span
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum AstFormat {
+    /// Lisp style, eg (+ 1 2)
+    Sexp,
+    /// One JSON object per program
+    Json,
+    /// An indented tree with spans
+    Tree,
+    /// A Graphviz digraph
+    Dot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SymbolFormat {
+    /// A line each, indented by how deeply they're nested, eg variable x file.lox:1:1
+    Outline,
+    /// One JSON object per line, with file, kind, name, span, and scope
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Target {
+    /// JavaScript for node
+    Js,
+    /// WebAssembly text, for numbers and bools only
+    Wat,
 }
 
 #[derive(Debug, Subcommand)]
 enum Command {
     /// Tokenize and print all tokens.
-    Tokenize,
+    Tokenize {
+        #[command(flatten)]
+        input: Input,
+
+        /// How to print each token
+        #[arg(long, value_enum, default_value_t = TokenFormat::CodeCrafters)]
+        format: TokenFormat,
+    },
     /// Parse and print the AST.
-    Parse,
-    /// Evaluate the source expression.
-    Evaluate,
+    Parse {
+        #[command(flatten)]
+        input: Input,
+
+        /// How to print the AST
+        #[arg(long, value_enum, default_value_t = AstFormat::Sexp)]
+        format: AstFormat,
+
+        /// Stop reporting syntax errors after this many
+        #[arg(long)]
+        max_errors: Option<usize>,
+    },
+    /// Evaluate the source expression, a single one with no trailing semicolon.
+    Evaluate {
+        #[command(flatten)]
+        input: Input,
+    },
     /// Run the source program.
-    Run,
+    Run {
+        #[command(flatten)]
+        input: Input,
+
+        /// Re-run whenever an input file, or a module one of them imports, changes
+        #[arg(short, long)]
+        watch: bool,
+
+        /// The inputs are Markdown, run each ```lox block in them in order as one program
+        #[arg(long, conflicts_with = "watch")]
+        literate: bool,
+
+        /// Print the Markdown back with what each block printed after it, instead of printing it
+        #[arg(long, requires = "literate")]
+        weave: bool,
+
+        /// Print the lines, functions, and nodes that took the most time to stderr
+        #[arg(long)]
+        profile: bool,
+
+        /// Write everything the profile found to this file as JSON
+        #[arg(long, value_name = "FILE")]
+        profile_json: Option<PathBuf>,
+
+        /// Print how many lines ran, then the source with how often each line ran, to stderr
+        #[arg(long)]
+        coverage: bool,
+
+        /// Write which lines ran to this file in LCOV format
+        #[arg(long, value_name = "FILE")]
+        lcov: Option<PathBuf>,
+
+        /// Print how many steps ran and how many bytes building strings copied to stderr
+        ///
+        /// Each + copies both of its strings, so `s = s + piece` in a loop copies all of s every
+        /// time and gets slower the longer s is. Appending to a StringBuilder() only copies the
+        /// piece, which is the difference these show.
+        #[arg(long)]
+        stats: bool,
+
+        /// Arguments passed to the program as ARGS (after --)
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Run the source program, stopping to step through it and look at variables.
+    Debug {
+        #[command(flatten)]
+        input: Input,
+
+        /// Stop before statements on this line, can be given more than once
+        #[arg(long = "break", value_name = "LINE")]
+        breakpoints: Vec<usize>,
+
+        /// Arguments passed to the program as ARGS (after --)
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Print the source formatted canonically.
+    Fmt {
+        #[command(flatten)]
+        input: Input,
+
+        /// Don't print anything, exit with an error if formatting would change the source
+        #[arg(long)]
+        check: bool,
+    },
+    /// Report likely mistakes without running the program.
+    Lint {
+        #[command(flatten)]
+        input: Input,
+    },
+    /// Report all syntax and resolution errors without running the program.
+    Check {
+        #[command(flatten)]
+        input: Input,
+
+        /// Stop reporting errors after this many
+        #[arg(long)]
+        max_errors: Option<usize>,
+    },
+    /// Print the source with syntax highlighting.
+    Highlight {
+        #[command(flatten)]
+        input: Input,
+
+        /// Write HTML with a CSS class per kind of token instead of terminal colors
+        #[arg(long)]
+        html: bool,
+    },
+    /// Print Markdown documentation for top level declarations from their `///` comments.
+    Doc {
+        #[command(flatten)]
+        input: Input,
+
+        /// Write HTML instead of Markdown
+        #[arg(long)]
+        html: bool,
+    },
+    /// List every declaration, with its kind, where it is, and the scope it's in.
+    Symbols {
+        #[command(flatten)]
+        input: Input,
+
+        /// How to print them
+        #[arg(long, value_enum, default_value_t = SymbolFormat::Outline)]
+        format: SymbolFormat,
+    },
+    /// Describe an error code (like E0001) with examples, or list every code.
+    Explain {
+        /// The code from an error, eg E0001
+        code: Option<String>,
+    },
+    /// Run test files, checking output against `// expect: ...` comments.
+    Test {
+        /// Test files, or directories to search for .lox files
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Time repeated runs of the program, without its output.
+    Bench {
+        #[command(flatten)]
+        input: Input,
+
+        /// How many runs to time
+        #[arg(long, default_value_t = 10)]
+        iterations: usize,
+
+        /// How many runs to do first without timing them
+        #[arg(long, default_value_t = 1)]
+        warmup: usize,
+    },
+    /// Parse the source and save it to run later with exec.
+    Compile {
+        #[command(flatten)]
+        input: Input,
+
+        /// Where to write the compiled program
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Translate the source into another language and print it.
+    Transpile {
+        #[command(flatten)]
+        input: Input,
+
+        /// The language to translate into
+        #[arg(long, value_enum, default_value_t = Target::Js)]
+        target: Target,
+    },
+    /// Run a program saved by compile.
+    ///
+    /// A file compile didn't save, or that a different version of it did, is a static error (exit
+    /// code 65) and nothing runs.
+    Exec {
+        /// The compiled program
+        file: PathBuf,
+
+        /// Arguments passed to the program as ARGS (after --)
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Start an interactive session.
+    Repl,
 }
 
+// Parsing and evaluating recurse once per level of nesting, so everything runs with room for
+// max_depth levels
 fn main() -> Result<()> {
-    let args = Args::parse();
-    if args.debug {
-        env_logger::Builder::new()
+    let args = Args::parse_or_run();
+    match stack::with_stack(args.max_depth, || cli(args)) {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(70);
+        }
+    }
+}
+
+fn cli(args: Args) -> Result<()> {
+    match &args.debug {
+        Some(phases) if phases.is_empty() => env_logger::Builder::new()
             .filter_level(log::LevelFilter::Debug)
-            .init();
-    } else {
-        env_logger::init();
+            .init(),
+        Some(phases) => env_logger::Builder::new().parse_filters(phases).init(),
+        None => env_logger::init(),
+    }
+
+    // ----- Explaining errors (no source at all) -----
+
+    if let Command::Explain { code } = &args.command {
+        let Some(code) = code else {
+            for explanation in explain::EXPLANATIONS {
+                println!("{}: {}", explanation.code, explanation.title);
+            }
+            return Ok(());
+        };
+
+        let explanation =
+            explain::explain(code).ok_or_else(|| anyhow!("Unknown error code: {}", code))?;
+        println!("# {}: {}\n\n{}", explanation.code, explanation.title, explanation.description);
+        return Ok(());
+    }
+
+    // ----- Interactive mode (doesn't read input up front) -----
+
+    if let Command::Repl = args.command {
+        return repl::run(global_env(!args.no_std), args.context());
+    }
+
+    // ----- Test runner (runs each test in a new process) -----
+
+    if let Command::Test { paths } = &args.command {
+        if !test_runner::run(paths)? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // ----- Running compiled programs (no source to parse) -----
+
+    if let Command::Exec {
+        file,
+        args: script_args,
+    } = &args.command
+    {
+        let loaded = std::fs::read(file).map_err(anyhow::Error::from);
+        let programs = match loaded.and_then(|bytes| compile::load(&bytes)) {
+            Ok(programs) => programs,
+            Err(e) => {
+                report(args.error_style(), None, &anyhow!("{}: {}", file.display(), e));
+                std::process::exit(65);
+            }
+        };
+
+        let mut env = script_env(script_args, !args.no_std);
+        let mut ctx = args.context();
+
+        if let Err(e) = run_programs(&programs, &mut env, &mut ctx) {
+            report_runtime(args.error_style(), &mut ctx, &e);
+            std::process::exit(70);
+        }
+        return Ok(());
+    }
+
+    // ----- Watch mode (re-reads input on every change) -----
+
+    if let Command::Run {
+        input,
+        watch: true,
+        args: script_args,
+        ..
+    } = &args.command
+    {
+        let paths = input
+            .inputs
+            .iter()
+            .map(|input| {
+                if input.is_file() {
+                    Ok(PathBuf::from(input.filename()))
+                } else {
+                    Err(anyhow!("--watch requires input files, not stdin"))
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if paths.is_empty() {
+            return Err(anyhow!("--watch requires at least one input file"));
+        }
+
+        return watch::watch(&paths, || {
+            // Unlike a normal run, errors are reported and then we wait for the next change
+            let mut ctx = args.context();
+            if let Err(errors) = run_files(&paths, script_args, !args.no_std, &mut ctx) {
+                for error in errors {
+                    eprintln!("{}", error);
+                }
+            }
+            ctx.imported
+        });
+    }
+
+    // ----- Tokenizing (streamed, so inputs don't have to fit in memory) -----
+
+    if let Command::Tokenize { input, format } = &args.command {
+        let tokenizers = if let Some(code) = &input.eval {
+            vec![Tokenizer::new(code)]
+        } else if input.inputs.is_empty() {
+            vec![Tokenizer::from_reader(std::io::stdin().lock())]
+        } else {
+            input
+                .inputs
+                .iter()
+                .map(|input| {
+                    let reader = BufReader::new(input.clone().into_reader()?);
+                    Ok(Tokenizer::from_reader(reader))
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        for mut tokenizer in tokenizers {
+            let _timed = Timed::start(phase::TOKENIZER, "Tokenizing");
+            for token in &mut tokenizer {
+                match format {
+                    TokenFormat::CodeCrafters => println!("{}", token.code_crafters_format()),
+                    TokenFormat::Json => println!("{}", json::Json::from(&token)),
+                }
+            }
+
+            if tokenizer.had_errors() {
+                for error in tokenizer.iter_errors() {
+                    eprintln!("{}", error);
+                }
+                std::process::exit(65);
+            }
+        }
+
+        return Ok(());
     }
 
     // ----- Shared filename / contents loading -----
 
-    let source = if args.input.is_none() {
+    let input = match &args.command {
+        Command::Parse { input, .. }
+        | Command::Evaluate { input }
+        | Command::Run { input, .. }
+        | Command::Debug { input, .. }
+        | Command::Fmt { input, .. }
+        | Command::Lint { input, .. }
+        | Command::Check { input, .. }
+        | Command::Highlight { input, .. }
+        | Command::Doc { input, .. }
+        | Command::Symbols { input, .. }
+        | Command::Bench { input, .. }
+        | Command::Compile { input, .. }
+        | Command::Transpile { input, .. } => input.clone(),
+        Command::Tokenize { .. }
+        | Command::Repl
+        | Command::Test { .. }
+        | Command::Exec { .. }
+        | Command::Explain { .. } => {
+            unreachable!("tokenize, repl, test, exec, and explain don't read source up front")
+        }
+    };
+
+    let mut sources = vec![];
+    if let Some(code) = input.eval {
+        sources.push(NamedSource::new("<eval>".to_string(), code));
+    } else if !input.inputs.is_empty() {
+        for input in input.inputs {
+            let name = if input.is_file() {
+                input.filename().to_string()
+            } else {
+                "<stdin>".to_string()
+            };
+            let contents = input.contents()?;
+            sources.push(NamedSource::new(name, contents));
+        }
+    } else {
         let name = "<stdin>".to_string();
         let mut contents = String::new();
         std::io::stdin().read_to_string(&mut contents)?;
-        NamedSource::new(name, contents)
-    } else {
-        let input = args.input.unwrap();
-        let name = if input.is_file() {
-            input.filename().to_string()
-        } else {
-            "<stdin>".to_string()
-        };
-        let contents = input.contents()?;
-        NamedSource::new(name, contents)
+        sources.push(NamedSource::new(name, contents));
+    }
+
+    // ----- Literate Markdown -----
+
+    // Each block is a source of its own, so errors point at the line in the document
+    let documents = match args.command {
+        Command::Run { literate: true, .. } => {
+            let documents = sources
+                .iter()
+                .map(|source| (source.clone(), literate::code_blocks(&source.bytes)))
+                .collect::<Vec<_>>();
+            sources = documents
+                .iter()
+                .flat_map(|(document, blocks)| {
+                    blocks.iter().map(|block| block.source(&document.name))
+                })
+                .collect();
+            documents
+        }
+        _ => vec![],
     };
 
-    // ----- Tokenizing -----
+    // ----- Highlighting -----
+
+    if let Command::Highlight { html, .. } = args.command {
+        for source in &sources {
+            if html {
+                print!("{}", highlight::html(&source.bytes));
+            } else {
+                println!("{}", highlight::ansi(&source.bytes));
+            }
+        }
+        return Ok(());
+    }
 
-    log::debug!("Tokenizing...");
-    let mut tokenizer = Tokenizer::new(&source.bytes);
+    // ----- Checking -----
 
-    if let Command::Tokenize = args.command {
-        for token in &mut tokenizer {
-            println!("{}", token.code_crafters_format());
+    if let Command::Check { max_errors, .. } = args.command {
+        let mut reporter = ErrorReporter::new(args.error_style(), max_errors);
+        let mut sink = args.diagnostics(Level::Allow)?;
+
+        // Run provides these, so checked programs can use them as well
+        let mut resolver = resolver::Resolver::new().with_compat(args.compat);
+        for name in script_env(&[], !args.no_std).names() {
+            resolver.define_global(&name);
         }
 
-        if tokenizer.had_errors() {
-            for error in tokenizer.iter_errors() {
-                eprintln!("{}", error);
+        for source in &sources {
+            let (mut ast, errors) = parse_all_source(source, args.max_depth);
+            for error in errors {
+                reporter.report(Some(source), &error);
+            }
+
+            // Even with syntax errors, whatever did parse can still be checked
+            resolver.resolve(&mut ast);
+            for error in resolver.take_errors() {
+                reporter.report(Some(source), &error.into());
+            }
+
+            if sink.enabled() {
+                sink.report(&source.name, lint::lint(&ast));
             }
+        }
+
+        report_warnings(args.error_style(), &sink);
+        if reporter.finish() || sink.denied() {
             std::process::exit(65);
         }
+        return Ok(());
+    }
+
+    // ----- Formatting -----
+
+    if let Command::Fmt { check, .. } = args.command {
+        let mut unformatted = false;
+
+        for source in &sources {
+            let (ast, trivia) = parse_with_trivia(source, &args);
+            let formatted = formatter::format(&ast, &trivia);
+
+            if !check {
+                print!("{}", formatted);
+            } else if formatted.trim_end() != source.bytes.trim_end() {
+                // Inputs are read with trailing whitespace trimmed, so that can't be checked
+                eprintln!("{} is not formatted", source.name);
+                unformatted = true;
+            }
+        }
 
+        if unformatted {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // ----- Documentation -----
+
+    if let Command::Doc { html, .. } = args.command {
+        for source in &sources {
+            let (ast, trivia) = parse_with_trivia(source, &args);
+            let items = doc::items(&ast, &trivia);
+
+            if html {
+                print!("{}", doc::html(source, &items));
+            } else {
+                print!("{}", doc::markdown(source, &items));
+            }
+        }
         return Ok(());
     }
 
     // ----- Parsing -----
 
-    log::debug!("Parsing...");
-    let mut parser = Parser::from(tokenizer);
+    // Parse reports every syntax error it can find, not just the first
+    if let Command::Parse { max_errors, .. } = args.command {
+        let mut reporter = ErrorReporter::new(args.error_style(), max_errors);
+        for source in &sources {
+            for error in parse_all_source(source, args.max_depth).1 {
+                reporter.report(Some(source), &error);
+            }
+        }
 
-    let ast = match parser.parse() {
-        Ok(ast) => ast,
-        Err(e) => {
-            eprintln!("{}", e);
+        if reporter.finish() {
             std::process::exit(65);
         }
-    };
+    }
 
-    if parser.tokenizer_had_errors() {
-        for error in parser.tokenizer_iter_errors() {
-            eprintln!("{}", error);
+    // Each file is tokenized and parsed on its own, so line numbers in errors are per file.
+    // Evaluate takes a single expression each, with no ; and no statements.
+    let runs = matches!(
+        args.command,
+        Command::Evaluate { .. } | Command::Run { .. } | Command::Debug { .. }
+    );
+    let mut errors = ErrorBuffer::new(args.error_style());
+    let mut programs = vec![];
+    for source in sources {
+        let parsed = match args.command {
+            Command::Evaluate { .. } => parse_expression_source(&source, args.max_depth),
+            _ => parse_source_with_max_depth(&source, args.max_depth),
+        };
+        match parsed {
+            Ok(ast) => programs.push((source, ast)),
+            Err(parse_errors) => {
+                for error in parse_errors {
+                    errors.push(Some(&source), error);
+                }
+                if !args.strict_order {
+                    break;
+                }
+            }
         }
+    }
+
+    // With --strict-order, a run keeps going to find resolver errors in the files that parsed
+    if !(runs && args.strict_order) && errors.flush() {
         std::process::exit(65);
     }
 
-    if let Command::Parse = args.command {
-        println!("{}", ast);
+    if let Command::Parse { format, .. } = args.command {
+        for (_, ast) in &programs {
+            match format {
+                AstFormat::Sexp => println!("{}", ast),
+                AstFormat::Json => println!("{}", ast_output::json(ast)),
+                AstFormat::Tree => print!("{}", ast_output::tree(ast)),
+                AstFormat::Dot => print!("{}", ast_output::dot(ast)),
+            }
+        }
+        return Ok(());
+    }
+
+    // ----- Compiling -----
+
+    if let Command::Compile { output, .. } = &args.command {
+        std::fs::write(output, compile::compile(&programs))?;
+        return Ok(());
+    }
+
+    // ----- Transpiling -----
+
+    if let Command::Transpile { target, .. } = args.command {
+        match target {
+            // The standard library goes along with it, since there's nothing to load it from
+            Target::Js => {
+                if !args.no_std {
+                    programs.splice(0..0, stdlib::programs());
+                }
+                print!("{}", transpile::javascript(&programs, args.compat))
+            }
+            Target::Wat => match wat::wat(&programs) {
+                Ok(module) => print!("{}", module),
+                Err(errors) => {
+                    for error in errors {
+                        eprintln!("{}", error);
+                    }
+                    std::process::exit(65);
+                }
+            },
+        }
+        return Ok(());
+    }
+
+    // ----- Benchmarking -----
+
+    if let Command::Bench {
+        iterations, warmup, ..
+    } = args.command
+    {
+        let report = bench::bench(iterations, warmup, || {
+            // Each run starts fresh, with no input and output thrown away
+            let mut env = script_env(&[], !args.no_std);
+            let mut ctx = Context {
+                input: Box::new(std::io::empty()),
+                output: Box::new(std::io::sink()),
+                ..args.context()
+            };
+
+            // A run that fails stops the benchmark, reported the same as it would be by run
+            if let Err(e) = run_programs(&programs, &mut env, &mut ctx) {
+                report_runtime(args.error_style(), &mut ctx, &e);
+                std::process::exit(70);
+            }
+            Ok(ctx.steps)
+        })?;
+
+        println!("iterations: {} (warmup {})", report.iterations, warmup);
+        println!("min:        {:?}", report.min);
+        println!("median:     {:?}", report.median);
+        println!("mean:       {:?}", report.mean);
+        println!("nodes:      {} per run", report.steps);
+        return Ok(());
+    }
+
+    // ----- Linting -----
+
+    // Every rule is on, and warnings are the output so they go to stdout
+    if let Command::Lint { .. } = &args.command {
+        let mut sink = args.diagnostics(Level::Warn)?;
+        for (source, ast) in &programs {
+            sink.report(&source.name, lint::lint(ast));
+        }
+
+        for reported in sink.reported() {
+            match args.error_format {
+                ErrorFormat::Human => println!("{}", reported),
+                ErrorFormat::Json => println!("{}", reported.json()),
+            }
+        }
+
+        if sink.denied() {
+            std::process::exit(65);
+        }
+        return Ok(());
+    }
+
+    // ----- Listing symbols -----
+
+    if let Command::Symbols { format, .. } = args.command {
+        for (source, ast) in &programs {
+            let symbols = symbols::symbols(ast);
+            match format {
+                SymbolFormat::Outline => print!("{}", symbols::outline(&source.name, &symbols)),
+                SymbolFormat::Json => {
+                    for symbol in &symbols {
+                        println!("{}", symbols::json(&source.name, symbol));
+                    }
+                }
+            }
+        }
         return Ok(());
     }
 
     // ----- Evaluating -----
 
     match args.command {
-        Command::Evaluate | Command::Run => {
-            let mut env = EnvironmentStack::new();
-            let output = match ast.evaluate(&mut env) {
+        Command::Evaluate { .. } | Command::Run { .. } | Command::Debug { .. } => {
+            let mut env = match &args.command {
+                Command::Run { args: script_args, .. }
+                | Command::Debug { args: script_args, .. } => {
+                    script_env(script_args, !args.no_std)
+                }
+                _ => global_env(!args.no_std),
+            };
+
+            // Only errors that would happen no matter what stop the program before it starts
+            let globals = env.names();
+            for (source, error) in resolve_programs(&mut programs, &globals, args.compat) {
+                errors.push(Some(source), error.into());
+            }
+            if errors.flush() {
+                std::process::exit(65);
+            }
+
+            // Warnings are off unless asked for, then they're like errors that don't stop it
+            let mut sink = args.diagnostics(Level::Allow)?;
+            if sink.enabled() {
+                for (source, ast) in &programs {
+                    sink.report(&source.name, lint::lint(ast));
+                }
+                report_warnings(args.error_style(), &sink);
+                if sink.denied() {
+                    std::process::exit(65);
+                }
+            }
+
+            let (profile, profile_json, coverage, lcov, stats) = match &args.command {
+                Command::Run {
+                    profile,
+                    profile_json,
+                    coverage,
+                    lcov,
+                    stats,
+                    ..
+                } => (*profile, profile_json.clone(), *coverage, lcov.clone(), *stats),
+                _ => (false, None, false, None, false),
+            };
+
+            let mut ctx = Context {
+                profile: (profile || profile_json.is_some()).then(Profile::new),
+                coverage: (coverage || lcov.is_some()).then(Coverage::new),
+                ..args.context()
+            };
+
+            if let Command::Debug { breakpoints, .. } = &args.command {
+                let mut debugger = Debugger::new();
+                for line in breakpoints {
+                    debugger.add_breakpoint(*line);
+                }
+                if !breakpoints.is_empty() {
+                    debugger.continue_to_breakpoint();
+                }
+                ctx.debugger = Some(debugger);
+            }
+
+            // Weaving needs what each block printed on its own
+            let weave = matches!(args.command, Command::Run { weave: true, .. });
+            let mut outputs = None;
+            let result = if weave {
+                let buffer = OutputBuffer::new();
+                ctx.output = Box::new(buffer.clone());
+                run_programs_each(&programs, &mut env, &mut ctx, &buffer).map(|printed| {
+                    outputs = Some(printed);
+                    EvalOutcome::Unit
+                })
+            } else {
+                run_programs(&programs, &mut env, &mut ctx)
+            };
+
+            // Even a run that failed spent its time somewhere
+            if let Some(report) = &ctx.profile {
+                if profile {
+                    eprint!("{}", report.report());
+                }
+                if let Some(path) = profile_json {
+                    std::fs::write(&path, format!("{}\n", report.json()))
+                        .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+                }
+            }
+            if stats {
+                let strings = &ctx.strings;
+                eprintln!("Steps: {}", ctx.steps);
+                eprintln!(
+                    "Strings made with +: {} ({} bytes copied)",
+                    strings.concatenations, strings.concatenated_bytes
+                );
+                eprintln!(
+                    "Appends to string builders: {} ({} bytes copied)",
+                    strings.appends, strings.appended_bytes
+                );
+            }
+            if let Some(report) = &ctx.coverage {
+                if coverage {
+                    eprint!("{}", report.report(&programs));
+                }
+                if let Some(path) = lcov {
+                    std::fs::write(&path, report.lcov(&programs))
+                        .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+                }
+            }
+
+            let output = match result {
                 Ok(value) => value,
+                Err(e) if e.is::<debugger::Quit>() => return Ok(()),
                 Err(e) => {
-                    eprintln!("{}", e);
+                    report_runtime(args.error_style(), &mut ctx, &e);
                     std::process::exit(70);
                 }
             };
 
-            // Eval prints the last command, run doesn't
-            // For *reasons* numbers should't print .0 here
-            if let Command::Evaluate = args.command {
+            // Eval prints the last command the way print would, run doesn't
+            if let Command::Evaluate { .. } = args.command {
                 match output {
-                    values::Value::Number(n) => println!("{n}"),
-                    _ => println!("{}", output),
+                    EvalOutcome::Value(value) => println!("{}", value.display(ctx.print_style)),
+                    EvalOutcome::Unit => unreachable!("evaluate only parses expressions"),
+                }
+            } else if let Some(outputs) = outputs {
+                let mut outputs = outputs.as_slice();
+                for (document, blocks) in &documents {
+                    let (printed, rest) = outputs.split_at(blocks.len());
+                    outputs = rest;
+
+                    // Inputs are read with trailing whitespace trimmed, put back the last newline
+                    let woven = literate::weave(&document.bytes, blocks, printed);
+                    println!("{}", woven.trim_end_matches('\n'));
                 }
-            } else if let Command::Run = args.command {
-                // Do nothing
             }
         }
         _ => {}
@@ -153,3 +1142,254 @@ fn main() -> Result<()> {
     // Success (so far)
     Ok(())
 }
+
+// Every syntax error in a source, tokenizer errors first, along with whatever did parse
+// They're collected rather than printed, so check and parse can report them in their own way
+fn parse_all_source(source: &NamedSource, max_depth: usize) -> (Ast, Vec<anyhow::Error>) {
+    let _timed = Timed::start(phase::PARSER, format!("Parsing {}", source.name));
+    let mut parser = Parser::from(Tokenizer::new(&source.bytes)).with_max_depth(max_depth);
+    let (ast, parse_errors) = parser.parse_all();
+
+    let mut errors = parser
+        .tokenizer_iter_errors()
+        .map(|e| e.clone().into())
+        .collect::<Vec<anyhow::Error>>();
+    errors.extend(parse_errors);
+
+    (ast, errors)
+}
+
+// Parse a source along with its comments, for fmt and doc which put them back in
+// Syntax errors are reported and exit with 65, since there's nothing to format or document
+fn parse_with_trivia(source: &NamedSource, args: &Args) -> (Ast, Trivia) {
+    let mut parser = Parser::from(Tokenizer::new(&source.bytes)).with_max_depth(args.max_depth);
+    let ast = match parser.parse() {
+        Ok(ast) if !parser.tokenizer_had_errors() => ast,
+        Ok(_) => {
+            for error in parser.tokenizer_iter_errors() {
+                report(args.error_style(), Some(source), &error.clone().into());
+            }
+            std::process::exit(65);
+        }
+        Err(e) => {
+            report(args.error_style(), Some(source), &e);
+            std::process::exit(65);
+        }
+    };
+
+    let comments = parser.tokenizer_iter_comments().cloned().collect::<Vec<_>>();
+    let trivia = Trivia::attach(&ast, source, &comments);
+    (ast, trivia)
+}
+
+// Reports errors as they're found, up to an optional limit
+struct ErrorReporter {
+    style: ErrorStyle,
+    max_errors: Option<usize>,
+    count: usize,
+}
+
+impl ErrorReporter {
+    fn new(style: ErrorStyle, max_errors: Option<usize>) -> Self {
+        Self {
+            style,
+            max_errors,
+            count: 0,
+        }
+    }
+
+    fn report(&mut self, source: Option<&NamedSource>, error: &anyhow::Error) {
+        if self.max_errors.map_or(true, |max| self.count < max) {
+            report(self.style, source, error);
+        }
+        self.count += 1;
+    }
+
+    // Say how many were left out, returns true if there were any errors at all
+    fn finish(&self) -> bool {
+        // Tools reading JSON would choke on the note, not having the rest is the same
+        let json = self.style.format == ErrorFormat::Json;
+        if let Some(max) = self.max_errors.filter(|max| self.count > *max && !json) {
+            eprintln!("{} more errors not shown", self.count - max);
+        }
+        self.count > 0
+    }
+}
+
+// Errors held until a flush point, so they're printed in a set order however they were found:
+// each input's tokenizer then parser errors, in the order the inputs were given, then resolver
+// errors. Nothing runs until they've been flushed.
+struct ErrorBuffer {
+    style: ErrorStyle,
+    errors: Vec<(Option<NamedSource>, anyhow::Error)>,
+}
+
+impl ErrorBuffer {
+    fn new(style: ErrorStyle) -> Self {
+        Self {
+            style,
+            errors: vec![],
+        }
+    }
+
+    fn push(&mut self, source: Option<&NamedSource>, error: anyhow::Error) {
+        self.errors.push((source.cloned(), error));
+    }
+
+    // Print everything held so far, returns true if there was anything
+    fn flush(&mut self) -> bool {
+        let flushed = !self.errors.is_empty();
+        for (source, error) in self.errors.drain(..) {
+            report(self.style, source.as_ref(), &error);
+        }
+        flushed
+    }
+}
+
+// How errors are printed, from --plain and --error-format
+#[derive(Debug, Clone, Copy)]
+struct ErrorStyle {
+    format: ErrorFormat,
+    plain: bool,
+}
+
+// Print an error to stderr, with the source it's from if there's a terminal to read it
+fn report(style: ErrorStyle, source: Option<&NamedSource>, error: &anyhow::Error) {
+    if style.format == ErrorFormat::Json {
+        eprintln!("{}", diagnostic::json(source, error));
+        return;
+    }
+
+    match source {
+        Some(source) if !style.plain && std::io::stderr().is_terminal() => {
+            eprint!("{}", diagnostic::render(source, error))
+        }
+        _ => eprintln!("{}", error),
+    }
+}
+
+// Warnings go to stderr with errors, in the same format
+fn report_warnings(style: ErrorStyle, sink: &DiagnosticSink) {
+    for reported in sink.reported() {
+        match style.format {
+            ErrorFormat::Human => eprintln!("{}", reported),
+            ErrorFormat::Json => eprintln!("{}", reported.json()),
+        }
+    }
+}
+
+// Runtime errors also say what called what to get there, if it was inside calls
+//
+// Whatever the program printed comes first, printf without a newline included.
+fn report_runtime(style: ErrorStyle, ctx: &mut Context, error: &anyhow::Error) {
+    let _ = ctx.output.flush();
+    report(style, ctx.source.as_ref(), error);
+    if style.format == ErrorFormat::Human {
+        eprint!("{}", diagnostic::backtrace(&ctx.trace));
+    }
+}
+
+// The global environment before the program runs, with the standard library unless it's off
+fn global_env(std: bool) -> EnvironmentStack<Value> {
+    let mut env = EnvironmentStack::new();
+    if std {
+        stdlib::load(&mut env);
+    }
+    env
+}
+
+// The global environment for a script, with its command line arguments
+fn script_env(script_args: &[String], std: bool) -> EnvironmentStack<Value> {
+    let script_args = script_args
+        .iter()
+        .map(|arg| Value::from(arg.as_str()))
+        .collect::<Vec<_>>();
+
+    let mut env = global_env(std);
+    env.define("ARGC", Value::Integer(script_args.len() as i64));
+    env.define("ARGS", Value::List(Arc::new(script_args)));
+    env
+}
+
+// Read, parse, and run a list of files from scratch, collecting any errors
+fn run_files(
+    paths: &[PathBuf],
+    script_args: &[String],
+    std: bool,
+    ctx: &mut Context,
+) -> Result<(), Vec<String>> {
+    let mut programs = vec![];
+    for path in paths {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| vec![format!("{}: {}", path.display(), e)])?;
+        let source = NamedSource::new(path.display().to_string(), contents);
+        let ast = parse_source_with_max_depth(&source, ctx.max_depth)
+            .map_err(|errors| errors.iter().map(|e| e.to_string()).collect::<Vec<_>>())?;
+        programs.push((source, ast));
+    }
+
+    let mut env = script_env(script_args, std);
+    let errors = resolve_programs(&mut programs, &env.names(), ctx.compat);
+    if !errors.is_empty() {
+        return Err(errors.iter().map(|(_, e)| e.to_string()).collect());
+    }
+
+    run_programs(&programs, &mut env, ctx).map_err(|e| vec![e.to_string()])?;
+    Ok(())
+}
+
+// Resolve each program in order with the same globals, returning the errors that would stop it
+fn resolve_programs<'a>(
+    programs: &'a mut [(NamedSource, Ast)],
+    globals: &[String],
+    compat: bool,
+) -> Vec<(&'a NamedSource, resolver::ResolverError)> {
+    let mut resolver = resolver::Resolver::new().with_compat(compat);
+    for name in globals {
+        resolver.define_global(name);
+    }
+
+    let mut errors = vec![];
+    for (source, ast) in programs.iter_mut() {
+        resolver.resolve(ast);
+        let source = &*source;
+        for error in resolver.take_errors() {
+            if error.is_fatal() {
+                errors.push((source, error));
+            }
+        }
+    }
+    errors
+}
+
+// Evaluate each program in order in the same global environment, returning how the last went
+fn run_programs(
+    programs: &[(NamedSource, Ast)],
+    env: &mut EnvironmentStack<Value>,
+    ctx: &mut Context,
+) -> Result<EvalOutcome> {
+    let mut output = EvalOutcome::Unit;
+    ctx.start_run();
+    for (source, ast) in programs {
+        ctx.source = Some(source.clone());
+        output = ast.evaluate(env, ctx)?;
+    }
+    Ok(output)
+}
+
+// Like run_programs, with what each one printed to output
+fn run_programs_each(
+    programs: &[(NamedSource, Ast)],
+    env: &mut EnvironmentStack<Value>,
+    ctx: &mut Context,
+    output: &OutputBuffer,
+) -> Result<Vec<String>> {
+    let mut printed = vec![];
+    ctx.start_run();
+    for (source, ast) in programs {
+        ctx.source = Some(source.clone());
+        ast.evaluate(env, ctx)?;
+        printed.push(output.take());
+    }
+    Ok(printed)
+}
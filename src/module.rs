@@ -0,0 +1,66 @@
+use std::path::{Path, PathBuf};
+
+/// Where `import "name";` looks for name.lox, after the directory of the file importing it
+///
+/// Roots are searched in order: --include flags first, then each directory in LOX_PATH
+/// (separated like PATH is). The first file that exists is the one imported.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModulePath {
+    roots: Vec<PathBuf>,
+}
+
+/// The environment variable with more roots, for `ModulePath::with_env`
+pub const LOX_PATH: &str = "LOX_PATH";
+
+impl ModulePath {
+    pub fn new(roots: impl IntoIterator<Item = PathBuf>) -> Self {
+        Self {
+            roots: roots.into_iter().collect(),
+        }
+    }
+
+    /// The same with the roots in LOX_PATH after these, if it's set
+    pub fn with_env(mut self) -> Self {
+        if let Some(paths) = std::env::var_os(LOX_PATH) {
+            self.roots
+                .extend(std::env::split_paths(&paths).filter(|root| !root.as_os_str().is_empty()));
+        }
+        self
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Every file a module could be, in the order they're tried
+    ///
+    /// From is the name of the source importing it, which is only used if it's a file. Modules
+    /// can be given with or without .lox, and can have directories in them (eg "util/strings").
+    pub fn candidates(&self, from: Option<&str>, module: &str) -> Vec<PathBuf> {
+        let file = if module.ends_with(".lox") {
+            PathBuf::from(module)
+        } else {
+            PathBuf::from(format!("{module}.lox"))
+        };
+
+        // Names like <stdin> and <repl> aren't files, so those import from the current directory
+        let importer = from
+            .filter(|name| !name.starts_with('<'))
+            .map(|name| Path::new(name).parent().unwrap_or(Path::new("")))
+            .unwrap_or(Path::new(""));
+
+        std::iter::once(importer)
+            .chain(self.roots.iter().map(PathBuf::as_path))
+            .map(|root| root.join(&file))
+            .collect()
+    }
+
+    /// The file to import, or every path that was tried if there isn't one
+    pub fn find(&self, from: Option<&str>, module: &str) -> Result<PathBuf, Vec<PathBuf>> {
+        let candidates = self.candidates(from, module);
+        match candidates.iter().find(|path| path.is_file()) {
+            Some(path) => Ok(path.clone()),
+            None => Err(candidates),
+        }
+    }
+}
@@ -1,7 +1,9 @@
+use crate::span::Span;
+
 #[derive(Clone, PartialEq)]
 pub struct NamedSource {
-    pub(crate) name: String,
-    pub(crate) bytes: String,
+    pub name: String,
+    pub bytes: String,
     pub(crate) chars: Vec<char>,
 }
 
@@ -10,10 +12,17 @@ impl NamedSource {
         let chars = bytes.chars().collect();
         NamedSource { name, bytes, chars }
     }
+
+    /// The original source text covered by a span
+    pub fn slice(&self, span: &Span) -> String {
+        let end = span.end.min(self.chars.len());
+        let start = span.start.min(end);
+        self.chars[start..end].iter().collect()
+    }
 }
 
 impl std::fmt::Debug for NamedSource {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{}", self.name)
     }
-}
\ No newline at end of file
+}
@@ -1,51 +1,258 @@
+use std::collections::HashMap;
 use std::fmt::{self, Display};
+use std::ops::Index;
+use std::sync::Arc;
 
 use crate::{
+    const_enum,
+    context::DEFAULT_MAX_DEPTH,
+    interner::Interner,
+    phase,
+    resolver::Resolution,
     span::Span,
-    tokenizer::{Keyword, Token, Tokenizer, TokenizerError},
+    tokenizer::{Comment, Keyword, Token, Tokenizer, TokenizerError},
     values::Value,
 };
-use anyhow::{anyhow, Ok, Result};
+use anyhow::{Ok, Result};
+use thiserror::Error;
+
+/// Everything that can go wrong parsing, on top of tokenizer errors
+///
+/// Each displays the same way the official Lox implementation reports it.
+#[derive(Clone, Debug, Error)]
+pub enum ParseError {
+    #[error("[line {}] Error at '{}': {}", .0.line, .1, .2)]
+    Expected(Span, String, String), // Found, what was expected
+
+    #[error("[line {}] Error at '{}': Expect expression", .0.line, .1)]
+    ExpectedExpression(Span, String),
+
+    #[error("[line {}] Error at '=': Invalid assignment target.", .0.line)]
+    InvalidAssignmentTarget(Span),
+
+    #[error("[line {}] Error: Expect ';'", .0.line)]
+    ExpectedSemicolon(Span),
+
+    #[error("[line {}] Error at end: {}", .0.line, .1)]
+    UnexpectedEnd(Span, String),
+
+    #[error("[line {}] Error: expression too deeply nested", .0.line)]
+    TooDeep(Span),
+}
 
 #[derive(Debug)]
 pub struct Parser<'a> {
     tokenizer: Tokenizer<'a>,
+    names: Interner, // Every variable name in the program
+    ast: Ast,        // Every node parsed so far
+    depth: usize,    // How many nested expressions and statements are being parsed
+    max_depth: usize,
+}
+
+/// Where a node is in the Ast it belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+/// A parsed program (or expression), with every node stored together and referring to its
+/// children by NodeId
+///
+/// Children are always added before their parents, so the last node added is the root.
+#[derive(Debug, Default)]
+pub struct Ast {
+    nodes: Vec<AstNode>,
+    resolutions: HashMap<NodeId, Resolution>, // Filled in by the resolver, if it's run
 }
 
 #[derive(Debug)]
 pub enum AstNode {
     Literal(Span, Value),
-    Symbol(Span, String),
-
-    Group(Span, Vec<AstNode>), // No new scope
-    Block(Span, Vec<AstNode>), // New scope
+    Symbol(Span, Arc<str>),
+
+    Group(Span, NodeId),      // (expr), no new scope
+    Block(Span, Vec<NodeId>), // New scope
+
+    Binary(Span, BinaryOp, NodeId, NodeId),
+    Unary(Span, UnaryOp, NodeId),
+    Logical(Span, LogicalOp, NodeId, NodeId), // Short circuits, rhs only if lhs doesn't decide
+    Call(Span, NodeId, Vec<NodeId>),          // Callee, args
+    Get(Span, NodeId, String),         // Property access, eg Color.Red
+    OptionalGet(Span, NodeId, String), // obj?.name, nil if obj is nil
+    OptionalChain(Span, NodeId),       // Stops at the first nil OptionalGet inside
+    Coalesce(Span, NodeId, NodeId),    // a ?? b, b only if a is nil
+    Index(Span, NodeId, NodeId),       // s[i]
+
+    Declaration(Span, Arc<str>, NodeId), // Creates new variables
+    Assignment(Span, Arc<str>, NodeId),  // Sets values, error on undeclared
+
+    Enum(Span, String, Vec<String>),       // Name, variants
+    Import(Span, String),                  // Module name, eg "math" for math.lox
+    ForIn(Span, Arc<str>, NodeId, NodeId), // Variable, iterable, body
+    Assert(Span, NodeId, Option<NodeId>),  // Condition, message
+    Print(Span, NodeId),
+    Throw(Span, NodeId),
+    Try(Span, NodeId, Option<(Arc<str>, NodeId)>, Option<NodeId>), // Body, catch, finally
+
+    Program(Span, Vec<NodeId>),
+}
 
-    Application(Span, Box<AstNode>, Vec<AstNode>),
+// Operators each have their own node, displayed as the symbol they're written with
+const_enum! {
+    pub BinaryOp as &str {
+        Plus => "+",
+        Minus => "-",
+        Times => "*",
+        Divide => "/",
+        Equal => "==",
+        NotEqual => "!=",
+        Is => "is",
+        Less => "<",
+        LessEqual => "<=",
+        Greater => ">",
+        GreaterEqual => ">=",
+    }
+}
 
-    Declaration(Span, String, Box<AstNode>), // Creates new variables
-    Assignment(Span, String, Box<AstNode>),  // Sets values, error on undeclared
+const_enum! {
+    pub UnaryOp as &str {
+        Negate => "-",
+        Not => "!",
+    }
+}
 
-    Program(Span, Vec<AstNode>),
+const_enum! {
+    pub LogicalOp as &str {
+        And => "and",
+        Or => "or",
+    }
 }
 
 impl<'a> From<Tokenizer<'a>> for Parser<'a> {
     fn from(value: Tokenizer<'a>) -> Self {
-        Parser { tokenizer: value }
+        Parser {
+            tokenizer: value,
+            names: Interner::new(),
+            ast: Ast::new(),
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+impl Ast {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store a node whose children are already in this Ast
+    pub fn add(&mut self, node: AstNode) -> NodeId {
+        self.nodes.push(node);
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// The whole program, or the expression for a single expression
+    pub fn root(&self) -> NodeId {
+        assert!(!self.nodes.is_empty(), "an empty Ast has no root");
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Every node in the order they were added, children before their parents
+    pub fn nodes(&self) -> impl Iterator<Item = &AstNode> {
+        self.nodes.iter()
+    }
+
+    /// Display a node and everything under it as an s-expression
+    pub fn display(&self, id: NodeId) -> NodeDisplay<'_> {
+        NodeDisplay { ast: self, id }
+    }
+
+    /// Where the local variable a Symbol or Assignment refers to is, None if it's looked up by name
+    pub fn resolution(&self, id: NodeId) -> Option<Resolution> {
+        self.resolutions.get(&id).copied()
+    }
+
+    pub(crate) fn set_resolutions(&mut self, resolutions: HashMap<NodeId, Resolution>) {
+        self.resolutions = resolutions;
     }
 }
 
-impl Display for AstNode {
+impl NodeId {
+    /// Nodes are numbered from 0 in the order they were added
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+impl Index<NodeId> for Ast {
+    type Output = AstNode;
+
+    fn index(&self, id: NodeId) -> &AstNode {
+        &self.nodes[id.0]
+    }
+}
+
+impl Display for Ast {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
+        write!(f, "{}", self.display(self.root()))
+    }
+}
+
+/// A node in an Ast to display, see Ast::display
+pub struct NodeDisplay<'a> {
+    ast: &'a Ast,
+    id: NodeId,
+}
+
+impl Display for NodeDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |id: &NodeId| self.ast.display(*id);
+
+        match &self.ast[self.id] {
             AstNode::Literal(_, value) => write!(f, "{}", value),
             AstNode::Symbol(_, name) => write!(f, "{}", name),
-            AstNode::Declaration(_, name, value) => write!(f, "(var {} {})", name, value),
-            AstNode::Assignment(_, name, value) => write!(f, "(= {} {})", name, value),
+            AstNode::Declaration(_, name, value) => write!(f, "(var {} {})", name, show(value)),
+            AstNode::Assignment(_, name, value) => write!(f, "(= {} {})", name, show(value)),
+            AstNode::Print(_, value) => write!(f, "(print {})", show(value)),
+            AstNode::Throw(_, value) => write!(f, "(throw {})", show(value)),
+            AstNode::Import(_, module) => write!(f, "(import {:?})", module),
+            AstNode::Group(_, expression) => write!(f, "(group {})", show(expression)),
+            AstNode::Get(_, object, name) => write!(f, "(. {} {})", show(object), name),
+            AstNode::OptionalGet(_, object, name) => write!(f, "(?. {} {})", show(object), name),
+            AstNode::OptionalChain(_, chain) => write!(f, "{}", show(chain)),
+            AstNode::Coalesce(_, lhs, rhs) => write!(f, "(?? {} {})", show(lhs), show(rhs)),
+            AstNode::Index(_, object, index) => {
+                write!(f, "(index {} {})", show(object), show(index))
+            }
+            AstNode::ForIn(_, name, iterable, body) => {
+                write!(f, "(for {} {} {})", name, show(iterable), show(body))
+            }
 
-            AstNode::Group(_, nodes) => {
-                write!(f, "(group")?;
-                for node in nodes {
-                    write!(f, " {}", node)?;
+            AstNode::Enum(_, name, variants) => {
+                write!(f, "(enum {}", name)?;
+                for variant in variants {
+                    write!(f, " {}", variant)?;
+                }
+                write!(f, ")")?;
+
+                std::fmt::Result::Ok(())
+            }
+
+            AstNode::Assert(_, condition, message) => {
+                write!(f, "(assert {}", show(condition))?;
+                if let Some(message) = message {
+                    write!(f, " {}", show(message))?;
+                }
+                write!(f, ")")?;
+
+                std::fmt::Result::Ok(())
+            }
+
+            AstNode::Try(_, body, catch, finally) => {
+                write!(f, "(try {}", show(body))?;
+                if let Some((name, handler)) = catch {
+                    write!(f, " (catch {} {})", name, show(handler))?;
+                }
+                if let Some(finally) = finally {
+                    write!(f, " (finally {})", show(finally))?;
                 }
                 write!(f, ")")?;
 
@@ -57,10 +264,10 @@ impl Display for AstNode {
                 let mut first = true;
                 for node in nodes {
                     if first {
-                        write!(f, "{}", node)?;
+                        write!(f, "{}", show(node))?;
                         first = false;
                     } else {
-                        write!(f, " {}", node)?;
+                        write!(f, " {}", show(node))?;
                     }
                 }
                 write!(f, "}}")?;
@@ -68,10 +275,18 @@ impl Display for AstNode {
                 std::fmt::Result::Ok(())
             }
 
-            AstNode::Application(_, func, args) => {
-                write!(f, "({}", func)?;
+            AstNode::Binary(_, op, lhs, rhs) => {
+                write!(f, "({} {} {})", op.to_value(), show(lhs), show(rhs))
+            }
+            AstNode::Unary(_, op, operand) => write!(f, "({} {})", op.to_value(), show(operand)),
+            AstNode::Logical(_, op, lhs, rhs) => {
+                write!(f, "({} {} {})", op.to_value(), show(lhs), show(rhs))
+            }
+
+            AstNode::Call(_, func, args) => {
+                write!(f, "({}", show(func))?;
                 for arg in args {
-                    write!(f, " {}", arg)?;
+                    write!(f, " {}", show(arg))?;
                 }
                 write!(f, ")")?;
 
@@ -80,7 +295,7 @@ impl Display for AstNode {
 
             AstNode::Program(_, nodes) => {
                 for node in nodes {
-                    write!(f, "{}\n", node)?;
+                    writeln!(f, "{}", show(node))?;
                 }
 
                 std::fmt::Result::Ok(())
@@ -90,20 +305,273 @@ impl Display for AstNode {
 }
 
 impl AstNode {
+    /// The name of this kind of node, eg Literal
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AstNode::Literal(_, _) => "Literal",
+            AstNode::Symbol(_, _) => "Symbol",
+            AstNode::Group(_, _) => "Group",
+            AstNode::Block(_, _) => "Block",
+            AstNode::Binary(_, _, _, _) => "Binary",
+            AstNode::Unary(_, _, _) => "Unary",
+            AstNode::Logical(_, _, _, _) => "Logical",
+            AstNode::Call(_, _, _) => "Call",
+            AstNode::Get(_, _, _) => "Get",
+            AstNode::OptionalGet(_, _, _) => "OptionalGet",
+            AstNode::OptionalChain(_, _) => "OptionalChain",
+            AstNode::Coalesce(_, _, _) => "Coalesce",
+            AstNode::Index(_, _, _) => "Index",
+            AstNode::ForIn(_, _, _, _) => "ForIn",
+            AstNode::Enum(_, _, _) => "Enum",
+            AstNode::Import(_, _) => "Import",
+            AstNode::Declaration(_, _, _) => "Declaration",
+            AstNode::Assignment(_, _, _) => "Assignment",
+            AstNode::Assert(_, _, _) => "Assert",
+            AstNode::Print(_, _) => "Print",
+            AstNode::Throw(_, _) => "Throw",
+            AstNode::Try(_, _, _, _) => "Try",
+            AstNode::Program(_, _) => "Program",
+        }
+    }
+
+    /// If this is run for what it does rather than for a value, eg Print but not Call
+    pub fn is_statement(&self) -> bool {
+        matches!(
+            self,
+            AstNode::Block(_, _)
+                | AstNode::ForIn(_, _, _, _)
+                | AstNode::Enum(_, _, _)
+                | AstNode::Import(_, _)
+                | AstNode::Declaration(_, _, _)
+                | AstNode::Assert(_, _, _)
+                | AstNode::Print(_, _)
+                | AstNode::Throw(_, _)
+                | AstNode::Try(_, _, _, _)
+                | AstNode::Program(_, _)
+        )
+    }
+
     pub fn span(&self) -> Span {
         match self {
             AstNode::Literal(span, _)
             | AstNode::Symbol(span, _)
             | AstNode::Group(span, _)
             | AstNode::Block(span, _)
-            | AstNode::Application(span, _, _)
+            | AstNode::Binary(span, _, _, _)
+            | AstNode::Unary(span, _, _)
+            | AstNode::Logical(span, _, _, _)
+            | AstNode::Call(span, _, _)
+            | AstNode::Get(span, _, _)
+            | AstNode::OptionalGet(span, _, _)
+            | AstNode::OptionalChain(span, _)
+            | AstNode::Coalesce(span, _, _)
+            | AstNode::Index(span, _, _)
+            | AstNode::ForIn(span, _, _, _)
+            | AstNode::Enum(span, _, _)
+            | AstNode::Import(span, _)
             | AstNode::Declaration(span, _, _)
             | AstNode::Assignment(span, _, _)
+            | AstNode::Assert(span, _, _)
+            | AstNode::Print(span, _)
+            | AstNode::Throw(span, _)
+            | AstNode::Try(span, _, _, _)
             | AstNode::Program(span, _) => *span,
         }
     }
+
+    /// For passes that move nodes, eg reparsing after an edit
+    pub fn span_mut(&mut self) -> &mut Span {
+        match self {
+            AstNode::Literal(span, _)
+            | AstNode::Symbol(span, _)
+            | AstNode::Group(span, _)
+            | AstNode::Block(span, _)
+            | AstNode::Binary(span, _, _, _)
+            | AstNode::Unary(span, _, _)
+            | AstNode::Logical(span, _, _, _)
+            | AstNode::Call(span, _, _)
+            | AstNode::Get(span, _, _)
+            | AstNode::OptionalGet(span, _, _)
+            | AstNode::OptionalChain(span, _)
+            | AstNode::Coalesce(span, _, _)
+            | AstNode::Index(span, _, _)
+            | AstNode::ForIn(span, _, _, _)
+            | AstNode::Enum(span, _, _)
+            | AstNode::Import(span, _)
+            | AstNode::Declaration(span, _, _)
+            | AstNode::Assignment(span, _, _)
+            | AstNode::Assert(span, _, _)
+            | AstNode::Print(span, _)
+            | AstNode::Throw(span, _)
+            | AstNode::Try(span, _, _, _)
+            | AstNode::Program(span, _) => span,
+        }
+    }
+
+    /// The nodes directly inside this one, in the order they're evaluated
+    pub fn children(&self) -> Vec<NodeId> {
+        match self {
+            AstNode::Literal(_, _)
+            | AstNode::Symbol(_, _)
+            | AstNode::Enum(_, _, _)
+            | AstNode::Import(_, _) => vec![],
+
+            AstNode::Group(_, child)
+            | AstNode::Unary(_, _, child)
+            | AstNode::Get(_, child, _)
+            | AstNode::OptionalGet(_, child, _)
+            | AstNode::OptionalChain(_, child)
+            | AstNode::Declaration(_, _, child)
+            | AstNode::Assignment(_, _, child)
+            | AstNode::Print(_, child)
+            | AstNode::Throw(_, child) => vec![*child],
+
+            AstNode::Binary(_, _, lhs, rhs)
+            | AstNode::Logical(_, _, lhs, rhs)
+            | AstNode::Coalesce(_, lhs, rhs)
+            | AstNode::Index(_, lhs, rhs)
+            | AstNode::ForIn(_, _, lhs, rhs) => vec![*lhs, *rhs],
+
+            AstNode::Block(_, nodes) | AstNode::Program(_, nodes) => nodes.clone(),
+            AstNode::Call(_, function, args) => std::iter::once(*function)
+                .chain(args.iter().copied())
+                .collect(),
+            AstNode::Assert(_, condition, message) => {
+                std::iter::once(*condition).chain(*message).collect()
+            }
+            AstNode::Try(_, body, catch, finally) => std::iter::once(*body)
+                .chain(catch.as_ref().map(|(_, handler)| *handler))
+                .chain(*finally)
+                .collect(),
+        }
+    }
+
+    /// The same node with each child replaced, in the order children gives them
+    pub fn map_children(&self, child: &mut impl FnMut(NodeId) -> NodeId) -> AstNode {
+        let mut children = |nodes: &[NodeId]| nodes.iter().map(|id| child(*id)).collect::<Vec<_>>();
+
+        match self {
+            AstNode::Literal(span, value) => AstNode::Literal(*span, value.clone()),
+            AstNode::Symbol(span, name) => AstNode::Symbol(*span, name.clone()),
+            AstNode::Group(span, expression) => AstNode::Group(*span, child(*expression)),
+            AstNode::Block(span, nodes) => AstNode::Block(*span, children(nodes)),
+            AstNode::Binary(span, op, lhs, rhs) => {
+                AstNode::Binary(*span, *op, child(*lhs), child(*rhs))
+            }
+            AstNode::Unary(span, op, operand) => AstNode::Unary(*span, *op, child(*operand)),
+            AstNode::Logical(span, op, lhs, rhs) => {
+                AstNode::Logical(*span, *op, child(*lhs), child(*rhs))
+            }
+            AstNode::Call(span, function, args) => {
+                let function = children(std::slice::from_ref(function))[0];
+                AstNode::Call(*span, function, children(args))
+            }
+            AstNode::Get(span, object, name) => AstNode::Get(*span, child(*object), name.clone()),
+            AstNode::OptionalGet(span, object, name) => {
+                AstNode::OptionalGet(*span, child(*object), name.clone())
+            }
+            AstNode::OptionalChain(span, chain) => AstNode::OptionalChain(*span, child(*chain)),
+            AstNode::Coalesce(span, lhs, rhs) => AstNode::Coalesce(*span, child(*lhs), child(*rhs)),
+            AstNode::Index(span, object, index) => {
+                AstNode::Index(*span, child(*object), child(*index))
+            }
+            AstNode::Declaration(span, name, value) => {
+                AstNode::Declaration(*span, name.clone(), child(*value))
+            }
+            AstNode::Assignment(span, name, value) => {
+                AstNode::Assignment(*span, name.clone(), child(*value))
+            }
+            AstNode::Enum(span, name, variants) => {
+                AstNode::Enum(*span, name.clone(), variants.clone())
+            }
+            AstNode::Import(span, module) => AstNode::Import(*span, module.clone()),
+            AstNode::ForIn(span, name, iterable, body) => {
+                AstNode::ForIn(*span, name.clone(), child(*iterable), child(*body))
+            }
+            AstNode::Assert(span, condition, message) => {
+                AstNode::Assert(*span, child(*condition), message.map(&mut *child))
+            }
+            AstNode::Print(span, value) => AstNode::Print(*span, child(*value)),
+            AstNode::Throw(span, value) => AstNode::Throw(*span, child(*value)),
+            AstNode::Try(span, body, catch, finally) => {
+                let body = child(*body);
+                let catch = catch
+                    .as_ref()
+                    .map(|(name, handler)| (name.clone(), child(*handler)));
+                AstNode::Try(*span, body, catch, finally.map(&mut *child))
+            }
+            AstNode::Program(span, nodes) => AstNode::Program(*span, children(nodes)),
+        }
+    }
+}
+
+// Which way a chain of the same operator groups: left is (a - b) - c, right is a = (b = c)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Associativity {
+    Left,
+    Right,
+}
+
+// The node an infix operator makes from its two sides
+#[derive(Debug, Clone, Copy)]
+enum Infix {
+    Assign,
+    Coalesce,
+    Logical(LogicalOp),
+    Binary(BinaryOp),
+}
+
+#[derive(Debug)]
+struct Operator {
+    keyword: Keyword,
+    infix: Infix,
+    precedence: u8, // Higher binds tighter
+    associativity: Associativity,
+}
+
+const fn infix(
+    keyword: Keyword,
+    infix: Infix,
+    precedence: u8,
+    associativity: Associativity,
+) -> Operator {
+    Operator {
+        keyword,
+        infix,
+        precedence,
+        associativity,
+    }
+}
+
+const fn binary(keyword: Keyword, op: BinaryOp, precedence: u8) -> Operator {
+    infix(keyword, Infix::Binary(op), precedence, Associativity::Left)
 }
 
+// Every binary operator, loosest first. Calls, indexes, and properties bind tighter than any.
+const INFIX_OPERATORS: &[Operator] = &[
+    infix(Keyword::Equal, Infix::Assign, 1, Associativity::Right),
+    infix(Keyword::QuestionQuestion, Infix::Coalesce, 2, Associativity::Left),
+    infix(Keyword::Or, Infix::Logical(LogicalOp::Or), 3, Associativity::Left),
+    infix(Keyword::And, Infix::Logical(LogicalOp::And), 4, Associativity::Left),
+    binary(Keyword::BangEqual, BinaryOp::NotEqual, 5),
+    binary(Keyword::EqualEqual, BinaryOp::Equal, 5),
+    binary(Keyword::Is, BinaryOp::Is, 5),
+    binary(Keyword::Greater, BinaryOp::Greater, 6),
+    binary(Keyword::GreaterEqual, BinaryOp::GreaterEqual, 6),
+    binary(Keyword::Less, BinaryOp::Less, 6),
+    binary(Keyword::LessEqual, BinaryOp::LessEqual, 6),
+    binary(Keyword::Minus, BinaryOp::Minus, 7),
+    binary(Keyword::Plus, BinaryOp::Plus, 7),
+    binary(Keyword::Slash, BinaryOp::Divide, 8),
+    binary(Keyword::Star, BinaryOp::Times, 8),
+];
+
+// Unary operators, which bind tighter than any binary one
+const PREFIX_OPERATORS: &[(Keyword, UnaryOp)] = &[
+    (Keyword::Bang, UnaryOp::Not),
+    (Keyword::Minus, UnaryOp::Negate),
+];
+
 macro_rules! matches_keyword {
     (
         $token:expr =>
@@ -120,50 +588,192 @@ macro_rules! matches_keyword {
 }
 
 impl Parser<'_> {
-    pub fn parse(&mut self) -> Result<AstNode> {
+    pub fn parse(&mut self) -> Result<Ast> {
         let mut nodes = vec![];
         let mut span = Span::ZERO;
 
         while let Some(token) = self.tokenizer.peek() {
-            if token == &Token::EOF {
+            if let Token::EOF(_) = token {
                 break;
             }
 
             let node = self.parse_declaration()?;
-            span = span.merge(&node.span());
+            span = span.merge(&self.span(node));
             nodes.push(node);
         }
 
-        Ok(AstNode::Program(span, nodes))
+        self.add(AstNode::Program(span, nodes));
+        Ok(std::mem::take(&mut self.ast))
     }
 
-    fn parse_declaration(&mut self) -> Result<AstNode> {
-        log::debug!("parse_declaration");
+    /// Parse the whole program, skipping to the next statement after each error so all can be reported
+    /// The program contains every statement that did parse
+    pub fn parse_all(&mut self) -> (Ast, Vec<anyhow::Error>) {
+        let mut nodes = vec![];
+        let mut span = Span::ZERO;
+        let mut errors = vec![];
+
+        while let Some(token) = self.tokenizer.peek() {
+            if let Token::EOF(_) = token {
+                break;
+            }
+
+            match self.parse_declaration() {
+                std::result::Result::Ok(node) => {
+                    span = span.merge(&self.span(node));
+                    nodes.push(node);
+                }
+                Err(e) => {
+                    errors.push(e);
+                    self.synchronize();
+                }
+            }
+        }
+
+        self.add(AstNode::Program(span, nodes));
+        (std::mem::take(&mut self.ast), errors)
+    }
+
+    // After an error, skip past the end of the current statement or up to the start of the next
+    fn synchronize(&mut self) {
+        while let Some(token) = self.tokenizer.peek() {
+            match token {
+                Token::EOF(_) => return,
+                Token::Keyword(_, Keyword::Semicolon | Keyword::RightBrace) => {
+                    self.tokenizer.next();
+                    return;
+                }
+                Token::Keyword(
+                    _,
+                    Keyword::Var
+                    | Keyword::Enum
+                    | Keyword::For
+                    | Keyword::Print
+                    | Keyword::Assert
+                    | Keyword::Throw
+                    | Keyword::Try
+                    | Keyword::Import,
+                ) => return,
+                _ => {
+                    self.tokenizer.next();
+                }
+            }
+        }
+    }
+
+    /// Parse input that must be exactly one expression, with no trailing semicolon
+    /// The expression is the root of the returned Ast
+    pub fn parse_single_expression(&mut self) -> Result<Ast> {
+        self.parse_expression()?;
+
+        match self.tokenizer.peek() {
+            Some(Token::EOF(_)) | None => Ok(std::mem::take(&mut self.ast)),
+            Some(token) => Err(unexpected(token, "Expect end of expression").into()),
+        }
+    }
+
+    /// How deeply expressions and statements can nest before it's an error rather than a stack
+    /// overflow, the same as Context::max_depth by default
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    fn add(&mut self, node: AstNode) -> NodeId {
+        self.ast.add(node)
+    }
+
+    // Parse one level deeper, anything that recurses for each level of nesting goes through here
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let depth = self.depth;
+        let result = self.deeper().and_then(|()| parse(self));
+        self.depth = depth;
+        result
+    }
+
+    // One level deeper until the enclosing nested call returns, for loops that build each node on top
+    // of the last one (1 + 2 + 3, f()()), since everything after parsing recurses through those too
+    fn deeper(&mut self) -> Result<()> {
+        if self.depth >= self.max_depth {
+            let span = self.tokenizer.peek().map_or(Span::ZERO, |token| *token.span());
+            return Err(ParseError::TooDeep(span).into());
+        }
+
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn span(&self, id: NodeId) -> Span {
+        self.ast[id].span()
+    }
+
+    fn parse_declaration(&mut self) -> Result<NodeId> {
+        log::debug!(target: phase::PARSER, "parse_declaration");
 
         match self.tokenizer.peek() {
             Some(Token::Keyword(_, Keyword::Var)) => self.parse_var_statement(),
+            Some(Token::Keyword(_, Keyword::Enum)) => self.parse_enum_declaration(),
             _ => self.parse_statement(),
         }
     }
 
-    fn parse_statement(&mut self) -> Result<AstNode> {
-        log::debug!("parse_statement");
+    // enum Name { A, B, C } with an optional trailing comma
+    fn parse_enum_declaration(&mut self) -> Result<NodeId> {
+        let keyword = self.tokenizer.next().unwrap();
+        let span = keyword.span();
+        log::debug!(target: phase::PARSER, "parse_enum @ {span:?}");
+
+        let name = self.consume_identifier("Expect enum name")?;
+        self.consume_keyword(Keyword::LeftBrace, "Expect '{' before enum variants")?;
+
+        let mut variants = vec![];
+        loop {
+            if let Some(Token::Keyword(_, Keyword::RightBrace)) = self.tokenizer.peek() {
+                break;
+            }
+
+            variants.push(self.consume_identifier("Expect variant name")?);
+
+            if let Some(Token::Keyword(_, Keyword::Comma)) = self.tokenizer.peek() {
+                self.tokenizer.next();
+            } else {
+                break;
+            }
+        }
+
+        let right_brace =
+            self.consume_keyword(Keyword::RightBrace, "Expect '}' after enum variants")?;
+        let span = span.merge(right_brace.span());
+
+        Ok(self.add(AstNode::Enum(span, name, variants)))
+    }
+
+    fn parse_statement(&mut self) -> Result<NodeId> {
+        log::debug!(target: phase::PARSER, "parse_statement");
+        self.nested(Self::parse_statement_kind)
+    }
 
+    fn parse_statement_kind(&mut self) -> Result<NodeId> {
         match self.tokenizer.peek() {
             Some(Token::Keyword(_, Keyword::LeftBrace)) => self.parse_block(),
             Some(Token::Keyword(_, Keyword::Print)) => self.parse_print_statement(),
+            Some(Token::Keyword(_, Keyword::Assert)) => self.parse_assert_statement(),
+            Some(Token::Keyword(_, Keyword::For)) => self.parse_for_statement(),
+            Some(Token::Keyword(_, Keyword::Throw)) => self.parse_throw_statement(),
+            Some(Token::Keyword(_, Keyword::Try)) => self.parse_try_statement(),
+            Some(Token::Keyword(_, Keyword::Import)) => self.parse_import_statement(),
             _ => self.parse_expression_statement(),
         }
     }
 
-    fn parse_block(&mut self) -> Result<AstNode> {
+    fn parse_block(&mut self) -> Result<NodeId> {
         let left_brace = self.tokenizer.next().unwrap();
         let span = left_brace.span();
-        log::debug!("parse_block @ {span:?}");
+        log::debug!(target: phase::PARSER, "parse_block @ {span:?}");
 
         let mut nodes = vec![];
         while let Some(token) = self.tokenizer.peek() {
-            if let Token::Keyword(_, Keyword::RightBrace) = token {
+            if let Token::Keyword(_, Keyword::RightBrace) | Token::EOF(_) = token {
                 break;
             }
 
@@ -171,13 +781,13 @@ impl Parser<'_> {
             nodes.push(node);
         }
 
-        let right_brace = self.tokenizer.next().unwrap();
-        let span = span.merge(&right_brace.span());
+        let right_brace = self.consume_keyword(Keyword::RightBrace, "Expect '}' after block")?;
+        let span = span.merge(right_brace.span());
 
-        Ok(AstNode::Block(span, nodes))
+        Ok(self.add(AstNode::Block(span, nodes)))
     }
 
-    fn parse_expression_statement(&mut self) -> Result<AstNode> {
+    fn parse_expression_statement(&mut self) -> Result<NodeId> {
         let expression = self.parse_expression()?;
 
         // TODO: Should the span include this ;?
@@ -187,39 +797,156 @@ impl Parser<'_> {
         Ok(expression)
     }
 
-    fn parse_print_statement(&mut self) -> Result<AstNode> {
+    fn parse_print_statement(&mut self) -> Result<NodeId> {
+        let keyword = self.tokenizer.next().unwrap();
+        let span = keyword.span();
+        log::debug!(target: phase::PARSER, "parse_print @ {span:?}");
+
+        let expression = self.parse_expression()?;
+        let span = span.merge(&self.span(expression));
+
+        let semicolon = self.consume_semicolon_or_eof()?;
+        let span = semicolon.map_or(span, |semicolon| span.merge(semicolon.span()));
+
+        Ok(self.add(AstNode::Print(span, expression)))
+    }
+
+    // for (var name in iterable) body, iterating characters of strings or items of lists
+    fn parse_for_statement(&mut self) -> Result<NodeId> {
+        let keyword = self.tokenizer.next().unwrap();
+        let span = keyword.span();
+        log::debug!(target: phase::PARSER, "parse_for @ {span:?}");
+
+        self.consume_keyword(Keyword::LeftParen, "Expect '(' after 'for'")?;
+        self.consume_keyword(Keyword::Var, "Expect 'var' in for loop")?;
+        let name = self.consume_identifier("Expect loop variable name")?;
+        let name = self.names.intern(&name);
+        self.consume_keyword(Keyword::In, "Expect 'in' after loop variable")?;
+        let iterable = self.parse_expression()?;
+        self.consume_keyword(Keyword::RightParen, "Expect ')' after for clauses")?;
+
+        let body = self.parse_statement()?;
+        let span = span.merge(&self.span(body));
+
+        Ok(self.add(AstNode::ForIn(span, name, iterable, body)))
+    }
+
+    // assert expr; or assert expr, message;
+    fn parse_assert_statement(&mut self) -> Result<NodeId> {
         let keyword = self.tokenizer.next().unwrap();
         let span = keyword.span();
-        log::debug!("parse_print @ {span:?}");
+        log::debug!(target: phase::PARSER, "parse_assert @ {span:?}");
+
+        let condition = self.parse_expression()?;
+        let mut span = span.merge(&self.span(condition));
+
+        let message = if let Some(Token::Keyword(_, Keyword::Comma)) = self.tokenizer.peek() {
+            self.tokenizer.next();
+            let message = self.parse_expression()?;
+            span = span.merge(&self.span(message));
+            Some(message)
+        } else {
+            None
+        };
+
+        let semicolon = self.consume_semicolon_or_eof()?;
+        let span = semicolon.map_or(span, |semicolon| span.merge(semicolon.span()));
+
+        Ok(self.add(AstNode::Assert(span, condition, message)))
+    }
+
+    fn parse_throw_statement(&mut self) -> Result<NodeId> {
+        let keyword = self.tokenizer.next().unwrap();
+        let span = keyword.span();
+        log::debug!(target: phase::PARSER, "parse_throw @ {span:?}");
 
         let expression = self.parse_expression()?;
-        let span = span.merge(&expression.span());
+        let span = span.merge(&self.span(expression));
 
         let semicolon = self.consume_semicolon_or_eof()?;
-        let span = span.merge(&semicolon.span());
+        let span = semicolon.map_or(span, |semicolon| span.merge(semicolon.span()));
 
-        Ok(AstNode::Application(
-            span,
-            Box::new(AstNode::Symbol(span, "print".to_string())),
-            vec![expression],
-        ))
+        Ok(self.add(AstNode::Throw(span, expression)))
     }
 
-    fn parse_var_statement(&mut self) -> Result<AstNode> {
+    // import "name"; with the name as a string, since it can have slashes
+    fn parse_import_statement(&mut self) -> Result<NodeId> {
+        let keyword = self.tokenizer.next().unwrap();
+        let span = keyword.span();
+        log::debug!(target: phase::PARSER, "parse_import @ {span:?}");
+
+        let (module, span) = match self.tokenizer.next() {
+            Some(Token::Literal(name_span, _, Value::String(module))) => {
+                (module.to_string(), span.merge(&name_span))
+            }
+            Some(token) => return Err(unexpected(&token, "Expect module name string").into()),
+            None => unreachable!("EOF is always emitted"),
+        };
+
+        let semicolon = self.consume_semicolon_or_eof()?;
+        let span = semicolon.map_or(span, |semicolon| span.merge(semicolon.span()));
+
+        Ok(self.add(AstNode::Import(span, module)))
+    }
+
+    // try { ... } [catch (name) { ... }] [finally { ... }], at least one of catch/finally
+    fn parse_try_statement(&mut self) -> Result<NodeId> {
+        let keyword = self.tokenizer.next().unwrap();
+        let span = keyword.span();
+        log::debug!(target: phase::PARSER, "parse_try @ {span:?}");
+
+        self.expect_keyword(Keyword::LeftBrace, "Expect '{' after 'try'")?;
+        let body = self.parse_block()?;
+        let mut span = span.merge(&self.span(body));
+
+        let catch = if let Some(Token::Keyword(_, Keyword::Catch)) = self.tokenizer.peek() {
+            self.tokenizer.next();
+            self.consume_keyword(Keyword::LeftParen, "Expect '(' after 'catch'")?;
+
+            let name = self.consume_identifier("Expect exception variable name")?;
+            let name = self.names.intern(&name);
+
+            self.consume_keyword(Keyword::RightParen, "Expect ')' after exception variable")?;
+            self.expect_keyword(Keyword::LeftBrace, "Expect '{' after 'catch'")?;
+            let handler = self.parse_block()?;
+            span = span.merge(&self.span(handler));
+
+            Some((name, handler))
+        } else {
+            None
+        };
+
+        let finally = if let Some(Token::Keyword(_, Keyword::Finally)) = self.tokenizer.peek() {
+            self.tokenizer.next();
+            self.expect_keyword(Keyword::LeftBrace, "Expect '{' after 'finally'")?;
+            let finally = self.parse_block()?;
+            span = span.merge(&self.span(finally));
+
+            Some(finally)
+        } else {
+            None
+        };
+
+        if catch.is_none() && finally.is_none() {
+            let message = "Expect 'catch' or 'finally'".to_string();
+            return Err(ParseError::Expected(span, "try".to_string(), message).into());
+        }
+
+        Ok(self.add(AstNode::Try(span, body, catch, finally)))
+    }
+
+    fn parse_var_statement(&mut self) -> Result<NodeId> {
         let var_keyword = self.tokenizer.next().unwrap();
         let span = var_keyword.span();
-        log::debug!("parse_var @ {span:?}");
+        log::debug!(target: phase::PARSER, "parse_var @ {span:?}");
 
-        let name = if let Some(Token::Identifier(span, name)) = self.tokenizer.next() {
-            span.merge(&span);
-            name
-        } else {
-            let line = self.line_number(span.start);
-            return Err(anyhow!(
-                "[line {}] Error at '{}': Expect identifier",
-                line,
-                var_keyword
-            ));
+        let name = match self.tokenizer.next() {
+            Some(Token::Identifier(_, name)) => self.names.intern(&name),
+            Some(token) => return Err(unexpected(&token, "Expect identifier").into()),
+            None => {
+                let message = "Expect identifier".to_string();
+                return Err(ParseError::Expected(*span, var_keyword.lexeme(), message).into());
+            }
         };
 
         // We want to have '= expr ;' or ';'
@@ -227,230 +954,289 @@ impl Parser<'_> {
             // End of expression, default to nil and return immediately
             Some(Token::Keyword(semispan, Keyword::Semicolon)) => {
                 let span = span.merge(&semispan);
-                Ok(AstNode::Declaration(
-                    span,
-                    name,
-                    Box::new(AstNode::Literal(span, Value::Nil)),
-                ))
+                let nil = self.add(AstNode::Literal(span, Value::Nil));
+                Ok(self.add(AstNode::Declaration(span, name, nil)))
             }
             // Equal, parse expression
             Some(Token::Keyword(_, Keyword::Equal)) => {
                 let expression = self.parse_expression()?;
-                let span = span.merge(&expression.span());
+                let span = span.merge(&self.span(expression));
 
                 let semicolon = self.consume_semicolon_or_eof()?;
-                let span = span.merge(&semicolon.span());
+                let span = semicolon.map_or(span, |semicolon| span.merge(semicolon.span()));
 
-                Ok(AstNode::Declaration(span, name, Box::new(expression)))
-            }
-            // Anything else is an error, split for better reporting
-            Some(token) => {
-                let line = self.line_number(token.span().start);
-                Err(anyhow!(
-                    "[line {}] Error at '{}': Expect '=' or ';'",
-                    line,
-                    var_keyword
-                ))
+                Ok(self.add(AstNode::Declaration(span, name, expression)))
             }
+            // Anything else is an error
+            Some(token) => Err(unexpected(&token, "Expect '=' or ';'").into()),
             None => {
-                let line = self.line_number(span.start);
-                Err(anyhow!(
-                    "[line {}] Error at '{}': Expect '=' or ';'",
-                    line,
-                    var_keyword
-                ))
+                let message = "Expect '=' or ';'".to_string();
+                Err(ParseError::Expected(*span, var_keyword.lexeme(), message).into())
             }
         }
     }
 
-    fn parse_expression(&mut self) -> Result<AstNode> {
-        log::debug!("parse_expression");
-        self.parse_assignment()
+    fn parse_expression(&mut self) -> Result<NodeId> {
+        log::debug!(target: phase::PARSER, "parse_expression");
+        self.parse_infix(0)
     }
 
-    fn parse_assignment(&mut self) -> Result<AstNode> {
-        let mut lhs = self.parse_equality()?;
+    // Precedence climbing: parse anything binding at least as tightly as min_precedence, so the
+    // right side of each operator only takes what binds tighter than it (or the same, going right)
+    fn parse_infix(&mut self, min_precedence: u8) -> Result<NodeId> {
+        self.nested(|parser| parser.parse_infix_from(min_precedence))
+    }
+
+    fn parse_infix_from(&mut self, min_precedence: u8) -> Result<NodeId> {
+        let mut lhs = self.parse_unary()?;
 
-        if let Some(Token::Keyword(_, Keyword::Equal)) = self.tokenizer.peek() {
-            log::debug!("parse_assignment");
+        while let Some((op_span, operator)) = self.peek_infix() {
+            if operator.precedence < min_precedence {
+                break;
+            }
+            log::debug!(target: phase::PARSER, "parse_infix {} @ op_span: {:?}", operator.keyword, op_span);
 
             // The lhs has to be a symbol to assign to
             // Evaluation will handle assignment to undefined variables
-            let name = if let AstNode::Symbol(_, name) = &lhs {
-                name.clone()
-            } else {
-                let line = lhs.span().line;
-                return Err(anyhow!(
-                    "[line {}] Error at '=': Invalid assignment target.",
-                    line
-                ));
-            };
+            if matches!(operator.infix, Infix::Assign)
+                && !matches!(self.ast[lhs], AstNode::Symbol(_, _))
+            {
+                return Err(ParseError::InvalidAssignmentTarget(self.span(lhs)).into());
+            }
 
+            self.deeper()?;
             self.tokenizer.next();
-            let rhs = self.parse_assignment()?;
-            let span = lhs.span().merge(&rhs.span());
+            let rhs = match operator.associativity {
+                Associativity::Left => self.parse_infix(operator.precedence + 1)?,
+                Associativity::Right => self.parse_infix(operator.precedence)?,
+            };
 
-            lhs = AstNode::Assignment(span, name, Box::new(rhs));
+            let span = self.span(lhs).merge(&op_span.merge(&self.span(rhs)));
+            lhs = match operator.infix {
+                Infix::Assign => {
+                    let AstNode::Symbol(_, name) = &self.ast[lhs] else {
+                        unreachable!("checked before parsing the rhs");
+                    };
+                    let name = name.clone();
+                    let span = self.span(lhs).merge(&self.span(rhs));
+                    self.add(AstNode::Assignment(span, name, rhs))
+                }
+                Infix::Coalesce => {
+                    let span = self.span(lhs).merge(&self.span(rhs));
+                    self.add(AstNode::Coalesce(span, lhs, rhs))
+                }
+                Infix::Logical(op) => self.add(AstNode::Logical(span, op, lhs, rhs)),
+                Infix::Binary(op) => self.add(AstNode::Binary(span, op, lhs, rhs)),
+            };
         }
 
         Ok(lhs)
     }
 
-    fn parse_equality(&mut self) -> Result<AstNode> {
-        let mut lhs = self.parse_comparison()?;
-
-        while let Some((&op_span, op)) = matches_keyword!(
-            self.tokenizer.peek() => BangEqual, EqualEqual,
-        ) {
-            log::debug!("parse_equality @ op_span: {:?}", op_span);
-
-            self.tokenizer.next();
-            let rhs = self.parse_comparison()?;
-            let span = lhs.span().merge(&op_span.merge(&rhs.span()));
-
-            lhs =
-                AstNode::Application(span, Box::new(AstNode::Symbol(op_span, op)), vec![lhs, rhs]);
+    fn peek_infix(&mut self) -> Option<(Span, &'static Operator)> {
+        match self.tokenizer.peek() {
+            Some(Token::Keyword(span, keyword)) => INFIX_OPERATORS
+                .iter()
+                .find(|operator| operator.keyword == *keyword)
+                .map(|operator| (*span, operator)),
+            _ => None,
         }
-
-        Ok(lhs)
     }
 
-    fn parse_comparison(&mut self) -> Result<AstNode> {
-        let mut lhs = self.parse_term()?;
+    fn parse_unary(&mut self) -> Result<NodeId> {
+        let Some((op_span, op)) = self.peek_prefix() else {
+            return self.parse_call();
+        };
+        log::debug!(target: phase::PARSER, "parse_unary @ op_span: {:?}", op_span);
 
-        while let Some((&op_span, op)) = matches_keyword!(
-            self.tokenizer.peek() => Greater, GreaterEqual, Less, LessEqual,
-        ) {
-            log::debug!("parse_comparison @ op_span: {:?}", op_span);
+        self.tokenizer.next();
+        let rhs = self.nested(Self::parse_unary)?;
+        let span = op_span.merge(&self.span(rhs));
 
-            self.tokenizer.next();
-            let rhs = self.parse_term()?;
-            let span = lhs.span().merge(&op_span.merge(&rhs.span()));
+        Ok(self.add(AstNode::Unary(span, op, rhs)))
+    }
 
-            lhs =
-                AstNode::Application(span, Box::new(AstNode::Symbol(op_span, op)), vec![lhs, rhs]);
+    fn peek_prefix(&mut self) -> Option<(Span, UnaryOp)> {
+        match self.tokenizer.peek() {
+            Some(Token::Keyword(span, keyword)) => PREFIX_OPERATORS
+                .iter()
+                .find(|(prefix, _)| prefix == keyword)
+                .map(|(_, op)| (*span, *op)),
+            _ => None,
         }
-
-        Ok(lhs)
     }
 
-    fn parse_term(&mut self) -> Result<AstNode> {
-        let mut lhs = self.parse_factor()?;
-
-        while let Some((&op_span, op)) = matches_keyword!(
-            self.tokenizer.peek() => Minus, Plus,
-        ) {
-            log::debug!("parse_term @ op_span: {:?}", op_span);
-
-            self.tokenizer.next();
-            let rhs = self.parse_factor()?;
-            let span = lhs.span().merge(&op_span.merge(&rhs.span()));
+    // Calls are any number of (args), [index], or .property after a primary, eg f(1)(2) or Color.Red.name
+    // If any ?. appears, the whole chain is wrapped so that a nil receiver short circuits it
+    fn parse_call(&mut self) -> Result<NodeId> {
+        let mut callee = self.parse_primary()?;
+        let mut optional = false;
+
+        loop {
+            if let Some((_, op)) = matches_keyword!(self.tokenizer.peek() => Dot, QuestionDot) {
+                self.deeper()?;
+                self.tokenizer.next();
+
+                let (name_span, name) = match self.tokenizer.next() {
+                    Some(Token::Identifier(span, name)) => (span, name),
+                    Some(token) => {
+                        let error = unexpected(&token, "Expect property name after '.'");
+                        return Err(error.into());
+                    }
+                    None => unreachable!("EOF is always emitted"),
+                };
+
+                let span = self.span(callee).merge(&name_span);
+                callee = if op == Keyword::QuestionDot.to_value() {
+                    optional = true;
+                    self.add(AstNode::OptionalGet(span, callee, name))
+                } else {
+                    self.add(AstNode::Get(span, callee, name))
+                };
+                continue;
+            }
 
-            lhs =
-                AstNode::Application(span, Box::new(AstNode::Symbol(op_span, op)), vec![lhs, rhs]);
-        }
+            if let Some(Token::Keyword(_, Keyword::LeftBracket)) = self.tokenizer.peek() {
+                self.deeper()?;
+                self.tokenizer.next();
 
-        Ok(lhs)
-    }
+                let index = self.parse_expression()?;
+                let right_bracket =
+                    self.consume_keyword(Keyword::RightBracket, "Expect ']' after index")?;
+                let span = self.span(callee).merge(right_bracket.span());
 
-    fn parse_factor(&mut self) -> Result<AstNode> {
-        let mut lhs = self.parse_unary()?;
+                callee = self.add(AstNode::Index(span, callee, index));
+                continue;
+            }
 
-        while let Some((&op_span, op)) = matches_keyword!(
-            self.tokenizer.peek() => Slash, Star,
-        ) {
-            log::debug!("parse_factor @ op_span: {:?}", op_span);
+            if !matches!(
+                self.tokenizer.peek(),
+                Some(Token::Keyword(_, Keyword::LeftParen))
+            ) {
+                break;
+            }
 
+            log::debug!(target: phase::PARSER, "parse_call @ {:?}", self.span(callee));
+            self.deeper()?;
             self.tokenizer.next();
-            let rhs = self.parse_unary()?;
-            let span = lhs.span().merge(&op_span.merge(&rhs.span()));
 
-            lhs =
-                AstNode::Application(span, Box::new(AstNode::Symbol(op_span, op)), vec![lhs, rhs]);
-        }
+            let mut args = vec![];
+            if !matches!(
+                self.tokenizer.peek(),
+                Some(Token::Keyword(_, Keyword::RightParen))
+            ) {
+                loop {
+                    args.push(self.parse_expression()?);
 
-        Ok(lhs)
-    }
+                    if let Some(Token::Keyword(_, Keyword::Comma)) = self.tokenizer.peek() {
+                        self.tokenizer.next();
+                    } else {
+                        break;
+                    }
+                }
+            }
 
-    fn parse_unary(&mut self) -> Result<AstNode> {
-        if let Some((&op_span, op)) = matches_keyword!(
-            self.tokenizer.peek() => Bang, Minus,
-        ) {
-            log::debug!("parse_unary @ op_span: {:?}", op_span);
+            let right_paren =
+                self.consume_keyword(Keyword::RightParen, "Expect ')' after arguments")?;
+            let span = self.span(callee).merge(right_paren.span());
 
-            self.tokenizer.next();
-            let rhs = self.parse_unary()?;
-            let span = op_span.merge(&rhs.span());
-
-            Ok(AstNode::Application(
-                span,
-                Box::new(AstNode::Symbol(op_span, op)),
-                vec![rhs],
-            ))
-        } else {
-            self.parse_primary()
+            callee = self.add(AstNode::Call(span, callee, args));
+        }
+
+        if optional {
+            callee = self.add(AstNode::OptionalChain(self.span(callee), callee));
         }
+
+        Ok(callee)
     }
 
-    fn parse_primary(&mut self) -> Result<AstNode> {
+    fn parse_primary(&mut self) -> Result<NodeId> {
         if let Some(token) = self.tokenizer.next() {
-            log::debug!("parse_primary @ {:?}", token.span());
+            log::debug!(target: phase::PARSER, "parse_primary @ {:?}", token.span());
 
             match token {
-                Token::Literal(span, _, v) => Ok(AstNode::Literal(span, v)),
+                Token::Literal(span, _, v) => Ok(self.add(AstNode::Literal(span, v))),
                 Token::Keyword(left_span, Keyword::LeftParen) => {
                     let group = self.parse_expression()?;
-                    if let Some(Token::Keyword(right_span, Keyword::RightParen)) =
-                        self.tokenizer.next()
-                    {
-                        let span = left_span.merge(&right_span);
-                        Ok(AstNode::Group(span, vec![group]))
-                    } else {
-                        let line = self.line_number(left_span.start);
-                        Err(anyhow!(
-                            "[line {}] Error at '{}': Expect expression",
-                            line,
-                            token
-                        ))
+                    match self.tokenizer.next() {
+                        Some(Token::Keyword(right_span, Keyword::RightParen)) => {
+                            let span = left_span.merge(&right_span);
+                            Ok(self.add(AstNode::Group(span, group)))
+                        }
+                        Some(end @ Token::EOF(_)) => {
+                            Err(unexpected(&end, "Expect ')' after expression").into())
+                        }
+                        _ => {
+                            let span = left_span;
+                            Err(ParseError::ExpectedExpression(span, token.lexeme()).into())
+                        }
                     }
                 }
-                Token::EOF => Err(anyhow!("Error at EOF: Expect expression")),
-                Token::Identifier(span, id) => Ok(AstNode::Symbol(span, id)),
+                Token::EOF(_) => Err(unexpected(&token, "Expect expression").into()),
+                Token::Identifier(span, id) => {
+                    let name = self.names.intern(&id);
+                    Ok(self.add(AstNode::Symbol(span, name)))
+                }
+                // print is a statement, but can also be used as a function value (var p = print;)
+                Token::Keyword(span, Keyword::Print) => {
+                    let name = self.names.intern(Keyword::Print.to_value());
+                    Ok(self.add(AstNode::Symbol(span, name)))
+                }
                 Token::Keyword(span, keyword) => {
-                    let line = self.line_number(span.start);
-                    Err(anyhow!(
-                        "[line {}] Error at '{}': Expect expression",
-                        line,
-                        keyword.to_value()
-                    ))
+                    let found = keyword.to_value().to_string();
+                    Err(ParseError::ExpectedExpression(span, found).into())
                 }
             }
         } else {
-            let line = self.line_number(self.tokenizer.source.len());
-            Err(anyhow!("[line {line}] Error at end: Expect '}}' ."))
+            let span = self.tokenizer.end_span();
+            Err(ParseError::UnexpectedEnd(span, "Expect '}' .".to_string()).into())
         }
     }
 
-    fn consume_semicolon_or_eof(&mut self) -> Result<Token> {
+    // The ; if there was one, None at EOF (which isn't consumed, and isn't part of the statement)
+    fn consume_semicolon_or_eof(&mut self) -> Result<Option<Token>> {
         match self.tokenizer.peek() {
-            Some(Token::Keyword(_, Keyword::Semicolon)) => Ok(self.tokenizer.next().unwrap()),
-            Some(Token::EOF) => Ok(Token::EOF),
+            Some(Token::Keyword(_, Keyword::Semicolon)) => Ok(self.tokenizer.next()),
+            Some(Token::EOF(_)) => Ok(None),
 
-            Some(token) => {
-                let line = token.span().line;
-                Err(anyhow!("[line {}] Error: Expect ';'", line))
-            }
+            Some(token) => Err(ParseError::ExpectedSemicolon(*token.span()).into()),
 
             _ => unreachable!("EOF should be handled above"),
         }
     }
+
+    // Check that the next token is the given keyword without consuming it
+    fn expect_keyword(&mut self, keyword: Keyword, message: &str) -> Result<()> {
+        match self.tokenizer.peek() {
+            Some(Token::Keyword(_, k)) if *k == keyword => Ok(()),
+            Some(token) => Err(unexpected(token, message).into()),
+            None => unreachable!("EOF is always emitted"),
+        }
+    }
+
+    fn consume_identifier(&mut self, message: &str) -> Result<String> {
+        match self.tokenizer.next() {
+            Some(Token::Identifier(_, name)) => Ok(name),
+            Some(token) => Err(unexpected(&token, message).into()),
+            None => unreachable!("EOF is always emitted"),
+        }
+    }
+
+    fn consume_keyword(&mut self, keyword: Keyword, message: &str) -> Result<Token> {
+        self.expect_keyword(keyword, message)?;
+        Ok(self.tokenizer.next().unwrap())
+    }
 }
 
-impl Parser<'_> {
-    fn line_number(&self, byte_pos: usize) -> usize {
-        self.tokenizer.source[..byte_pos].lines().count()
+// Found token instead of what the message says was expected, reported at the end for EOF
+fn unexpected(token: &Token, message: &str) -> ParseError {
+    match token {
+        Token::EOF(span) => ParseError::UnexpectedEnd(*span, message.to_string()),
+        token => ParseError::Expected(*token.span(), token.lexeme(), message.to_string()),
     }
+}
 
+impl Parser<'_> {
     pub fn tokenizer_had_errors(&self) -> bool {
         self.tokenizer.had_errors()
     }
@@ -458,4 +1244,152 @@ impl Parser<'_> {
     pub fn tokenizer_iter_errors(&self) -> impl Iterator<Item = &TokenizerError> {
         self.tokenizer.iter_errors()
     }
+
+    pub fn tokenizer_iter_comments(&self) -> impl Iterator<Item = &Comment> {
+        self.tokenizer.iter_comments()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(ast: &mut Ast, n: i64) -> NodeId {
+        ast.add(AstNode::Literal(Span::ZERO, Value::Integer(n)))
+    }
+
+    #[test]
+    fn nodes_are_numbered_in_the_order_they_are_added() {
+        let mut ast = Ast::new();
+        let one = number(&mut ast, 1);
+        let two = number(&mut ast, 2);
+        let sum = ast.add(AstNode::Binary(Span::ZERO, BinaryOp::Plus, one, two));
+
+        assert_eq!((one.index(), two.index(), sum.index()), (0, 1, 2));
+        assert!(matches!(ast[two], AstNode::Literal(_, Value::Integer(2))));
+        assert_eq!(ast[sum].children(), [one, two]);
+        assert_eq!(ast.nodes().count(), 3);
+    }
+
+    #[test]
+    fn the_root_is_the_last_node_added() {
+        let mut ast = Ast::new();
+        let value = number(&mut ast, 1);
+        assert_eq!(ast.root(), value);
+
+        let print = ast.add(AstNode::Print(Span::ZERO, value));
+        let program = ast.add(AstNode::Program(Span::ZERO, vec![print]));
+        assert_eq!(ast.root(), program);
+        assert_eq!(ast.to_string(), "(print 1.0)\n");
+
+        let parsed = Parser::from(Tokenizer::new("print 1;")).parse().unwrap();
+        assert!(matches!(parsed[parsed.root()], AstNode::Program(_, _)));
+    }
+
+    #[test]
+    #[should_panic(expected = "an empty Ast has no root")]
+    fn an_empty_ast_has_no_root() {
+        Ast::new().root();
+    }
+
+    // Nodes are only referred to by id, so one can be the child of more than one parent
+    #[test]
+    fn nodes_can_be_shared() {
+        let mut ast = Ast::new();
+        let one = number(&mut ast, 1);
+        let doubled = ast.add(AstNode::Binary(Span::ZERO, BinaryOp::Plus, one, one));
+        let negated = ast.add(AstNode::Unary(Span::ZERO, UnaryOp::Negate, one));
+        ast.add(AstNode::Block(Span::ZERO, vec![doubled, negated]));
+
+        assert_eq!(ast.to_string(), "{(+ 1.0 1.0) (- 1.0)}");
+        assert_eq!(ast.nodes().count(), 4);
+    }
+
+    #[test]
+    fn maps_children_in_order() {
+        let mut ast = Ast::new();
+        let one = number(&mut ast, 1);
+        let two = number(&mut ast, 2);
+        let three = number(&mut ast, 3);
+        let sum = ast.add(AstNode::Binary(Span::ZERO, BinaryOp::Plus, one, two));
+
+        let mut seen = vec![];
+        let mapped = ast[sum].map_children(&mut |id| {
+            seen.push(id);
+            three
+        });
+        assert_eq!(seen, [one, two]);
+        assert_eq!(mapped.children(), [three, three]);
+    }
+
+    fn parse_error(source: &str) -> ParseError {
+        let error = Parser::from(Tokenizer::new(source)).parse().unwrap_err();
+        error.downcast::<ParseError>().expect("a ParseError")
+    }
+
+    #[test]
+    fn errors_are_typed_with_where_they_happened() {
+        let error = parse_error("var a = 1;\n1 = 2;");
+        assert!(matches!(error, ParseError::InvalidAssignmentTarget(span) if span.line == 2));
+        assert_eq!(
+            error.to_string(),
+            "[line 2] Error at '=': Invalid assignment target."
+        );
+
+        match parse_error("print ;") {
+            ParseError::ExpectedExpression(span, found) => {
+                assert_eq!(found, ";");
+                assert_eq!((span.line, span.column, span.start, span.end), (1, 7, 6, 7));
+            }
+            error => panic!("expected ExpectedExpression, got {error:?}"),
+        }
+
+        let error = parse_error("print (1");
+        assert!(
+            matches!(error, ParseError::UnexpectedEnd(_, ref message) if message == "Expect ')' after expression")
+        );
+        assert_eq!(
+            error.to_string(),
+            "[line 1] Error at end: Expect ')' after expression"
+        );
+    }
+
+    #[test]
+    fn parse_all_skips_to_the_next_statement_after_each_error() {
+        let source = "var a = ;\nprint 1;\nprint (;\n{ print 2 }\nprint 3;";
+        let (ast, errors) = Parser::from(Tokenizer::new(source)).parse_all();
+
+        let messages = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "[line 1] Error at ';': Expect expression",
+                "[line 3] Error at ';': Expect expression",
+                "[line 4] Error: Expect ';'",
+            ]
+        );
+
+        // Statements around the errors are still there
+        let AstNode::Program(_, statements) = &ast[ast.root()] else {
+            panic!("expected a program");
+        };
+        assert_eq!(statements.len(), 2);
+    }
+
+    #[test]
+    fn variable_names_share_one_allocation() {
+        let ast = Parser::from(Tokenizer::new("var a = 1;\nprint a;"))
+            .parse()
+            .unwrap();
+        let names = ast
+            .nodes()
+            .filter_map(|node| match node {
+                AstNode::Declaration(_, name, _) | AstNode::Symbol(_, name) => Some(name),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(names.len(), 2);
+        assert!(Arc::ptr_eq(names[0], names[1]));
+    }
 }
@@ -0,0 +1,40 @@
+use std::time::{Duration, Instant};
+
+// The log target for each phase, so eg --debug=parser only shows the parser's messages
+pub const TOKENIZER: &str = "tokenizer";
+pub const PARSER: &str = "parser";
+pub const RESOLVER: &str = "resolver";
+pub const EVALUATOR: &str = "evaluator";
+pub const CALL: &str = "evaluator::call"; // Under the evaluator, so it includes calls
+
+/// A phase (or a call) from when it starts until it's dropped, logged at both ends
+///
+/// Profile gets how long calls took from these, so it and the log always agree.
+pub struct Timed {
+    target: &'static str,
+    what: String,
+    started: Instant,
+}
+
+impl Timed {
+    pub fn start(target: &'static str, what: impl Into<String>) -> Self {
+        let what = what.into();
+        log::debug!(target: target, "{what} started");
+
+        Self {
+            target,
+            what,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+impl Drop for Timed {
+    fn drop(&mut self) {
+        log::debug!(target: self.target, "{} done in {:?}", self.what, self.elapsed());
+    }
+}
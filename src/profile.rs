@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::hook::{EvalHook, Node};
+use crate::json::Json;
+use crate::span::Span;
+use crate::values::Value;
+
+/// How many times something ran and how long it took
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stats {
+    pub count: usize,
+    pub time: Duration,
+}
+
+impl Stats {
+    fn add(&mut self, time: Duration) {
+        self.count += 1;
+        self.time += time;
+    }
+}
+
+/// Where the time went in a run, filled in as a hook while Context::profile is set
+///
+/// Lines and nodes get self time, not counting the nodes inside them, so each adds up to the
+/// whole run. Functions get the total time of their calls, including anything they call.
+#[derive(Debug, Default)]
+pub struct Profile {
+    sources: Vec<String>,
+    lines: HashMap<(usize, usize), Stats>, // Source index and line
+    nodes: HashMap<(usize, usize, usize, &'static str), Stats>, // And column and kind
+    functions: HashMap<String, Stats>,
+    started: Vec<Instant>,   // When each node being evaluated started
+    children: Vec<Duration>, // And the time spent in its children
+}
+
+/// One row of the report, the most time first
+#[derive(Debug, Clone, PartialEq)]
+pub struct HotSpot {
+    pub name: String,
+    pub stats: Stats,
+}
+
+// Only the hottest of each are worth reading in the text report
+const REPORT_ROWS: usize = 10;
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // There are only ever a few sources, so this is faster than hashing names for every node
+    fn source_index(&mut self, source: &str) -> usize {
+        match self.sources.iter().position(|s| s == source) {
+            Some(index) => index,
+            None => {
+                self.sources.push(source.to_string());
+                self.sources.len() - 1
+            }
+        }
+    }
+
+    pub fn lines(&self) -> Vec<HotSpot> {
+        hottest(
+            self.lines.iter().map(|((source, line), stats)| {
+                (format!("{}:{line}", self.sources[*source]), *stats)
+            }),
+        )
+    }
+
+    pub fn nodes(&self) -> Vec<HotSpot> {
+        hottest(
+            self.nodes
+                .iter()
+                .map(|((source, line, column, kind), stats)| {
+                    let name = format!("{}:{line}:{column} {kind}", self.sources[*source]);
+                    (name, *stats)
+                }),
+        )
+    }
+
+    pub fn functions(&self) -> Vec<HotSpot> {
+        hottest(
+            self.functions
+                .iter()
+                .map(|(name, stats)| (name.clone(), *stats)),
+        )
+    }
+
+    /// The hottest lines, functions, and nodes as a table for people to read
+    pub fn report(&self) -> String {
+        let mut output = String::new();
+        let sections = [
+            ("lines (self time)", "line", self.lines()),
+            ("functions (total time)", "function", self.functions()),
+            ("nodes (self time)", "node", self.nodes()),
+        ];
+
+        for (title, what, rows) in sections {
+            if !output.is_empty() {
+                output.push('\n');
+            }
+            output.push_str(&format!("{title}:\n"));
+            output.push_str(&format!("{:>12} {:>10}  {what}\n", "time", "count"));
+            for row in rows.iter().take(REPORT_ROWS) {
+                output.push_str(&format!(
+                    "{:>12.3?} {:>10}  {}\n",
+                    row.stats.time, row.stats.count, row.name
+                ));
+            }
+            if rows.len() > REPORT_ROWS {
+                output.push_str(&format!(
+                    "{:>23}  ... {} more\n",
+                    "",
+                    rows.len() - REPORT_ROWS
+                ));
+            }
+        }
+
+        output
+    }
+
+    /// Everything, hottest first, with times in nanoseconds
+    pub fn json(&self) -> Json {
+        let rows = |rows: Vec<HotSpot>| {
+            Json::Array(
+                rows.into_iter()
+                    .map(|row| {
+                        Json::object([
+                            ("name", row.name.into()),
+                            ("count", row.stats.count.into()),
+                            ("nanos", Json::Integer(row.stats.time.as_nanos() as i64)),
+                        ])
+                    })
+                    .collect(),
+            )
+        };
+
+        Json::object([
+            ("lines", rows(self.lines())),
+            ("functions", rows(self.functions())),
+            ("nodes", rows(self.nodes())),
+        ])
+    }
+}
+
+impl EvalHook for Profile {
+    fn before_node(&mut self, _node: &Node) -> Result<()> {
+        self.children.push(Duration::ZERO);
+        self.started.push(Instant::now());
+        Ok(())
+    }
+
+    fn after_node(&mut self, node: &Node, _result: &Result<Value>) -> Result<()> {
+        let elapsed = self
+            .started
+            .pop()
+            .map_or(Duration::ZERO, |started| started.elapsed());
+        let children = self.children.pop().unwrap_or_default();
+        if let Some(parent) = self.children.last_mut() {
+            *parent += elapsed;
+        }
+
+        let own = elapsed.saturating_sub(children);
+        let (span, kind) = (node.span(), node.kind());
+        let source = self.source_index(node.source);
+        self.lines.entry((source, span.line)).or_default().add(own);
+        self.nodes
+            .entry((source, span.line, span.column, kind))
+            .or_default()
+            .add(own);
+        Ok(())
+    }
+
+    fn on_call(&mut self, callee: &Value, _span: Span, elapsed: Duration) {
+        self.functions
+            .entry(callee.to_string())
+            .or_default()
+            .add(elapsed);
+    }
+}
+
+// Most time first, ties by name so the order is stable
+fn hottest(rows: impl Iterator<Item = (String, Stats)>) -> Vec<HotSpot> {
+    let mut rows = rows
+        .map(|(name, stats)| HotSpot { name, stats })
+        .collect::<Vec<_>>();
+    rows.sort_by(|a, b| b.stats.time.cmp(&a.stats.time).then(a.name.cmp(&b.name)));
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+
+    fn profile(source: &str) -> Profile {
+        let mut interpreter = Interpreter::new();
+        interpreter.context_mut().profile = Some(Profile::new());
+        interpreter.run_named("test.lox", source).unwrap();
+        interpreter.context_mut().profile.take().unwrap()
+    }
+
+    fn counts(rows: Vec<HotSpot>) -> Vec<(String, usize)> {
+        let mut counts = rows
+            .into_iter()
+            .map(|row| (row.name, row.stats.count))
+            .collect::<Vec<_>>();
+        counts.sort();
+        counts
+    }
+
+    #[test]
+    fn counts_every_line_node_and_call() {
+        let profile =
+            profile("var total = 0;\nfor (var x in list(1, 2)) {\n  total = total + x;\n}");
+
+        assert_eq!(
+            counts(profile.lines()),
+            vec![
+                ("test.lox:0".to_string(), 1),
+                ("test.lox:1".to_string(), 2),
+                ("test.lox:2".to_string(), 7),
+                ("test.lox:3".to_string(), 8),
+            ]
+        );
+        assert!(counts(profile.nodes()).contains(&("test.lox:3:3 Assignment".to_string(), 2)));
+        assert_eq!(
+            counts(profile.functions()),
+            vec![("<builtin list>".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn reports_and_json_have_every_section() {
+        let profile = profile("print 1;");
+
+        let report = profile.report();
+        for title in [
+            "lines (self time):",
+            "functions (total time):",
+            "nodes (self time):",
+        ] {
+            assert!(report.contains(title), "{report}");
+        }
+
+        let json = profile.json().to_string();
+        assert!(
+            json.contains("\"name\":\"test.lox:1\",\"count\":2"),
+            "{json}"
+        );
+    }
+}
@@ -0,0 +1,330 @@
+use std::ops::Range;
+
+use crate::parser::{Ast, AstNode, NodeId, Parser};
+use crate::phase;
+use crate::span::Span;
+use crate::tokenizer::{Keyword, Token, Tokenizer};
+
+/// A change to a source: the bytes in range (from before the change) are replaced with text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+impl TextEdit {
+    pub fn new(range: Range<usize>, text: impl Into<String>) -> Self {
+        Self {
+            range,
+            text: text.into(),
+        }
+    }
+
+    /// The source with this edit made
+    pub fn apply(&self, source: &str) -> String {
+        let mut edited = source.to_string();
+        edited.replace_range(self.range.clone(), &self.text);
+        edited
+    }
+}
+
+impl Parser<'_> {
+    /// Parse old_source with edit made, only redoing the statements the edit touched
+    ///
+    /// Statements before the edit are copied as they were, so they keep the same NodeIds, and
+    /// ones after are copied with their spans moved. The result is the same as parsing all of
+    /// the new source; if the changed statements don't parse on their own (eg the edit opened a
+    /// string or a block) that's what it does instead.
+    pub fn reparse(
+        old: &Ast,
+        old_source: &str,
+        edit: &TextEdit,
+    ) -> Result<Ast, Vec<anyhow::Error>> {
+        let source = edit.apply(old_source);
+        match reparse_statements(old, old_source, &source, edit) {
+            Some(ast) => Ok(ast),
+            None => {
+                log::debug!(target: phase::PARSER, "Reparsing all of the source, the edit didn't stay in its statements");
+                parse_all_of(&source)
+            }
+        }
+    }
+}
+
+fn parse_all_of(source: &str) -> Result<Ast, Vec<anyhow::Error>> {
+    let mut parser = Parser::from(Tokenizer::new(source));
+    let ast = parser.parse().map_err(|e| vec![e])?;
+
+    if parser.tokenizer_had_errors() {
+        return Err(parser
+            .tokenizer_iter_errors()
+            .map(|e| e.clone().into())
+            .collect());
+    }
+
+    Ok(ast)
+}
+
+// None if the statements around the edit can't be reparsed by themselves
+fn reparse_statements(old: &Ast, old_source: &str, source: &str, edit: &TextEdit) -> Option<Ast> {
+    let AstNode::Program(_, statements) = &old[old.root()] else {
+        return None;
+    };
+    let spans = statements
+        .iter()
+        .map(|id| old[*id].span())
+        .collect::<Vec<_>>();
+
+    // Statements touching the edit, and one more on either side in case the edit joins them
+    let first = spans
+        .iter()
+        .position(|span| span.byte_end >= edit.range.start)
+        .unwrap_or(spans.len())
+        .saturating_sub(1);
+    let last = spans
+        .iter()
+        .rposition(|span| span.byte_start <= edit.range.end)
+        .map_or(1, |i| i + 2)
+        .min(spans.len());
+
+    // Reparse everything up to the next statement that's kept, comments and whitespace included.
+    // Expression statements' spans don't include their ;, so start at the first statement changed
+    // rather than after the one before.
+    let start = match first {
+        0 => 0,
+        i => spans[i].byte_start,
+    };
+    let old_end = spans
+        .get(last)
+        .map_or(old_source.len(), |span| span.byte_start);
+    let end = old_end + edit.text.len() - edit.range.len();
+    let region = source.get(start..end)?;
+
+    // A comment running into the next statement would hide it, only a full parse gets that right
+    if region
+        .rsplit('\n')
+        .next()
+        .is_some_and(|line| line.contains("//"))
+    {
+        return None;
+    }
+
+    // Only the last statement can end at EOF without a ;, and the end of the region isn't EOF
+    if last < statements.len() {
+        let ending = Tokenizer::new(region)
+            .take_while(|token| !matches!(token, Token::EOF(_)))
+            .last();
+        if !matches!(
+            ending,
+            None | Some(Token::Keyword(_, Keyword::Semicolon | Keyword::RightBrace))
+        ) {
+            return None;
+        }
+    }
+
+    let mut parser = Parser::from(Tokenizer::new(region));
+    let parsed = parser.parse().ok()?;
+    if parser.tokenizer_had_errors() {
+        return None;
+    }
+    drop(parser);
+
+    let mut ast = Ast::new();
+    let mut nodes = copy(&mut ast, old, None, &statements[..first], &Shift::NONE);
+
+    let AstNode::Program(_, changed) = &parsed[parsed.root()] else {
+        unreachable!("parse always makes a Program");
+    };
+    let shift = Shift::between(region, 0, source, start);
+    nodes.extend(copy(&mut ast, &parsed, None, changed, &shift));
+
+    if last < statements.len() {
+        let shift = Shift::between(old_source, old_end, source, end);
+        let before = statements[last - 1];
+        nodes.extend(copy(
+            &mut ast,
+            old,
+            Some(before),
+            &statements[last..],
+            &shift,
+        ));
+    }
+
+    let span = nodes
+        .iter()
+        .fold(Span::ZERO, |span, id| span.merge(&ast[*id].span()));
+    ast.add(AstNode::Program(span, nodes));
+    Some(ast)
+}
+
+// How to move spans from the same text somewhere else, eg after an edit
+struct Shift {
+    lines: isize,
+    columns: isize, // Only for nodes on the first line, where the text moved within the line
+    chars: isize,
+    bytes: isize,
+    first_line_end: usize, // Where that line ends before moving
+}
+
+impl Shift {
+    const NONE: Shift = Shift {
+        lines: 0,
+        columns: 0,
+        chars: 0,
+        bytes: 0,
+        first_line_end: 0,
+    };
+
+    // From the text at byte from in one source to the same text at byte to in another
+    fn between(from_source: &str, from: usize, to_source: &str, to: usize) -> Shift {
+        let (before, after) = (&from_source[..from], &to_source[..to]);
+        let column = |before: &str| before.chars().rev().take_while(|c| *c != '\n').count();
+        let lines = |before: &str| before.matches('\n').count();
+
+        Shift {
+            lines: lines(after) as isize - lines(before) as isize,
+            columns: column(after) as isize - column(before) as isize,
+            chars: after.chars().count() as isize - before.chars().count() as isize,
+            bytes: to as isize - from as isize,
+            first_line_end: from_source[from..]
+                .find('\n')
+                .map_or(from_source.len(), |i| from + i),
+        }
+    }
+
+    // Lines are the tokenizer's (where a token ends, for strings with newlines in them), but
+    // columns are always where it starts, so only the offset says if it's on the first line
+    fn apply(&self, span: Span) -> Span {
+        let by = |n: usize, delta: isize| (n as isize + delta) as usize;
+
+        Span {
+            line: by(span.line, self.lines),
+            column: if span.byte_start < self.first_line_end {
+                by(span.column, self.columns)
+            } else {
+                span.column
+            },
+            start: by(span.start, self.chars),
+            end: by(span.end, self.chars),
+            byte_start: by(span.byte_start, self.bytes),
+            byte_end: by(span.byte_end, self.bytes),
+        }
+    }
+}
+
+// A statement's nodes are all together, in the order they were added (along with any the parser
+// made and then didn't use), so copying every node from after the one before to the last
+// statement gives the same NodeIds as a fresh parse
+fn copy(
+    ast: &mut Ast,
+    from: &Ast,
+    before: Option<NodeId>,
+    statements: &[NodeId],
+    shift: &Shift,
+) -> Vec<NodeId> {
+    let Some(last) = statements.last() else {
+        return vec![];
+    };
+    let first = before.map_or(0, |id| id.index() + 1);
+
+    let mut copies = vec![];
+    for node in from.nodes().skip(first).take(last.index() + 1 - first) {
+        let node = with_children(node, shift, &mut |child| copies[child.index() - first]);
+        copies.push(ast.add(node));
+    }
+
+    statements
+        .iter()
+        .map(|id| copies[id.index() - first])
+        .collect()
+}
+
+// The same node with its span shifted and each child replaced
+fn with_children(
+    node: &AstNode,
+    shift: &Shift,
+    child: &mut impl FnMut(NodeId) -> NodeId,
+) -> AstNode {
+    let mut node = node.map_children(child);
+    let span = node.span_mut();
+    *span = shift.apply(*span);
+    node
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "var a = 1;\nprint a + 2;\n{ var b = \"b\"; print b; }\nprint a";
+
+    // Everything about a tree but its NodeIds, which only say what order nodes were added in
+    fn shape(ast: &Ast, id: NodeId) -> String {
+        let node = &ast[id];
+        let children = node.children().into_iter().map(|child| shape(ast, child));
+        format!(
+            "{} {:?} ({})",
+            node.kind(),
+            node.span(),
+            children.collect::<Vec<_>>().join(" ")
+        )
+    }
+
+    fn statements(ast: &Ast) -> Vec<NodeId> {
+        match &ast[ast.root()] {
+            AstNode::Program(_, statements) => statements.clone(),
+            _ => unreachable!("parse always makes a Program"),
+        }
+    }
+
+    // Reparsing has to get exactly what parsing the edited source from scratch does
+    fn check(edit: TextEdit) -> (Ast, Ast) {
+        let old = Parser::from(Tokenizer::new(SOURCE)).parse().unwrap();
+        let reparsed = Parser::reparse(&old, SOURCE, &edit).unwrap();
+
+        let source = edit.apply(SOURCE);
+        let parsed = Parser::from(Tokenizer::new(&source)).parse().unwrap();
+        assert_eq!(reparsed.to_string(), parsed.to_string());
+        assert_eq!(
+            shape(&reparsed, reparsed.root()),
+            shape(&parsed, parsed.root())
+        );
+
+        (old, reparsed)
+    }
+
+    #[test]
+    fn applies_edits() {
+        let edit = TextEdit::new(8..9, "42");
+        assert_eq!(edit.apply("var a = 1;"), "var a = 42;");
+    }
+
+    #[test]
+    fn reparses_an_edit_inside_one_statement() {
+        let start = SOURCE.find("2;").unwrap();
+        let (old, reparsed) = check(TextEdit::new(start..start + 1, "(3 * 4)"));
+
+        // The first statement was before the edit, so it's kept as it was
+        assert_eq!(statements(&old)[0], statements(&reparsed)[0]);
+    }
+
+    #[test]
+    fn reparses_an_edit_spanning_two_statements() {
+        let start = SOURCE.find("2;").unwrap();
+        let end = SOURCE.find("print b").unwrap();
+        check(TextEdit::new(start..end, "3;\n{ var c = nil; "));
+    }
+
+    #[test]
+    fn reparses_an_edit_at_eof() {
+        check(TextEdit::new(
+            SOURCE.len()..SOURCE.len(),
+            " + 1;\nprint \"done\";",
+        ));
+    }
+
+    #[test]
+    fn reparses_all_of_the_source_when_an_edit_comments_out_a_line() {
+        let start = SOURCE.find("{ var b").unwrap();
+        check(TextEdit::new(start..start, "// "));
+    }
+}
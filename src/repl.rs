@@ -0,0 +1,406 @@
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Editor, Helper};
+
+use crate::builtins::{BuiltIn, Namespace};
+use crate::context::Context;
+use crate::diagnostic;
+use crate::environment::EnvironmentStack;
+use crate::evaluator::{self, EvalOutcome, Evaluate};
+use crate::formatter;
+use crate::named_source::NamedSource;
+use crate::parser::{Ast, AstNode, NodeId, Parser};
+use crate::tokenizer::{Keyword, Tokenizer};
+use crate::values::Value;
+
+const PROMPT: &str = "> ";
+const HISTORY_FILE: &str = ".jp-lox_history";
+
+// A single line of input is either an expression to print or statements to run silently
+enum Line {
+    Expression(Ast),
+    Statements(Ast),
+}
+
+/// Read, evaluate, and print lines until EOF, keeping variables between lines
+///
+/// Lines run in env, so whatever's defined in it to start with (eg the standard library) is there.
+/// In a terminal lines are read with rustyline, so they can be edited, recalled from the history in
+/// ~/.jp-lox_history (or searched for with Ctrl-R), and tab completed.
+pub fn run(mut env: EnvironmentStack<Value>, mut ctx: Context) -> Result<()> {
+    let mut editor = Editor::<Completions, DefaultHistory>::new()?;
+    let history = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+    if let Some(path) = &history {
+        // There isn't one the first time
+        let _ = editor.load_history(path);
+    }
+
+    let interactive = io::stdin().is_terminal() && io::stdout().is_terminal();
+    let mut session = Session::default();
+
+    loop {
+        let line = if interactive {
+            editor.set_helper(Some(Completions(complete(&env, &ctx, ""))));
+            match editor.readline(PROMPT) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        } else {
+            match read_plain_line()? {
+                Some(line) => line,
+                None => return Ok(()),
+            }
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if let Some(command) = line.trim().strip_prefix(':') {
+            match command.split_once(' ').unwrap_or((command, "")) {
+                ("history", _) => {
+                    for (i, entry) in editor.history().iter().enumerate() {
+                        println!("{:>4}  {}", i + 1, entry);
+                    }
+                }
+                ("search", needle) => match editor
+                    .history()
+                    .iter()
+                    .rev()
+                    .find(|entry| entry.contains(needle.trim()))
+                {
+                    Some(entry) => println!("{}", entry),
+                    None => eprintln!("No history matching '{}'", needle.trim()),
+                },
+                ("save", path) if !path.trim().is_empty() => {
+                    if let Err(e) = session.save(Path::new(path.trim())) {
+                        eprintln!("Couldn't save {}: {}", path.trim(), e);
+                    }
+                }
+                ("open", path) if !path.trim().is_empty() => {
+                    let path = path.trim();
+                    let source = match fs::read_to_string(path) {
+                        Ok(source) => source,
+                        Err(e) => {
+                            eprintln!("Couldn't open {}: {}", path, e);
+                            continue;
+                        }
+                    };
+
+                    // Declarations in the file are kept, so saving again includes them
+                    if let Err(e) = eval_source(&mut env, &mut ctx, &mut session, path, &source) {
+                        eprintln!("{}", e);
+                        eprint!("{}", diagnostic::backtrace(&ctx.trace));
+                    }
+                }
+                ("type", source) if !source.trim().is_empty() => {
+                    match type_of(&mut env, &mut ctx, source.trim()) {
+                        Ok(Some(description)) => println!("{}", description),
+                        Ok(None) => eprintln!("Usage: :type <expression>"),
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            eprint!("{}", diagnostic::backtrace(&ctx.trace));
+                        }
+                    }
+                }
+                ("save" | "open", _) => eprintln!("Usage: :{} <file>", command.trim()),
+                ("type", _) => eprintln!("Usage: :type <expression>"),
+                _ => eprintln!("Unknown command: :{}", command),
+            }
+            continue;
+        }
+
+        // History is a convenience, so failing to save it isn't worth interrupting the session
+        if editor.add_history_entry(line.as_str()).unwrap_or(false) {
+            if let Some(path) = &history {
+                let _ = editor.append_history(path);
+            }
+        }
+
+        // Errors are reported but never end the session
+        if let Err(e) = eval_source(&mut env, &mut ctx, &mut session, "<repl>", &line) {
+            eprintln!("{}", e);
+            eprint!("{}", diagnostic::backtrace(&ctx.trace));
+        }
+    }
+}
+
+// Without a terminal there's nothing to edit, lines are read as they are with the prompt still
+// shown, so piped input reads like a session typed in
+fn read_plain_line() -> io::Result<Option<String>> {
+    print!("{PROMPT}");
+    io::stdout().flush()?;
+
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line)? == 0 {
+        println!();
+        return Ok(None);
+    }
+
+    let trimmed = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(trimmed);
+    Ok(Some(line))
+}
+
+// A line typed in or a file from :open, each statement kept in the session once it's run, so a
+// line that fails partway keeps what it declared before that
+fn eval_source(
+    env: &mut EnvironmentStack<Value>,
+    ctx: &mut Context,
+    session: &mut Session,
+    name: &str,
+    source: &str,
+) -> Result<()> {
+    let parsed = match parse_line(source) {
+        Ok(parsed) => parsed,
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{}", error);
+            }
+            return Ok(());
+        }
+    };
+
+    ctx.source = Some(NamedSource::new(name.to_string(), source.to_string()));
+    ctx.start_run();
+
+    match &parsed {
+        Line::Expression(ast) => {
+            match ast.evaluate(env, ctx)? {
+                EvalOutcome::Value(value) => println!("{}", value.display(ctx.print_style)),
+                EvalOutcome::Unit => {}
+            }
+            session.record(ast, ast.root());
+        }
+        Line::Statements(ast) => {
+            evaluator::evaluate_each(ast, env, ctx, |id| session.record(ast, id))?;
+        }
+    }
+    Ok(())
+}
+
+// What an expression's value is: a builtin's signature, or the name of its type
+//
+// There are no static types, so the expression is evaluated to find out. None if it's not an
+// expression, without having run anything.
+fn type_of(
+    env: &mut EnvironmentStack<Value>,
+    ctx: &mut Context,
+    source: &str,
+) -> Result<Option<String>> {
+    let Ok(Line::Expression(ast)) = parse_line(source) else {
+        return Ok(None);
+    };
+
+    ctx.source = Some(NamedSource::new("<repl>".to_string(), source.to_string()));
+    ctx.start_run();
+
+    Ok(match ast.evaluate(env, ctx)? {
+        EvalOutcome::Value(Value::Builtin(builtin)) => Some(builtin.signature().to_string()),
+        EvalOutcome::Value(value) => Some(value.type_name().to_string()),
+        EvalOutcome::Unit => None,
+    })
+}
+
+// Try the line as a bare expression first, then fall back to a full program
+fn parse_line(line: &str) -> Result<Line, Vec<String>> {
+    let mut parser = Parser::from(Tokenizer::new(line));
+    if let Ok(ast) = parser.parse_single_expression() {
+        if !parser.tokenizer_had_errors() {
+            return Ok(Line::Expression(ast));
+        }
+    }
+
+    let mut parser = Parser::from(Tokenizer::new(line));
+    let result = parser.parse();
+
+    let mut errors = parser
+        .tokenizer_iter_errors()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>();
+
+    match result {
+        Ok(ast) if errors.is_empty() => Ok(Line::Statements(ast)),
+        Ok(_) => Err(errors),
+        Err(e) => {
+            errors.push(e.to_string());
+            Err(errors)
+        }
+    }
+}
+
+/// Keywords, builtins, and defined variables starting with the given prefix
+fn complete(env: &EnvironmentStack<Value>, ctx: &Context, prefix: &str) -> Vec<String> {
+    let mut candidates = env.names();
+
+    for keyword in Keyword::values() {
+        let name = keyword.to_value().to_string();
+        if name.chars().all(|c| c.is_alphabetic()) {
+            candidates.push(name);
+        }
+    }
+
+    for builtin in BuiltIn::values()
+        .into_iter()
+        .filter(|b| ctx.builtins.allows(*b) && (ctx.compat || b.namespace().is_none()))
+    {
+        let name = builtin.name();
+        if name.chars().all(|c| c.is_alphanumeric()) {
+            candidates.push(name.to_string());
+        }
+    }
+
+    for namespace in Namespace::values() {
+        candidates.push(namespace.to_value().to_string());
+    }
+
+    let mut matches = candidates
+        .into_iter()
+        .filter(|name| name.starts_with(prefix))
+        .collect::<Vec<_>>();
+    matches.sort();
+    matches.dedup();
+    matches
+}
+
+// Tab completes the identifier before the cursor from the names there were when the line was
+// started, since the editor can't hold on to the environment while lines run in it
+struct Completions(Vec<String>);
+
+impl Completer for Completions {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let before = &line[..pos];
+        let start = before
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .map_or(0, |i| i + before[i..].chars().next().unwrap().len_utf8());
+
+        let word = &before[start..];
+        let names = self.0.iter().filter(|name| name.starts_with(word));
+        Ok((start, names.cloned().collect()))
+    }
+}
+
+impl Hinter for Completions {
+    type Hint = String;
+}
+
+impl Highlighter for Completions {}
+
+impl Validator for Completions {}
+
+impl Helper for Completions {}
+
+// What's been declared so far, for :save to write out as a script that gets back to the same state
+//
+// Only top level declarations and assignments are kept, once they've run without errors. Prints
+// and other expressions don't change anything, and loops or blocks that set globals are left out.
+#[derive(Default)]
+struct Session {
+    declarations: Vec<String>,
+}
+
+impl Session {
+    fn record(&mut self, ast: &Ast, id: NodeId) {
+        if matches!(
+            ast[id],
+            AstNode::Declaration(..) | AstNode::Enum(..) | AstNode::Assignment(..)
+        ) {
+            self.declarations.push(formatter::format_statement(ast, id));
+        }
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        let mut contents = self.declarations.join("\n");
+        if !contents.is_empty() {
+            contents.push('\n');
+        }
+        fs::write(path, contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::Environment;
+
+    #[test]
+    fn completes_keywords_builtins_and_variables() {
+        let mut env = EnvironmentStack::new();
+        env.define("printed", Value::Nil);
+        env.define("total", Value::Nil);
+        let ctx = Context::default();
+
+        assert_eq!(complete(&env, &ctx, "pri"), ["print", "printed", "printf"]);
+        assert_eq!(complete(&env, &ctx, "tot"), ["total"]);
+        assert_eq!(complete(&env, &ctx, "whi"), ["while"]);
+        assert_eq!(complete(&env, &ctx, "Ma"), ["Math"]);
+        assert!(complete(&env, &ctx, "zzz").is_empty());
+    }
+
+    #[test]
+    fn completes_namespaced_builtins_only_with_compat() {
+        let env = EnvironmentStack::new();
+        let mut ctx = Context::default();
+        assert!(complete(&env, &ctx, "sqr").is_empty());
+
+        ctx.compat = true;
+        assert_eq!(complete(&env, &ctx, "sqr"), ["sqrt"]);
+    }
+
+    #[test]
+    fn completes_only_allowed_builtins() {
+        let env = EnvironmentStack::new();
+        let mut ctx = Context::default();
+        assert_eq!(complete(&env, &ctx, "cl"), ["class", "clock"]);
+
+        ctx.builtins = crate::builtins::BuiltinSet::sandbox();
+        assert_eq!(complete(&env, &ctx, "cl"), ["class"]);
+    }
+
+    #[test]
+    fn completes_the_identifier_before_the_cursor() {
+        let completions = Completions(vec!["print".into(), "printed".into(), "total".into()]);
+        let history = DefaultHistory::new();
+        let context = rustyline::Context::new(&history);
+
+        let complete = |line: &str, pos| completions.complete(line, pos, &context).unwrap();
+        assert_eq!(
+            complete("é + pri", 8),
+            (5, vec!["print".into(), "printed".into()])
+        );
+        assert_eq!(complete("1 + to; pri", 6), (4, vec!["total".into()]));
+        assert_eq!(complete("x", 1), (0, vec![]));
+    }
+
+    #[test]
+    fn keeps_declarations_from_before_an_error_on_the_same_line() {
+        let mut env = EnvironmentStack::new();
+        let mut ctx = Context::default();
+        let mut session = Session::default();
+
+        let line = "var a = 1; a = 2; print nil + 1; var b = 3;";
+        assert!(eval_source(&mut env, &mut ctx, &mut session, "<repl>", line).is_err());
+        eval_source(&mut env, &mut ctx, &mut session, "<repl>", "a = 3").unwrap();
+
+        assert_eq!(session.declarations, ["var a = 1;", "a = 2;", "a = 3;"]);
+        assert_eq!(env.get("b"), None);
+    }
+}
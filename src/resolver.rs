@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::builtins::{BuiltIn, Namespace};
+use crate::parser::{Ast, AstNode, NodeId};
+use crate::phase::{self, Timed};
+use crate::span::Span;
+use crate::tokenizer::Keyword;
+use crate::visit::{walk, Visitor};
+
+/// Everything the resolver can find wrong with a program before it runs
+#[derive(Debug, Error)]
+pub enum ResolverError {
+    #[error("[line {}] Error at '{}': Undefined variable.", .0.line, .1)]
+    UndefinedVariable(Span, String),
+
+    #[error("[line {}] Error at '{}': Can't read local variable in its own initializer.", .0.line, .1)]
+    OwnInitializer(Span, String),
+
+    #[error("[line {}] Error at '{}': Already a variable with this name in this scope.", .0.line, .1)]
+    AlreadyDeclared(Span, String),
+
+    #[error("[line {}] Error at '{}': Already a variant with this name in this enum.", .0.line, .1)]
+    DuplicateVariant(Span, String),
+}
+
+impl ResolverError {
+    /// Undefined globals are only runtime errors when running, since check is stricter
+    pub fn is_fatal(&self) -> bool {
+        !matches!(self, ResolverError::UndefinedVariable(_, _))
+    }
+}
+
+/// Where a local variable reference finds its value
+///
+/// Depth is how many scopes out from the reference the variable was declared, slot is the order
+/// it was declared in within that scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resolution {
+    pub depth: usize,
+    pub slot: usize,
+}
+
+// The names declared in one scope, in order, and if each is done initializing
+#[derive(Debug, Default)]
+struct Scope {
+    names: Vec<(String, bool)>,
+    imported: bool, // A module run here declares into it, so later slots can't be known
+}
+
+/// Static checks over a parsed program, run without evaluating anything
+///
+/// This tracks which variables are declared in each scope, resolves each local reference to the
+/// scope and slot that declares it, and reports references that can't work when they're
+/// evaluated. The resolutions are stored in the Ast for the evaluator, which looks up anything
+/// not resolved (globals, builtins, and names from modules) by name. Globals carry over between
+/// calls to resolve, so several files can be checked as one program. There are no functions or
+/// classes yet, so return and this are already rejected by the parser.
+#[derive(Debug)]
+pub struct Resolver {
+    scopes: Vec<Scope>, // Globals first
+    resolutions: HashMap<NodeId, Resolution>,
+    errors: Vec<ResolverError>,
+    imported: bool, // Any global could be from a module after an import
+    compat: bool,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self {
+            scopes: vec![Scope::default()],
+            resolutions: HashMap::new(),
+            errors: vec![],
+            imported: false,
+            compat: false,
+        }
+    }
+
+    /// Allow namespaced builtins by their names alone, the same as Context.compat
+    pub fn with_compat(mut self, compat: bool) -> Self {
+        self.compat = compat;
+        self
+    }
+
+    /// Declare a global defined outside of the program, like ARGS
+    pub fn define_global(&mut self, name: &str) {
+        self.scopes[0].names.push((name.to_string(), true));
+    }
+
+    pub fn had_errors(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    pub fn iter_errors(&self) -> impl Iterator<Item = &ResolverError> {
+        self.errors.iter()
+    }
+
+    /// Take the errors found so far, leaving none
+    pub fn take_errors(&mut self) -> Vec<ResolverError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Check a program and store where each of its local variables are in it
+    pub fn resolve(&mut self, ast: &mut Ast) {
+        let _timed = Timed::start(phase::RESOLVER, "Resolving");
+        self.visit(ast, ast.root());
+        ast.set_resolutions(std::mem::take(&mut self.resolutions));
+    }
+
+    fn enter(&mut self, names: Vec<(String, bool)>) {
+        self.scopes.push(Scope {
+            names,
+            imported: false,
+        });
+    }
+
+    fn declare(&mut self, span: Span, name: &str, defined: bool) {
+        let global = self.scopes.len() == 1;
+        let scope = &mut self.scopes.last_mut().unwrap().names;
+
+        if !global && scope.iter().any(|(n, _)| n == name) {
+            self.errors
+                .push(ResolverError::AlreadyDeclared(span, name.to_string()));
+        }
+        scope.push((name.to_string(), defined));
+    }
+
+    fn define(&mut self, name: &str) {
+        let scope = &mut self.scopes.last_mut().unwrap().names;
+        if let Some((_, defined)) = scope.iter_mut().rev().find(|(n, _)| n == name) {
+            *defined = true;
+        }
+    }
+
+    // Same lookup order as the evaluator: keywords, variables, then named builtins, constants, and
+    // namespaces
+    fn reference(&mut self, id: NodeId, span: Span, name: &str) {
+        if Keyword::try_from(name).is_ok() {
+            return;
+        }
+
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            let Some(slot) = scope.names.iter().rposition(|(n, _)| n == name) else {
+                continue;
+            };
+
+            if !scope.names[slot].1 {
+                self.errors
+                    .push(ResolverError::OwnInitializer(span, name.to_string()));
+            }
+            if depth < self.scopes.len() - 1 && !scope.imported {
+                self.resolutions.insert(id, Resolution { depth, slot });
+            }
+            return;
+        }
+
+        if !self.imported
+            && BuiltIn::global(name, self.compat).is_none()
+            && BuiltIn::constant(name, self.compat).is_none()
+            && Namespace::try_from(name).is_err()
+        {
+            self.errors
+                .push(ResolverError::UndefinedVariable(span, name.to_string()));
+        }
+    }
+}
+
+impl Visitor for Resolver {
+    fn visit(&mut self, ast: &Ast, id: NodeId) {
+        match &ast[id] {
+            AstNode::Symbol(span, name) => self.reference(id, *span, name),
+
+            AstNode::Block(_, _) => {
+                self.enter(vec![]);
+                walk(self, ast, id);
+                self.scopes.pop();
+            }
+
+            // Globals can be redeclared and are only looked up when needed, locals can't be
+            AstNode::Declaration(span, name, value) if self.scopes.len() > 1 => {
+                self.declare(*span, name, false);
+                self.visit(ast, *value);
+                self.define(name);
+            }
+            AstNode::Declaration(span, name, value) => {
+                self.visit(ast, *value);
+                self.declare(*span, name, true);
+            }
+
+            AstNode::Assignment(span, name, value) => {
+                self.visit(ast, *value);
+                self.reference(id, *span, name);
+            }
+
+            // Variants don't have spans of their own, so duplicates are reported at the enum
+            AstNode::Enum(span, name, variants) => {
+                for (i, variant) in variants.iter().enumerate() {
+                    if variants[..i].contains(variant) {
+                        self.errors
+                            .push(ResolverError::DuplicateVariant(*span, variant.clone()));
+                    }
+                }
+                self.declare(*span, name, true);
+            }
+
+            // Whatever the module declares isn't known until it's run
+            AstNode::Import(_, _) => {
+                self.imported = true;
+                self.scopes.last_mut().unwrap().imported = true;
+            }
+
+            AstNode::ForIn(_, name, iterable, body) => {
+                self.visit(ast, *iterable);
+                self.enter(vec![(name.to_string(), true)]);
+                self.visit(ast, *body);
+                self.scopes.pop();
+            }
+
+            AstNode::Try(_, body, catch, finally) => {
+                self.visit(ast, *body);
+
+                if let Some((name, handler)) = catch {
+                    self.enter(vec![(name.to_string(), true)]);
+                    self.visit(ast, *handler);
+                    self.scopes.pop();
+                }
+
+                if let Some(finally) = finally {
+                    self.visit(ast, *finally);
+                }
+            }
+
+            // Everything else only has its children resolved
+            _ => walk(self, ast, id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::tokenizer::Tokenizer;
+
+    // Each variable reference and assignment in order, with where it was resolved to
+    struct References(Vec<(String, Option<Resolution>)>);
+
+    impl Visitor for References {
+        fn visit(&mut self, ast: &Ast, id: NodeId) {
+            walk(self, ast, id);
+            if let AstNode::Symbol(_, name) | AstNode::Assignment(_, name, _) = &ast[id] {
+                self.0.push((name.to_string(), ast.resolution(id)));
+            }
+        }
+    }
+
+    fn references(source: &str) -> Vec<(String, Option<Resolution>)> {
+        let mut ast = Parser::from(Tokenizer::new(source)).parse().unwrap();
+        let mut resolver = Resolver::new();
+        resolver.resolve(&mut ast);
+        assert!(!resolver.had_errors(), "{:?}", resolver.take_errors());
+
+        let mut references = References(vec![]);
+        references.visit(&ast, ast.root());
+        references.0
+    }
+
+    fn at(name: &str, depth: usize, slot: usize) -> (String, Option<Resolution>) {
+        (name.to_string(), Some(Resolution { depth, slot }))
+    }
+
+    #[test]
+    fn locals_resolve_to_depth_and_slot() {
+        let source = "var g = 1; { var a = g; var b = 2; { var a = 3; print b; a = a; } b = 4; }";
+
+        assert_eq!(
+            references(source),
+            vec![
+                ("g".to_string(), None),
+                at("b", 1, 1),
+                at("a", 0, 0),
+                at("a", 0, 0),
+                at("b", 0, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn loop_and_catch_variables_are_in_their_own_scope() {
+        let source = "for (var x in list(1)) { var y = x; print y; } try {} catch (e) { print e; }";
+
+        assert_eq!(
+            references(source),
+            vec![
+                ("list".to_string(), None),
+                at("x", 1, 0),
+                at("y", 0, 0),
+                at("e", 1, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn imports_leave_the_rest_of_their_scope_by_name() {
+        let source = "{ var a = 1; print a; import \"module\"; print a; }";
+
+        assert_eq!(
+            references(source),
+            vec![at("a", 0, 0), ("a".to_string(), None)]
+        );
+    }
+}
@@ -1,10 +1,17 @@
 use std::fmt::{self, Display, Formatter};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Span {
     pub line: usize,
+    pub column: usize, // 1-based, in characters from the start of the line
+
+    // Character offsets, for indexing the source as chars
     pub start: usize,
     pub end: usize,
+
+    // Byte offsets, for slicing the source as a str
+    pub byte_start: usize,
+    pub byte_end: usize,
 }
 
 impl Display for Span {
@@ -16,15 +23,118 @@ impl Display for Span {
 impl Span {
     pub const ZERO: Span = Span {
         line: 0,
+        column: 0,
         start: 0,
         end: 0,
+        byte_start: 0,
+        byte_end: 0,
     };
 
+    // Where the merged span starts comes from whichever starts first
     pub fn merge(&self, other: &Span) -> Span {
-        let line = self.line.min(other.line);
-        let start = self.start.min(other.start);
-        let end = self.end.max(other.end);
+        let first = if other.start < self.start {
+            other
+        } else {
+            self
+        };
+        let last = if other.end > self.end { other } else { self };
+
+        Span {
+            line: self.line.min(other.line),
+            column: first.column,
+            start: first.start,
+            end: last.end,
+            byte_start: first.byte_start,
+            byte_end: last.byte_end,
+        }
+    }
+}
+
+/// A position in a source as both a character and a byte offset, and the line and column there
+///
+/// Spans only keep the line and column where they start, this is for getting them anywhere else
+/// (like where a diagnostic points) without counting one kind of offset as the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceCursor {
+    pub line: usize,
+    pub column: usize, // 1-based, in characters from the start of the line
+    pub char: usize,
+    pub byte: usize,
+}
+
+impl SourceCursor {
+    pub const START: SourceCursor = SourceCursor {
+        line: 1,
+        column: 1,
+        char: 0,
+        byte: 0,
+    };
+
+    /// Walk source up to a character offset, stopping at the end if it's past that
+    pub fn at(source: &str, offset: usize) -> SourceCursor {
+        let mut cursor = SourceCursor::START;
+        for c in source.chars().take(offset) {
+            cursor.advance(c);
+        }
+        cursor
+    }
+
+    /// Move past one character
+    pub fn advance(&mut self, c: char) {
+        self.char += 1;
+        self.byte += c.len_utf8();
+
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursors_count_characters_and_bytes_separately() {
+        let source = "é😀\nab";
+        assert_eq!(SourceCursor::at(source, 0), SourceCursor::START);
+        assert_eq!(
+            SourceCursor::at(source, 2),
+            SourceCursor {
+                line: 1,
+                column: 3,
+                char: 2,
+                byte: 6,
+            }
+        );
+        assert_eq!(
+            SourceCursor::at(source, 4),
+            SourceCursor {
+                line: 2,
+                column: 2,
+                char: 4,
+                byte: 8,
+            }
+        );
+
+        // Past the end stops there
+        assert_eq!(SourceCursor::at(source, 100), SourceCursor::at(source, 5));
+    }
+
+    #[test]
+    fn cursors_agree_with_token_spans() {
+        let source = "print \"é😀\";\n  x;";
+        let spans = crate::tokenizer::Tokenizer::new(source)
+            .map(|token| *token.span())
+            .collect::<Vec<_>>();
 
-        Span { line, start, end }
+        for span in spans {
+            let cursor = SourceCursor::at(source, span.start);
+            assert_eq!((cursor.line, cursor.column), (span.line, span.column));
+            assert_eq!(cursor.byte, span.byte_start);
+        }
     }
 }
@@ -0,0 +1,45 @@
+use crate::context::Context;
+use crate::environment::Environment;
+use crate::evaluator::Evaluate;
+use crate::interpreter::parse_source;
+use crate::named_source::NamedSource;
+use crate::parser::Ast;
+use crate::values::Value;
+
+/// The standard library, written in Lox and built into the binary, in the order it's loaded
+///
+/// Without functions it can only name builtins with their first arguments bound and constants
+/// made from them, so list helpers and assertions wait until there are functions to write them
+/// with.
+pub const SOURCES: [(&str, &str); 2] = [
+    ("<std/math>", include_str!("stdlib/math.lox")),
+    ("<std/strings>", include_str!("stdlib/strings.lox")),
+];
+
+/// Define everything in the standard library as globals in env
+///
+/// It runs with a context of its own, so it isn't traced, profiled, or limited like the program
+/// is. The sources are part of the binary, so one that doesn't run is a bug here.
+pub fn load(env: &mut impl Environment<Value>) {
+    let mut ctx = Context::default();
+    for (source, ast) in programs() {
+        if let Err(error) = ast.evaluate(env, &mut ctx) {
+            panic!("{} doesn't run: {error}", source.name);
+        }
+    }
+}
+
+/// The standard library parsed, for anything that needs it as programs instead of globals
+///
+/// The transpiler puts these ahead of the program, so JavaScript has the same globals.
+pub fn programs() -> Vec<(NamedSource, Ast)> {
+    SOURCES
+        .iter()
+        .map(|(name, code)| {
+            let source = NamedSource::new(name.to_string(), code.to_string());
+            let ast = parse_source(&source)
+                .unwrap_or_else(|errors| panic!("{name} doesn't parse: {:?}", errors));
+            (source, ast)
+        })
+        .collect()
+}
@@ -0,0 +1,153 @@
+use crate::const_enum;
+use crate::json::Json;
+use crate::parser::{Ast, AstNode, NodeId};
+use crate::span::Span;
+use crate::visit::{walk, Visitor};
+
+const_enum! {
+    pub SymbolKind as &str {
+        Variable => "variable",
+        Enum => "enum",
+        Variant => "variant",
+    }
+}
+
+/// A name a program declares, for outlines in editors and other tools
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub kind: SymbolKind,
+    pub name: String,
+    pub span: Span,
+
+    /// The node it's declared in, None for globals
+    pub scope: Option<Scope>,
+
+    /// How many scopes that's inside of, counting its own, 0 for globals
+    pub depth: usize,
+}
+
+/// A node with names declared in it: a Block, a ForIn or Try for its loop or catch variable, or
+/// an Enum for its variants
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scope {
+    pub kind: &'static str,
+    pub span: Span,
+}
+
+/// Every declaration in the order they're written, including locals, loop and catch variables,
+/// and enum variants
+///
+/// There are no functions or classes yet, so those never show up. Variants don't have spans of
+/// their own, they have their enum's.
+pub fn symbols(ast: &Ast) -> Vec<Symbol> {
+    let mut collector = Collector {
+        scopes: vec![],
+        symbols: vec![],
+    };
+    collector.visit(ast, ast.root());
+    collector.symbols
+}
+
+/// One line per symbol, indented by how deeply it's nested, with where it's declared
+pub fn outline(file: &str, symbols: &[Symbol]) -> String {
+    let mut output = String::new();
+    for symbol in symbols {
+        output.push_str(&format!(
+            "{}{} {} {}:{}:{}\n",
+            "  ".repeat(symbol.depth),
+            symbol.kind.to_value(),
+            symbol.name,
+            file,
+            symbol.span.line,
+            symbol.span.column
+        ));
+    }
+    output
+}
+
+pub fn json(file: &str, symbol: &Symbol) -> Json {
+    Json::object([
+        ("file", file.into()),
+        ("kind", symbol.kind.to_value().into()),
+        ("name", symbol.name.as_str().into()),
+        ("span", (&symbol.span).into()),
+        (
+            "scope",
+            symbol.scope.map_or(Json::Null, |scope| {
+                Json::object([("kind", scope.kind.into()), ("span", (&scope.span).into())])
+            }),
+        ),
+    ])
+}
+
+struct Collector {
+    scopes: Vec<Scope>,
+    symbols: Vec<Symbol>,
+}
+
+impl Collector {
+    fn declare(&mut self, kind: SymbolKind, name: &str, span: Span) {
+        self.symbols.push(Symbol {
+            kind,
+            name: name.to_string(),
+            span,
+            scope: self.scopes.last().copied(),
+            depth: self.scopes.len(),
+        });
+    }
+
+    // Visit a child with names declared in node's own scope
+    fn scoped(&mut self, ast: &Ast, node: NodeId, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(Scope {
+            kind: ast[node].kind(),
+            span: ast[node].span(),
+        });
+        f(self);
+        self.scopes.pop();
+    }
+}
+
+impl Visitor for Collector {
+    fn visit(&mut self, ast: &Ast, id: NodeId) {
+        match &ast[id] {
+            AstNode::Declaration(span, name, _) => {
+                self.declare(SymbolKind::Variable, name, *span);
+                walk(self, ast, id);
+            }
+
+            AstNode::Enum(span, name, variants) => {
+                self.declare(SymbolKind::Enum, name, *span);
+                self.scoped(ast, id, |collector| {
+                    for variant in variants {
+                        collector.declare(SymbolKind::Variant, variant, *span);
+                    }
+                });
+            }
+
+            AstNode::Block(_, _) => self.scoped(ast, id, |collector| walk(collector, ast, id)),
+
+            AstNode::ForIn(span, name, iterable, body) => {
+                self.visit(ast, *iterable);
+                self.scoped(ast, id, |collector| {
+                    collector.declare(SymbolKind::Variable, name, *span);
+                    collector.visit(ast, *body);
+                });
+            }
+
+            AstNode::Try(span, body, catch, finally) => {
+                self.visit(ast, *body);
+                if let Some((name, handler)) = catch {
+                    self.scoped(ast, id, |collector| {
+                        collector.declare(SymbolKind::Variable, name, *span);
+                        collector.visit(ast, *handler);
+                    });
+                }
+                if let Some(finally) = finally {
+                    self.visit(ast, *finally);
+                }
+            }
+
+            _ => walk(self, ast, id),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::Result;
+
+const EXPECT_OUTPUT: &str = "// expect: ";
+const EXPECT_RUNTIME_ERROR: &str = "// expect runtime error: ";
+const EXPECT_ERROR: &str = "// Error";
+const EXPECT_LINE_ERROR: &str = "// [line ";
+
+// Exit codes for static (tokenizing and parsing) and runtime errors
+const STATIC_ERROR: i32 = 65;
+const RUNTIME_ERROR: i32 = 70;
+
+/// What a test file says should happen when it's run, from comments in the file
+///
+/// These are the same as the official Lox test suite:
+/// - `// expect: output` for each line printed, in order
+/// - `// expect runtime error: message` for a runtime error (exit code 70)
+/// - `// Error at 'x': message` or `// [line N] Error ...` for a static error (exit code 65)
+#[derive(Debug, Default, PartialEq)]
+struct Expectations {
+    output: Vec<String>,
+    errors: Vec<String>,
+    exit_code: i32,
+}
+
+impl Expectations {
+    fn parse(source: &str) -> Self {
+        let mut expectations = Expectations::default();
+
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+
+            if let Some((_, output)) = line.split_once(EXPECT_OUTPUT) {
+                expectations.output.push(output.to_string());
+            } else if let Some((_, message)) = line.split_once(EXPECT_RUNTIME_ERROR) {
+                expectations.errors.push(message.to_string());
+                expectations.exit_code = RUNTIME_ERROR;
+            } else if let Some(index) = line.find(EXPECT_LINE_ERROR) {
+                expectations.errors.push(line[index + 3..].to_string());
+                expectations.exit_code = STATIC_ERROR;
+            } else if let Some(index) = line.find(EXPECT_ERROR) {
+                let error = &line[index + 3..];
+                expectations
+                    .errors
+                    .push(format!("[line {line_number}] {error}"));
+                expectations.exit_code = STATIC_ERROR;
+            }
+        }
+
+        expectations
+    }
+}
+
+/// Run every .lox file in the given files and directories, printing failures and a summary
+///
+/// Each test runs in a new process of this same executable, so crashes and exits are contained,
+/// with --deterministic so clock() and random() give the same answers every time.
+/// Returns true if every test passed.
+pub fn run(paths: &[PathBuf]) -> Result<bool> {
+    let mut files = vec![];
+    for path in paths {
+        discover(path, &mut files)?;
+    }
+    files.sort();
+
+    let exe = std::env::current_exe()?;
+    let mut passed = 0;
+    let mut failed = 0;
+
+    for file in &files {
+        let failures = run_test(&exe, file)?;
+
+        if failures.is_empty() {
+            passed += 1;
+        } else {
+            failed += 1;
+            println!("FAIL {}", file.display());
+            for failure in failures {
+                println!("    {}", failure);
+            }
+        }
+    }
+
+    println!("{} passed, {} failed", passed, failed);
+    Ok(failed == 0)
+}
+
+fn discover(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            discover(&entry?.path(), files)?;
+        }
+    } else if path.extension().is_some_and(|ext| ext == "lox") {
+        files.push(path.to_path_buf());
+    }
+
+    Ok(())
+}
+
+// Run a single test, returning a description of each way it didn't match expectations
+fn run_test(exe: &Path, file: &Path) -> Result<Vec<String>> {
+    let expected = Expectations::parse(&fs::read_to_string(file)?);
+    let output = Command::new(exe).args(["--deterministic", "run"]).arg(file).output()?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let exit_code = output.status.code().unwrap_or(-1);
+
+    let mut failures = vec![];
+
+    let actual_output = stdout.lines().collect::<Vec<_>>();
+    if actual_output != expected.output {
+        failures.push(format!(
+            "Expected output {:?}, got {:?}",
+            expected.output, actual_output
+        ));
+    }
+
+    for error in &expected.errors {
+        if !stderr.contains(error.as_str()) {
+            failures.push(format!("Expected error '{}', got {:?}", error, stderr.trim_end()));
+        }
+    }
+
+    if expected.errors.is_empty() && !stderr.is_empty() {
+        failures.push(format!("Unexpected error {:?}", stderr.trim_end()));
+    }
+
+    if exit_code != expected.exit_code {
+        failures.push(format!(
+            "Expected exit code {}, got {}",
+            expected.exit_code, exit_code
+        ));
+    }
+
+    Ok(failures)
+}
@@ -1,14 +1,21 @@
+use std::fmt;
+use std::io::BufRead;
+
+use anyhow::{bail, ensure};
 use convert_case::{Case, Casing};
 use derive_more::Display;
 use thiserror::Error;
 
 use crate::const_enum;
+use crate::phase;
 use crate::span::Span;
 use crate::values::Value;
 
 #[derive(Debug, Display, Clone, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Token {
-    EOF,
+    #[display("EOF")]
+    EOF(Span), // Empty, at the very end of the source
 
     #[display("{}", _1)]
     Keyword(Span, Keyword),
@@ -27,50 +34,63 @@ pub enum TokenizerError {
 
     #[error("[line {}] Unterminated string", .0.line)]
     UnterminatedString(Span),
+
+    #[error("[line {}] Malformed number: {}", .0.line, .1)]
+    MalformedNumber(Span, String),
+
+    #[error("[line {}] Error reading source: {}", .0.line, .1)]
+    ReadFailed(Span, String),
 }
 
 // Code crafters requires a very specific output format, implement it here
 impl Token {
     pub fn code_crafters_format(&self) -> String {
+        // Only numbers and strings have a literal value, everything else is null
+        let literal = match self {
+            Token::Literal(
+                _,
+                _,
+                value @ (Value::Integer(_) | Value::Number(_) | Value::String(_)),
+            ) => value.to_string(),
+            _ => "null".to_string(),
+        };
+
+        format!("{} {} {}", self.kind_name(), self.lexeme(), literal)
+    }
+}
+
+impl Token {
+    /// The kind of token in the same style as the code crafters format, eg LEFT_PAREN
+    pub fn kind_name(&self) -> String {
         match self {
-            Token::EOF => "EOF  null".to_string(),
-            Token::Keyword(_, keyword) => {
-                let name = keyword.to_string().to_case(Case::ScreamingSnake);
-                let lexeme = keyword.to_value();
+            Token::EOF(_) => "EOF".to_string(),
+            Token::Keyword(_, keyword) => keyword.to_string().to_case(Case::ScreamingSnake),
+            Token::Literal(_, _, value) => match value {
+                Value::Nil | Value::Bool(_) => value.to_string().to_case(Case::ScreamingSnake),
+                Value::Integer(_) | Value::Number(_) => "NUMBER".to_string(),
+                Value::String(_) => "STRING".to_string(),
+                _ => value.type_name().to_case(Case::ScreamingSnake),
+            },
+            Token::Identifier(_, _) => "IDENTIFIER".to_string(),
+        }
+    }
 
-                format!("{name} {lexeme} null")
-            }
-            Token::Literal(_, lexeme, value) => {
-                let name = match value {
-                    Value::Nil => {
-                        return "NIL nil null".to_string();
-                    }
-                    Value::Bool(_) => {
-                        let name = value.to_string().to_case(Case::ScreamingSnake);
-                        return format!("{name} {value} null");
-                    }
-                    Value::Number(_) => "NUMBER",
-                    Value::String(_) => "STRING",
-                    Value::Builtin(_) => "BUILTIN",
-                };
-                format!("{name} {lexeme} {value}")
-            }
-            Token::Identifier(_, name) => {
-                format!("IDENTIFIER {name} null")
-            }
+    /// The source text of this token
+    pub fn lexeme(&self) -> String {
+        match self {
+            Token::EOF(_) => String::new(),
+            Token::Keyword(_, keyword) => keyword.to_value().to_string(),
+            Token::Literal(_, lexeme, _) => lexeme.clone(),
+            Token::Identifier(_, name) => name.clone(),
         }
     }
-}
 
-impl Token {
-    #[allow(dead_code)]
     pub fn span(&self) -> &Span {
         match self {
-            Token::EOF => &Span::ZERO,
-
-            Token::Keyword(span, _) | Token::Literal(span, _, _) | Token::Identifier(span, _) => {
-                span
-            }
+            Token::EOF(span)
+            | Token::Keyword(span, _)
+            | Token::Literal(span, _, _)
+            | Token::Identifier(span, _) => span,
         }
     }
 }
@@ -83,21 +103,32 @@ const_enum! {
         BangEqual => "!=",
         LessEqual => "<=",
         GreaterEqual => ">=",
+        QuestionQuestion => "??",
+        QuestionDot => "?.",
 
         And => "and",
+        Assert => "assert",
+        Catch => "catch",
         Class => "class",
         Else => "else",
+        Enum => "enum",
         False => "false",
+        Finally => "finally",
         For => "for",
         Fun => "fun",
         If => "if",
+        Import => "import",
+        In => "in",
+        Is => "is",
         Nil => "nil",
         Or => "or",
         Print => "print",
         Return => "return",
         Super => "super",
         This => "this",
+        Throw => "throw",
         True => "true",
+        Try => "try",
         Var => "var",
         While => "while",
 
@@ -105,6 +136,8 @@ const_enum! {
         RightParen => ")",
         LeftBrace => "{",
         RightBrace => "}",
+        LeftBracket => "[",
+        RightBracket => "]",
         Comma => ",",
         Dot => ".",
         Semicolon => ";",
@@ -119,19 +152,35 @@ const_enum! {
     }
 }
 
+// A // comment (or #! line) through the end of its line, including the leading slashes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub span: Span,
+    pub text: String,
+}
+
 // The current state of the tokenizer, use it as an iterator (in general)
 #[derive(Debug)]
 pub struct Tokenizer<'a> {
-    // Internal state stored as raw bytes
-    pub(crate) source: &'a str,
-    byte_pos: usize,
+    // Where more source comes from when streaming, None once it's all been read
+    reader: Option<Input<'a>>,
+
+    // All of the source when it's given up front, for slicing out exactly what was written
+    source: &'a str,
+
+    // The byte offset of each character, so spans can have both
+    byte_offsets: Vec<usize>,
+    bytes_read: usize,
 
     // Internal state stored as utf8 characters, processed once
+    // When streaming, only from the start of the current line; offset is how many came before
     chars: Vec<char>,
     char_pos: usize,
+    offset: usize,
 
     // The current position of the iterator in the source code
     line: usize,
+    line_start: usize, // Character offset the current line starts at
 
     // Flag that the iterator has already emitted EOF, so should not iterate any more
     emitted_eof: bool,
@@ -139,30 +188,145 @@ pub struct Tokenizer<'a> {
     // Collect tokenizer errors this tokenizer has encountered.
     errors: Vec<TokenizerError>,
 
+    // Comments aren't tokens, but are kept to the side for tools like the formatter
+    comments: Vec<Comment>,
+
     // The currently peeked token
     peeked: Option<Token>,
 }
 
+// A reader of source code, which can't derive Debug itself
+struct Input<'a>(Box<dyn BufRead + 'a>);
+
+impl fmt::Debug for Input<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Input").finish_non_exhaustive()
+    }
+}
+
 impl<'a> Tokenizer<'a> {
     pub fn new(source: &'a str) -> Self {
         Self {
+            reader: None,
             source,
-            byte_pos: 0,
+
+            byte_offsets: source.char_indices().map(|(i, _)| i).collect(),
+            bytes_read: source.len(),
 
             chars: source.chars().collect(),
             char_pos: 0,
+            offset: 0,
 
             line: 1,
+            line_start: 0,
 
             emitted_eof: false,
             errors: Vec::new(),
+            comments: Vec::new(),
 
             peeked: None,
         }
     }
+
+    /// Tokenize source as it's read, a line at a time, without holding all of it in memory
+    ///
+    /// Tokens, spans, and errors are the same as for new; failing to read is one more error,
+    /// after which the source ends.
+    pub fn from_reader(reader: impl BufRead + 'a) -> Self {
+        let mut tokenizer = Self::new("");
+        tokenizer.reader = Some(Input(Box::new(reader)));
+        tokenizer
+    }
+
+    /// The source text a span covers exactly as written, eg a token's lexeme from its span
+    ///
+    /// Streaming tokenizers (from_reader) don't keep what they've read, so it's always empty.
+    pub fn slice(&self, span: &Span) -> &'a str {
+        self.source
+            .get(span.byte_start..span.byte_end)
+            .unwrap_or_default()
+    }
 }
 
 impl Tokenizer<'_> {
+    // A span between two character offsets, ending on the current line
+    fn span(&self, start: usize, end: usize) -> Span {
+        // Only multi-line strings start before the current line, so only they need to look back
+        let line_start = if start >= self.line_start {
+            self.line_start
+        } else {
+            self.chars[..start]
+                .iter()
+                .rposition(|c| *c == '\n')
+                .map_or(0, |i| i + 1)
+        };
+
+        Span {
+            line: self.line,
+            column: start - line_start + 1,
+            start: self.offset + start,
+            end: self.offset + end,
+            byte_start: self.byte_offset(start),
+            byte_end: self.byte_offset(end),
+        }
+    }
+
+    // One past the last character is the end of the source (so far)
+    fn byte_offset(&self, char_index: usize) -> usize {
+        self.byte_offsets
+            .get(char_index)
+            .copied()
+            .unwrap_or(self.bytes_read)
+    }
+
+    // Drop everything before the current line, nothing can refer back to it
+    fn discard_read_lines(&mut self) {
+        let consumed = self.line_start.min(self.char_pos);
+
+        self.chars.drain(..consumed);
+        self.byte_offsets.drain(..consumed);
+        self.offset += consumed;
+        self.char_pos -= consumed;
+        self.line_start -= consumed;
+    }
+
+    // Read the next line from the reader onto the end of chars, false if there's nothing left
+    fn read_line(&mut self) -> bool {
+        let Some(Input(reader)) = &mut self.reader else {
+            return false;
+        };
+
+        let mut line = String::new();
+        let result = reader.read_line(&mut line);
+
+        match result {
+            Ok(0) => {
+                self.reader = None;
+                false
+            }
+            Ok(_) => {
+                for (i, c) in line.char_indices() {
+                    self.byte_offsets.push(self.bytes_read + i);
+                    self.chars.push(c);
+                }
+                self.bytes_read += line.len();
+                true
+            }
+            Err(error) => {
+                let span = self.end_span();
+                self.errors
+                    .push(TokenizerError::ReadFailed(span, error.to_string()));
+                self.reader = None;
+                false
+            }
+        }
+    }
+
+    /// An empty span at the very end of the source, for errors about what's missing
+    pub fn end_span(&self) -> Span {
+        self.span(self.chars.len(), self.chars.len())
+    }
+
     pub fn had_errors(&self) -> bool {
         !self.errors.is_empty()
     }
@@ -171,6 +335,11 @@ impl Tokenizer<'_> {
         self.errors.iter()
     }
 
+    /// Comments seen so far, in source order
+    pub fn iter_comments(&self) -> impl Iterator<Item = &Comment> {
+        self.comments.iter()
+    }
+
     pub fn peek(&mut self) -> Option<&Token> {
         if self.peeked.is_none() {
             self.peeked = self.next();
@@ -178,267 +347,584 @@ impl Tokenizer<'_> {
 
         self.peeked.as_ref()
     }
+
+    /// Tokenize source checking everything tools rely on, for fuzzing and property tests
+    ///
+    /// Errors in the source are fine, it's only an Err if an invariant doesn't hold: tokens are
+    /// in order within the source, each slices back to its lexeme, there's one EOF at the very
+    /// end, and streaming gets the same tokens, comments, and errors.
+    pub fn check(source: &str) -> anyhow::Result<()> {
+        let mut tokenizer = Tokenizer::new(source);
+        let tokens = tokenizer.by_ref().collect::<Vec<_>>();
+        let end = source.chars().count();
+
+        let Some((Token::EOF(eof), rest)) = tokens.split_last() else {
+            bail!("the last token isn't EOF");
+        };
+        ensure!(
+            (eof.start, eof.byte_start) == (end, source.len()),
+            "EOF isn't at the end: {eof:?}"
+        );
+        ensure!(tokenizer.next().is_none(), "tokens after EOF");
+
+        let mut last_end = 0;
+        for token in rest {
+            let span = token.span();
+            ensure!(!matches!(token, Token::EOF(_)), "EOF before the end");
+            ensure!(
+                span.line > 0 && span.column > 0 && last_end <= span.start,
+                "{token:?} is out of order"
+            );
+            ensure!(
+                span.start < span.end && span.end <= end,
+                "{token:?} isn't in the source"
+            );
+            ensure!(
+                source.get(span.byte_start..span.byte_end).is_some()
+                    && tokenizer.slice(span) == token.lexeme(),
+                "{token:?} doesn't slice back to its lexeme"
+            );
+            last_end = span.end;
+        }
+
+        for comment in tokenizer.iter_comments() {
+            ensure!(
+                tokenizer.slice(&comment.span) == comment.text,
+                "{comment:?} doesn't slice back to its text"
+            );
+        }
+
+        let mut streaming = Tokenizer::from_reader(source.as_bytes());
+        let streamed = streaming.by_ref().collect::<Vec<_>>();
+        ensure!(streamed == tokens, "streaming made different tokens");
+        ensure!(
+            streaming.comments == tokenizer.comments,
+            "streaming found different comments"
+        );
+        ensure!(
+            format!("{:?}", streaming.errors) == format!("{:?}", tokenizer.errors),
+            "streaming found different errors"
+        );
+
+        Ok(())
+    }
 }
 
 impl<'a> Iterator for Tokenizer<'a> {
     type Item = Token;
 
     fn next(&mut self) -> Option<Self::Item> {
+        // If we have a peeked token, clear and return it (even EOF, which was emitted to peek)
+        if let Some(token) = self.peeked.take() {
+            log::debug!(target: phase::TOKENIZER, "Clearing peeked token: {}", token);
+            return Some(token);
+        }
+
         // We've already consumed the iterator
         if self.emitted_eof {
             return None;
         }
 
-        // If we have a peeked token, clear and return it
-        if let Some(token) = self.peeked.take() {
-            log::debug!("Clearing peeked token: {}", token);
-            self.peeked = None;
-            return Some(token);
-        }
+        // Whitespace, comments, and errors don't make tokens, so keep going until something does
+        'tokens: loop {
+            // When streaming, each line is read once the last is used up
+            // Lines end in a newline, so every token but strings is within the one that's read
+            if self.char_pos >= self.chars.len() && self.reader.is_some() {
+                self.discard_read_lines();
+                self.read_line();
+            }
 
-        // We've reached the end of the source
-        if self.char_pos >= self.chars.len() {
-            log::debug!("Reached EOF");
+            // We've reached the end of the source
+            if self.char_pos >= self.chars.len() {
+                log::debug!(target: phase::TOKENIZER, "Reached EOF");
 
-            self.emitted_eof = true;
-            return Some(Token::EOF);
-        }
+                self.emitted_eof = true;
+                return Some(Token::EOF(self.end_span()));
+            }
+
+            // Try to match comments, from // to EOL
+            // A #! line at the very start makes the script executable, it's kept as a comment too
+            let shebang = self.offset + self.char_pos == 0 && self.chars.starts_with(&['#', '!']);
+            if shebang
+                || self.char_pos + 1 < self.chars.len()
+                    && self.chars[self.char_pos] == '/'
+                    && self.chars[self.char_pos + 1] == '/'
+            {
+                log::debug!(target: phase::TOKENIZER, "Matching comment");
+
+                let start = self.char_pos;
+                while self.char_pos < self.chars.len() && self.chars[self.char_pos] != '\n' {
+                    self.char_pos += 1;
+                }
+
+                self.comments.push(Comment {
+                    span: self.span(start, self.char_pos),
+                    text: self.chars[start..self.char_pos].iter().collect(),
+                });
+
+                continue;
+            }
 
-        // Try to match comments, from // to EOL
-        if self.char_pos < self.chars.len() - 1
-            && self.chars[self.char_pos] == '/'
-            && self.chars[self.char_pos + 1] == '/'
-        {
-            log::debug!("Matching comment");
+            // Read strings, currently there is no escaping, so read until a matching "
+            // Strings can span lines, but one that never ends is reported on the line it started and
+            // only swallows the rest of that line, so everything after it is still tokenized
+            if self.chars[self.char_pos] == '"' {
+                log::debug!(target: phase::TOKENIZER, "Matching string");
 
-            while self.char_pos < self.chars.len() && self.chars[self.char_pos] != '\n' {
+                let mut value = String::new();
+                let start = self.char_pos;
+                let (start_line, start_line_start) = (self.line, self.line_start);
+                let mut first_newline = None;
                 self.char_pos += 1;
-                self.byte_pos += 1;
+
+                loop {
+                    if self.char_pos >= self.chars.len() && !self.read_line() {
+                        let end = first_newline.unwrap_or(self.char_pos);
+                        self.line = start_line;
+                        self.line_start = start_line_start;
+
+                        let error_span = self.span(start, end);
+                        self.errors
+                            .push(TokenizerError::UnterminatedString(error_span));
+
+                        self.char_pos = end;
+                        continue 'tokens;
+                    }
+
+                    if self.chars[self.char_pos] == '"' {
+                        break;
+                    }
+
+                    if self.chars[self.char_pos] == '\n' {
+                        first_newline.get_or_insert(self.char_pos);
+                        self.line += 1;
+                        self.line_start = self.char_pos + 1;
+                    }
+
+                    let c = self.chars[self.char_pos];
+                    value.push(c);
+                    self.char_pos += 1;
+                }
+
+                // Consume closing "
+                self.char_pos += 1;
+                let end = self.char_pos;
+
+                return Some(Token::Literal(
+                    self.span(start, end),
+                    format!("\"{value}\""),
+                    Value::String(value.into()),
+                ));
             }
 
-            return self.next();
-        }
+            // Read hex (0xFF) and binary (0b1010) integers
+            // The prefix must be followed by at least one digit in that base (0x is an error)
+            if self.chars[self.char_pos] == '0'
+                && self.char_pos + 1 < self.chars.len()
+                && matches!(self.chars[self.char_pos + 1], 'x' | 'X' | 'b' | 'B')
+            {
+                log::debug!(target: phase::TOKENIZER, "Matching prefixed number");
+
+                let radix = match self.chars[self.char_pos + 1] {
+                    'x' | 'X' => 16,
+                    _ => 2,
+                };
 
-        // Read strings, currently there is no escaping, so read until a matching " or EOL
-        // If we reach EOL, report an error and continue on the next line
-        if self.chars[self.char_pos] == '"' {
-            log::debug!("Matching string");
+                let start = self.char_pos;
+                let mut lexeme = self.chars[start..start + 2].iter().collect::<String>();
+                self.char_pos += 2;
 
-            let mut value = String::new();
-            let start = self.char_pos;
-            self.char_pos += 1;
-            self.byte_pos += 1;
-
-            loop {
-                if self.char_pos >= self.chars.len() {
-                    let error_span = Span {
-                        line: self.line,
-                        start,
-                        end: self.char_pos,
-                    };
+                while self.char_pos < self.chars.len() && self.chars[self.char_pos].is_digit(radix)
+                {
+                    lexeme.push(self.chars[self.char_pos]);
+                    self.char_pos += 1;
+                }
+
+                let span = self.span(start, self.char_pos);
+
+                if lexeme.len() == 2 {
                     self.errors
-                        .push(TokenizerError::UnterminatedString(error_span));
-                    return self.next();
+                        .push(TokenizerError::MalformedNumber(span, lexeme));
+                    continue;
                 }
 
-                if self.chars[self.char_pos] == '"' {
-                    break;
+                // Too large for an integer, fall back to a float like decimal literals do
+                let digits = &lexeme[2..];
+                let value = match i64::from_str_radix(digits, radix) {
+                    Ok(i) => Value::Integer(i),
+                    Err(_) => Value::Number(digits.chars().fold(0.0, |acc, c| {
+                        acc * radix as f64 + c.to_digit(radix).unwrap() as f64
+                    })),
+                };
+                if matches!(value, Value::Number(n) if n.is_infinite()) {
+                    self.errors
+                        .push(TokenizerError::MalformedNumber(span, lexeme));
+                    continue;
                 }
 
-                if self.chars[self.char_pos] == '\n' {
-                    self.line += 1
+                return Some(Token::Literal(span, lexeme, value));
+            }
+
+            // Read numbers
+            // Numbers must start with a digit (cannot do .1)
+            // Numbers can contain a single . (cannot do 1.2.3)
+            // Numbers must have a digit after the . (cannot do 1. That's two tokens)
+            // Numbers can end with an exponent, which always makes them floats (1e9)
+            if self.chars[self.char_pos].is_ascii_digit() {
+                log::debug!(target: phase::TOKENIZER, "Matching number");
+
+                let mut lexeme = String::new();
+                let mut has_dot = false;
+                let mut last_dot = false;
+                let start = self.char_pos;
+
+                while self.char_pos < self.chars.len() {
+                    let c = self.chars[self.char_pos];
+
+                    if c.is_ascii_digit() {
+                        lexeme.push(c);
+                        last_dot = false;
+                    } else if c == '.' && !has_dot {
+                        lexeme.push(c);
+                        has_dot = true;
+                        last_dot = true;
+                    } else {
+                        break;
+                    }
+
+                    self.char_pos += 1;
                 }
 
-                let c = self.chars[self.char_pos];
-                value.push(c);
+                // If the last character was a dot, we need to back up
+                if last_dot {
+                    lexeme.pop();
+                    self.char_pos -= 1;
+                }
 
-                self.byte_pos += c.len_utf8();
-                self.char_pos += 1;
-            }
+                // Numbers can have an exponent (1e9, 1.5e-3), but only if a digit follows the e
+                // (and its sign), otherwise the number ends before it: 2else is 2 and else
+                let at = |i: usize| self.chars.get(self.char_pos + i).copied();
+                let has_exponent = matches!(at(0), Some('e' | 'E'))
+                    && match at(1) {
+                        Some('+' | '-') => at(2).is_some_and(|c| c.is_ascii_digit()),
+                        c => c.is_some_and(|c| c.is_ascii_digit()),
+                    };
+                if has_exponent {
+                    lexeme.push(self.chars[self.char_pos]);
+                    self.char_pos += 1;
 
-            // Consume closing "
-            self.char_pos += 1;
-            self.byte_pos += 1;
-            let end = self.char_pos;
-
-            return Some(Token::Literal(
-                Span {
-                    line: self.line,
-                    start,
-                    end,
-                },
-                format!("\"{value}\""),
-                Value::String(value),
-            ));
-        }
+                    if matches!(self.chars[self.char_pos], '+' | '-') {
+                        lexeme.push(self.chars[self.char_pos]);
+                        self.char_pos += 1;
+                    }
 
-        // Read numbers
-        // Numbers must start with a digit (cannot do .1)
-        // Numbers can contain a single . (cannot do 1.2.3)
-        // Numbers must have a digit after the . (cannot do 1. That's two tokens)
-        if self.chars[self.char_pos].is_digit(10) {
-            log::debug!("Matching number");
-
-            let mut lexeme = String::new();
-            let mut has_dot = false;
-            let mut last_dot = false;
-            let start = self.char_pos;
-
-            while self.char_pos < self.chars.len() {
-                let c = self.chars[self.char_pos];
-
-                if c.is_digit(10) {
-                    lexeme.push(c);
-                    last_dot = false;
-                } else if c == '.' && !has_dot {
-                    lexeme.push(c);
-                    has_dot = true;
-                    last_dot = true;
-                } else {
-                    break;
+                    while self.char_pos < self.chars.len()
+                        && self.chars[self.char_pos].is_ascii_digit()
+                    {
+                        lexeme.push(self.chars[self.char_pos]);
+                        self.char_pos += 1;
+                    }
                 }
 
-                self.char_pos += 1;
-                self.byte_pos += 1;
-            }
+                // Literals without a decimal point are integers, unless they're too big for one
+                let value = match lexeme.parse::<i64>() {
+                    Ok(i) if !has_exponent => Value::Integer(i),
+                    _ => Value::Number(lexeme.parse().unwrap()),
+                };
+                let end = self.char_pos;
 
-            // If the last character was a dot, we need to back up
-            if last_dot {
-                lexeme.pop();
-                self.char_pos -= 1;
-                self.byte_pos -= 1;
+                // Too large even for a float (1e999), which would otherwise quietly be inf
+                if matches!(value, Value::Number(n) if n.is_infinite()) {
+                    self.errors.push(TokenizerError::MalformedNumber(
+                        self.span(start, end),
+                        lexeme,
+                    ));
+                    continue;
+                }
+
+                return Some(Token::Literal(self.span(start, end), lexeme, value));
             }
 
-            let value: f64 = lexeme.parse().unwrap();
-            let end = self.char_pos;
-
-            return Some(Token::Literal(
-                Span {
-                    line: self.line,
-                    start,
-                    end,
-                },
-                lexeme,
-                Value::Number(value),
-            ));
-        }
+            // Read constant values
+            for (lexeme, value) in Value::CONSTANT_VALUES.iter() {
+                let lexeme_chars = lexeme.chars().collect::<Vec<_>>();
+                if self.chars[self.char_pos..].starts_with(&lexeme_chars) {
+                    log::debug!(target: phase::TOKENIZER, "Matching constant: {}", lexeme);
+
+                    let start = self.char_pos;
+                    self.char_pos += lexeme.len();
+                    let end = self.char_pos;
+                    return Some(Token::Literal(
+                        self.span(start, end),
+                        lexeme.to_string(),
+                        value.clone(),
+                    ));
+                }
+            }
 
-        // Read constant values
-        for (lexeme, value) in Value::CONSTANT_VALUES.iter() {
-            let lexeme_chars = lexeme.chars().collect::<Vec<_>>();
-            if self.chars[self.char_pos..].starts_with(&lexeme_chars) {
-                log::debug!("Matching constant: {}", lexeme);
+            // Match identifiers
+            // Identifiers start with a letter or _
+            // Identifiers can contain letters, numbers, and _
+            if self.chars[self.char_pos].is_alphabetic() || self.chars[self.char_pos] == '_' {
+                log::debug!(target: phase::TOKENIZER, "Matching identifier");
 
+                let mut value = String::new();
                 let start = self.char_pos;
-                self.char_pos += lexeme.len();
-                self.byte_pos += lexeme.len();
-                let end = self.char_pos;
-                return Some(Token::Literal(
-                    Span {
-                        line: self.line,
-                        start,
-                        end,
-                    },
-                    lexeme.to_string(),
-                    value.clone(),
-                ));
-            }
-        }
 
-        // Match identifiers
-        // Identifiers start with a letter or _
-        // Identifiers can contain letters, numbers, and _
-        if self.chars[self.char_pos].is_alphabetic() || self.chars[self.char_pos] == '_' {
-            log::debug!("Matching identifier");
+                while self.char_pos < self.chars.len() {
+                    let c = self.chars[self.char_pos];
 
-            let mut value = String::new();
-            let start = self.char_pos;
+                    if c.is_alphanumeric() || c == '_' {
+                        value.push(c);
+                    } else {
+                        break;
+                    }
 
-            while self.char_pos < self.chars.len() {
-                let c = self.chars[self.char_pos];
+                    self.char_pos += 1;
+                }
 
-                if c.is_alphanumeric() || c == '_' {
-                    value.push(c);
+                let end = self.char_pos;
+
+                // Check if it's actually a keyword
+                // This is called 'maximal munch', so superduper doesn't get parsed as <super><duper>
+                if let Ok(keyword) = Keyword::try_from(value.as_str()) {
+                    return Some(Token::Keyword(self.span(start, end), keyword));
                 } else {
-                    break;
+                    return Some(Token::Identifier(self.span(start, end), value));
                 }
+            }
 
-                self.char_pos += 1;
-                self.byte_pos += 1;
+            // Match remaining keywords, this will include ones that are symbolic
+            for keyword in Keyword::values() {
+                let pattern = keyword.to_value();
+                let pattern_chars = pattern.chars().collect::<Vec<_>>();
+
+                if self.chars[self.char_pos..].starts_with(&pattern_chars) {
+                    log::debug!(target: phase::TOKENIZER, "Matching keyword: {}", keyword);
+
+                    let start = self.char_pos;
+                    self.char_pos += pattern_chars.len();
+                    let end = self.char_pos;
+
+                    return Some(Token::Keyword(self.span(start, end), keyword));
+                }
             }
 
-            let end = self.char_pos;
-
-            // Check if it's actually a keyword
-            // This is called 'maximal munch', so superduper doesn't get parsed as <super><duper>
-            if let Ok(keyword) = Keyword::try_from(value.as_str()) {
-                return Some(Token::Keyword(
-                    Span {
-                        line: self.line,
-                        start,
-                        end,
-                    },
-                    keyword,
-                ));
-            } else {
-                return Some(Token::Identifier(
-                    Span {
-                        line: self.line,
-                        start,
-                        end,
-                    },
-                    value,
-                ));
+            // The only things that should be left are whitespace
+            // Anything else is an error
+            let c = self.chars[self.char_pos];
+            self.char_pos += 1;
+
+            // Newlines don't emit a token, but '\n' does increment the line number
+            if c.is_whitespace() {
+                if c == '\n' {
+                    self.line += 1;
+                    self.line_start = self.char_pos;
+                }
+                continue;
             }
+
+            // Anything else should emit an error and continue as best we can
+            self.errors.push(TokenizerError::UnexpectedCharacter(
+                self.span(self.char_pos - 1, self.char_pos),
+                c,
+            ));
         }
+    }
+}
 
-        // Match remaining keywords, this will include ones that are symbolic
-        for keyword in Keyword::values() {
-            let pattern = keyword.to_value();
-            let pattern_chars = pattern.chars().collect::<Vec<_>>();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{DeterministicHost, Host};
+
+    // Pieces of Lox that are most likely to trip up the tokenizer, plus some multibyte characters
+    const PIECES: &[&str] = &[
+        ".", "..", "1", "1.", ".5", "12.34", "\"", "\"str\"", "/", "//", "/*", "*/", "\n", "\r\n",
+        " ", "\t", "=", "==", "!=", "<=", "?.", "??", "and", "var", "x", "_", "é", "😀", "\u{0}",
+        "\u{feff}", "#", "@", "`",
+    ];
+
+    fn check_bytes(bytes: &[u8]) {
+        // Streaming takes any bytes, bad UTF-8 is a read error
+        Tokenizer::from_reader(bytes).for_each(drop);
+
+        let source = String::from_utf8_lossy(bytes);
+        if let Err(error) = Tokenizer::check(&source) {
+            panic!("{error} in {source:?}");
+        }
+    }
 
-            if self.chars[self.char_pos..].starts_with(&pattern_chars) {
-                log::debug!("Matching keyword: {}", keyword);
+    #[test]
+    fn checks_edge_cases() {
+        for source in ["", ".", "1.", "a.", "1 .", "\"", "/", "/*", "é", "😀."] {
+            check_bytes(source.as_bytes());
+        }
+    }
 
-                let start = self.char_pos;
-                self.byte_pos += pattern.len();
-                self.char_pos += pattern_chars.len();
-                let end = self.char_pos;
+    #[test]
+    fn checks_multibyte_characters_split_anywhere() {
+        let source = "print \"é😀\"; // 😀\n";
+        for split in 0..=source.len() {
+            check_bytes(&source.as_bytes()[..split]);
+            check_bytes(&source.as_bytes()[split..]);
+        }
+    }
 
-                return Some(Token::Keyword(
-                    Span {
-                        line: self.line,
-                        start,
-                        end,
-                    },
-                    keyword,
-                ));
-            }
+    #[test]
+    fn checks_random_pieces() {
+        let mut host = DeterministicHost::new(639);
+        for _ in 0..2_000 {
+            let count = (host.random() * 20.0) as usize;
+            let source = (0..count)
+                .map(|_| PIECES[(host.random() * PIECES.len() as f64) as usize])
+                .collect::<String>();
+            check_bytes(source.as_bytes());
         }
+    }
 
-        // The only things that should be left are whitespace
-        // Anything else is an error
-        let c = self.chars[self.char_pos];
-        self.char_pos += 1;
-        self.byte_pos += c.len_utf8();
+    #[test]
+    fn checks_random_bytes() {
+        let mut host = DeterministicHost::new(639);
+        for _ in 0..2_000 {
+            let count = (host.random() * 20.0) as usize;
+            let bytes = (0..count)
+                .map(|_| (host.random() * 256.0) as u8)
+                .collect::<Vec<_>>();
+            check_bytes(&bytes);
+        }
+    }
 
-        // Newlines don't emit a token, but '\n' does increment the line number
-        if c.is_whitespace() {
-            if c == '\n' {
-                self.line += 1;
-            }
-            return self.next();
+    #[test]
+    fn slices_each_token_as_written() {
+        let source = "var é = \"😀\" >= 1.50;\n// done";
+        let mut tokenizer = Tokenizer::new(source);
+        let spans = tokenizer
+            .by_ref()
+            .map(|token| *token.span())
+            .collect::<Vec<_>>();
+        let lexemes = spans
+            .iter()
+            .map(|span| tokenizer.slice(span))
+            .collect::<Vec<_>>();
+
+        // Numbers keep their own spelling, not the 1.5 they'd print as
+        assert_eq!(
+            lexemes,
+            vec!["var", "é", "=", "\"😀\"", ">=", "1.50", ";", ""]
+        );
+        assert_eq!((spans[1].start, spans[1].byte_start), (4, 4));
+        assert_eq!((spans[4].start, spans[4].byte_start), (12, 16));
+    }
+
+    #[test]
+    fn streaming_has_nothing_to_slice() {
+        let mut tokenizer = Tokenizer::from_reader("var x;".as_bytes());
+        let span = *tokenizer.next().unwrap().span();
+        assert_eq!(tokenizer.slice(&span), "");
+    }
+
+    // Reads a byte at a time, counting them, so a test can see how far the tokenizer has read
+    struct Trickle<'a> {
+        bytes: &'a [u8],
+        read: std::rc::Rc<std::cell::Cell<usize>>,
+    }
+
+    impl std::io::Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let Some((first, rest)) = self.bytes.split_first() else {
+                return Ok(0);
+            };
+            buf[0] = *first;
+            self.bytes = rest;
+            self.read.set(self.read.get() + 1);
+            Ok(1)
         }
+    }
 
-        // Anything else should emit an error and continue as best we can
-        self.errors.push(TokenizerError::UnexpectedCharacter(
-            Span {
-                line: self.line,
-                start: self.char_pos,
-                end: self.char_pos,
+    #[test]
+    fn streams_a_line_at_a_time() {
+        let source = "var a = 1;\nprint a;\nprint \"two\nlines\";\n";
+        let read = std::rc::Rc::default();
+        let reader = std::io::BufReader::with_capacity(
+            1,
+            Trickle {
+                bytes: source.as_bytes(),
+                read: std::rc::Rc::clone(&read),
             },
-            c,
+        );
+        let mut tokenizer = Tokenizer::from_reader(reader);
+
+        // Nothing past the first line has been read for its first token
+        tokenizer.next();
+        assert_eq!(read.get(), "var a = 1;\n".len());
+
+        // Only the line being tokenized is kept, earlier ones are dropped
+        let tokens = tokenizer.by_ref().take(5).collect::<Vec<_>>();
+        assert_eq!(
+            tokens.last().map(Token::to_string).as_deref(),
+            Some("Print")
+        );
+        assert!(
+            tokenizer.chars.len() <= "print a;\n".len(),
+            "{:?}",
+            tokenizer.chars
+        );
+
+        // Spans still count from the start, even for a string split over lines as it's read, and
+        // are on the line it ends on like they are without streaming
+        let tokens = tokenizer.collect::<Vec<_>>();
+        let string = tokens
+            .iter()
+            .find(|token| matches!(token, Token::Literal(..)))
+            .unwrap();
+        let start = source.find("\"two").unwrap();
+        assert_eq!(string.span().line, 4);
+        assert_eq!(
+            (string.span().byte_start, string.span().byte_end),
+            (start, start + "\"two\nlines\"".len())
+        );
+        assert_eq!(tokens, Tokenizer::new(source).skip(6).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn stops_at_a_read_error() {
+        let mut tokenizer = Tokenizer::from_reader(&b"print 1;\nprint \xff;\n"[..]);
+        let tokens = tokenizer
+            .by_ref()
+            .map(|token| token.to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(tokens, ["Print", "1.0", "Semicolon", "EOF"]);
+        assert!(matches!(
+            tokenizer.iter_errors().collect::<Vec<_>>()[..],
+            [TokenizerError::ReadFailed(span, _)] if span.line == 2
         ));
-        self.next()
+    }
+
+    #[test]
+    fn numbers_too_large_for_a_float_are_malformed() {
+        let hex = format!("0x{}", "f".repeat(300));
+        let source = format!("1e999 1e308 {hex} {}", "9".repeat(400));
+        let mut tokenizer = Tokenizer::new(&source);
+        let literals = tokenizer
+            .by_ref()
+            .filter(|token| matches!(token, Token::Literal(..)))
+            .count();
+        let errors = tokenizer
+            .iter_errors()
+            .map(|error| error.to_string())
+            .collect::<Vec<_>>();
+
+        // Only 1e308 is still a number
+        assert_eq!(literals, 1);
+        assert_eq!(
+            errors,
+            [
+                "[line 1] Malformed number: 1e999".to_string(),
+                format!("[line 1] Malformed number: {hex}"),
+                format!("[line 1] Malformed number: {}", "9".repeat(400)),
+            ]
+        );
     }
 }
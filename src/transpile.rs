@@ -0,0 +1,459 @@
+use crate::builtins::{BuiltIn, Namespace};
+use crate::json::Json;
+use crate::named_source::NamedSource;
+use crate::parser::{Ast, AstNode, NodeId};
+use crate::tokenizer::Keyword;
+use crate::values::Value;
+
+/// Runtime support every transpiled program starts with, builtins and Lox semantics
+const PRELUDE: &str = include_str!("transpile_prelude.js");
+
+// Names that are fine in Lox but not as JavaScript identifiers, these get a trailing _
+const RESERVED: &[&str] = &[
+    "arguments",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "enum",
+    "eval",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "implements",
+    "import",
+    "in",
+    "instanceof",
+    "interface",
+    "let",
+    "new",
+    "null",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "undefined",
+    "var",
+    "void",
+    "while",
+    "with",
+    "yield",
+];
+
+/// Translate parsed programs into one standalone JavaScript file, runnable with node
+///
+/// Programs run in order and share globals, the same as passing several files to run. Runtime
+/// errors and uncaught throws are printed to stderr like the interpreter does and set the exit
+/// code to 70. The tree currently has no functions or classes, so neither do the programs.
+pub fn javascript(programs: &[(NamedSource, Ast)], compat: bool) -> String {
+    let mut output = String::from(PRELUDE);
+    output.push_str("\nconst $ = __lox.builtins;\n");
+    for namespace in Namespace::values() {
+        let members = namespace.members().into_iter().map(Json::from);
+        output.push_str(&format!(
+            "__lox.namespace({}, {});\n",
+            Json::from(namespace.to_value()),
+            Json::Array(members.collect())
+        ));
+    }
+    output.push_str("\n__lox.run(() => {\n");
+
+    // Script arguments come from node's command line instead of after --
+    let mut transpiler = Transpiler {
+        output: String::new(),
+        depth: 1,
+        scopes: vec![vec![
+            ("ARGS".to_string(), "ARGS".to_string()),
+            ("ARGC".to_string(), "ARGC".to_string()),
+        ]],
+        source: None,
+        ast: &Ast::new(),
+        compat,
+    };
+    transpiler.line("let ARGS = process.argv.slice(2);");
+    transpiler.line("let ARGC = ARGS.length;");
+
+    for (source, ast) in programs {
+        transpiler.source = Some(source);
+        transpiler.ast = ast;
+        transpiler.statement(ast.root());
+    }
+
+    output.push_str(&transpiler.output);
+    output.push_str("});\n");
+    output
+}
+
+struct Transpiler<'a> {
+    output: String,
+    depth: usize,
+    scopes: Vec<Vec<(String, String)>>, // Lox and JavaScript names declared in each enclosing block
+    source: Option<&'a NamedSource>,
+    ast: &'a Ast, // The program being transpiled
+    compat: bool, // Namespaced builtins can be used by their names alone
+}
+
+impl<'a> Transpiler<'a> {
+    fn line(&mut self, text: &str) {
+        self.output.push_str(&"    ".repeat(self.depth));
+        self.output.push_str(text);
+        self.output.push('\n');
+    }
+
+    fn enter(&mut self) {
+        self.depth += 1;
+        self.scopes.push(vec![]);
+    }
+
+    fn exit(&mut self) {
+        self.depth -= 1;
+        self.scopes.pop();
+    }
+
+    fn is_declared(&self, name: &str) -> bool {
+        self.scopes
+            .iter()
+            .any(|scope| scope.iter().any(|(n, _)| n == name))
+    }
+
+    // Returns true if this is new to the current scope, redeclaring just assigns
+    // One that shadows a variable from an enclosing block gets a name of its own, since a let
+    // hides the outer variable for the whole block in JavaScript, even before it's declared
+    fn declare(&mut self, name: &str) -> bool {
+        let js_name = if self.is_declared(name) {
+            format!("{}${}", mangle(name), self.scopes.len() - 1)
+        } else {
+            mangle(name)
+        };
+
+        let scope = self.scopes.last_mut().expect("at least the global scope");
+        if scope.iter().any(|(n, _)| n == name) {
+            false
+        } else {
+            scope.push((name.to_string(), js_name));
+            true
+        }
+    }
+
+    // The JavaScript name of the innermost variable called name
+    fn variable(&self, name: &str) -> String {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter())
+            .find(|(n, _)| n == name)
+            .map_or_else(|| mangle(name), |(_, js_name)| js_name.clone())
+    }
+
+    // Statements in a block, each on its own line at the current depth
+    fn block(&mut self, nodes: &[NodeId]) {
+        self.enter();
+        for node in nodes {
+            self.statement(*node);
+        }
+        self.exit();
+    }
+
+    fn statement(&mut self, id: NodeId) {
+        let ast = self.ast;
+        match &ast[id] {
+            AstNode::Program(_, nodes) => {
+                for node in nodes {
+                    self.statement(*node);
+                }
+            }
+            AstNode::Group(_, expression) => self.statement(*expression),
+
+            AstNode::Block(_, nodes) => {
+                self.line("{");
+                self.block(nodes);
+                self.line("}");
+            }
+
+            AstNode::Declaration(_, name, value) => {
+                let value = self.expression(*value);
+                let keyword = if self.declare(name) { "let " } else { "" };
+                self.line(&format!("{keyword}{} = {value};", self.variable(name)));
+            }
+
+            AstNode::Enum(_, name, variants) => {
+                let variants = Json::Array(variants.iter().map(|v| v.as_str().into()).collect());
+                let name_literal = Json::from(name.as_str());
+                let keyword = if self.declare(name) { "let " } else { "" };
+                self.line(&format!(
+                    "{keyword}{} = __lox.enumType({name_literal}, {variants});",
+                    self.variable(name)
+                ));
+            }
+
+            AstNode::ForIn(span, name, iterable, body) => {
+                let iterable = self.expression(*iterable);
+                self.scopes.push(vec![]);
+                self.declare(name);
+                self.line(&format!(
+                    "for (let {} of __lox.iterate({iterable}, {})) {{",
+                    self.variable(name),
+                    span.line
+                ));
+                self.depth += 1;
+                self.statement(*body);
+                self.exit();
+                self.line("}");
+            }
+
+            AstNode::Assert(span, condition, message) => {
+                let (name, text) = match self.source {
+                    Some(source) => (source.name.clone(), source.slice(&ast[*condition].span())),
+                    None => ("<unknown>".to_string(), ast.display(*condition).to_string()),
+                };
+                let condition = self.expression(*condition);
+                let message = match message {
+                    Some(message) => format!("() => {}", self.expression(*message)),
+                    None => "null".to_string(),
+                };
+                self.line(&format!(
+                    "__lox.assert({condition}, {message}, {}, {}, {});",
+                    span.line,
+                    Json::from(name),
+                    Json::from(text)
+                ));
+            }
+
+            AstNode::Print(_, value) => {
+                let value = self.expression(*value);
+                self.line(&format!("$[\"print\"]({value});"));
+            }
+
+            AstNode::Throw(span, value) => {
+                let value = self.expression(*value);
+                self.line(&format!("throw __lox.thrown({value}, {});", span.line));
+            }
+
+            // Modules are found when they're run, which the transpiled program can't do
+            AstNode::Import(span, module) => {
+                let message = format!(
+                    "[line {}] Can't import '{module}' in JavaScript.",
+                    span.line
+                );
+                self.line(&format!("__lox.fail({});", Json::from(message.as_str())));
+            }
+
+            AstNode::Try(_, body, catch, finally) => {
+                self.line("try {");
+                self.block(std::slice::from_ref(body));
+
+                if let Some((name, handler)) = catch {
+                    self.line("} catch (__error) {");
+                    self.enter();
+                    self.declare(name);
+                    let variable = self.variable(name);
+                    self.line(&format!("let {variable} = __lox.caught(__error);"));
+                    self.statement(*handler);
+                    self.exit();
+                }
+
+                if let Some(finally) = finally {
+                    self.line("} finally {");
+                    self.block(std::slice::from_ref(finally));
+                }
+
+                self.line("}");
+            }
+
+            _ => {
+                let expression = self.expression(id);
+                self.line(&format!("{expression};"));
+            }
+        }
+    }
+
+    fn expression(&mut self, id: NodeId) -> String {
+        let ast = self.ast;
+        match &ast[id] {
+            AstNode::Literal(_, value) => literal(value),
+
+            AstNode::Symbol(span, name) => match self.symbol(name) {
+                Some(symbol) => symbol,
+                None => format!(
+                    "__lox.undefinedVariable({}, {})",
+                    Json::from(name.as_ref()),
+                    span.line
+                ),
+            },
+
+            AstNode::Group(_, expression) => format!("({})", self.expression(*expression)),
+
+            // Operators are the builtins of the same name, which check their operands' types
+            AstNode::Binary(span, op, lhs, rhs) => {
+                let (lhs, rhs) = (self.expression(*lhs), self.expression(*rhs));
+                let op = Json::from(op.to_value());
+                format!("__lox.at({}, $[{op}], {lhs}, {rhs})", span.line)
+            }
+            AstNode::Unary(span, op, operand) => {
+                let operand = self.expression(*operand);
+                let op = Json::from(op.to_value());
+                format!("__lox.at({}, $[{op}], {operand})", span.line)
+            }
+
+            AstNode::Logical(_, op, lhs, rhs) => {
+                let (lhs, rhs) = (self.expression(*lhs), self.expression(*rhs));
+                format!("__lox.{}({lhs}, () => {rhs})", op.to_value())
+            }
+
+            AstNode::Call(span, function, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.expression(*arg))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                match &ast[*function] {
+                    // Builtins can't fail to be callable, so skip the check
+                    AstNode::Symbol(_, name) if self.resolves_to_builtin(name) => {
+                        let name = Json::from(name.as_ref());
+                        if args.is_empty() {
+                            format!("__lox.at({}, $[{name}])", span.line)
+                        } else {
+                            format!("__lox.at({}, $[{name}], {args})", span.line)
+                        }
+                    }
+                    _ => {
+                        let function = self.expression(*function);
+                        if args.is_empty() {
+                            format!("__lox.call({function}, {})", span.line)
+                        } else {
+                            format!("__lox.call({function}, {}, {args})", span.line)
+                        }
+                    }
+                }
+            }
+
+            AstNode::Get(span, object, name) => {
+                let object = self.expression(*object);
+                format!(
+                    "__lox.get({object}, {}, {})",
+                    Json::from(name.as_str()),
+                    span.line
+                )
+            }
+
+            AstNode::OptionalGet(span, object, name) => {
+                let object = self.expression(*object);
+                format!(
+                    "__lox.getOptional({object}, {}, {})",
+                    Json::from(name.as_str()),
+                    span.line
+                )
+            }
+
+            AstNode::OptionalChain(_, chain) => {
+                format!("__lox.chain(() => {})", self.expression(*chain))
+            }
+
+            AstNode::Coalesce(_, lhs, rhs) => {
+                format!("({} ?? {})", self.expression(*lhs), self.expression(*rhs))
+            }
+
+            AstNode::Index(span, object, index) => {
+                let object = self.expression(*object);
+                let index = self.expression(*index);
+                format!("__lox.index({object}, {index}, {})", span.line)
+            }
+
+            AstNode::Assignment(span, name, value) => {
+                let value = self.expression(*value);
+                if self.is_declared(name) {
+                    format!("({} = {value})", self.variable(name))
+                } else {
+                    let name = Json::from(name.as_ref());
+                    format!("__lox.undefinedVariable({name}, {})", span.line)
+                }
+            }
+
+            // Anything else is a statement, run it in a function so it can be used as a value
+            _ => {
+                let depth = self.depth;
+                let output = std::mem::take(&mut self.output);
+
+                self.depth = 1;
+                self.scopes.push(vec![]);
+                self.statement(id);
+                self.scopes.pop();
+                self.depth = depth;
+
+                let body = std::mem::replace(&mut self.output, output);
+                let indent = "    ".repeat(depth);
+                let body = body
+                    .lines()
+                    .map(|line| format!("{indent}{line}\n"))
+                    .collect::<String>();
+                format!("(() => {{\n{body}{indent}    return null;\n{indent}}})()")
+            }
+        }
+    }
+
+    // Same order as the evaluator: keywords, variables, then named builtins, constants, and
+    // namespaces
+    // None if it's undefined, since nothing can be declared later without functions
+    fn symbol(&self, name: &str) -> Option<String> {
+        let builtin = BuiltIn::global(name, self.compat).is_some()
+            || BuiltIn::constant(name, self.compat).is_some()
+            || Namespace::try_from(name).is_ok();
+        if Keyword::try_from(name).is_ok() || (!self.is_declared(name) && builtin) {
+            Some(format!("$[{}]", Json::from(name)))
+        } else if self.is_declared(name) {
+            Some(self.variable(name))
+        } else {
+            None
+        }
+    }
+
+    fn resolves_to_builtin(&self, name: &str) -> bool {
+        BuiltIn::global(name, self.compat).is_some()
+            && (Keyword::try_from(name).is_ok() || !self.is_declared(name))
+    }
+}
+
+fn literal(value: &Value) -> String {
+    match value {
+        Value::Nil => "null".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Integer(i) => i.to_string(),
+        Value::Number(n) if n.is_infinite() && *n > 0.0 => "Infinity".to_string(),
+        Value::Number(n) if n.is_infinite() => "-Infinity".to_string(),
+        Value::Number(n) if n.is_nan() => "NaN".to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => Json::from(&**s).to_string(),
+        // The parser only makes the literals above
+        value => unreachable!("{value} can't be a literal"),
+    }
+}
+
+fn mangle(name: &str) -> String {
+    if RESERVED.contains(&name) || name.starts_with("__") || name == "$" {
+        format!("{name}_")
+    } else {
+        name.to_string()
+    }
+}
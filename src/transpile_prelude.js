@@ -0,0 +1,437 @@
+"use strict";
+
+// Runtime support for Lox programs transpiled to JavaScript by jp-lox
+// Lox nil is null, numbers are numbers, strings are strings, and lists are arrays
+const __lox = (() => {
+    class LoxError extends Error {}
+
+    // A value thrown by Lox code, as opposed to a runtime error
+    class Thrown {
+        constructor(value, line) {
+            this.value = value;
+            this.line = line;
+        }
+    }
+
+    // Thrown by ?. on nil and caught by the chain it's in
+    const SHORT_CIRCUIT = Symbol("short circuit");
+
+    class LoxEnum {
+        constructor(name, variants) {
+            this.name = name;
+            this.variants = variants.map((variant, ordinal) => new LoxVariant(this, variant, ordinal));
+        }
+    }
+
+    class LoxVariant {
+        constructor(type, name, ordinal) {
+            this.type = type;
+            this.name = name;
+            this.ordinal = ordinal;
+        }
+    }
+
+    // Builtins grouped under a name, eg Math for Math.sqrt
+    class LoxNamespace {
+        constructor(name, members) {
+            this.name = name;
+            this.members = members;
+        }
+    }
+
+    // Appended to in place, the pieces are only joined when it's turned back into a string
+    class LoxStringBuilder {
+        constructor(initial) {
+            this.pieces = initial === undefined ? [] : [initial];
+        }
+
+        toString() {
+            const text = this.pieces.join("");
+            this.pieces = [text];
+            return text;
+        }
+    }
+
+    const fail = (message) => {
+        throw new LoxError(message);
+    };
+
+    const isNumber = (value) => typeof value === "number";
+    const isString = (value) => typeof value === "string";
+    const isCallable = (value) => typeof value === "function";
+    const isTruthy = (value) => value !== null && value !== false;
+
+    const typeName = (value) => {
+        if (value === null) return "nil";
+        if (typeof value === "boolean") return "bool";
+        if (isNumber(value)) return "number";
+        if (isString(value)) return "string";
+        if (isCallable(value)) return "function";
+        if (Array.isArray(value)) return "list";
+        if (value instanceof LoxEnum) return "enum";
+        if (value instanceof LoxVariant) return "variant";
+        if (value instanceof LoxNamespace) return "namespace";
+        if (value instanceof LoxStringBuilder) return "builder";
+        return "unknown";
+    };
+
+    const numberToString = (n) => {
+        if (n === Infinity) return "inf";
+        if (n === -Infinity) return "-inf";
+        return String(n);
+    };
+
+    // The same as print
+    const stringify = (value) => {
+        if (value === null) return "nil";
+        if (isNumber(value)) return numberToString(value);
+        if (Array.isArray(value)) return "[" + value.map(stringify).join(", ") + "]";
+        if (isCallable(value)) return value.loxDisplay ?? "<function>";
+        if (value instanceof LoxEnum) return `<enum ${value.name}>`;
+        if (value instanceof LoxVariant) return `${value.type.name}.${value.name}`;
+        if (value instanceof LoxNamespace) return `<namespace ${value.name}>`;
+        if (value instanceof LoxStringBuilder) return value.toString();
+        return String(value);
+    };
+
+    // A type name the way it reads in a sentence, eg a number or an integer, with nil on its own
+    const withArticle = (type) => (type === "nil" ? type : /^[aeiou]/.test(type) ? `an ${type}` : `a ${type}`);
+
+    // The same messages as the interpreter's for builtin arguments, by the builtin's Lox name
+    const expected = (name, param, type, value) =>
+        fail(`${name}: Expected ${withArticle(type)} for ${param} but got ${withArticle(typeName(value))}.`);
+    const invalid = (name, args) => fail(`${name}: Invalid arguments (${args.map(stringify).join(", ")}).`);
+
+    // Rust rounds exact ties to even when formatting, JavaScript rounds them up
+    const toFixed = (n, digits) => {
+        const scaled = n * 10 ** digits;
+        if (Number.isInteger(scaled * 2) && !Number.isInteger(scaled)) {
+            const even = Math.floor(scaled) % 2 === 0 ? Math.floor(scaled) : Math.ceil(scaled);
+            return (even / 10 ** digits).toFixed(digits);
+        }
+        return n.toFixed(digits);
+    };
+
+    // stdin is read all at once the first time it's needed, then handed out a line at a time
+    let inputLines = null;
+    const readLine = () => {
+        if (inputLines === null) {
+            const text = require("fs").readFileSync(0, "utf8");
+            inputLines = text === "" ? [] : text.replace(/\r?\n$/, "").split(/\r?\n/);
+        }
+        return inputLines.length === 0 ? null : inputLines.shift();
+    };
+
+    const parseNumber = (s) => {
+        const n = Number(s.trim());
+        return s.trim() === "" || !Number.isFinite(n) ? null : n;
+    };
+
+    const equal = (a, b) => {
+        if (Array.isArray(a) && Array.isArray(b)) {
+            return a.length === b.length && a.every((item, i) => equal(item, b[i]));
+        }
+        return a === b;
+    };
+
+    // Parameters by name, eg numbers("Math.pow", { base, exponent })
+    const numbers = (name, params) => {
+        for (const [param, value] of Object.entries(params)) {
+            if (!isNumber(value)) expected(name, param, "number", value);
+        }
+    };
+
+    // The same messages as the interpreter's operators, without the line
+    const operands = (...args) => {
+        if (!args.every(isNumber)) fail(args.length === 1 ? "Operand must be a number." : "Operands must be numbers.");
+    };
+
+    const compare = (test) => (a, b) => {
+        if ((isNumber(a) && isNumber(b)) || (isString(a) && isString(b))) return test(a, b);
+        fail("Operands must be two numbers or two strings.");
+    };
+
+    const formatString = (format, args) => {
+        let result = "";
+        let nextArg = 0;
+
+        for (let i = 0; i < format.length; i++) {
+            const c = format[i];
+            if ((c === "{" || c === "}") && format[i + 1] === c) {
+                result += c;
+                i++;
+            } else if (c === "{") {
+                const end = format.indexOf("}", i);
+                if (end === -1) fail("Unterminated placeholder in format string.");
+
+                const spec = format.slice(i + 1, end);
+                const [position, precision] = spec.split(":");
+                const index = position === "" ? nextArg++ : Number(position);
+                if (!Number.isInteger(index)) fail(`Invalid placeholder {${spec}} in format string.`);
+                if (index >= args.length) fail(`Missing argument ${index} in format string.`);
+
+                const arg = args[index];
+                if (precision === undefined) {
+                    result += stringify(arg);
+                } else {
+                    const digits = Number(precision.slice(1));
+                    if (!precision.startsWith(".") || !Number.isInteger(digits)) {
+                        fail(`Invalid precision {${spec}} in format string.`);
+                    }
+                    if (isNumber(arg)) result += toFixed(arg, digits);
+                    else if (isString(arg)) result += [...arg].slice(0, digits).join("");
+                    else fail(`Cannot apply precision to ${stringify(arg)} in format string.`);
+                }
+                i = end;
+            } else if (c === "}") {
+                fail("Unmatched '}' in format string.");
+            } else {
+                result += c;
+            }
+        }
+
+        return result;
+    };
+
+    // Builtins and operators don't know their line, so errors from them get the line of the call
+    // they failed in, like the interpreter's
+    const at = (line, f, ...args) => {
+        try {
+            return f(...args);
+        } catch (error) {
+            if (error instanceof LoxError && !/^\[line \d+\] /.test(error.message)) {
+                error.message = `[line ${line}] ${error.message}`;
+            }
+            throw error;
+        }
+    };
+
+    const call = (f, line, ...args) => {
+        if (!isCallable(f)) fail(`[line ${line}] Can only call functions and classes.`);
+        return at(line, f, ...args);
+    };
+
+    const loxSort = (items, f) => {
+        const sorted = [...items];
+        if (f === undefined) {
+            return sorted.sort((a, b) => {
+                if (isString(a) && isString(b)) return a < b ? -1 : a > b ? 1 : 0;
+                // NaN is neither before nor after anything
+                if (isNumber(a) && isNumber(b) && !Number.isNaN(a - b)) return a - b;
+                fail(`Cannot compare ${stringify(a)} and ${stringify(b)} without a comparison function.`);
+            });
+        }
+
+        return sorted.sort((a, b) => {
+            const result = call(f, 0, a, b);
+            if (isNumber(result) && !Number.isNaN(result)) return result;
+            fail(`Sort comparison must return a number, got ${stringify(result)}.`);
+        });
+    };
+
+    const builtins = {
+        "+": (a, b) => {
+            if ((isNumber(a) && isNumber(b)) || (isString(a) && isString(b))) return a + b;
+            fail("Operands must be two numbers or two strings.");
+        },
+        "-": (...args) => {
+            operands(...args);
+            return args.length === 1 ? -args[0] : args[0] - args[1];
+        },
+        "*": (a, b) => (operands(a, b), a * b),
+        "/": (a, b) => (operands(a, b), b === 0 ? fail("Division by zero.") : a / b),
+        and: (a, b) => (isTruthy(a) ? b : a),
+        or: (a, b) => (isTruthy(a) ? a : b),
+        "!": (v) => !isTruthy(v),
+        "<": compare((a, b) => a < b),
+        "<=": compare((a, b) => a <= b),
+        ">": compare((a, b) => a > b),
+        ">=": compare((a, b) => a >= b),
+        "==": (a, b) => equal(a, b),
+        "!=": (a, b) => !equal(a, b),
+        is: (a, b) => a === b,
+
+        sqrt: (x) => (numbers("Math.sqrt", { x }), Math.sqrt(x)),
+        abs: (x) => (numbers("Math.abs", { x }), Math.abs(x)),
+        floor: (x) => (numbers("Math.floor", { x }), Math.floor(x)),
+        ceil: (x) => (numbers("Math.ceil", { x }), Math.ceil(x)),
+        round: (x) => (numbers("Math.round", { x }), Math.round(x)),
+        min: (a, b) => (numbers("Math.min", { a, b }), Math.min(a, b)),
+        max: (a, b) => (numbers("Math.max", { a, b }), Math.max(a, b)),
+        sin: (x) => (numbers("Math.sin", { x }), Math.sin(x)),
+        cos: (x) => (numbers("Math.cos", { x }), Math.cos(x)),
+        pow: (base, exponent) => (numbers("Math.pow", { base, exponent }), Math.pow(base, exponent)),
+
+        parseNumber: (s) => parseNumber(s),
+        toFixed: (n, digits) => {
+            numbers("String.toFixed", { n, digits });
+            if (!Number.isInteger(digits)) invalid("String.toFixed", [n, digits]);
+            if (digits < 0 || digits > 100) fail(`String.toFixed: Digits must be between 0 and 100, got ${digits}.`);
+            return toFixed(n, digits);
+        },
+        format: (format, ...args) => formatString(format, args),
+        upper: (text) => (isString(text) ? text.toUpperCase() : expected("String.upper", "text", "string", text)),
+        lower: (text) => (isString(text) ? text.toLowerCase() : expected("String.lower", "text", "string", text)),
+        StringBuilder: (initial) => {
+            if (initial !== undefined && !isString(initial)) expected("StringBuilder", "initial", "string", initial);
+            return new LoxStringBuilder(initial);
+        },
+        append: (builder, piece) => {
+            if (!(builder instanceof LoxStringBuilder)) expected("String.append", "builder", "builder", builder);
+            // Shown first, showing the builder itself joins its pieces into a new array
+            const shown = stringify(piece);
+            builder.pieces.push(shown);
+            return builder;
+        },
+        toString: (v) => stringify(v),
+        type: (v) => typeName(v),
+        callDepth: () => fail("callDepth isn't available in JavaScript"),
+        stackTrace: () => fail("stackTrace isn't available in JavaScript"),
+
+        list: (...items) => items,
+        len: (v) => {
+            if (v instanceof LoxStringBuilder) return [...v.toString()].length;
+            return isString(v) ? [...v].length : Array.isArray(v) ? v.length : invalid("len", [v]);
+        },
+        map: (items, f) => items.map((item) => call(f, 0, item)),
+        filter: (items, f) => items.filter((item) => {
+            const result = call(f, 0, item);
+            return isTruthy(result);
+        }),
+        reduce: (items, f, init) => items.reduce((acc, item) => call(f, 0, acc, item), init),
+        sort: (items, f) => loxSort(items, f),
+        partial: (f, ...bound) => {
+            const partial = (...args) => call(f, 0, ...bound, ...args);
+            partial.loxDisplay = `<partial ${stringify(f)}>`;
+            return partial;
+        },
+
+        clock: () => Date.now() / 1000,
+        random: () => Math.random(),
+        print: (v) => (console.log(stringify(v)), null),
+        printf: (format, ...args) => (process.stdout.write(formatString(format, args)), null),
+        readLine: () => readLine(),
+        readNumber: () => {
+            const line = readLine();
+            return line === null ? null : parseNumber(line);
+        },
+        getenv: () => fail("Environment access is disabled (use --allow-env)."),
+        setenv: () => fail("Environment access is disabled (use --allow-env)."),
+
+        PI: Math.PI,
+        E: Math.E,
+    };
+
+    for (const [name, f] of Object.entries(builtins)) {
+        if (isCallable(f)) f.loxDisplay = `<builtin ${name}>`;
+    }
+
+    return {
+        builtins,
+        at,
+        call,
+        fail,
+
+        // The transpiler says what's in each, so they match the interpreter's
+        namespace(name, members) {
+            const entries = members.map((member) => [member, builtins[member]]);
+            builtins[name] = new LoxNamespace(name, Object.fromEntries(entries));
+        },
+
+        get(object, name, line) {
+            if (object instanceof LoxNamespace) {
+                if (Object.hasOwn(object.members, name)) return object.members[name];
+                fail(`[line ${line}] Undefined property '${name}'.`);
+            }
+            if (object instanceof LoxEnum) {
+                const variant = object.variants.find((variant) => variant.name === name);
+                return variant ?? fail(`[line ${line}] Undefined property '${name}'.`);
+            }
+            if (object instanceof LoxVariant) {
+                if (name === "name" || name === "ordinal") return object[name];
+                fail(`[line ${line}] Undefined property '${name}'.`);
+            }
+            if (object instanceof LoxStringBuilder) {
+                if (name === "append") return builtins.partial(builtins.append, object);
+                if (name === "toString") return builtins.partial(builtins.toString, object);
+                fail(`[line ${line}] Undefined property '${name}'.`);
+            }
+            fail(`[line ${line}] Only instances have properties.`);
+        },
+
+        getOptional(object, name, line) {
+            if (object === null) throw SHORT_CIRCUIT;
+            return this.get(object, name, line);
+        },
+
+        chain(f) {
+            try {
+                return f();
+            } catch (error) {
+                if (error === SHORT_CIRCUIT) return null;
+                throw error;
+            }
+        },
+
+        // The right side is a function, only called if the left doesn't decide the result
+        and(a, b) {
+            return isTruthy(a) ? b() : a;
+        },
+
+        or(a, b) {
+            return isTruthy(a) ? a : b();
+        },
+
+        index(object, index, line) {
+            if (!Number.isInteger(index)) fail(`[line ${line}] Index must be an integer.`);
+            if (!isString(object) && !Array.isArray(object)) {
+                fail(`[line ${line}] Only strings and lists can be indexed.`);
+            }
+
+            const items = isString(object) ? [...object] : object;
+            if (index < 0 || index >= items.length) fail(`[line ${line}] Index ${index} out of range.`);
+            return items[index];
+        },
+
+        iterate(iterable, line) {
+            if (isString(iterable)) return [...iterable];
+            if (Array.isArray(iterable)) return [...iterable];
+            fail(`[line ${line}] Can only iterate over strings and lists.`);
+        },
+
+        undefinedVariable(name, line) {
+            fail(`[line ${line}] Undefined variable '${name}'`);
+        },
+
+        enumType: (name, variants) => new LoxEnum(name, variants),
+
+        assert(condition, message, line, name, text) {
+            if (isTruthy(condition)) return;
+            if (message) fail(`[line ${line}] Assertion failed in ${name}: ${stringify(message())} (${text})`);
+            fail(`[line ${line}] Assertion failed in ${name}: ${text}`);
+        },
+
+        thrown: (value, line) => new Thrown(value, line),
+
+        // Only values thrown by Lox can be caught, runtime errors keep going
+        caught(error) {
+            if (error instanceof Thrown) return error.value;
+            throw error;
+        },
+
+        run(main) {
+            try {
+                main();
+            } catch (error) {
+                if (error instanceof Thrown) {
+                    console.error(`[line ${error.line}] Uncaught exception: ${stringify(error.value)}`);
+                } else if (error instanceof LoxError) {
+                    console.error(error.message);
+                } else {
+                    throw error;
+                }
+                process.exitCode = 70;
+            }
+        },
+    };
+})();
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use crate::named_source::NamedSource;
+use crate::parser::{Ast, AstNode, NodeId};
+use crate::span::SourceCursor;
+use crate::tokenizer::Comment;
+
+/// Something on its own line between statements
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line {
+    Blank, // Any number of blank lines in a row, there's only ever one of these between others
+    Comment(Comment),
+}
+
+/// The comments and blank lines around one statement
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Around {
+    /// Everything between the statement before (or the start of the block) and this one
+    pub leading: Vec<Line>,
+
+    /// A comment after it on the same line it ends on
+    pub trailing: Option<Comment>,
+}
+
+/// Comments and blank lines, which aren't part of the AST, attached to the statements they're
+/// next to so tools like the formatter can put them back
+///
+/// Statements are those in the program and in blocks. A comment on the same line after a
+/// statement trails it, anything else leads the statement that follows it. Comments after the
+/// last statement in a block (or the program) are at its closing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trivia {
+    statements: HashMap<NodeId, Around>,
+    closing: HashMap<NodeId, Vec<Line>>,
+}
+
+impl Trivia {
+    /// Attach comments (from Parser::tokenizer_iter_comments) to the statements in ast
+    pub fn attach(ast: &Ast, source: &NamedSource, comments: &[Comment]) -> Trivia {
+        let mut attacher = Attacher {
+            ast,
+            source,
+            comments,
+            next_comment: 0,
+            trivia: Trivia::default(),
+        };
+
+        let root = ast.root();
+        match &ast[root] {
+            AstNode::Program(_, nodes) => attacher.statements(root, nodes, usize::MAX),
+            _ => attacher.statements(root, &[root], usize::MAX),
+        }
+
+        attacher.trivia
+    }
+
+    /// What's around a statement, nothing if it isn't one
+    pub fn around(&self, id: NodeId) -> Option<&Around> {
+        self.statements.get(&id)
+    }
+
+    pub fn leading(&self, id: NodeId) -> &[Line] {
+        self.around(id).map_or(&[], |around| &around.leading)
+    }
+
+    pub fn trailing(&self, id: NodeId) -> Option<&Comment> {
+        self.around(id)?.trailing.as_ref()
+    }
+
+    /// The /// comments right before a statement (no blank line between), without the slashes
+    ///
+    /// Each comment is a line, with one space after the slashes dropped if it's there.
+    pub fn doc(&self, id: NodeId) -> Option<String> {
+        let lines = self
+            .leading(id)
+            .iter()
+            .rev()
+            .map_while(|line| match line {
+                Line::Comment(comment) if is_doc(&comment.text) => Some(&comment.text[3..]),
+                _ => None,
+            })
+            .map(|text| text.strip_prefix(' ').unwrap_or(text))
+            .collect::<Vec<_>>();
+
+        if lines.is_empty() {
+            return None;
+        }
+        Some(lines.into_iter().rev().collect::<Vec<_>>().join("\n"))
+    }
+
+    /// Everything after the last statement in a block or program, before it ends
+    pub fn closing(&self, id: NodeId) -> &[Line] {
+        self.closing.get(&id).map_or(&[], |lines| lines)
+    }
+}
+
+struct Attacher<'a> {
+    ast: &'a Ast,
+    source: &'a NamedSource,
+    comments: &'a [Comment],
+    next_comment: usize,
+    trivia: Trivia,
+}
+
+impl<'a> Attacher<'a> {
+    // Comments are taken in order, so this walks statements in the order they're written
+    fn statements(&mut self, parent: NodeId, nodes: &[NodeId], end: usize) {
+        let mut last_end = None;
+
+        for &node in nodes {
+            let span = self.ast[node].span();
+
+            let mut leading = self.lines_before(&mut last_end, span.start);
+            if self.blank_line(last_end, span.start) {
+                leading.push(Line::Blank);
+            }
+
+            self.statement(node);
+            let trailing = self.take_trailing_comment(span.end).cloned();
+            self.trivia
+                .statements
+                .insert(node, Around { leading, trailing });
+
+            last_end = Some(span.end);
+        }
+
+        let closing = self.lines_before(&mut last_end, end);
+        if !closing.is_empty() {
+            self.trivia.closing.insert(parent, closing);
+        }
+    }
+
+    // Comments inside blocks belong to the statements in them
+    fn statement(&mut self, id: NodeId) {
+        let ast = self.ast;
+        match &ast[id] {
+            AstNode::Block(span, nodes) | AstNode::Program(span, nodes) => {
+                self.statements(id, nodes, span.end)
+            }
+            AstNode::ForIn(_, _, _, body) => self.statement(*body),
+            AstNode::Try(_, body, catch, finally) => {
+                self.statement(*body);
+                if let Some((_, handler)) = catch {
+                    self.statement(*handler);
+                }
+                if let Some(finally) = finally {
+                    self.statement(*finally);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Comments on their own lines before position, with a blank line before any that had them
+    fn lines_before(&mut self, last_end: &mut Option<usize>, position: usize) -> Vec<Line> {
+        let mut lines = vec![];
+        while let Some(comment) = self.take_comment_before(position) {
+            if self.blank_line(*last_end, comment.span.start) {
+                lines.push(Line::Blank);
+            }
+            lines.push(Line::Comment(comment.clone()));
+            *last_end = Some(comment.span.end);
+        }
+        lines
+    }
+
+    // The next comment if it starts before the given position
+    fn take_comment_before(&mut self, position: usize) -> Option<&'a Comment> {
+        let comment = self.comments.get(self.next_comment)?;
+        if comment.span.start >= position {
+            return None;
+        }
+
+        self.next_comment += 1;
+        Some(comment)
+    }
+
+    // The next comment if it's after the given position but on the same line
+    fn take_trailing_comment(&mut self, position: usize) -> Option<&'a Comment> {
+        let comment = self.comments.get(self.next_comment)?;
+        if comment.span.start < position
+            || self.line_of(comment.span.start) != self.line_of(position)
+        {
+            return None;
+        }
+
+        self.next_comment += 1;
+        Some(comment)
+    }
+
+    // If there were any blank lines between two things in the source, never at the start
+    fn blank_line(&self, last_end: Option<usize>, start: usize) -> bool {
+        last_end.is_some_and(|last_end| self.line_of(start) > self.line_of(last_end) + 1)
+    }
+
+    fn line_of(&self, position: usize) -> usize {
+        SourceCursor::at(&self.source.bytes, position).line
+    }
+}
+
+// Doc comments have exactly three slashes, four or more is a divider or commented out code
+fn is_doc(text: &str) -> bool {
+    text.starts_with("///") && !text.starts_with("////")
+}
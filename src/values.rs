@@ -1,12 +1,75 @@
+use std::cmp::Ordering;
 use std::fmt::{self, Display};
+use std::sync::{Arc, Mutex};
 
-#[derive(Debug, Clone, PartialEq)]
+use crate::builtins::{BuiltIn, Namespace};
+
+#[derive(Debug, Clone)]
 pub enum Value {
     Nil,
     Bool(bool),
+    Integer(i64),
     Number(f64),
-    String(String),
-    Builtin(String),
+    String(Arc<str>),
+    Builtin(BuiltIn),
+    Partial(Arc<Value>, Arc<Vec<Value>>), // A callable with some arguments already bound
+    List(Arc<Vec<Value>>),
+    Enum(Arc<EnumType>),
+    EnumVariant(Arc<EnumType>, usize), // Enum and ordinal
+    Native(Arc<NativeFunction>),       // A Rust function registered by an embedder
+    Namespace(Namespace),              // Builtins grouped under a name, eg Math for Math.sqrt
+    StringBuilder(Arc<Mutex<String>>), // Appended to in place, unlike strings
+}
+
+/// An enum declaration; variants compare by identity, so two enums with the same names differ
+#[derive(Debug)]
+pub struct EnumType {
+    pub name: String,
+    pub variants: Vec<String>,
+}
+
+type NativeFn = dyn Fn(&[Value]) -> anyhow::Result<Value> + Send + Sync;
+
+/// A function from the program embedding the interpreter, called like any builtin
+///
+/// Natives compare by identity, since there's no way to compare closures.
+pub struct NativeFunction {
+    pub name: String,
+    function: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new<F>(name: &str, function: F) -> Self
+    where
+        F: Fn(&[Value]) -> anyhow::Result<Value> + Send + Sync + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            function: Box::new(function),
+        }
+    }
+
+    pub fn call(&self, args: &[Value]) -> anyhow::Result<Value> {
+        (self.function)(args)
+    }
+}
+
+/// How print shows numbers, everything else prints the same either way
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PrintStyle {
+    /// Like the official Lox, whole numbers never have a decimal point (2.0 prints 2)
+    #[default]
+    Lox,
+    /// Floats always have a decimal point and integers never do (2.0 prints 2.0, 2 prints 2)
+    Plain,
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
 }
 
 impl Value {
@@ -15,6 +78,135 @@ impl Value {
         ("true", Value::Bool(true)),
         ("false", Value::Bool(false)),
     ];
+
+    /// The name of this value's type, as returned by type()
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "bool",
+            Value::Integer(_) | Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Builtin(_) | Value::Partial(_, _) | Value::Native(_) => "function",
+            Value::List(_) => "list",
+            Value::Enum(_) => "enum",
+            Value::EnumVariant(_, _) => "variant",
+            Value::Namespace(_) => "namespace",
+            Value::StringBuilder(_) => "builder",
+        }
+    }
+
+    pub fn is_callable(&self) -> bool {
+        matches!(
+            self,
+            Value::Builtin(_) | Value::Partial(_, _) | Value::Native(_)
+        )
+    }
+
+    /// Only nil and false are falsey, everything else (0 and "" included) is truthy
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn is_number(&self) -> bool {
+        matches!(self, Value::Integer(_) | Value::Number(_))
+    }
+
+    // Widen either numeric representation to a float
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Integer(i) => Some(*i as f64),
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Order two numbers, None if either isn't one or is NaN
+    ///
+    /// Integers and floats are compared exactly, the same as ==, rather than widening the integer
+    /// to a float that might round it (2^53 + 1 isn't greater than 2^53 as floats).
+    pub fn compare_numbers(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => Some(a.cmp(b)),
+            (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+            (Value::Integer(a), Value::Number(b)) => compare_integer_to_float(*a, *b),
+            (Value::Number(a), Value::Integer(b)) => {
+                compare_integer_to_float(*b, *a).map(Ordering::reverse)
+            }
+            _ => None,
+        }
+    }
+
+    /// If both are the same value, for `is`: the same list rather than one with the same items
+    ///
+    /// Everything else compares the same as ==, lists being the only values that are compared by
+    /// what's in them.
+    pub fn is(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::List(a), Value::List(b)) => Arc::ptr_eq(a, b),
+            _ => self == other,
+        }
+    }
+
+    /// How print shows this value, numbers without a .0 unless style says otherwise
+    pub fn display(&self, style: PrintStyle) -> String {
+        match (self, style) {
+            (Value::Integer(n), _) => n.to_string(),
+            (Value::Number(n), PrintStyle::Lox) => n.to_string(),
+            (Value::List(values), _) => {
+                let items = values
+                    .iter()
+                    .map(|value| value.display(style))
+                    .collect::<Vec<_>>();
+                format!("[{}]", items.join(", "))
+            }
+            _ => self.repr(),
+        }
+    }
+
+    /// How print, toString, and error messages show this value by default
+    pub fn lox_display(&self) -> String {
+        self.display(PrintStyle::Lox)
+    }
+
+    /// How tokens and the AST show this value, whole numbers always with a .0
+    ///
+    /// This is the same as Display.
+    pub fn repr(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// What == means: nil, bools, numbers, and strings by value, lists by their items, and functions,
+/// enums, and string builders by identity, so two partials binding the same arguments are
+/// different functions
+///
+/// NaN isn't equal to anything, itself included, the same as clox.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            // Integers and floats are the same number if they have the same value (1 == 1.0),
+            // compared as integers since past 2^53 floats can't hold every integer
+            (Value::Integer(_), Value::Number(_)) | (Value::Number(_), Value::Integer(_)) => {
+                self.compare_numbers(other) == Some(Ordering::Equal)
+            }
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Builtin(a), Value::Builtin(b)) => a == b,
+            (Value::Partial(f1, args1), Value::Partial(f2, args2)) => {
+                Arc::ptr_eq(f1, f2) && Arc::ptr_eq(args1, args2)
+            }
+            (Value::List(a), Value::List(b)) => a == b,
+            (Value::Enum(a), Value::Enum(b)) => Arc::ptr_eq(a, b),
+            (Value::EnumVariant(a, i), Value::EnumVariant(b, j)) => Arc::ptr_eq(a, b) && i == j,
+            (Value::Native(a), Value::Native(b)) => Arc::ptr_eq(a, b),
+            (Value::Namespace(a), Value::Namespace(b)) => a == b,
+            (Value::StringBuilder(a), Value::StringBuilder(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
 }
 
 impl Display for Value {
@@ -22,9 +214,8 @@ impl Display for Value {
         match self {
             Value::Nil => write!(f, "nil"),
             Value::Bool(b) => write!(f, "{}", b),
+            Value::Integer(i) => write!(f, "{}.0", i),
             Value::Number(n) => {
-                // I don't make the rules
-                // TODO: Make the rules
                 if n.fract() == 0.0 {
                     write!(f, "{:.1}", n)
                 } else {
@@ -32,7 +223,258 @@ impl Display for Value {
                 }
             }
             Value::String(s) => write!(f, "{}", s),
-            Value::Builtin(s) => write!(f, "<builtin {}>", s),
+            Value::Builtin(builtin) => write!(f, "<builtin {}>", builtin.name()),
+            Value::Partial(callable, _) => write!(f, "<partial {}>", callable),
+            Value::Native(native) => write!(f, "<native {}>", native.name),
+            Value::Namespace(namespace) => write!(f, "<namespace {}>", namespace.to_value()),
+            Value::StringBuilder(builder) => write!(f, "{}", builder.lock().unwrap()),
+            Value::Enum(enum_type) => write!(f, "<enum {}>", enum_type.name),
+            Value::EnumVariant(enum_type, ordinal) => {
+                write!(f, "{}.{}", enum_type.name, enum_type.variants[*ordinal])
+            }
+            Value::List(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    // Numbers in lists print the way print would
+                    match value {
+                        Value::Integer(_) | Value::Number(_) => {
+                            write!(f, "{}", value.lox_display())?
+                        }
+                        _ => write!(f, "{}", value)?,
+                    }
+                }
+                write!(f, "]")
+            }
         }
     }
 }
+
+// Conversions from Rust values, so embedders can pass arguments without building Values by hand
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Number(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value.into())
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(values: Vec<T>) -> Self {
+        Value::List(Arc::new(values.into_iter().map(Into::into).collect()))
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Nil, Into::into)
+    }
+}
+
+impl From<()> for Value {
+    fn from(_: ()) -> Self {
+        Value::Nil
+    }
+}
+
+/// A type name the way it reads in a sentence, eg a number or an integer, with nil on its own
+pub fn with_article(type_name: &str) -> String {
+    match type_name {
+        "nil" => type_name.to_string(),
+        _ if type_name.starts_with(['a', 'e', 'i', 'o', 'u']) => format!("an {type_name}"),
+        _ => format!("a {type_name}"),
+    }
+}
+
+// Conversions back to Rust, these fail with the type that was expected and the one found
+
+fn expected(expected: &str, value: &Value) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Expected {}, got {}: {}",
+        with_article(expected),
+        with_article(value.type_name()),
+        value.lox_display()
+    )
+}
+
+impl TryFrom<Value> for bool {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            value => Err(expected("bool", &value)),
+        }
+    }
+}
+
+// The floats an i64 can hold, i64::MIN is exact but i64::MAX rounds up to 2^63 which is past it
+const I64_RANGE: std::ops::Range<f64> = i64::MIN as f64..i64::MAX as f64;
+
+// Floats past either end of an i64 are bigger or smaller than any of them, otherwise the whole part
+// can be compared as integers and only the fraction is left to break a tie
+fn compare_integer_to_float(a: i64, b: f64) -> Option<Ordering> {
+    if b.is_nan() {
+        None
+    } else if b < I64_RANGE.start {
+        Some(Ordering::Greater)
+    } else if b >= I64_RANGE.end {
+        Some(Ordering::Less)
+    } else {
+        let whole = a.cmp(&(b.trunc() as i64));
+        Some(whole.then(0.0_f64.partial_cmp(&b.fract())?))
+    }
+}
+
+// Floats only convert if they're whole and in range, so nothing is silently truncated
+impl TryFrom<Value> for i64 {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Integer(i) => Ok(i),
+            Value::Number(n) if n.fract() == 0.0 && I64_RANGE.contains(&n) => Ok(n as i64),
+            value => Err(expected("integer", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        value.as_f64().ok_or_else(|| expected("number", &value))
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(s) => Ok(s.to_string()),
+            value => Err(expected("string", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for Vec<Value> {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::List(items) => Ok(items.as_ref().clone()),
+            value => Err(expected("list", &value)),
+        }
+    }
+}
+
+impl Value {
+    /// Convert to any Rust type with a TryFrom<Value>, eg `value.extract::<f64>()`
+    pub fn extract<T: TryFrom<Value, Error = anyhow::Error>>(self) -> anyhow::Result<T> {
+        T::try_from(self)
+    }
+
+    /// Convert a list to a Vec of any Rust type with a TryFrom<Value>
+    pub fn extract_list<T: TryFrom<Value, Error = anyhow::Error>>(self) -> anyhow::Result<Vec<T>> {
+        Vec::<Value>::try_from(self)?
+            .into_iter()
+            .map(T::try_from)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integers_and_floats_are_equal_only_if_they_are_the_same_number() {
+        assert_eq!(Value::Integer(1), Value::Number(1.0));
+        assert_eq!(Value::Number(-3.0), Value::Integer(-3));
+        assert_ne!(Value::Integer(1), Value::Number(1.5));
+        assert_ne!(Value::Integer(0), Value::Number(f64::NAN));
+
+        // 2^53 + 1 rounds to 2^53 as a float, but they're different integers
+        let big = 9007199254740993;
+        assert_ne!(Value::Integer(big), Value::Number(9007199254740992.0));
+        assert_eq!(Value::Integer(big - 1), Value::Number(9007199254740992.0));
+
+        // Past the ends of i64 there's no integer they could be
+        assert_eq!(Value::Integer(i64::MIN), Value::Number(i64::MIN as f64));
+        assert_ne!(Value::Integer(i64::MAX), Value::Number(i64::MAX as f64));
+    }
+
+    #[test]
+    fn integers_and_floats_are_ordered_exactly() {
+        use Ordering::*;
+        let order = |a: i64, b: f64| Value::Integer(a).compare_numbers(&Value::Number(b));
+
+        // 2^53 + 1 would round down to 2^53 if it were compared as a float
+        let big = 9007199254740993;
+        assert_eq!(order(big, 9007199254740992.0), Some(Greater));
+        assert_eq!(order(big - 1, 9007199254740992.0), Some(Equal));
+        assert_eq!(order(-big, -9007199254740992.0), Some(Less));
+        assert_eq!(
+            Value::Number(9007199254740992.0).compare_numbers(&Value::Integer(big)),
+            Some(Less)
+        );
+
+        // Fractions break ties on the whole part, whichever sign it is
+        assert_eq!(order(1, 1.5), Some(Less));
+        assert_eq!(order(-1, -1.5), Some(Greater));
+        assert_eq!(order(0, -0.5), Some(Greater));
+
+        // Floats past either end of i64 are past every integer
+        assert_eq!(order(i64::MAX, i64::MAX as f64), Some(Less));
+        assert_eq!(order(i64::MIN, -1e300), Some(Greater));
+        assert_eq!(order(0, f64::INFINITY), Some(Less));
+
+        assert_eq!(order(0, f64::NAN), None);
+        assert_eq!(
+            Value::Integer(0).compare_numbers(&Value::String("0".into())),
+            None
+        );
+    }
+
+    #[test]
+    fn print_styles_only_change_whole_floats() {
+        let list =
+            Value::List(vec![Value::Number(2.0), Value::Integer(2), Value::Number(2.5)].into());
+
+        assert_eq!(list.display(PrintStyle::Lox), "[2, 2, 2.5]");
+        assert_eq!(list.display(PrintStyle::Plain), "[2.0, 2, 2.5]");
+        assert_eq!(list.lox_display(), list.display(PrintStyle::Lox));
+        assert_eq!(Value::Number(2.0).repr(), "2.0");
+        assert_eq!(Value::Number(2.0).lox_display(), "2");
+
+        let string = Value::String("2.0".into());
+        assert_eq!(string.display(PrintStyle::Lox), "2.0");
+        assert_eq!(string.display(PrintStyle::Plain), "2.0");
+    }
+}
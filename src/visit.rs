@@ -0,0 +1,111 @@
+use crate::parser::{Ast, AstNode, NodeId};
+
+/// A pass over an Ast that only cares about some kinds of node, walking through the rest
+///
+/// Override visit to match the nodes the pass is about and call walk for everything else (and
+/// for those too, if their children should be visited as usual).
+pub trait Visitor {
+    fn visit(&mut self, ast: &Ast, id: NodeId) {
+        walk(self, ast, id);
+    }
+}
+
+/// Visit each child of a node in the order they're evaluated
+pub fn walk<V: Visitor + ?Sized>(visitor: &mut V, ast: &Ast, id: NodeId) {
+    for child in ast[id].children() {
+        visitor.visit(ast, child);
+    }
+}
+
+/// A pass that builds a new Ast from an old one, eg to simplify or rewrite nodes
+///
+/// By default every node is copied as it was, children first so they're added before their
+/// parents. Override fold to replace some nodes, and call fold_children for the rest.
+pub trait Fold {
+    fn fold(&mut self, from: &Ast, id: NodeId, into: &mut Ast) -> NodeId {
+        fold_children(self, from, id, into)
+    }
+}
+
+/// Fold each child of a node into the new Ast, then add a copy of the node pointing at them
+pub fn fold_children<F: Fold + ?Sized>(
+    folder: &mut F,
+    from: &Ast,
+    id: NodeId,
+    into: &mut Ast,
+) -> NodeId {
+    let node: AstNode = from[id].map_children(&mut |child| folder.fold(from, child, into));
+    into.add(node)
+}
+
+/// Fold a whole Ast, the result's root is the folded root
+pub fn fold_ast<F: Fold + ?Sized>(folder: &mut F, ast: &Ast) -> Ast {
+    let mut folded = Ast::new();
+    folder.fold(ast, ast.root(), &mut folded);
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::tokenizer::Tokenizer;
+    use crate::values::Value;
+
+    fn parse(source: &str) -> Ast {
+        Parser::from(Tokenizer::new(source)).parse().unwrap()
+    }
+
+    // Every symbol, but not inside blocks
+    struct Symbols(Vec<String>);
+
+    impl Visitor for Symbols {
+        fn visit(&mut self, ast: &Ast, id: NodeId) {
+            match &ast[id] {
+                AstNode::Symbol(_, name) => self.0.push(name.to_string()),
+                AstNode::Block(_, _) => {}
+                _ => walk(self, ast, id),
+            }
+        }
+    }
+
+    #[test]
+    fn visits_in_evaluation_order_and_skips_what_isnt_walked() {
+        let ast = parse("var a = b + c;\n{ print d; }\nprint f(e);");
+        let mut symbols = Symbols(vec![]);
+        symbols.visit(&ast, ast.root());
+
+        assert_eq!(symbols.0, vec!["b", "c", "f", "e"]);
+    }
+
+    struct Identity;
+    impl Fold for Identity {}
+
+    // Doubles every number
+    struct Double;
+
+    impl Fold for Double {
+        fn fold(&mut self, from: &Ast, id: NodeId, into: &mut Ast) -> NodeId {
+            match &from[id] {
+                AstNode::Literal(span, Value::Integer(n)) => {
+                    into.add(AstNode::Literal(*span, Value::Integer(n * 2)))
+                }
+                _ => fold_children(self, from, id, into),
+            }
+        }
+    }
+
+    #[test]
+    fn folds_copy_everything_they_dont_replace() {
+        let ast = parse("var a = 1 + 2;\n{ print a * 3; }");
+
+        let copied = fold_ast(&mut Identity, &ast);
+        assert_eq!(copied.to_string(), ast.to_string());
+
+        let doubled = fold_ast(&mut Double, &ast);
+        assert_eq!(
+            doubled.to_string(),
+            "(var a (+ 2.0 4.0))\n{(print (* a 6.0))}\n"
+        );
+    }
+}
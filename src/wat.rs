@@ -0,0 +1,347 @@
+use crate::named_source::NamedSource;
+use crate::parser::{Ast, AstNode, LogicalOp, NodeId};
+use crate::values::Value;
+
+// Every value is one of these in WebAssembly; there's no nil, strings, or lists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    Number, // f64, integers included
+    Bool,   // i32, 0 or 1
+}
+
+impl Type {
+    fn wat(&self) -> &'static str {
+        match self {
+            Type::Number => "f64",
+            Type::Bool => "i32",
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Type::Number => "number",
+            Type::Bool => "bool",
+        }
+    }
+}
+
+/// Lower parsed programs to a WebAssembly text module, or list everything that can't be lowered
+///
+/// Only numbers, bools, variables, blocks, operators, a few math builtins, and print are
+/// supported; all numbers are f64. The module exports `main` and imports `print_number` and
+/// `print_bool` from `lox` for the host to print with.
+pub fn wat(programs: &[(NamedSource, Ast)]) -> Result<String, Vec<String>> {
+    let mut lowering = Lowering {
+        ast: &Ast::new(),
+        body: vec![],
+        locals: vec![],
+        scopes: vec![vec![]],
+        errors: vec![],
+    };
+
+    for (_, ast) in programs {
+        lowering.ast = ast;
+        lowering.statement(ast.root());
+    }
+
+    if !lowering.errors.is_empty() {
+        return Err(lowering.errors);
+    }
+
+    let mut output = String::from("(module\n");
+    output.push_str("  (import \"lox\" \"print_number\" (func $print_number (param f64)))\n");
+    output.push_str("  (import \"lox\" \"print_bool\" (func $print_bool (param i32)))\n");
+    output.push_str("  (func $main (export \"main\")\n");
+    for (name, t) in &lowering.locals {
+        output.push_str(&format!("    (local ${} {})\n", name, t.wat()));
+    }
+    for instruction in &lowering.body {
+        output.push_str(&format!("    {}\n", instruction));
+    }
+    output.push_str("  )\n)\n");
+
+    Ok(output)
+}
+
+struct Lowering<'a> {
+    ast: &'a Ast,                      // The program being lowered
+    body: Vec<String>,                 // Instructions for main, in order
+    locals: Vec<(String, Type)>,       // Every local in main, named uniquely
+    scopes: Vec<Vec<(String, usize)>>, // Lox names to indexes in locals
+    errors: Vec<String>,
+}
+
+impl<'a> Lowering<'a> {
+    fn emit(&mut self, instruction: impl Into<String>) {
+        self.body.push(instruction.into());
+    }
+
+    fn unsupported(&mut self, id: NodeId) {
+        let ast = self.ast;
+        let line = ast[id].span().line;
+        let what = match &ast[id] {
+            AstNode::Literal(_, value) => format!("{} literal", value.type_name()),
+            AstNode::Symbol(_, name) => format!("Symbol '{name}'"),
+            AstNode::Call(_, function, _) => match &ast[*function] {
+                AstNode::Symbol(_, name) => format!("Call of '{name}'"),
+                _ => "Call".to_string(),
+            },
+            node => node.kind().to_string(),
+        };
+        self.errors
+            .push(format!("[line {line}] Unsupported in wat: {what}"));
+    }
+
+    fn type_error(&mut self, id: NodeId, message: &str) {
+        let line = self.ast[id].span().line;
+        self.errors
+            .push(format!("[line {line}] Type error in wat: {message}"));
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.iter().rev().find(|(n, _)| n == name))
+            .map(|(_, index)| *index)
+    }
+
+    // Shadowed names get a suffix so every local in main is distinct, Lox names can't have a .
+    fn declare(&mut self, name: &str, t: Type) -> usize {
+        let local = if self.locals.iter().any(|(n, _)| n == name) {
+            format!("{name}.{}", self.locals.len())
+        } else {
+            name.to_string()
+        };
+
+        self.locals.push((local, t));
+        let index = self.locals.len() - 1;
+        self.scopes
+            .last_mut()
+            .expect("at least the global scope")
+            .push((name.to_string(), index));
+        index
+    }
+
+    fn statement(&mut self, id: NodeId) {
+        let ast = self.ast;
+        match &ast[id] {
+            AstNode::Program(_, nodes) => {
+                for node in nodes {
+                    self.statement(*node);
+                }
+            }
+            AstNode::Group(_, expression) => self.statement(*expression),
+
+            AstNode::Block(_, nodes) => {
+                self.scopes.push(vec![]);
+                for node in nodes {
+                    self.statement(*node);
+                }
+                self.scopes.pop();
+            }
+
+            AstNode::Declaration(_, name, value) => {
+                if let Some(t) = self.expression(*value) {
+                    let index = self.declare(name, t);
+                    let local = self.locals[index].0.clone();
+                    self.emit(format!("local.set ${local}"));
+                }
+            }
+
+            // print is only a statement here, since it has no value without nil
+            AstNode::Print(_, value) => match self.expression(*value) {
+                Some(Type::Number) => self.emit("call $print_number"),
+                Some(Type::Bool) => self.emit("call $print_bool"),
+                None => {}
+            },
+
+            _ => {
+                if self.expression(id).is_some() {
+                    self.emit("drop");
+                }
+            }
+        }
+    }
+
+    // Emit code leaving one value on the stack, None (with an error recorded) if it can't be
+    fn expression(&mut self, id: NodeId) -> Option<Type> {
+        let ast = self.ast;
+        match &ast[id] {
+            AstNode::Literal(_, Value::Integer(i)) => {
+                self.emit(format!("f64.const {}", *i as f64));
+                Some(Type::Number)
+            }
+            AstNode::Literal(_, Value::Number(n)) if n.is_finite() => {
+                self.emit(format!("f64.const {:?}", n));
+                Some(Type::Number)
+            }
+            AstNode::Literal(_, Value::Bool(b)) => {
+                self.emit(format!("i32.const {}", *b as i32));
+                Some(Type::Bool)
+            }
+
+            AstNode::Symbol(_, name) => match self.lookup(name) {
+                Some(index) => {
+                    let (local, t) = self.locals[index].clone();
+                    self.emit(format!("local.get ${local}"));
+                    Some(t)
+                }
+                None => {
+                    self.unsupported(id);
+                    None
+                }
+            },
+
+            AstNode::Group(_, expression) => self.expression(*expression),
+
+            AstNode::Assignment(_, name, value) => {
+                let t = self.expression(*value)?;
+                match self.lookup(name) {
+                    Some(index) if self.locals[index].1 == t => {
+                        let local = self.locals[index].0.clone();
+                        self.emit(format!("local.tee ${local}"));
+                        Some(t)
+                    }
+                    Some(index) => {
+                        let message = format!(
+                            "can't assign a {} to {}, which is a {}",
+                            t.name(),
+                            name,
+                            self.locals[index].1.name()
+                        );
+                        self.type_error(id, &message);
+                        None
+                    }
+                    None => {
+                        self.unsupported(id);
+                        None
+                    }
+                }
+            }
+
+            AstNode::Binary(_, op, lhs, rhs) => self.application(id, op.to_value(), &[*lhs, *rhs]),
+            AstNode::Unary(_, op, operand) => self.application(id, op.to_value(), &[*operand]),
+            AstNode::Logical(_, op, lhs, rhs) => self.logical(id, *op, *lhs, *rhs),
+
+            AstNode::Call(_, function, args) => match &ast[*function] {
+                AstNode::Symbol(_, name) if self.lookup(name).is_none() => {
+                    self.application(id, name, args)
+                }
+                // Math.sqrt and the rest are the same as the names alone
+                AstNode::Get(_, namespace, name) if self.is_math(&ast[*namespace]) => {
+                    self.application(id, name, args)
+                }
+                _ => {
+                    self.unsupported(id);
+                    None
+                }
+            },
+
+            _ => {
+                self.unsupported(id);
+                None
+            }
+        }
+    }
+
+    // The Math namespace, unless a local shadows it
+    fn is_math(&self, node: &AstNode) -> bool {
+        matches!(node, AstNode::Symbol(_, name) if &**name == "Math" && self.lookup(name).is_none())
+    }
+
+    fn application(&mut self, id: NodeId, name: &str, args: &[NodeId]) -> Option<Type> {
+        use Type::*;
+
+        // Lower every argument first so all errors inside them are reported
+        let mut types = vec![];
+        for arg in args {
+            types.push(self.expression(*arg));
+        }
+        let types = types.into_iter().collect::<Option<Vec<_>>>()?;
+
+        let (instruction, result) = match (name, types.as_slice()) {
+            ("+", [Number, Number]) => ("f64.add", Number),
+            ("-", [Number, Number]) => ("f64.sub", Number),
+            ("-", [Number]) => ("f64.neg", Number),
+            ("*", [Number, Number]) => ("f64.mul", Number),
+            ("/", [Number, Number]) => ("f64.div", Number),
+
+            ("<", [Number, Number]) => ("f64.lt", Bool),
+            ("<=", [Number, Number]) => ("f64.le", Bool),
+            (">", [Number, Number]) => ("f64.gt", Bool),
+            (">=", [Number, Number]) => ("f64.ge", Bool),
+            ("==" | "is", [Number, Number]) => ("f64.eq", Bool),
+            ("!=", [Number, Number]) => ("f64.ne", Bool),
+            ("==" | "is", [Bool, Bool]) => ("i32.eq", Bool),
+            ("!=", [Bool, Bool]) => ("i32.ne", Bool),
+
+            ("!", [Bool]) => ("i32.eqz", Bool),
+
+            ("sqrt", [Number]) => ("f64.sqrt", Number),
+            ("abs", [Number]) => ("f64.abs", Number),
+            ("floor", [Number]) => ("f64.floor", Number),
+            ("ceil", [Number]) => ("f64.ceil", Number),
+            ("min", [Number, Number]) => ("f64.min", Number),
+            ("max", [Number, Number]) => ("f64.max", Number),
+
+            // Values of different types are never equal
+            ("==" | "!=" | "is", [_, _]) => {
+                self.emit("drop");
+                self.emit("drop");
+                let equal = if name == "!=" { 1 } else { 0 };
+                self.emit(format!("i32.const {equal}"));
+                return Some(Bool);
+            }
+
+            (
+                "+" | "-" | "*" | "/" | "<" | "<=" | ">" | ">=" | "!" | "sqrt" | "abs" | "floor"
+                | "ceil" | "min" | "max",
+                _,
+            ) => {
+                let types = types.iter().map(Type::name).collect::<Vec<_>>();
+                let message = format!("{name} can't be applied to ({})", types.join(", "));
+                self.type_error(id, &message);
+                return None;
+            }
+
+            _ => {
+                self.unsupported(id);
+                return None;
+            }
+        };
+
+        self.emit(instruction);
+        Some(result)
+    }
+
+    // Only bools, so the rhs is in an if that's skipped when the lhs already decided it
+    fn logical(&mut self, id: NodeId, op: LogicalOp, lhs: NodeId, rhs: NodeId) -> Option<Type> {
+        let lhs = self.expression(lhs);
+        self.emit("if (result i32)");
+        let rhs = match op {
+            LogicalOp::And => {
+                let rhs = self.expression(rhs);
+                self.emit("else");
+                self.emit("i32.const 0");
+                rhs
+            }
+            LogicalOp::Or => {
+                self.emit("i32.const 1");
+                self.emit("else");
+                self.expression(rhs)
+            }
+        };
+        self.emit("end");
+
+        match (lhs?, rhs?) {
+            (Type::Bool, Type::Bool) => Some(Type::Bool),
+            (lhs, rhs) => {
+                let (op, lhs, rhs) = (op.to_value(), lhs.name(), rhs.name());
+                let message = format!("{op} can't be applied to ({lhs}, {rhs})");
+                self.type_error(id, &message);
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// ANSI escape codes to clear the screen and move the cursor to the top left
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// Call run once, then again each time any of the watched files is modified
+///
+/// The watched files are paths and every file run returns, which is the modules a run imported, so
+/// they're found again each time it runs. Files are polled for changes in their modification time,
+/// which is coarse but needs no platform specific file system notifications. This never returns
+/// unless reading one of paths fails before the first run.
+pub fn watch(paths: &[PathBuf], mut run: impl FnMut() -> Vec<PathBuf>) -> Result<()> {
+    for path in paths {
+        fs::metadata(path)?;
+    }
+
+    let mut watched = paths.to_vec();
+    loop {
+        print!("{CLEAR_SCREEN}");
+        io::stdout().flush()?;
+
+        // Before running, so saving a file while it runs is a change that runs it again
+        let started = SystemTime::now();
+        let before = watched
+            .iter()
+            .cloned()
+            .zip(modified_times(&watched))
+            .collect::<Vec<_>>();
+
+        watched = paths.to_vec();
+        for path in run() {
+            if !watched.contains(&path) {
+                watched.push(path);
+            }
+        }
+
+        // Modules this run found for the first time weren't there to check before it, so one
+        // modified since it started has no time to compare with and counts as changed
+        let last_modified = watched
+            .iter()
+            .map(|path| match before.iter().find(|(p, _)| p == path) {
+                Some((_, modified)) => *modified,
+                None => modified_time(path).filter(|modified| *modified < started),
+            })
+            .collect::<Vec<_>>();
+
+        loop {
+            thread::sleep(POLL_INTERVAL);
+
+            // Editors often replace files on save, so a missing file is likely to come back and
+            // only files that are there can have changed
+            let modified = modified_times(&watched);
+            let changed = modified
+                .iter()
+                .zip(&last_modified)
+                .any(|(now, before)| now.is_some() && now != before);
+
+            if changed {
+                break;
+            }
+        }
+    }
+}
+
+fn modified_times(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths.iter().map(|path| modified_time(path)).collect()
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,44 @@
+/*
+ * Compiles against include/jp_lox.h and links with the library from `just ffi`, so a header
+ * that's drifted from src/ffi.rs fails to build or prints something else. Run with `just
+ * test-ffi`.
+ */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "jp_lox.h"
+
+static int failures = 0;
+
+static void expect(JpLox *lox, const char *source, int code, const char *error) {
+    int result = jp_lox_run(lox, source);
+    const char *message = jp_lox_last_error(lox);
+    fflush(stdout);
+
+    if (result != code) {
+        printf("FAIL %s: returned %d, expected %d\n", source, result, code);
+        failures++;
+    } else if (error == NULL ? message != NULL : message == NULL || strstr(message, error) == NULL) {
+        printf("FAIL %s: last error %s, expected %s\n", source, message ? message : "NULL",
+               error ? error : "NULL");
+        failures++;
+    }
+}
+
+int main(void) {
+    JpLox *lox = jp_lox_new();
+
+    expect(lox, "var greeting = \"hello from C\";", JP_LOX_OK, NULL);
+    expect(lox, "print greeting;", JP_LOX_OK, NULL);
+    expect(lox, "var = 1;", JP_LOX_SYNTAX_ERROR, "Expect identifier");
+    expect(lox, "print missing;", JP_LOX_RUNTIME_ERROR, "Undefined variable 'missing'");
+    expect(lox, NULL, JP_LOX_INVALID_ARGUMENT, "source is NULL");
+    expect(lox, "print len(greeting);", JP_LOX_OK, NULL);
+
+    jp_lox_free(lox);
+    jp_lox_free(NULL);
+
+    if (failures == 0) printf("ok\n");
+    return failures == 0 ? 0 : 1;
+}
@@ -0,0 +1,8 @@
+# Errors are printed and it keeps watching, so fixing them runs it again
+echo 'print "before"; print missing;' > program.lox
+"$1" run --watch program.lox > output 2>&1 &
+sleep 1
+echo 'print "fixed";' > program.lox
+sleep 1
+kill $!
+sed 's/\x1b\[2J\x1b\[H/[clear]\n/g' output
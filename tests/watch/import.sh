@@ -0,0 +1,9 @@
+# Saving a module the script imports runs it again too
+echo 'var name = "first";' > module.lox
+echo 'import "module"; print name;' > program.lox
+"$1" run --watch program.lox > output 2>&1 &
+sleep 1
+echo 'var name = "second";' > module.lox
+sleep 1
+kill $!
+sed 's/\x1b\[2J\x1b\[H/[clear]\n/g' output
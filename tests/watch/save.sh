@@ -0,0 +1,8 @@
+# Saving the script runs it again with what was saved
+echo 'print "first";' > program.lox
+"$1" run --watch program.lox > output 2>&1 &
+sleep 1
+echo 'print "second";' > program.lox
+sleep 1
+kill $!
+sed 's/\x1b\[2J\x1b\[H/[clear]\n/g' output